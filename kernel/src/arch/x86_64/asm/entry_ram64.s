@@ -58,25 +58,89 @@ _setup_pto:
     mov    $0xc0000080,%ecx
     wrmsr
 
+    # %r13 = SEV encryption mask (C-bit) or 0
+    xor    %r13, %r13
+
+    # With SEV-ES cpuid raises a #VC, which can't be handled this early.
+    # vmrun sets bit 0 of %rsi for SEV-ES guests, then the C-bit position is
+    # requested with the GHCB MSR protocol instead.
+    bt     $0, %esi
+    jnc    2f
+
+    mov    $0xc0010130, %ecx # GHCB MSR
+    mov    $0x40000004, %eax # CPUID request (0x004) for %ebx (bits 31:30 = 1)
+    mov    $0x8000001f, %edx # CPUID function
+    wrmsr
+    .byte  0xf3, 0x0f, 0x01, 0xd9 # vmgexit (rep vmmcall)
+    rdmsr
+    and    $0xfff, %eax
+    cmp    $0x005, %eax # CPUID response, %ebx value in %edx
+    jne    .pto_halt_loop
+    mov    %edx, %esi # C-bit position in bits 5:0
+    jmp    3f
+
+2:
+    mov    $0x80000000, %eax
+    cpuid
+    cmp    $0x8000001f, %eax
+    jb     .no_sev
+
+    mov    $0x8000001f, %eax
+    cpuid
+    bt     $1, %eax # SEV supported
+    jnc    .no_sev
+    mov    %ebx, %esi # C-bit position in bits 5:0
+
+3:
+    mov    $0xc0010131, %ecx # SEV_STATUS MSR
+    rdmsr
+    bt     $0, %eax # SEV enabled
+    jnc    .no_sev
+
+    mov    %esi, %ecx
+    and    $0x3f, %ecx
+    mov    $1, %r13d
+    shl    %cl, %r13
+    mov    %r13, _sev_encryption_mask(%rip)
+
+    # set the C-bit in the identity and physical offset mappings
+    mov    $_pml2ident, %edx
+    movabs $_pml3to, %rsi
+    xor    %ecx, %ecx
+1:
+    or     %r13, (%rdx,%rcx,8)
+    or     %r13, (%rsi,%rcx,8)
+    inc    %ecx
+    cmp    $512, %ecx
+    jne    1b
+
+.no_sev:
     mov  $_pml2ident, %eax
-    orb  $0b00000111, %al # writable (bit 1), present (bit 0)
+    orb  $0b00000111, %al # user (bit 2), writable (bit 1), present (bit 0)
+    or   %r13, %rax
     mov  $_pml3ident, %edx
-    movl %eax, (%edx)
+    mov  %rax, (%rdx)
 
     mov  $_pml3ident, %edx
-    orb  $0b00000111, %dl # writable (bit 1), present (bit 0)
+    orb  $0b00000111, %dl # user (bit 2), writable (bit 1), present (bit 0)
+    or   %r13, %rdx
     mov  $_pml4t, %eax
-    mov  %edx, (%eax)
-    mov  %rax, %cr3
+    mov  %rdx, (%rax)
+    # the C-bit in CR3 marks the level 4 table as encrypted
+    mov  %rax, %rdx
+    or   %r13, %rdx
+    mov  %rdx, %cr3
 
     invlpg (%eax)
 
     # setup physical offset page table
     movabs $_pml3to, %rax
     orb  $0b00000011, %al # writable (bit 1), present (bit 0)
+    mov  %rax, %rcx
+    or   %r13, %rcx
     movabs  $_pml4t, %rdx
     addl $128, %edx
-    movl %eax, (%edx)
+    mov  %rcx, (%rdx)
     invlpg (%rax)
 
 _before_jump:
@@ -94,6 +158,12 @@ _before_jump:
     hlt
     jmp .pto_halt_loop
 
+.section .pmldata, "aw"
+.global _sev_encryption_mask
+.align 8
+_sev_encryption_mask:
+.quad 0
+
 stack_size = 0x10000
 
 .section .bss.stack, "aw"
@@ -14,3 +14,20 @@ _rdfsbase:
 _wrfsbase:
     wrfsbase %rdi
     retq
+
+.section .text, "ax"
+.global _rdgsbase
+.type _rdgsbase, @function
+.p2align 4
+_rdgsbase:
+    rdgsbase %rax
+    retq
+
+.section .text, "ax"
+.global _wrgsbase
+.type _wrgsbase, @function
+.code64
+.p2align 4
+_wrgsbase:
+    wrgsbase %rdi
+    retq
@@ -24,15 +24,21 @@ _isr_\num:
 
     # rsp is first argument
     movq    %rsp, %rdi
-    subq   $(XSAVE_STACK_OFFSET), %rsp
-
-    # add xsave area and align stack
 .if \has_error
     addq    $(11*8), %rdi
 .else
     addq    $(10*8), %rdi
 .endif
 
+    # a stack frame of the interrupted instruction pointer,
+    # so the frame pointer chain continues in the interrupted code
+    pushq   (%rdi)
+    pushq   %rbp
+    movq    %rsp, %rbp
+
+    # add xsave area and align stack
+    subq   $(XSAVE_STACK_OFFSET), %rsp
+
     # align stack
     andq   $(~(0x40-1)), %rsp
 
@@ -60,6 +66,7 @@ _isr_\num:
     xrstor (%rsp)
 
     # xrstor end
+    movq    -16(%rbx), %rbp
     movq    %rbx, %rsp
 
     popq    %rbx
@@ -73,6 +80,11 @@ _isr_\num:
     popq    %rsi
     popq    %rdi
 
+.if \has_error
+    # pop the error code
+    addq    $8, %rsp
+.endif
+
     iretq
     .p2align 4
 .endm
@@ -98,7 +110,8 @@ ISR 17 has_error=1
 ISR 18 has_error=0
 ISR 19 has_error=0
 ISR 20 has_error=0
-# 21..=29
+# 21..=28
+ISR 29 has_error=1
 ISR 30 has_error=1
 
 #ISR 32 has_error=0
@@ -5,513 +5,513 @@
 _pml2ident:
 .quad 0x183
 .quad 0x200183
-.quad 0x400187
-.quad 0x600187
-.quad 0x800187
-.quad 0xa00187
-.quad 0xc00187
-.quad 0xe00187
-.quad 0x1000187
-.quad 0x1200187
-.quad 0x1400187
-.quad 0x1600187
-.quad 0x1800187
-.quad 0x1a00187
-.quad 0x1c00187
-.quad 0x1e00187
-.quad 0x2000187
-.quad 0x2200187
-.quad 0x2400187
-.quad 0x2600187
-.quad 0x2800187
-.quad 0x2a00187
-.quad 0x2c00187
-.quad 0x2e00187
-.quad 0x3000187
-.quad 0x3200187
-.quad 0x3400187
-.quad 0x3600187
-.quad 0x3800187
-.quad 0x3a00187
-.quad 0x3c00187
-.quad 0x3e00187
-.quad 0x4000187
-.quad 0x4200187
-.quad 0x4400187
-.quad 0x4600187
-.quad 0x4800187
-.quad 0x4a00187
-.quad 0x4c00187
-.quad 0x4e00187
-.quad 0x5000187
-.quad 0x5200187
-.quad 0x5400187
-.quad 0x5600187
-.quad 0x5800187
-.quad 0x5a00187
-.quad 0x5c00187
-.quad 0x5e00187
-.quad 0x6000187
-.quad 0x6200187
-.quad 0x6400187
-.quad 0x6600187
-.quad 0x6800187
-.quad 0x6a00187
-.quad 0x6c00187
-.quad 0x6e00187
-.quad 0x7000187
-.quad 0x7200187
-.quad 0x7400187
-.quad 0x7600187
-.quad 0x7800187
-.quad 0x7a00187
-.quad 0x7c00187
-.quad 0x7e00187
-.quad 0x8000187
-.quad 0x8200187
-.quad 0x8400187
-.quad 0x8600187
-.quad 0x8800187
-.quad 0x8a00187
-.quad 0x8c00187
-.quad 0x8e00187
-.quad 0x9000187
-.quad 0x9200187
-.quad 0x9400187
-.quad 0x9600187
-.quad 0x9800187
-.quad 0x9a00187
-.quad 0x9c00187
-.quad 0x9e00187
-.quad 0xa000187
-.quad 0xa200187
-.quad 0xa400187
-.quad 0xa600187
-.quad 0xa800187
-.quad 0xaa00187
-.quad 0xac00187
-.quad 0xae00187
-.quad 0xb000187
-.quad 0xb200187
-.quad 0xb400187
-.quad 0xb600187
-.quad 0xb800187
-.quad 0xba00187
-.quad 0xbc00187
-.quad 0xbe00187
-.quad 0xc000187
-.quad 0xc200187
-.quad 0xc400187
-.quad 0xc600187
-.quad 0xc800187
-.quad 0xca00187
-.quad 0xcc00187
-.quad 0xce00187
-.quad 0xd000187
-.quad 0xd200187
-.quad 0xd400187
-.quad 0xd600187
-.quad 0xd800187
-.quad 0xda00187
-.quad 0xdc00187
-.quad 0xde00187
-.quad 0xe000187
-.quad 0xe200187
-.quad 0xe400187
-.quad 0xe600187
-.quad 0xe800187
-.quad 0xea00187
-.quad 0xec00187
-.quad 0xee00187
-.quad 0xf000187
-.quad 0xf200187
-.quad 0xf400187
-.quad 0xf600187
-.quad 0xf800187
-.quad 0xfa00187
-.quad 0xfc00187
-.quad 0xfe00187
-.quad 0x10000187
-.quad 0x10200187
-.quad 0x10400187
-.quad 0x10600187
-.quad 0x10800187
-.quad 0x10a00187
-.quad 0x10c00187
-.quad 0x10e00187
-.quad 0x11000187
-.quad 0x11200187
-.quad 0x11400187
-.quad 0x11600187
-.quad 0x11800187
-.quad 0x11a00187
-.quad 0x11c00187
-.quad 0x11e00187
-.quad 0x12000187
-.quad 0x12200187
-.quad 0x12400187
-.quad 0x12600187
-.quad 0x12800187
-.quad 0x12a00187
-.quad 0x12c00187
-.quad 0x12e00187
-.quad 0x13000187
-.quad 0x13200187
-.quad 0x13400187
-.quad 0x13600187
-.quad 0x13800187
-.quad 0x13a00187
-.quad 0x13c00187
-.quad 0x13e00187
-.quad 0x14000187
-.quad 0x14200187
-.quad 0x14400187
-.quad 0x14600187
-.quad 0x14800187
-.quad 0x14a00187
-.quad 0x14c00187
-.quad 0x14e00187
-.quad 0x15000187
-.quad 0x15200187
-.quad 0x15400187
-.quad 0x15600187
-.quad 0x15800187
-.quad 0x15a00187
-.quad 0x15c00187
-.quad 0x15e00187
-.quad 0x16000187
-.quad 0x16200187
-.quad 0x16400187
-.quad 0x16600187
-.quad 0x16800187
-.quad 0x16a00187
-.quad 0x16c00187
-.quad 0x16e00187
-.quad 0x17000187
-.quad 0x17200187
-.quad 0x17400187
-.quad 0x17600187
-.quad 0x17800187
-.quad 0x17a00187
-.quad 0x17c00187
-.quad 0x17e00187
-.quad 0x18000187
-.quad 0x18200187
-.quad 0x18400187
-.quad 0x18600187
-.quad 0x18800187
-.quad 0x18a00187
-.quad 0x18c00187
-.quad 0x18e00187
-.quad 0x19000187
-.quad 0x19200187
-.quad 0x19400187
-.quad 0x19600187
-.quad 0x19800187
-.quad 0x19a00187
-.quad 0x19c00187
-.quad 0x19e00187
-.quad 0x1a000187
-.quad 0x1a200187
-.quad 0x1a400187
-.quad 0x1a600187
-.quad 0x1a800187
-.quad 0x1aa00187
-.quad 0x1ac00187
-.quad 0x1ae00187
-.quad 0x1b000187
-.quad 0x1b200187
-.quad 0x1b400187
-.quad 0x1b600187
-.quad 0x1b800187
-.quad 0x1ba00187
-.quad 0x1bc00187
-.quad 0x1be00187
-.quad 0x1c000187
-.quad 0x1c200187
-.quad 0x1c400187
-.quad 0x1c600187
-.quad 0x1c800187
-.quad 0x1ca00187
-.quad 0x1cc00187
-.quad 0x1ce00187
-.quad 0x1d000187
-.quad 0x1d200187
-.quad 0x1d400187
-.quad 0x1d600187
-.quad 0x1d800187
-.quad 0x1da00187
-.quad 0x1dc00187
-.quad 0x1de00187
-.quad 0x1e000187
-.quad 0x1e200187
-.quad 0x1e400187
-.quad 0x1e600187
-.quad 0x1e800187
-.quad 0x1ea00187
-.quad 0x1ec00187
-.quad 0x1ee00187
-.quad 0x1f000187
-.quad 0x1f200187
-.quad 0x1f400187
-.quad 0x1f600187
-.quad 0x1f800187
-.quad 0x1fa00187
-.quad 0x1fc00187
-.quad 0x1fe00187
-.quad 0x20000187
-.quad 0x20200187
-.quad 0x20400187
-.quad 0x20600187
-.quad 0x20800187
-.quad 0x20a00187
-.quad 0x20c00187
-.quad 0x20e00187
-.quad 0x21000187
-.quad 0x21200187
-.quad 0x21400187
-.quad 0x21600187
-.quad 0x21800187
-.quad 0x21a00187
-.quad 0x21c00187
-.quad 0x21e00187
-.quad 0x22000187
-.quad 0x22200187
-.quad 0x22400187
-.quad 0x22600187
-.quad 0x22800187
-.quad 0x22a00187
-.quad 0x22c00187
-.quad 0x22e00187
-.quad 0x23000187
-.quad 0x23200187
-.quad 0x23400187
-.quad 0x23600187
-.quad 0x23800187
-.quad 0x23a00187
-.quad 0x23c00187
-.quad 0x23e00187
-.quad 0x24000187
-.quad 0x24200187
-.quad 0x24400187
-.quad 0x24600187
-.quad 0x24800187
-.quad 0x24a00187
-.quad 0x24c00187
-.quad 0x24e00187
-.quad 0x25000187
-.quad 0x25200187
-.quad 0x25400187
-.quad 0x25600187
-.quad 0x25800187
-.quad 0x25a00187
-.quad 0x25c00187
-.quad 0x25e00187
-.quad 0x26000187
-.quad 0x26200187
-.quad 0x26400187
-.quad 0x26600187
-.quad 0x26800187
-.quad 0x26a00187
-.quad 0x26c00187
-.quad 0x26e00187
-.quad 0x27000187
-.quad 0x27200187
-.quad 0x27400187
-.quad 0x27600187
-.quad 0x27800187
-.quad 0x27a00187
-.quad 0x27c00187
-.quad 0x27e00187
-.quad 0x28000187
-.quad 0x28200187
-.quad 0x28400187
-.quad 0x28600187
-.quad 0x28800187
-.quad 0x28a00187
-.quad 0x28c00187
-.quad 0x28e00187
-.quad 0x29000187
-.quad 0x29200187
-.quad 0x29400187
-.quad 0x29600187
-.quad 0x29800187
-.quad 0x29a00187
-.quad 0x29c00187
-.quad 0x29e00187
-.quad 0x2a000187
-.quad 0x2a200187
-.quad 0x2a400187
-.quad 0x2a600187
-.quad 0x2a800187
-.quad 0x2aa00187
-.quad 0x2ac00187
-.quad 0x2ae00187
-.quad 0x2b000187
-.quad 0x2b200187
-.quad 0x2b400187
-.quad 0x2b600187
-.quad 0x2b800187
-.quad 0x2ba00187
-.quad 0x2bc00187
-.quad 0x2be00187
-.quad 0x2c000187
-.quad 0x2c200187
-.quad 0x2c400187
-.quad 0x2c600187
-.quad 0x2c800187
-.quad 0x2ca00187
-.quad 0x2cc00187
-.quad 0x2ce00187
-.quad 0x2d000187
-.quad 0x2d200187
-.quad 0x2d400187
-.quad 0x2d600187
-.quad 0x2d800187
-.quad 0x2da00187
-.quad 0x2dc00187
-.quad 0x2de00187
-.quad 0x2e000187
-.quad 0x2e200187
-.quad 0x2e400187
-.quad 0x2e600187
-.quad 0x2e800187
-.quad 0x2ea00187
-.quad 0x2ec00187
-.quad 0x2ee00187
-.quad 0x2f000187
-.quad 0x2f200187
-.quad 0x2f400187
-.quad 0x2f600187
-.quad 0x2f800187
-.quad 0x2fa00187
-.quad 0x2fc00187
-.quad 0x2fe00187
-.quad 0x30000187
-.quad 0x30200187
-.quad 0x30400187
-.quad 0x30600187
-.quad 0x30800187
-.quad 0x30a00187
-.quad 0x30c00187
-.quad 0x30e00187
-.quad 0x31000187
-.quad 0x31200187
-.quad 0x31400187
-.quad 0x31600187
-.quad 0x31800187
-.quad 0x31a00187
-.quad 0x31c00187
-.quad 0x31e00187
-.quad 0x32000187
-.quad 0x32200187
-.quad 0x32400187
-.quad 0x32600187
-.quad 0x32800187
-.quad 0x32a00187
-.quad 0x32c00187
-.quad 0x32e00187
-.quad 0x33000187
-.quad 0x33200187
-.quad 0x33400187
-.quad 0x33600187
-.quad 0x33800187
-.quad 0x33a00187
-.quad 0x33c00187
-.quad 0x33e00187
-.quad 0x34000187
-.quad 0x34200187
-.quad 0x34400187
-.quad 0x34600187
-.quad 0x34800187
-.quad 0x34a00187
-.quad 0x34c00187
-.quad 0x34e00187
-.quad 0x35000187
-.quad 0x35200187
-.quad 0x35400187
-.quad 0x35600187
-.quad 0x35800187
-.quad 0x35a00187
-.quad 0x35c00187
-.quad 0x35e00187
-.quad 0x36000187
-.quad 0x36200187
-.quad 0x36400187
-.quad 0x36600187
-.quad 0x36800187
-.quad 0x36a00187
-.quad 0x36c00187
-.quad 0x36e00187
-.quad 0x37000187
-.quad 0x37200187
-.quad 0x37400187
-.quad 0x37600187
-.quad 0x37800187
-.quad 0x37a00187
-.quad 0x37c00187
-.quad 0x37e00187
-.quad 0x38000187
-.quad 0x38200187
-.quad 0x38400187
-.quad 0x38600187
-.quad 0x38800187
-.quad 0x38a00187
-.quad 0x38c00187
-.quad 0x38e00187
-.quad 0x39000187
-.quad 0x39200187
-.quad 0x39400187
-.quad 0x39600187
-.quad 0x39800187
-.quad 0x39a00187
-.quad 0x39c00187
-.quad 0x39e00187
-.quad 0x3a000187
-.quad 0x3a200187
-.quad 0x3a400187
-.quad 0x3a600187
-.quad 0x3a800187
-.quad 0x3aa00187
-.quad 0x3ac00187
-.quad 0x3ae00187
-.quad 0x3b000187
-.quad 0x3b200187
-.quad 0x3b400187
-.quad 0x3b600187
-.quad 0x3b800187
-.quad 0x3ba00187
-.quad 0x3bc00187
-.quad 0x3be00187
-.quad 0x3c000187
-.quad 0x3c200187
-.quad 0x3c400187
-.quad 0x3c600187
-.quad 0x3c800187
-.quad 0x3ca00187
-.quad 0x3cc00187
-.quad 0x3ce00187
-.quad 0x3d000187
-.quad 0x3d200187
-.quad 0x3d400187
-.quad 0x3d600187
-.quad 0x3d800187
-.quad 0x3da00187
-.quad 0x3dc00187
-.quad 0x3de00187
-.quad 0x3e000187
-.quad 0x3e200187
-.quad 0x3e400187
-.quad 0x3e600187
-.quad 0x3e800187
-.quad 0x3ea00187
-.quad 0x3ec00187
-.quad 0x3ee00187
-.quad 0x3f000187
-.quad 0x3f200187
-.quad 0x3f400187
-.quad 0x3f600187
-.quad 0x3f800187
-.quad 0x3fa00187
-.quad 0x3fc00187
-.quad 0x3fe00187
+.quad 0x400183
+.quad 0x600183
+.quad 0x800183
+.quad 0xa00183
+.quad 0xc00183
+.quad 0xe00183
+.quad 0x1000183
+.quad 0x1200183
+.quad 0x1400183
+.quad 0x1600183
+.quad 0x1800183
+.quad 0x1a00183
+.quad 0x1c00183
+.quad 0x1e00183
+.quad 0x2000183
+.quad 0x2200183
+.quad 0x2400183
+.quad 0x2600183
+.quad 0x2800183
+.quad 0x2a00183
+.quad 0x2c00183
+.quad 0x2e00183
+.quad 0x3000183
+.quad 0x3200183
+.quad 0x3400183
+.quad 0x3600183
+.quad 0x3800183
+.quad 0x3a00183
+.quad 0x3c00183
+.quad 0x3e00183
+.quad 0x4000183
+.quad 0x4200183
+.quad 0x4400183
+.quad 0x4600183
+.quad 0x4800183
+.quad 0x4a00183
+.quad 0x4c00183
+.quad 0x4e00183
+.quad 0x5000183
+.quad 0x5200183
+.quad 0x5400183
+.quad 0x5600183
+.quad 0x5800183
+.quad 0x5a00183
+.quad 0x5c00183
+.quad 0x5e00183
+.quad 0x6000183
+.quad 0x6200183
+.quad 0x6400183
+.quad 0x6600183
+.quad 0x6800183
+.quad 0x6a00183
+.quad 0x6c00183
+.quad 0x6e00183
+.quad 0x7000183
+.quad 0x7200183
+.quad 0x7400183
+.quad 0x7600183
+.quad 0x7800183
+.quad 0x7a00183
+.quad 0x7c00183
+.quad 0x7e00183
+.quad 0x8000183
+.quad 0x8200183
+.quad 0x8400183
+.quad 0x8600183
+.quad 0x8800183
+.quad 0x8a00183
+.quad 0x8c00183
+.quad 0x8e00183
+.quad 0x9000183
+.quad 0x9200183
+.quad 0x9400183
+.quad 0x9600183
+.quad 0x9800183
+.quad 0x9a00183
+.quad 0x9c00183
+.quad 0x9e00183
+.quad 0xa000183
+.quad 0xa200183
+.quad 0xa400183
+.quad 0xa600183
+.quad 0xa800183
+.quad 0xaa00183
+.quad 0xac00183
+.quad 0xae00183
+.quad 0xb000183
+.quad 0xb200183
+.quad 0xb400183
+.quad 0xb600183
+.quad 0xb800183
+.quad 0xba00183
+.quad 0xbc00183
+.quad 0xbe00183
+.quad 0xc000183
+.quad 0xc200183
+.quad 0xc400183
+.quad 0xc600183
+.quad 0xc800183
+.quad 0xca00183
+.quad 0xcc00183
+.quad 0xce00183
+.quad 0xd000183
+.quad 0xd200183
+.quad 0xd400183
+.quad 0xd600183
+.quad 0xd800183
+.quad 0xda00183
+.quad 0xdc00183
+.quad 0xde00183
+.quad 0xe000183
+.quad 0xe200183
+.quad 0xe400183
+.quad 0xe600183
+.quad 0xe800183
+.quad 0xea00183
+.quad 0xec00183
+.quad 0xee00183
+.quad 0xf000183
+.quad 0xf200183
+.quad 0xf400183
+.quad 0xf600183
+.quad 0xf800183
+.quad 0xfa00183
+.quad 0xfc00183
+.quad 0xfe00183
+.quad 0x10000183
+.quad 0x10200183
+.quad 0x10400183
+.quad 0x10600183
+.quad 0x10800183
+.quad 0x10a00183
+.quad 0x10c00183
+.quad 0x10e00183
+.quad 0x11000183
+.quad 0x11200183
+.quad 0x11400183
+.quad 0x11600183
+.quad 0x11800183
+.quad 0x11a00183
+.quad 0x11c00183
+.quad 0x11e00183
+.quad 0x12000183
+.quad 0x12200183
+.quad 0x12400183
+.quad 0x12600183
+.quad 0x12800183
+.quad 0x12a00183
+.quad 0x12c00183
+.quad 0x12e00183
+.quad 0x13000183
+.quad 0x13200183
+.quad 0x13400183
+.quad 0x13600183
+.quad 0x13800183
+.quad 0x13a00183
+.quad 0x13c00183
+.quad 0x13e00183
+.quad 0x14000183
+.quad 0x14200183
+.quad 0x14400183
+.quad 0x14600183
+.quad 0x14800183
+.quad 0x14a00183
+.quad 0x14c00183
+.quad 0x14e00183
+.quad 0x15000183
+.quad 0x15200183
+.quad 0x15400183
+.quad 0x15600183
+.quad 0x15800183
+.quad 0x15a00183
+.quad 0x15c00183
+.quad 0x15e00183
+.quad 0x16000183
+.quad 0x16200183
+.quad 0x16400183
+.quad 0x16600183
+.quad 0x16800183
+.quad 0x16a00183
+.quad 0x16c00183
+.quad 0x16e00183
+.quad 0x17000183
+.quad 0x17200183
+.quad 0x17400183
+.quad 0x17600183
+.quad 0x17800183
+.quad 0x17a00183
+.quad 0x17c00183
+.quad 0x17e00183
+.quad 0x18000183
+.quad 0x18200183
+.quad 0x18400183
+.quad 0x18600183
+.quad 0x18800183
+.quad 0x18a00183
+.quad 0x18c00183
+.quad 0x18e00183
+.quad 0x19000183
+.quad 0x19200183
+.quad 0x19400183
+.quad 0x19600183
+.quad 0x19800183
+.quad 0x19a00183
+.quad 0x19c00183
+.quad 0x19e00183
+.quad 0x1a000183
+.quad 0x1a200183
+.quad 0x1a400183
+.quad 0x1a600183
+.quad 0x1a800183
+.quad 0x1aa00183
+.quad 0x1ac00183
+.quad 0x1ae00183
+.quad 0x1b000183
+.quad 0x1b200183
+.quad 0x1b400183
+.quad 0x1b600183
+.quad 0x1b800183
+.quad 0x1ba00183
+.quad 0x1bc00183
+.quad 0x1be00183
+.quad 0x1c000183
+.quad 0x1c200183
+.quad 0x1c400183
+.quad 0x1c600183
+.quad 0x1c800183
+.quad 0x1ca00183
+.quad 0x1cc00183
+.quad 0x1ce00183
+.quad 0x1d000183
+.quad 0x1d200183
+.quad 0x1d400183
+.quad 0x1d600183
+.quad 0x1d800183
+.quad 0x1da00183
+.quad 0x1dc00183
+.quad 0x1de00183
+.quad 0x1e000183
+.quad 0x1e200183
+.quad 0x1e400183
+.quad 0x1e600183
+.quad 0x1e800183
+.quad 0x1ea00183
+.quad 0x1ec00183
+.quad 0x1ee00183
+.quad 0x1f000183
+.quad 0x1f200183
+.quad 0x1f400183
+.quad 0x1f600183
+.quad 0x1f800183
+.quad 0x1fa00183
+.quad 0x1fc00183
+.quad 0x1fe00183
+.quad 0x20000183
+.quad 0x20200183
+.quad 0x20400183
+.quad 0x20600183
+.quad 0x20800183
+.quad 0x20a00183
+.quad 0x20c00183
+.quad 0x20e00183
+.quad 0x21000183
+.quad 0x21200183
+.quad 0x21400183
+.quad 0x21600183
+.quad 0x21800183
+.quad 0x21a00183
+.quad 0x21c00183
+.quad 0x21e00183
+.quad 0x22000183
+.quad 0x22200183
+.quad 0x22400183
+.quad 0x22600183
+.quad 0x22800183
+.quad 0x22a00183
+.quad 0x22c00183
+.quad 0x22e00183
+.quad 0x23000183
+.quad 0x23200183
+.quad 0x23400183
+.quad 0x23600183
+.quad 0x23800183
+.quad 0x23a00183
+.quad 0x23c00183
+.quad 0x23e00183
+.quad 0x24000183
+.quad 0x24200183
+.quad 0x24400183
+.quad 0x24600183
+.quad 0x24800183
+.quad 0x24a00183
+.quad 0x24c00183
+.quad 0x24e00183
+.quad 0x25000183
+.quad 0x25200183
+.quad 0x25400183
+.quad 0x25600183
+.quad 0x25800183
+.quad 0x25a00183
+.quad 0x25c00183
+.quad 0x25e00183
+.quad 0x26000183
+.quad 0x26200183
+.quad 0x26400183
+.quad 0x26600183
+.quad 0x26800183
+.quad 0x26a00183
+.quad 0x26c00183
+.quad 0x26e00183
+.quad 0x27000183
+.quad 0x27200183
+.quad 0x27400183
+.quad 0x27600183
+.quad 0x27800183
+.quad 0x27a00183
+.quad 0x27c00183
+.quad 0x27e00183
+.quad 0x28000183
+.quad 0x28200183
+.quad 0x28400183
+.quad 0x28600183
+.quad 0x28800183
+.quad 0x28a00183
+.quad 0x28c00183
+.quad 0x28e00183
+.quad 0x29000183
+.quad 0x29200183
+.quad 0x29400183
+.quad 0x29600183
+.quad 0x29800183
+.quad 0x29a00183
+.quad 0x29c00183
+.quad 0x29e00183
+.quad 0x2a000183
+.quad 0x2a200183
+.quad 0x2a400183
+.quad 0x2a600183
+.quad 0x2a800183
+.quad 0x2aa00183
+.quad 0x2ac00183
+.quad 0x2ae00183
+.quad 0x2b000183
+.quad 0x2b200183
+.quad 0x2b400183
+.quad 0x2b600183
+.quad 0x2b800183
+.quad 0x2ba00183
+.quad 0x2bc00183
+.quad 0x2be00183
+.quad 0x2c000183
+.quad 0x2c200183
+.quad 0x2c400183
+.quad 0x2c600183
+.quad 0x2c800183
+.quad 0x2ca00183
+.quad 0x2cc00183
+.quad 0x2ce00183
+.quad 0x2d000183
+.quad 0x2d200183
+.quad 0x2d400183
+.quad 0x2d600183
+.quad 0x2d800183
+.quad 0x2da00183
+.quad 0x2dc00183
+.quad 0x2de00183
+.quad 0x2e000183
+.quad 0x2e200183
+.quad 0x2e400183
+.quad 0x2e600183
+.quad 0x2e800183
+.quad 0x2ea00183
+.quad 0x2ec00183
+.quad 0x2ee00183
+.quad 0x2f000183
+.quad 0x2f200183
+.quad 0x2f400183
+.quad 0x2f600183
+.quad 0x2f800183
+.quad 0x2fa00183
+.quad 0x2fc00183
+.quad 0x2fe00183
+.quad 0x30000183
+.quad 0x30200183
+.quad 0x30400183
+.quad 0x30600183
+.quad 0x30800183
+.quad 0x30a00183
+.quad 0x30c00183
+.quad 0x30e00183
+.quad 0x31000183
+.quad 0x31200183
+.quad 0x31400183
+.quad 0x31600183
+.quad 0x31800183
+.quad 0x31a00183
+.quad 0x31c00183
+.quad 0x31e00183
+.quad 0x32000183
+.quad 0x32200183
+.quad 0x32400183
+.quad 0x32600183
+.quad 0x32800183
+.quad 0x32a00183
+.quad 0x32c00183
+.quad 0x32e00183
+.quad 0x33000183
+.quad 0x33200183
+.quad 0x33400183
+.quad 0x33600183
+.quad 0x33800183
+.quad 0x33a00183
+.quad 0x33c00183
+.quad 0x33e00183
+.quad 0x34000183
+.quad 0x34200183
+.quad 0x34400183
+.quad 0x34600183
+.quad 0x34800183
+.quad 0x34a00183
+.quad 0x34c00183
+.quad 0x34e00183
+.quad 0x35000183
+.quad 0x35200183
+.quad 0x35400183
+.quad 0x35600183
+.quad 0x35800183
+.quad 0x35a00183
+.quad 0x35c00183
+.quad 0x35e00183
+.quad 0x36000183
+.quad 0x36200183
+.quad 0x36400183
+.quad 0x36600183
+.quad 0x36800183
+.quad 0x36a00183
+.quad 0x36c00183
+.quad 0x36e00183
+.quad 0x37000183
+.quad 0x37200183
+.quad 0x37400183
+.quad 0x37600183
+.quad 0x37800183
+.quad 0x37a00183
+.quad 0x37c00183
+.quad 0x37e00183
+.quad 0x38000183
+.quad 0x38200183
+.quad 0x38400183
+.quad 0x38600183
+.quad 0x38800183
+.quad 0x38a00183
+.quad 0x38c00183
+.quad 0x38e00183
+.quad 0x39000183
+.quad 0x39200183
+.quad 0x39400183
+.quad 0x39600183
+.quad 0x39800183
+.quad 0x39a00183
+.quad 0x39c00183
+.quad 0x39e00183
+.quad 0x3a000183
+.quad 0x3a200183
+.quad 0x3a400183
+.quad 0x3a600183
+.quad 0x3a800183
+.quad 0x3aa00183
+.quad 0x3ac00183
+.quad 0x3ae00183
+.quad 0x3b000183
+.quad 0x3b200183
+.quad 0x3b400183
+.quad 0x3b600183
+.quad 0x3b800183
+.quad 0x3ba00183
+.quad 0x3bc00183
+.quad 0x3be00183
+.quad 0x3c000183
+.quad 0x3c200183
+.quad 0x3c400183
+.quad 0x3c600183
+.quad 0x3c800183
+.quad 0x3ca00183
+.quad 0x3cc00183
+.quad 0x3ce00183
+.quad 0x3d000183
+.quad 0x3d200183
+.quad 0x3d400183
+.quad 0x3d600183
+.quad 0x3d800183
+.quad 0x3da00183
+.quad 0x3dc00183
+.quad 0x3de00183
+.quad 0x3e000183
+.quad 0x3e200183
+.quad 0x3e400183
+.quad 0x3e600183
+.quad 0x3e800183
+.quad 0x3ea00183
+.quad 0x3ec00183
+.quad 0x3ee00183
+.quad 0x3f000183
+.quad 0x3f200183
+.quad 0x3f400183
+.quad 0x3f600183
+.quad 0x3f800183
+.quad 0x3fa00183
+.quad 0x3fc00183
+.quad 0x3fe00183
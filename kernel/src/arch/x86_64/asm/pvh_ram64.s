@@ -16,6 +16,8 @@ ram64_start:
     # HvmStartInfo is in %rbp
     # move to first C argument
     movq %rbx, %rdi
+    # no SEV-ES boot flag
+    xorl %esi, %esi
     movabs $_start_e820,%rax
     jmp _setup_pto
 
@@ -43,6 +43,12 @@ _syscall_enter:
 
     sti
 
+    # a stack frame of the userspace return pointer,
+    # so the frame pointer chain continues in userspace
+    pushq   XSAVE_STACK_OFFSET(%rsp)
+    pushq   %rbp
+    movq    %rsp, %rbp
+
     # SYSV:    rdi, rsi, rdx, rcx, r8, r9
     # SYSCALL: rdi, rsi, rdx, r10, r8, r9
     mov    %r10, %rcx
@@ -67,6 +73,9 @@ _syscall_enter:
     popq    %rdi
     popq    %rdi
 
+    popq    %rbp
+    addq    $8, %rsp
+
     cli
 
     # xrstor
@@ -5,6 +5,8 @@
 
 .p2align 4
 _usermode:
+    movq    %rcx,%rax      # FS base
+    movq    %r8,%rbx       # GS base
     movq    $0x1b,%r10     # ((gdt::USER_DATA_SEG << 3) | 3), # Data segment
     movq    %rsi,%r11      # stack pointer
     movq    $0x200,%r12    # (1 << 9), # Flags - Set interrupt enable flag
@@ -17,10 +19,17 @@ _usermode:
     push    %r13
     push    %r14
     push    %r15
+    xorq    %rcx,%rcx
+    movq    %rcx,%ds
+    movq    %rcx,%es
+    movq    %rcx,%fs
+    movq    %rcx,%gs
+    # after loading the null selectors, which might clear the bases
+    wrfsbase %rax
+    wrgsbase %rbx
     xorq    %rax,%rax
     xorq    %rdx,%rdx
     xorq    %rbx,%rbx
-    xorq    %rcx,%rcx
     xorq    %rsi,%rsi
     xorq    %rdi,%rdi
     xorq    %rbp,%rbp
@@ -32,12 +41,6 @@ _usermode:
     xorq    %r13,%r13
     xorq    %r14,%r14
     xorq    %r15,%r15
-    movq    %r11,%ds
-    movq    %r11,%es
-    movq    %r11,%fs
-    movq    %r11,%gs
-    wrfsbase %r11
-    wrgsbase %r11
     fninit
     pop     %rdi
     iretq
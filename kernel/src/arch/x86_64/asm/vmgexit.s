@@ -0,0 +1,8 @@
+.section .text, "ax"
+.global _vmgexit
+.type _vmgexit, @function
+.p2align 4
+_vmgexit:
+    # rep vmmcall
+    .byte  0xf3, 0x0f, 0x01, 0xd9
+    retq
@@ -1,9 +1,13 @@
+use super::loader::TlsTemplate;
 use super::syscall;
+use super::tls;
+use super::PAGESIZE;
+use super::PHYSICAL_MEMORY_OFFSET;
 use crate::arch::x86_64::structures::paging::{
     mapper::MapToError, FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags, Size4KiB,
 };
 use crate::memory::BootInfoFrameAllocator;
-use crate::{exit_hypervisor, HyperVisorExitCode};
+use crate::{debug, error, exit_hypervisor, warn, HyperVisorExitCode};
 use crt0stack::{self, Builder, Entry};
 use x86_64::instructions::random::RdRand;
 use x86_64::VirtAddr;
@@ -13,16 +17,19 @@ const USER_STACK_SIZE: usize = 8 * 1024 * 1024; // 8 MB
 const USER_STACK_OFFSET: usize = PML4_SIZE * 4;
 //const USER_HEAP_OFFSET: usize = PML4_SIZE;
 
-pub fn exec_elf(
+/// Size of the thread control block after the initial TLS block
+const TCB_SIZE: u64 = 64;
+
+/// Map the pages from `virt_start_addr` up to and including `virt_start_addr + len`
+fn map_user_pages(
     mapper: &mut OffsetPageTable,
     frame_allocator: &mut BootInfoFrameAllocator,
-    app_entry_point: *const u8,
-    app_load_addr: *const u8,
-    app_phnum: usize,
-) -> ! {
-    let virt_start_addr = VirtAddr::new(USER_STACK_OFFSET as u64);
+    virt_start_addr: VirtAddr,
+    len: usize,
+    flags: PageTableFlags,
+) {
     let start_page: Page = Page::containing_address(virt_start_addr);
-    let end_page: Page = Page::containing_address(virt_start_addr + USER_STACK_SIZE - 256u64);
+    let end_page: Page = Page::containing_address(virt_start_addr + len);
     let page_range = Page::range_inclusive(start_page, end_page);
 
     for page in page_range {
@@ -34,25 +41,104 @@ pub fn exec_elf(
             .map_to(
                 page,
                 frame,
-                PageTableFlags::PRESENT
-                    | PageTableFlags::WRITABLE
-                    | PageTableFlags::USER_ACCESSIBLE,
+                flags,
                 PageTableFlags::USER_ACCESSIBLE,
                 frame_allocator,
             )
             .unwrap()
             .flush();
     }
+}
+
+/// Map the initial TLS block of the app from its `PT_TLS`, returns the thread pointer
+///
+/// x86_64 uses TLS variant II: the TLS block ends at the thread pointer, which
+/// points to the TCB. The first word of the TCB is the thread pointer itself.
+/// The libc usually replaces the block with its own via `arch_prctl()`.
+fn init_tls(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BootInfoFrameAllocator,
+    template: &TlsTemplate,
+) -> VirtAddr {
+    let align = template.align.max(1);
+    let tls_size = (template.mem_size + align - 1) / align * align;
+    let len = tls_size + align + TCB_SIZE;
+
+    let start = VirtAddr::new(unsafe { super::NEXT_MMAP });
+    map_user_pages(
+        mapper,
+        frame_allocator,
+        start,
+        len as usize - 1,
+        PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::USER_ACCESSIBLE
+            | PageTableFlags::NO_EXECUTE,
+    );
+    unsafe {
+        super::NEXT_MMAP = (start + len).align_up(PAGESIZE as u64).as_u64();
+    }
+
+    let tp = (start + tls_size).align_up(align);
+    let block = tp - tls_size;
+    unsafe {
+        start.as_mut_ptr::<u8>().write_bytes(0u8, len as usize);
+        core::ptr::copy_nonoverlapping(
+            template.image.as_ptr::<u8>(),
+            block.as_mut_ptr::<u8>(),
+            template.file_size as usize,
+        );
+        tp.as_mut_ptr::<u64>().write(tp.as_u64());
+    }
+    tp
+}
+
+/// The encoded arguments and environment of the app, empty if not set by the hypervisor
+unsafe fn app_args() -> &'static [u8] {
+    let blob = super::APP_ARGS;
+    if blob.len == 0 {
+        return &[];
+    }
+    core::slice::from_raw_parts(
+        (PHYSICAL_MEMORY_OFFSET + blob.start) as *const u8,
+        blob.len as usize,
+    )
+}
+
+pub fn exec_elf(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BootInfoFrameAllocator,
+    app_entry_point: *const u8,
+    app_load_addr: *const u8,
+    app_phnum: usize,
+) -> ! {
+    // PT_GNU_STACK of the app decides, if the stack is executable
+    let mut flags =
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    if !unsafe { super::APP_EXEC_STACK } {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    map_user_pages(
+        mapper,
+        frame_allocator,
+        VirtAddr::new(USER_STACK_OFFSET as u64),
+        USER_STACK_SIZE - 256,
+        flags,
+    );
+
+    if let Some(template) = unsafe { super::APP_TLS } {
+        tls::set_fs_base(init_tls(mapper, frame_allocator, &template).as_u64());
+    }
 
-    const ELF64_HDR_SIZE: u64 = 0x40;
     const ELF64_PHDR_SIZE: u64 = 56;
 
-    let hwcap = unsafe { core::arch::x86_64::__cpuid(1) }.edx;
+    let hwcap = super::ghcb::cpuid(1).edx;
     let rdrand = RdRand::new();
     let (r1, r2) = match rdrand {
         None => {
             if cfg!(debug_assertions) {
-                eprintln!("!!! No RDRAND. Using pseudo random numbers!!!");
+                warn!("!!! No RDRAND. Using pseudo random numbers!!!");
                 (0xAFFE_AFFE_AFFE_AFFE_u64, 0xC0FF_EEC0_FFEE_C0FF_u64)
             } else {
                 panic!("No rdrand supported by CPU")
@@ -70,13 +156,29 @@ pub fn exec_elf(
     let mut sp_slice =
         unsafe { core::slice::from_raw_parts_mut((USER_STACK_OFFSET) as *mut u8, USER_STACK_SIZE) };
 
+    // The arguments and environment from the hypervisor, "/init" and "LANG=C" without
+    let blob = unsafe { app_args() };
+    let (args, env) = vmsyscall::bootinfo::decode_args(blob);
+    let mut args = args.map(|arg| core::str::from_utf8(arg).expect("argument is not UTF-8"));
+    let argv0 = args.next().unwrap_or("/init");
+
     let mut builder = Builder::new(&mut sp_slice);
-    builder.push("/init").unwrap();
+    builder.push(argv0).unwrap();
+    for arg in args {
+        builder.push(arg).unwrap();
+    }
     let mut builder = builder.done().unwrap();
-    builder.push("LANG=C").unwrap();
+    if blob.is_empty() {
+        builder.push("LANG=C").unwrap();
+    }
+    for var in env {
+        builder
+            .push(core::str::from_utf8(var).expect("environment is not UTF-8"))
+            .unwrap();
+    }
     let mut builder = builder.done().unwrap();
     for aux in &[
-        Entry::ExecFilename("/init"),
+        Entry::ExecFilename(argv0),
         Entry::Platform("x86_64"),
         Entry::Uid(1000),
         Entry::EUid(1000),
@@ -86,9 +188,11 @@ pub fn exec_elf(
         Entry::Secure(false),
         Entry::ClockTick(100),
         Entry::Flags(0),
-        Entry::PHdr((app_load_addr as u64 + ELF64_HDR_SIZE) as _),
+        Entry::PHdr(app_load_addr as _),
         Entry::PHent(ELF64_PHDR_SIZE as _),
         Entry::PHnum(app_phnum),
+        Entry::Entry(app_entry_point as _),
+        Entry::Base(unsafe { super::APP_BASE } as _),
         Entry::HwCap(hwcap as _),
         Entry::HwCap2(0),
         Entry::Random(ra),
@@ -98,23 +202,29 @@ pub fn exec_elf(
     let handle = builder.done().unwrap();
     let sp = handle.start_ptr() as *const () as usize;
 
-    #[cfg(debug_assertions)]
-    {
-        eprintln!("app_entry_point={:#X}", app_entry_point as u64);
-        eprintln!("app_load_addr={:#X}", app_load_addr as u64);
-        eprintln!("app_phnum={}", app_phnum);
-        eprintln!("stackpointer={:#X}", sp);
-        eprintln!("USER_STACK_OFFSET={:#X}", USER_STACK_OFFSET);
-        eprintln!("\n========= APP START =============\n");
-    }
+    debug!("app_entry_point={:#X}", app_entry_point as u64);
+    debug!("app_load_addr={:#X}", app_load_addr as u64);
+    debug!("app_phnum={}", app_phnum);
+    debug!("app_base={:#X}", unsafe { super::APP_BASE });
+    debug!("app_interp_entry={:#X}", unsafe {
+        super::APP_INTERP_ENTRY as u64
+    });
+    debug!("stackpointer={:#X}", sp);
+    debug!("USER_STACK_OFFSET={:#X}", USER_STACK_OFFSET);
+    debug!("========= APP START =============");
 
     if app_entry_point.is_null() {
-        eprintln!("app_entry_point.is_null()");
+        error!("app_entry_point.is_null()");
         exit_hypervisor(HyperVisorExitCode::Success);
         crate::hlt_loop()
     } else {
+        // A dynamically linked app is started by its ELF interpreter
+        let entry = match unsafe { super::APP_INTERP_ENTRY } {
+            interp_entry if interp_entry.is_null() => app_entry_point,
+            interp_entry => interp_entry,
+        };
         unsafe {
-            syscall::usermode(app_entry_point as usize, sp, 0);
+            syscall::usermode(entry as usize, sp, 0);
         }
     }
 }
@@ -1,5 +1,6 @@
 //! Global Descriptor Table init
 
+use crate::debug;
 use x86_64::instructions::segmentation::{load_ds, load_es, load_fs, load_gs, load_ss};
 use x86_64::instructions::tables::load_tss;
 use x86_64::structures::gdt::{
@@ -65,8 +66,7 @@ pub struct Selectors {
 }
 
 pub fn init() {
-    #[cfg(debug_assertions)]
-    eprintln!("init_gdt");
+    debug!("init_gdt");
 
     use x86_64::instructions::segmentation::set_cs;
 
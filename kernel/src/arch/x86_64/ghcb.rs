@@ -0,0 +1,295 @@
+//! Hypervisor communication via the GHCB
+//!
+//! With SEV-ES `in`/`out`, `cpuid` and MSR accesses raise a `#VC` exception
+//! instead of exiting to the hypervisor. The register values needed by the
+//! hypervisor are passed in the shared GHCB page and the guest exits with
+//! `VMGEXIT`.
+//!
+//! With the software GHCB protocol, the same requests are signaled with an `out`
+//! to the GHCB trigger port, so the protocol can be tested without SEV-ES.
+//!
+//! The `outb()`, `outw()`, `outl()` and `cpuid*()` functions use the GHCB
+//! directly, if there is one. Everything else raises a `#VC` handled by
+//! `vmm_communication_handler()`.
+
+use super::idt::InterruptStackFrame;
+use super::interrupts::SavedRegisters;
+use core::arch::x86_64::CpuidResult;
+use core::sync::atomic::{compiler_fence, Ordering};
+use vmsyscall::bootinfo::BootInfo;
+use vmsyscall::ghcb::*;
+use x86_64::instructions::port::Port;
+use x86_64::registers::model_specific::Msr;
+
+static mut GHCB: Option<&'static mut Ghcb> = None;
+static mut TRIGGER_PORT: u16 = 0;
+
+extern "C" {
+    fn _vmgexit();
+}
+
+/// Use the GHCB passed in the `BootInfo`
+///
+/// The GHCB page has to be shared with the host already.
+pub fn init(boot_info: &BootInfo) {
+    if boot_info.ghcb == 0 {
+        return;
+    }
+
+    unsafe {
+        TRIGGER_PORT = boot_info.ghcb_trigger_port;
+        GHCB.replace(&mut *(boot_info.ghcb as *mut Ghcb));
+    }
+}
+
+/// `true`, if requests go through the GHCB
+pub fn is_active() -> bool {
+    unsafe { GHCB.is_some() }
+}
+
+/// `true`, if the software GHCB protocol is used instead of `VMGEXIT`
+pub fn is_software() -> bool {
+    is_active() && unsafe { TRIGGER_PORT != 0 }
+}
+
+/// Send a request to the hypervisor
+///
+/// `setup` sets the register values of the request. On return, the GHCB
+/// contains the register values returned by the hypervisor.
+fn request(
+    exit_code: u64,
+    exit_info_1: u64,
+    exit_info_2: u64,
+    setup: impl FnOnce(&mut Ghcb),
+    result: impl FnOnce(&Ghcb),
+) {
+    // An interrupt handler printing something would overwrite the GHCB
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let ghcb: &mut Ghcb = unsafe { GHCB.as_mut().unwrap() };
+
+        ghcb.clear();
+        setup(ghcb);
+        ghcb.set_sw_exit(exit_code, exit_info_1, exit_info_2);
+
+        compiler_fence(Ordering::SeqCst);
+        unsafe {
+            if TRIGGER_PORT != 0 {
+                Port::<u16>::new(TRIGGER_PORT).write(1);
+            } else {
+                Msr::new(MSR_GHCB).write(ghcb as *const Ghcb as u64);
+                _vmgexit();
+            }
+        }
+        compiler_fence(Ordering::SeqCst);
+
+        // Nothing can be printed without a working GHCB
+        if ghcb.sw_exit_info_1() & 0xFFFF_FFFF != 0 {
+            crate::hlt_loop();
+        }
+
+        result(ghcb);
+    })
+}
+
+fn io_out(port: u16, value: u32, size: u8) {
+    request(
+        SVM_EXIT_IOIO,
+        ioio_exit_info(port, size, false),
+        0,
+        |ghcb| ghcb.set_rax(value as u64),
+        |_| {},
+    )
+}
+
+fn io_in(port: u16, size: u8) -> u32 {
+    let mut value = 0;
+    request(
+        SVM_EXIT_IOIO,
+        ioio_exit_info(port, size, true),
+        0,
+        |ghcb| ghcb.set_rax(0),
+        |ghcb| value = ghcb.rax().unwrap_or(!0) as u32,
+    );
+    value
+}
+
+fn msr_read(msr: u32) -> u64 {
+    let mut value = 0;
+    request(
+        SVM_EXIT_MSR,
+        0,
+        0,
+        |ghcb| ghcb.set_rcx(msr as u64),
+        |ghcb| value = (ghcb.rax().unwrap_or(0) & 0xFFFF_FFFF) | (ghcb.rdx().unwrap_or(0) << 32),
+    );
+    value
+}
+
+fn msr_write(msr: u32, value: u64) {
+    request(
+        SVM_EXIT_MSR,
+        1,
+        0,
+        |ghcb| {
+            ghcb.set_rcx(msr as u64);
+            ghcb.set_rax(value & 0xFFFF_FFFF);
+            ghcb.set_rdx(value >> 32);
+        },
+        |_| {},
+    )
+}
+
+fn ghcb_cpuid(leaf: u32, sub_leaf: u32) -> CpuidResult {
+    let mut res = CpuidResult {
+        eax: 0,
+        ebx: 0,
+        ecx: 0,
+        edx: 0,
+    };
+    request(
+        SVM_EXIT_CPUID,
+        0,
+        0,
+        |ghcb| {
+            ghcb.set_rax(leaf as u64);
+            ghcb.set_rcx(sub_leaf as u64);
+            // the XSAVE sizes depend on the enabled features
+            if leaf == 0xD {
+                ghcb.set_xcr0(super::xcr0::XCr0::read_raw());
+            }
+        },
+        |ghcb| {
+            res.eax = ghcb.rax().unwrap_or(0) as u32;
+            res.ebx = ghcb.rbx().unwrap_or(0) as u32;
+            res.ecx = ghcb.rcx().unwrap_or(0) as u32;
+            res.edx = ghcb.rdx().unwrap_or(0) as u32;
+        },
+    );
+    res
+}
+
+/// Write a byte to an I/O port
+pub unsafe fn outb(port: u16, value: u8) {
+    if is_active() {
+        io_out(port, value as u32, 1)
+    } else {
+        Port::<u8>::new(port).write(value)
+    }
+}
+
+/// Write a word to an I/O port
+pub unsafe fn outw(port: u16, value: u16) {
+    if is_active() {
+        io_out(port, value as u32, 2)
+    } else {
+        Port::<u16>::new(port).write(value)
+    }
+}
+
+/// Write a double word to an I/O port
+pub unsafe fn outl(port: u16, value: u32) {
+    if is_active() {
+        io_out(port, value, 4)
+    } else {
+        Port::<u32>::new(port).write(value)
+    }
+}
+
+/// `cpuid` for `leaf`
+pub fn cpuid(leaf: u32) -> CpuidResult {
+    cpuid_count(leaf, 0)
+}
+
+/// `cpuid` for `leaf` and `sub_leaf`
+pub fn cpuid_count(leaf: u32, sub_leaf: u32) -> CpuidResult {
+    if is_active() {
+        ghcb_cpuid(leaf, sub_leaf)
+    } else {
+        unsafe { core::arch::x86_64::__cpuid_count(leaf, sub_leaf) }
+    }
+}
+
+/// Handle the `#VC` exception
+///
+/// The error code is the SVM exit code of the instruction. The instruction
+/// is emulated via the GHCB and skipped.
+pub fn vmm_communication_handler(
+    stack_frame: &mut InterruptStackFrame,
+    regs: &mut SavedRegisters,
+    exit_code: u64,
+) {
+    if !is_active() {
+        crate::hlt_loop();
+    }
+
+    let rip: *const u8 = stack_frame.instruction_pointer.as_ptr();
+
+    let len = match exit_code {
+        SVM_EXIT_CPUID => {
+            let res = ghcb_cpuid(regs.rax as u32, regs.rcx as u32);
+            regs.rax = res.eax as u64;
+            regs.rbx = res.ebx as u64;
+            regs.rcx = res.ecx as u64;
+            regs.rdx = res.edx as u64;
+            2
+        }
+        SVM_EXIT_MSR => {
+            // 0f 30: wrmsr, 0f 32: rdmsr
+            if unsafe { rip.add(1).read() } == 0x30 {
+                msr_write(regs.rcx as u32, (regs.rax & 0xFFFF_FFFF) | (regs.rdx << 32));
+            } else {
+                let value = msr_read(regs.rcx as u32);
+                regs.rax = value & 0xFFFF_FFFF;
+                regs.rdx = value >> 32;
+            }
+            2
+        }
+        SVM_EXIT_IOIO => unsafe { emulate_io(rip, regs) },
+        _ => crate::hlt_loop(),
+    };
+
+    unsafe {
+        stack_frame.as_mut().instruction_pointer += len as u64;
+    }
+}
+
+/// Emulate `in` and `out`, returns the instruction length
+///
+/// String instructions are not supported.
+unsafe fn emulate_io(rip: *const u8, regs: &mut SavedRegisters) -> usize {
+    let mut len = 0;
+    let mut op_size = 4;
+
+    // operand size prefix
+    if rip.read() == 0x66 {
+        op_size = 2;
+        len += 1;
+    }
+
+    let opcode = rip.add(len).read();
+    let (port, insn_len) = match opcode {
+        // in/out with imm8 port
+        0xE4..=0xE7 => (rip.add(len + 1).read() as u16, 2),
+        // in/out with port in %dx
+        0xEC..=0xEF => (regs.rdx as u16, 1),
+        _ => crate::hlt_loop(),
+    };
+
+    let is_in = opcode & 0b10 == 0;
+    let size = if opcode & 1 == 0 { 1 } else { op_size };
+    let mask = (1u64 << (size * 8)) - 1;
+
+    if is_in {
+        let value = io_in(port, size) as u64 & mask;
+        regs.rax = if size == 4 {
+            // 32 bit operations zero extend
+            value
+        } else {
+            (regs.rax & !mask) | value
+        };
+    } else {
+        io_out(port, (regs.rax & mask) as u32, size);
+    }
+
+    len + insn_len
+}
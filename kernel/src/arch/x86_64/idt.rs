@@ -329,8 +329,19 @@ pub struct InterruptDescriptorTable {
     /// vector nr. 20
     pub virtualization: Entry<HandlerFunc>,
 
-    /// vector nr. 21-29
-    reserved_2: [Entry<HandlerFunc>; 9],
+    /// vector nr. 21-28
+    reserved_2: [Entry<HandlerFunc>; 8],
+
+    /// A VMM Communication Exception (`#VC`) is raised in an SEV-ES guest for instructions,
+    /// which would otherwise cause an automatic exit to the hypervisor, like `in`/`out`,
+    /// `cpuid`, `rdmsr` or `wrmsr`. The handler has to emulate the instruction by
+    /// communicating with the hypervisor via the GHCB.
+    ///
+    /// The error code is the SVM exit code of the intercepted instruction. The saved
+    /// instruction pointer points to the instruction that caused the `#VC`.
+    ///
+    /// The vector number of the `#VC` exception is 29.
+    pub vmm_communication_exception: Entry<HandlerFuncWithErrCode>,
 
     /// The Security Exception (`#SX`) signals security-sensitive events that occur while
     /// executing the VMM, in the form of an exception so that the VMM may take appropriate
@@ -395,7 +406,8 @@ impl InterruptDescriptorTable {
             machine_check: Entry::missing(),
             simd_floating_point: Entry::missing(),
             virtualization: Entry::missing(),
-            reserved_2: [Entry::missing(); 9],
+            reserved_2: [Entry::missing(); 8],
+            vmm_communication_exception: Entry::missing(),
             security_exception: Entry::missing(),
             reserved_3: Entry::missing(),
             interrupts: [Entry::missing(); 256 - 32],
@@ -427,7 +439,8 @@ impl InterruptDescriptorTable {
             machine_check: Entry::missing(),
             simd_floating_point: Entry::missing(),
             virtualization: Entry::missing(),
-            reserved_2: [Entry::missing(); 9],
+            reserved_2: [Entry::missing(); 8],
+            vmm_communication_exception: Entry::missing(),
             security_exception: Entry::missing(),
             reserved_3: Entry::missing(),
             interrupts: [Entry::missing(); 256 - 32],
@@ -457,7 +470,8 @@ impl InterruptDescriptorTable {
         self.machine_check = Entry::missing();
         self.simd_floating_point = Entry::missing();
         self.virtualization = Entry::missing();
-        self.reserved_2 = [Entry::missing(); 9];
+        self.reserved_2 = [Entry::missing(); 8];
+        self.vmm_communication_exception = Entry::missing();
         self.security_exception = Entry::missing();
         self.reserved_3 = Entry::missing();
         self.interrupts = [Entry::missing(); 256 - 32];
@@ -546,8 +560,8 @@ impl Index<usize> for InterruptDescriptorTable {
             19 => &self.simd_floating_point,
             20 => &self.virtualization,
             i @ 32..=255 => &self.interrupts[i - 32],
-            i @ 15 | i @ 31 | i @ 21..=29 => panic!("entry {} is reserved", i),
-            i @ 8 | i @ 10..=14 | i @ 17 | i @ 30 => {
+            i @ 15 | i @ 31 | i @ 21..=28 => panic!("entry {} is reserved", i),
+            i @ 8 | i @ 10..=14 | i @ 17 | i @ 29 | i @ 30 => {
                 panic!("entry {} is an exception with error code", i)
             }
             i @ 18 => panic!("entry {} is an diverging exception (must not return)", i),
@@ -576,8 +590,8 @@ impl IndexMut<usize> for InterruptDescriptorTable {
             19 => &mut self.simd_floating_point,
             20 => &mut self.virtualization,
             i @ 32..=255 => &mut self.interrupts[i - 32],
-            i @ 15 | i @ 31 | i @ 21..=29 => panic!("entry {} is reserved", i),
-            i @ 8 | i @ 10..=14 | i @ 17 | i @ 30 => {
+            i @ 15 | i @ 31 | i @ 21..=28 => panic!("entry {} is reserved", i),
+            i @ 8 | i @ 10..=14 | i @ 17 | i @ 29 | i @ 30 => {
                 panic!("entry {} is an exception with error code", i)
             }
             i @ 18 => panic!("entry {} is an diverging exception (must not return)", i),
@@ -1,8 +1,12 @@
 use super::gdt;
+use super::ghcb;
 use super::interrupts;
+use super::loader;
+use super::sev;
 use super::syscall;
 use super::xcr0::{XCr0, XCr0Flags};
 use crate::memory::BootInfoFrameAllocator;
+use crate::{debug, info};
 use vmsyscall::bootinfo::BootInfo;
 use vmsyscall::memory_map::MemoryRegionType;
 
@@ -12,17 +16,21 @@ use crate::arch::x86_64::structures::paging::{
 
 pub use x86_64::{PhysAddr, VirtAddr};
 
+use super::APP_ARGS;
+use super::APP_BASE;
 use super::APP_ENTRY_POINT;
+use super::APP_EXEC_STACK;
+use super::APP_INTERP_ENTRY;
 use super::APP_LOAD_ADDR;
 use super::APP_PH_NUM;
+use super::APP_TLS;
 use super::FRAME_ALLOCATOR;
 use super::MAPPER;
 use super::NEXT_MMAP;
 use super::STACK_SIZE;
 use super::STACK_START;
 use crate::arch::x86_64::PAGESIZE;
-
-const PHYSICAL_MEMORY_OFFSET: u64 = 0x800_0000_0000;
+use crate::arch::x86_64::PHYSICAL_MEMORY_OFFSET;
 
 static mut ENTRY_POINT: Option<
     fn(
@@ -47,26 +55,57 @@ pub fn init(
     crate::arch::init_syscall(boot_info);
     let boot_info = boot_info.clone();
 
+    let phys_mem_offset = VirtAddr::new(PHYSICAL_MEMORY_OFFSET);
+    let encryption_mask = sev::encryption_mask();
+
+    unsafe { MAPPER.replace(crate::memory::init(phys_mem_offset, encryption_mask)) };
+
+    let mut frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(boot_info.memory_map.clone(), encryption_mask) };
+
+    // With SEV the syscall page has to be shared with the host
+    sev::set_host_shared(
+        unsafe { MAPPER.as_mut().unwrap() },
+        &mut frame_allocator,
+        VirtAddr::new(unsafe { crate::arch::SYSCALL_PHYS_ADDR }),
+        PAGESIZE as _,
+    )
+    .unwrap_or_else(|_| crate::exit_hypervisor(crate::HyperVisorExitCode::Failed));
+
+    // The GHCB is shared with the host, too
+    if boot_info.ghcb != 0 {
+        sev::set_host_shared(
+            unsafe { MAPPER.as_mut().unwrap() },
+            &mut frame_allocator,
+            VirtAddr::new(boot_info.ghcb),
+            PAGESIZE as _,
+        )
+        .unwrap_or_else(|_| crate::hlt_loop());
+    }
+
+    ghcb::init(&boot_info);
+
     // *********************************
     // NO println! before this point!!
     // *********************************
 
+    crate::log::set_level(boot_info.log_level);
+
     unsafe {
-        let xsave_supported = (core::arch::x86_64::__cpuid(1).ecx & (1 << 26)) != 0;
+        let xsave_supported = (ghcb::cpuid(1).ecx & (1 << 26)) != 0;
         assert!(xsave_supported);
 
-        let xsaveopt_supported = (core::arch::x86_64::__cpuid_count(0xD, 1).eax & 1) == 1;
+        let xsaveopt_supported = (ghcb::cpuid_count(0xD, 1).eax & 1) == 1;
         assert!(xsaveopt_supported);
 
-        let sse_extended_supported =
-            (core::arch::x86_64::__cpuid_count(0xd, 0).eax & 0b111) == 0b111;
+        let sse_extended_supported = (ghcb::cpuid_count(0xd, 0).eax & 0b111) == 0b111;
         if sse_extended_supported {
             XCr0::update(|xcr0| xcr0.insert(XCr0Flags::YMM));
         } else {
             XCr0::update(|xcr0| xcr0.insert(XCr0Flags::SSE));
         }
 
-        let xsave_size = core::arch::x86_64::__cpuid(0xD).ebx;
+        let xsave_size = ghcb::cpuid(0xD).ebx;
         assert!(xsave_size < (16 * 64 - 64));
     }
     gdt::init();
@@ -75,35 +114,80 @@ pub fn init(
     //    #[cfg(feature = "nightly")]
     interrupts::init();
 
-    eprintln!("{:#?}", boot_info);
-
-    let phys_mem_offset = VirtAddr::new(PHYSICAL_MEMORY_OFFSET);
+    debug!("{:#?}", boot_info);
 
-    unsafe { MAPPER.replace(crate::memory::init(phys_mem_offset)) };
+    if let Some(cbit) = sev::cbit_position() {
+        info!("SEV enabled, C-bit {}", cbit);
+    }
 
-    unsafe {
-        APP_ENTRY_POINT = boot_info.entry_point;
-        APP_LOAD_ADDR = boot_info.load_addr;
-        APP_PH_NUM = boot_info.elf_phnum;
+    if ghcb::is_software() {
+        info!("Using the software GHCB protocol");
     }
 
+    crate::fd::init();
+    crate::syscall::set_policy(&boot_info.syscalls);
+    crate::secret::init(&boot_info);
+    crate::initrd::init(&boot_info);
+
     unsafe {
-        let e = boot_info
-            .memory_map
-            .iter()
-            .filter(|e| e.region_type == MemoryRegionType::Usable)
-            .last()
-            .unwrap();
-        assert!(e.region_type == MemoryRegionType::Usable);
-        NEXT_MMAP = e.range.start_addr();
-        eprintln!("NEXT_MMAP = {:#X}", NEXT_MMAP);
+        APP_ARGS = boot_info.args;
     }
 
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(boot_info.memory_map) };
+    if boot_info.app.len != 0 {
+        let mapper = unsafe { MAPPER.as_mut().unwrap() };
+        loader::unmap_identity(mapper);
+
+        let app = loader::load_elf(mapper, &mut frame_allocator, &boot_info.app)
+            .expect("loading the app failed");
+        let interp = match boot_info.interp.len {
+            0 => None,
+            _ => Some(
+                loader::load_elf(mapper, &mut frame_allocator, &boot_info.interp)
+                    .expect("loading the ELF interpreter failed"),
+            ),
+        };
+        loader::protect_relro(&app, interp.as_ref()).expect("protecting the RELRO range failed");
+
+        unsafe {
+            APP_ENTRY_POINT = app.entry.as_ptr();
+            APP_LOAD_ADDR = app.phdr.as_ptr();
+            APP_PH_NUM = app.phnum;
+            APP_EXEC_STACK = app.exec_stack;
+            APP_TLS = app.tls;
+            APP_BASE = interp.map_or(app.base, |interp| interp.base);
+            APP_INTERP_ENTRY = interp.map_or(core::ptr::null(), |interp| interp.entry.as_ptr());
+            NEXT_MMAP = interp
+                .map_or(app.end, |interp| interp.end.max(app.end))
+                .align_up(PAGESIZE as u64)
+                .as_u64();
+            debug!("NEXT_MMAP = {:#X}", NEXT_MMAP);
+        }
+    } else {
+        unsafe {
+            let e = boot_info
+                .memory_map
+                .iter()
+                .filter(|e| e.region_type == MemoryRegionType::Usable)
+                .last()
+                .unwrap();
+            assert!(e.region_type == MemoryRegionType::Usable);
+            NEXT_MMAP = e.range.start_addr();
+            debug!("NEXT_MMAP = {:#X}", NEXT_MMAP);
+        }
+    }
 
     #[cfg(feature = "allocator")]
-    init_heap(unsafe { MAPPER.as_mut().unwrap() }, &mut frame_allocator)
+    {
+        // The tmpfs lives on the heap
+        let heap_size = super::HEAP_SIZE + crate::tmpfs::heap_size(&boot_info.tmpfs);
+        init_heap(
+            unsafe { MAPPER.as_mut().unwrap() },
+            &mut frame_allocator,
+            heap_size,
+        )
         .expect("heap initialization failed");
+        crate::tmpfs::init(boot_info.tmpfs);
+    }
 
     let stack_pointer = init_stack(unsafe { MAPPER.as_mut().unwrap() }, &mut frame_allocator)
         .expect("stack initialization failed");
@@ -120,19 +204,19 @@ pub fn init(
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    heap_size: usize,
 ) -> Result<(), MapToError<Size4KiB>> {
-    use super::HEAP_SIZE;
     use super::HEAP_START;
 
     let page_range = {
         let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
+        let heap_end = heap_start + heap_size - 1u64;
         let heap_start_page = Page::containing_address(heap_start);
         let heap_end_page = Page::containing_address(heap_end);
         Page::range_inclusive(heap_start_page, heap_end_page)
     };
 
-    eprintln!("Trying to allocate heap: {:#?}", page_range);
+    debug!("Trying to allocate heap: {:#?}", page_range);
 
     for page in page_range {
         let frame = frame_allocator
@@ -145,10 +229,10 @@ pub fn init_heap(
             .map_to(page, frame, flags, PageTableFlags::empty(), frame_allocator)?
             .flush();
     }
-    eprintln!("Heap alloc done");
+    debug!("Heap alloc done");
 
     unsafe {
-        crate::ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        crate::ALLOCATOR.lock().init(HEAP_START, heap_size);
     }
 
     Ok(())
@@ -165,7 +249,7 @@ pub fn init_stack(
 
     let page_range = { Page::range_inclusive(stack_start_page + 1, stack_end_page - 1) };
 
-    eprintln!("Trying to allocate stack: {:#?}", page_range);
+    debug!("Trying to allocate stack: {:#?}", page_range);
 
     for page in page_range {
         let frame = frame_allocator
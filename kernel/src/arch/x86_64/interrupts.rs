@@ -1,6 +1,7 @@
 use super::gdt;
+use super::ghcb;
 use super::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
-use crate::{eprintln, exit_hypervisor, hlt_loop, HyperVisorExitCode};
+use crate::{debug, error, exit_hypervisor, hlt_loop, HyperVisorExitCode};
 
 extern "C" {
     pub fn _isr_0(vars: &mut InterruptStackFrame);
@@ -22,6 +23,7 @@ extern "C" {
     pub fn _isr_18(vars: &mut InterruptStackFrame) -> !;
     pub fn _isr_19(vars: &mut InterruptStackFrame);
     pub fn _isr_20(vars: &mut InterruptStackFrame);
+    pub fn _isr_29(vars: &mut InterruptStackFrame, error_code: u64);
     pub fn _isr_30(vars: &mut InterruptStackFrame, error_code: u64);
 /*
     pub fn _isr_32(vars: &mut InterruptStackFrame);
@@ -34,12 +36,37 @@ extern "C" {
 
 pub static mut IDT: Option<InterruptDescriptorTable> = None;
 
+/// The registers pushed by the ISR stubs in `int_handler.s`
+///
+/// For exceptions with an error code, they are located right below the
+/// error code on the stack.
+#[repr(C)]
+pub struct SavedRegisters {
+    pub rbx: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rax: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn run_interrupt_fn(
     vars: &mut InterruptStackFrame,
     error_code: u64,
     irq: u64,
 ) {
+    if irq == 29 {
+        // before any print, which raises a #VC itself with SEV-ES
+        let regs =
+            &mut *((vars as *mut InterruptStackFrame as *mut u64).sub(11) as *mut SavedRegisters);
+        return ghcb::vmm_communication_handler(vars, regs, error_code);
+    }
+
     println!("IRQ starts {}", irq);
     match irq {
         0 => divide_error_handler(vars),
@@ -63,7 +90,7 @@ pub unsafe extern "C" fn run_interrupt_fn(
         18 => machine_check_handler(vars),
         19 => simd_floating_point_handler(vars),
         20 => virtualization_handler(vars),
-        21..=29 => {}
+        21..=28 => {}
         30 => security_exception_handler(vars, error_code),
         31 => {}
         _ => panic!("Unknown int {}", irq),
@@ -73,8 +100,7 @@ pub unsafe extern "C" fn run_interrupt_fn(
 }
 
 pub fn init() {
-    #[cfg(debug_assertions)]
-    eprintln!("interrupts::init");
+    debug!("interrupts::init");
     unsafe {
         IDT.replace({
             let mut idt = InterruptDescriptorTable::new();
@@ -119,6 +145,8 @@ pub fn init() {
             idt.virtualization
                 .set_handler_fn(_isr_20)
                 .set_stack_index(6);
+            // No IST, a #VC can happen in any other handler running on an IST stack
+            idt.vmm_communication_exception.set_handler_fn(_isr_29);
             idt.security_exception
                 .set_handler_fn(_isr_30)
                 .set_stack_index(6);
@@ -143,136 +171,136 @@ pub fn init() {
 }
 
 fn stack_segment_fault(stack_frame: &mut InterruptStackFrame, error_code: u64) {
-    eprintln!("stack_segment_fault {}", error_code);
-    eprintln!("{:#?}", stack_frame);
+    error!("stack_segment_fault {}", error_code);
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn general_protection_fault(stack_frame: &mut InterruptStackFrame, error_code: u64) {
-    eprintln!("general_protection_fault {:#b}", error_code);
-    eprintln!("{:#?}", stack_frame);
+    error!("general_protection_fault {:#b}", error_code);
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn segment_not_present_handler(stack_frame: &mut InterruptStackFrame, error_code: u64) {
-    eprintln!("segment_not_present_handler {}", error_code);
-    eprintln!("{:#?}", stack_frame);
+    error!("segment_not_present_handler {}", error_code);
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn invalid_opcode_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("invalid_opcode_handler");
-    eprintln!("{:#?}", stack_frame);
+    error!("invalid_opcode_handler");
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn divide_error_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("divide_error_handler");
-    eprintln!("{:#?}", stack_frame);
+    error!("divide_error_handler");
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn debug_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("debug_handler");
-    eprintln!("{:#?}", stack_frame);
+    error!("debug_handler");
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn overflow_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("overflow_handler");
-    eprintln!("{:#?}", stack_frame);
+    error!("overflow_handler");
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn bound_range_exceeded_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("bound_range_exceeded_handler");
-    eprintln!("{:#?}", stack_frame);
+    error!("bound_range_exceeded_handler");
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn device_not_available_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("device_not_available_handler");
-    eprintln!("{:#?}", stack_frame);
+    error!("device_not_available_handler");
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn x87_floating_point_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("x87_floating_point_handler");
-    eprintln!("{:#?}", stack_frame);
+    error!("x87_floating_point_handler");
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn alignment_check_handler(stack_frame: &mut InterruptStackFrame, error_code: u64) {
-    eprintln!("alignment_check_handler");
-    eprintln!("Error Code: {:?}", error_code);
-    eprintln!("{:#?}", stack_frame);
+    error!("alignment_check_handler");
+    error!("Error Code: {:?}", error_code);
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn machine_check_handler(stack_frame: &mut InterruptStackFrame) -> ! {
-    eprintln!("machine_check_handler");
-    eprintln!("{:#?}", stack_frame);
+    error!("machine_check_handler");
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn simd_floating_point_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("simd_floating_point_handler");
-    eprintln!("{:#?}", stack_frame);
+    error!("simd_floating_point_handler");
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn virtualization_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("virtualization_handler");
-    eprintln!("{:#?}", stack_frame);
+    error!("virtualization_handler");
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn security_exception_handler(stack_frame: &mut InterruptStackFrame, error_code: u64) {
-    eprintln!("security_exception_handler");
-    eprintln!("Error Code: {:?}", error_code);
-    eprintln!("{:#?}", stack_frame);
+    error!("security_exception_handler");
+    error!("Error Code: {:?}", error_code);
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn invalid_tss_handler(stack_frame: &mut InterruptStackFrame, error_code: u64) {
-    eprintln!("invalid_tss_handler {}", error_code);
-    eprintln!("{:#?}", stack_frame);
+    error!("invalid_tss_handler {}", error_code);
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 fn breakpoint_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("EXCEPTION: BREAKPOINT");
-    eprintln!("{:#?}", stack_frame);
+    error!("EXCEPTION: BREAKPOINT");
+    error!("{:#?}", stack_frame);
 }
 
 fn non_maskable_interrupt_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("EXCEPTION: NMI");
-    eprintln!("{:#?}", stack_frame);
+    error!("EXCEPTION: NMI");
+    error!("{:#?}", stack_frame);
 }
 
 fn page_fault_handler(stack_frame: &mut InterruptStackFrame, error_code: PageFaultErrorCode) {
     use x86_64::registers::control::Cr2;
 
-    eprintln!("EXCEPTION: PAGE FAULT");
-    eprintln!("Accessed Address: {:?}", Cr2::read());
-    eprintln!("Error Code: {:?}", error_code);
-    eprintln!("{:#?}", stack_frame);
+    error!("EXCEPTION: PAGE FAULT");
+    error!("Accessed Address: {:?}", Cr2::read());
+    error!("Error Code: {:?}", error_code);
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
@@ -281,14 +309,14 @@ fn double_fault_handler(
     stack_frame: &mut InterruptStackFrame,
     _error_code: u64, // Always 0
 ) -> ! {
-    eprintln!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+    error!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 /*
 fn unknown_interrupt_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("EXCEPTION: unknown interrupt");
-    eprintln!("{:#?}", stack_frame);
+    error!("EXCEPTION: unknown interrupt");
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
@@ -0,0 +1,381 @@
+//! Loading of the app ELF files
+//!
+//! The hypervisor passes the raw ELF files of the app and of its interpreter
+//! in guest physical memory, described by an [`ElfBlob`]. The `PT_LOAD`
+//! segments are mapped to user virtual memory with the permissions of their
+//! program headers.
+
+use crate::arch::x86_64::structures::paging::{
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame, Size2MiB, Size4KiB,
+};
+use linux_errno::ErrNo;
+use vmsyscall::bootinfo::ElfBlob;
+use x86_64::{PhysAddr, VirtAddr};
+use xmas_elf::header;
+use xmas_elf::program::{self, ProgramHeader, ProgramHeader64};
+use xmas_elf::ElfFile;
+
+use super::mmap::{mprotect_user, PROT_READ};
+use super::PAGESIZE;
+use super::PHYSICAL_MEMORY_OFFSET;
+
+/// Start of the identity mapping of the boot code above the kernel
+const IDENTITY_UNMAP_START: u64 = 0x0040_0000; // 4 MiB
+/// End of the identity mapping of the boot code
+const IDENTITY_END: u64 = 0x4000_0000; // 1 GiB
+
+/// The user virtual memory ELF segments are mapped to, above the boot code
+/// and below the user stack
+const USER_ELF_START: u64 = IDENTITY_UNMAP_START;
+const USER_ELF_END: u64 = 0x0000_0080_0000_0000; // 512 GiB
+
+/// Program header type of the stack permissions
+const PT_GNU_STACK: u32 = 0x6474_e551;
+/// Program header type of the range read-only after relocation
+const PT_GNU_RELRO: u32 = 0x6474_e552;
+
+/// The `PT_GNU_RELRO` range of a static-PIE app, until it relocated itself
+static mut PENDING_RELRO: Option<Relro> = None;
+
+/// The whole pages of `PT_GNU_RELRO` in user memory
+#[derive(Clone, Copy, Debug)]
+pub struct Relro {
+    pub start: VirtAddr,
+    pub len: u64,
+}
+
+/// The initialization image of the thread local storage, from `PT_TLS`
+#[derive(Clone, Copy, Debug)]
+pub struct TlsTemplate {
+    /// The `.tdata` image in user memory
+    pub image: VirtAddr,
+    /// Size of `.tdata`
+    pub file_size: u64,
+    /// Size of `.tdata` and `.tbss`
+    pub mem_size: u64,
+    pub align: u64,
+}
+
+/// Where an ELF file was mapped
+#[derive(Clone, Copy, Debug)]
+pub struct LoadedElf {
+    /// The entry point (AT_ENTRY)
+    pub entry: VirtAddr,
+    /// The program headers (AT_PHDR)
+    pub phdr: VirtAddr,
+    /// Number of program headers
+    pub phnum: usize,
+    /// The load base of an `ET_DYN` file, `0` otherwise
+    pub base: u64,
+    /// End of the highest segment
+    pub end: VirtAddr,
+    /// The stack has to be executable, `PF_X` in `PT_GNU_STACK`
+    ///
+    /// Like Linux, a missing `PT_GNU_STACK` means an executable stack.
+    pub exec_stack: bool,
+    /// The TLS template, if the file has a `PT_TLS`
+    pub tls: Option<TlsTemplate>,
+    /// The RELRO range, if the file has a `PT_GNU_RELRO`
+    pub relro: Option<Relro>,
+}
+
+/// Remove the identity mapping of the first GiB above the kernel
+///
+/// The app is mapped at its own virtual addresses instead.
+pub fn unmap_identity(mapper: &mut OffsetPageTable) {
+    let start: Page<Size2MiB> = Page::containing_address(VirtAddr::new(IDENTITY_UNMAP_START));
+    let end: Page<Size2MiB> = Page::containing_address(VirtAddr::new(IDENTITY_END - 1));
+
+    for page in Page::range_inclusive(start, end) {
+        if let Ok((_, flush)) = Mapper::<Size2MiB>::unmap(mapper, page) {
+            flush.flush();
+        }
+    }
+}
+
+fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET + addr.as_u64())
+}
+
+fn load_segments<'a>(elf_file: &'a ElfFile<'a>) -> impl Iterator<Item = &'a ProgramHeader64> {
+    elf_file.program_iter().filter_map(|ph| match ph {
+        ProgramHeader::Ph64(header) if header.get_type() == Ok(program::Type::Load) => Some(header),
+        _ => None,
+    })
+}
+
+/// The flags of `page`, the union of all segments in the page
+fn page_flags(elf_file: &ElfFile, base: u64, page: Page) -> PageTableFlags {
+    let start = page.start_address().as_u64();
+    let end = start + PAGESIZE as u64;
+
+    load_segments(elf_file)
+        .filter(|s| base + s.virtual_addr < end && base + s.virtual_addr + s.mem_size > start)
+        .fold(
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE,
+            |mut flags, s| {
+                if s.flags.is_write() {
+                    flags.insert(PageTableFlags::WRITABLE);
+                }
+                if s.flags.is_execute() {
+                    flags.remove(PageTableFlags::NO_EXECUTE);
+                }
+                flags
+            },
+        )
+}
+
+/// The user virtual address `base + addr`, `ENOEXEC` outside of the ELF range
+fn user_addr(base: u64, addr: u64) -> Result<u64, ErrNo> {
+    base.checked_add(addr)
+        .filter(|addr| (USER_ELF_START..=USER_ELF_END).contains(addr))
+        .ok_or(ErrNo::ENOEXEC)
+}
+
+/// The user memory range of a `PT_LOAD` segment, checked against the file
+/// and the ELF range
+fn segment_range(segment: &ProgramHeader64, base: u64, len: u64) -> Result<(u64, u64), ErrNo> {
+    let file_end = segment
+        .offset
+        .checked_add(segment.file_size)
+        .ok_or(ErrNo::ENOEXEC)?;
+    if segment.file_size > segment.mem_size || file_end > len {
+        return Err(ErrNo::ENOEXEC);
+    }
+    let start = user_addr(base, segment.virtual_addr)?;
+    let end = start
+        .checked_add(segment.mem_size)
+        .filter(|end| *end <= USER_ELF_END)
+        .ok_or(ErrNo::ENOEXEC)?;
+    Ok((start, end))
+}
+
+/// Map a zeroed frame at `page`
+fn map_page(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    page: Page,
+    flags: PageTableFlags,
+) -> Result<PhysFrame, ErrNo> {
+    let frame = frame_allocator.allocate_frame().ok_or(ErrNo::ENOMEM)?;
+    let phys_frame = *frame;
+
+    unsafe {
+        phys_to_virt(phys_frame.start_address())
+            .as_mut_ptr::<u8>()
+            .write_bytes(0u8, PAGESIZE);
+    }
+
+    mapper
+        .map_to(
+            page,
+            frame,
+            flags,
+            PageTableFlags::USER_ACCESSIBLE,
+            frame_allocator,
+        )
+        .map_err(|_| ErrNo::ENOMEM)?
+        .flush();
+
+    Ok(phys_frame)
+}
+
+/// Map the `PT_LOAD` segments of the ELF file in `blob` to user memory
+///
+/// Pages shared by two segments get the permissions of both. All segments
+/// have to be in the user ELF range and must not overlap anything mapped
+/// before, an invalid file fails with `ENOEXEC` before memory is touched.
+pub fn load_elf(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    blob: &ElfBlob,
+) -> Result<LoadedElf, ErrNo> {
+    let data = unsafe {
+        core::slice::from_raw_parts(
+            phys_to_virt(PhysAddr::new(blob.start)).as_ptr::<u8>(),
+            blob.len as usize,
+        )
+    };
+
+    let elf_file = ElfFile::new(data).map_err(|_| ErrNo::ENOEXEC)?;
+    header::sanity_check(&elf_file).map_err(|_| ErrNo::ENOEXEC)?;
+
+    if elf_file.header.pt1.class() != header::Class::SixtyFour {
+        return Err(ErrNo::ENOEXEC);
+    }
+
+    let base = blob.base;
+    let mut end = VirtAddr::new(0);
+
+    // Nothing of the file may be mapped yet, only its own segments share pages
+    for segment in load_segments(&elf_file) {
+        if segment.mem_size == 0 {
+            continue;
+        }
+        let (start, seg_end) = segment_range(segment, base, blob.len)?;
+        let start_page: Page = Page::containing_address(VirtAddr::new(start));
+        let end_page: Page = Page::containing_address(VirtAddr::new(seg_end - 1));
+        if Page::range_inclusive(start_page, end_page)
+            .any(|page| mapper.translate_page(page).is_ok())
+        {
+            return Err(ErrNo::ENOEXEC);
+        }
+    }
+
+    for segment in load_segments(&elf_file) {
+        if segment.mem_size == 0 {
+            continue;
+        }
+
+        let (start, seg_end) = segment_range(segment, base, blob.len)?;
+        let start = VirtAddr::new(start);
+        let file_end = start.as_u64() + segment.file_size;
+        end = end.max(VirtAddr::new(seg_end));
+
+        let start_page: Page = Page::containing_address(start);
+        let end_page: Page = Page::containing_address(VirtAddr::new(seg_end - 1));
+
+        for page in Page::range_inclusive(start_page, end_page) {
+            let frame = match mapper.translate_page(page) {
+                // a page of an earlier segment of this file
+                Ok(frame) => frame,
+                Err(_) => map_page(
+                    mapper,
+                    frame_allocator,
+                    page,
+                    page_flags(&elf_file, base, page),
+                )?,
+            };
+
+            // Copy the part of the file in this page, the rest stays zeroed
+            let page_start = page.start_address().as_u64();
+            let copy_start = page_start.max(start.as_u64());
+            let copy_end = (page_start + PAGESIZE as u64).min(file_end);
+
+            if copy_start < copy_end {
+                let src = (segment.offset + copy_start - start.as_u64()) as usize;
+                let len = (copy_end - copy_start) as usize;
+                let dst = phys_to_virt(frame.start_address()) + (copy_start - page_start);
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        data[src..src + len].as_ptr(),
+                        dst.as_mut_ptr::<u8>(),
+                        len,
+                    );
+                }
+            }
+        }
+    }
+
+    let first = load_segments(&elf_file).next().ok_or(ErrNo::ENOEXEC)?;
+
+    // Without PT_PHDR, the program headers are expected in the first segment
+    let phdr = elf_file
+        .program_iter()
+        .find_map(|ph| match ph {
+            ProgramHeader::Ph64(header) if header.get_type() == Ok(program::Type::Phdr) => {
+                Some(header.virtual_addr)
+            }
+            _ => None,
+        })
+        .or_else(|| {
+            first
+                .virtual_addr
+                .checked_add(elf_file.header.pt2.ph_offset())?
+                .checked_sub(first.offset)
+        })
+        .ok_or(ErrNo::ENOEXEC)?;
+
+    let exec_stack = elf_file
+        .program_iter()
+        .find_map(|ph| match ph {
+            ProgramHeader::Ph64(header)
+                if header.get_type() == Ok(program::Type::OsSpecific(PT_GNU_STACK)) =>
+            {
+                Some(header.flags.is_execute())
+            }
+            _ => None,
+        })
+        .unwrap_or(true);
+
+    let tls = match elf_file.program_iter().find_map(|ph| match ph {
+        ProgramHeader::Ph64(header) if header.get_type() == Ok(program::Type::Tls) => Some(header),
+        _ => None,
+    }) {
+        Some(header) => Some(TlsTemplate {
+            image: VirtAddr::new(user_addr(base, header.virtual_addr)?),
+            file_size: header.file_size,
+            mem_size: header.mem_size,
+            align: header.align,
+        }),
+        None => None,
+    };
+
+    // Like ld.so, only the whole pages are made read-only
+    let relro = match elf_file.program_iter().find_map(|ph| match ph {
+        ProgramHeader::Ph64(header)
+            if header.get_type() == Ok(program::Type::OsSpecific(PT_GNU_RELRO)) =>
+        {
+            Some(header)
+        }
+        _ => None,
+    }) {
+        Some(header) => {
+            let start = VirtAddr::new(user_addr(base, header.virtual_addr)?);
+            let end = start
+                .as_u64()
+                .checked_add(header.mem_size)
+                .filter(|end| *end <= USER_ELF_END)
+                .ok_or(ErrNo::ENOEXEC)?;
+            let start = start.align_down(PAGESIZE as u64);
+            let end = VirtAddr::new(end).align_down(PAGESIZE as u64);
+            match end > start {
+                true => Some(Relro {
+                    start,
+                    len: end - start,
+                }),
+                false => None,
+            }
+        }
+        None => None,
+    };
+
+    Ok(LoadedElf {
+        entry: VirtAddr::new(user_addr(base, elf_file.header.pt2.entry_point())?),
+        phdr: VirtAddr::new(user_addr(base, phdr)?),
+        phnum: elf_file.program_iter().count(),
+        base,
+        end,
+        exec_stack,
+        tls,
+        relro,
+    })
+}
+
+/// Make the RELRO range of the app read-only, once it is relocated
+///
+/// ld.so protects the RELRO ranges of a dynamically linked app itself. A
+/// static app is never relocated, its range is protected right away. A
+/// static-PIE app relocates itself in its startup code, before its first
+/// syscall, where [`protect_pending_relro`] protects the range.
+pub fn protect_relro(app: &LoadedElf, interp: Option<&LoadedElf>) -> Result<(), ErrNo> {
+    match (app.relro, interp) {
+        (Some(relro), None) if app.base == 0 => {
+            mprotect_user(relro.start.as_u64() as _, relro.len as _, PROT_READ)
+        }
+        (Some(relro), None) => {
+            unsafe { PENDING_RELRO = Some(relro) };
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Make the RELRO range of a static-PIE app read-only, on its first syscall
+pub fn protect_pending_relro() {
+    if let Some(relro) = unsafe { PENDING_RELRO.take() } {
+        // nothing can have unmapped the range before the first syscall
+        mprotect_user(relro.start.as_u64() as _, relro.len as _, PROT_READ)
+            .expect("protecting the RELRO range failed");
+    }
+}
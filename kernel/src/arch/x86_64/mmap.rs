@@ -2,6 +2,7 @@ use crate::arch::x86_64::structures::paging::{
     mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
 };
 
+use linux_errno::ErrNo;
 use x86_64::structures::paging::FrameDeallocator;
 use x86_64::VirtAddr;
 
@@ -10,12 +11,8 @@ use super::MAPPER;
 use super::NEXT_MMAP;
 use super::PAGESIZE;
 
-// TODO: multi-thread or syscall-proxy
-pub fn mmap_user(len: usize) -> *mut u8 {
-    let virt_start_addr;
-    unsafe {
-        virt_start_addr = VirtAddr::new(NEXT_MMAP as u64);
-    }
+/// Map non-executable user pages from `virt_start_addr`, already mapped pages are kept
+fn map_user_pages(virt_start_addr: VirtAddr, len: usize) {
     let start_page: Page = Page::containing_address(virt_start_addr);
     let end_page: Page = Page::containing_address(virt_start_addr + len - 1u64);
     let page_range = Page::range_inclusive(start_page, end_page);
@@ -40,7 +37,8 @@ pub fn mmap_user(len: usize) -> *mut u8 {
                 frame,
                 PageTableFlags::PRESENT
                     | PageTableFlags::WRITABLE
-                    | PageTableFlags::USER_ACCESSIBLE,
+                    | PageTableFlags::USER_ACCESSIBLE
+                    | PageTableFlags::NO_EXECUTE,
                 PageTableFlags::USER_ACCESSIBLE,
                 &mut frame_allocator,
             )
@@ -59,75 +57,109 @@ pub fn mmap_user(len: usize) -> *mut u8 {
             .unwrap();
     }
 
+    unsafe {
+        FRAME_ALLOCATOR.replace(frame_allocator);
+        MAPPER.replace(mapper);
+    }
+}
+
+// TODO: multi-thread or syscall-proxy
+pub fn mmap_user(len: usize) -> *mut u8 {
+    let virt_start_addr;
+    unsafe {
+        virt_start_addr = VirtAddr::new(NEXT_MMAP as u64);
+    }
+    map_user_pages(virt_start_addr, len);
+
     let ret;
     unsafe {
         ret = NEXT_MMAP as *mut u8;
         ret.write_bytes(0u8, len);
 
         NEXT_MMAP = (virt_start_addr + len).align_up(PAGESIZE as u64).as_u64();
-
-        FRAME_ALLOCATOR.replace(frame_allocator);
-        MAPPER.replace(mapper);
     }
     ret
 }
 
-// TODO: muti-thread or syscall-proxy
-pub fn brk_user(len: usize) -> *mut u8 {
-    let virt_start_addr;
+/// Map zeroed user memory at `addr` for `mmap(…, MAP_FIXED, …)`
+///
+/// The content of already mapped pages in the range is overwritten.
+/// `addr` has to be page aligned.
+pub fn mmap_user_fixed(addr: usize, len: usize) -> *mut u8 {
+    map_user_pages(VirtAddr::new(addr as u64), len);
+    // already mapped pages might be read-only
+    mprotect_user(addr, len, PROT_READ | PROT_WRITE).unwrap();
+
+    let ret = addr as *mut u8;
     unsafe {
-        virt_start_addr = VirtAddr::new(NEXT_MMAP as u64);
+        ret.write_bytes(0u8, len);
+    }
+    ret
+}
+
+pub const PROT_READ: usize = 0x1;
+pub const PROT_WRITE: usize = 0x2;
+pub const PROT_EXEC: usize = 0x4;
+
+/// Change the permissions of the user pages from `addr` for `mprotect()`
+///
+/// `PROT_NONE` pages stay mapped, but are not user accessible. Fails with
+/// `ENOMEM`, if a page in the range is not mapped.
+pub fn mprotect_user(addr: usize, len: usize, prot: usize) -> Result<(), ErrNo> {
+    let virt_start_addr = VirtAddr::new(addr as u64);
+    if !virt_start_addr.is_aligned(PAGESIZE as u64) {
+        return Err(ErrNo::EINVAL);
+    }
+    if len == 0 {
+        return Ok(());
+    }
+
+    let mut flags = PageTableFlags::PRESENT;
+    if prot & (PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        flags |= PageTableFlags::USER_ACCESSIBLE;
     }
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
     let start_page: Page = Page::containing_address(virt_start_addr);
     let end_page: Page = Page::containing_address(virt_start_addr + len - 1u64);
     let page_range = Page::range_inclusive(start_page, end_page);
 
-    let mut frame_allocator;
-    let mut mapper;
-    unsafe {
-        frame_allocator = FRAME_ALLOCATOR.take().unwrap();
-        mapper = MAPPER.take().unwrap();
+    let mapper = unsafe { MAPPER.as_mut().unwrap() };
+
+    if page_range
+        .clone()
+        .any(|page| mapper.translate_page(page).is_err())
+    {
+        return Err(ErrNo::ENOMEM);
     }
 
     for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::<Size4KiB>::FrameAllocationFailed)
-            .unwrap();
-        //println!("page {:#?} frame {:#?}", page, frame);
         mapper
-            .map_to(
-                page,
-                frame,
-                PageTableFlags::PRESENT
-                    | PageTableFlags::WRITABLE
-                    | PageTableFlags::USER_ACCESSIBLE,
-                PageTableFlags::USER_ACCESSIBLE,
-                &mut frame_allocator,
-            )
-            .and_then(|f| {
-                f.flush();
-                Ok(())
-            })
-            .or_else(|e| match e {
-                MapToError::PageAlreadyMapped(f) => {
-                    frame_allocator.deallocate_frame(f);
-                    Ok(())
-                }
-                MapToError::ParentEntryHugePage => Ok(()),
-                _ => Err(e),
-            })
-            .unwrap();
+            .update_flags(page, flags)
+            .map_err(|_| ErrNo::ENOMEM)?
+            .flush();
+    }
+    Ok(())
+}
+
+// TODO: muti-thread or syscall-proxy
+pub fn brk_user(len: usize) -> *mut u8 {
+    let virt_start_addr;
+    unsafe {
+        virt_start_addr = VirtAddr::new(NEXT_MMAP as u64);
     }
+    map_user_pages(virt_start_addr, len);
 
     let ret;
     unsafe {
         ret = NEXT_MMAP as *mut u8;
         NEXT_MMAP += len as u64;
         ret.write_bytes(0u8, len);
-
-        FRAME_ALLOCATOR.replace(frame_allocator);
-        MAPPER.replace(mapper);
     }
     ret
 }
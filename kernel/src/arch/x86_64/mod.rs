@@ -2,13 +2,16 @@
 pub mod serial;
 
 pub mod gdt;
+pub mod ghcb;
 
 pub mod idt;
 pub mod interrupts;
 
+pub mod sev;
 mod start_e820;
 pub mod structures;
 pub mod syscall;
+pub mod tls;
 
 #[cfg(feature = "timer")]
 pub mod timer;
@@ -19,13 +22,17 @@ pub use exec::exec_elf;
 mod init;
 pub use init::init;
 
+mod loader;
+pub use loader::protect_pending_relro;
+
 mod mmap;
-pub use mmap::{brk_user, mmap_user};
+pub use mmap::{brk_user, mmap_user, mmap_user_fixed, mprotect_user};
 
 mod xcr0;
 
 use crate::arch::x86_64::structures::paging::OffsetPageTable;
 use crate::memory::BootInfoFrameAllocator;
+use vmsyscall::bootinfo::ArgsBlob;
 pub use x86_64::{PhysAddr, VirtAddr};
 
 /// Defines the entry point function.
@@ -48,12 +55,18 @@ macro_rules! entry_point {
 }
 
 pub const PAGESIZE: usize = 4096;
+pub const PHYSICAL_MEMORY_OFFSET: u64 = 0x800_0000_0000;
 pub const STACK_START: usize = 0x7F48_4800_0000;
 pub const STACK_SIZE: usize = 1 * 1024 * 1024; // 100 KiB
 
 static mut APP_ENTRY_POINT: *const u8 = core::ptr::null();
 static mut APP_LOAD_ADDR: *const u8 = core::ptr::null();
 static mut APP_PH_NUM: usize = 0;
+static mut APP_BASE: u64 = 0;
+static mut APP_EXEC_STACK: bool = false;
+static mut APP_TLS: Option<loader::TlsTemplate> = None;
+static mut APP_INTERP_ENTRY: *const u8 = core::ptr::null();
+static mut APP_ARGS: ArgsBlob = ArgsBlob { start: 0, len: 0 };
 static mut FRAME_ALLOCATOR: Option<BootInfoFrameAllocator> = None;
 static mut MAPPER: Option<OffsetPageTable> = None;
 
@@ -45,6 +45,25 @@ pub fn _eprint(args: ::core::fmt::Arguments) {
     });
 }
 
+/// Write raw bytes to the serial port of `fd`, returns the number of bytes written
+pub fn write_bytes(fd: u32, bytes: &[u8]) -> Result<usize, i64> {
+    use x86_64::instructions::interrupts;
+
+    let port: &Mutex<SerialPort> = if fd == 2 { &*SERIAL2 } else { &*SERIAL1 };
+    interrupts::without_interrupts(|| {
+        let mut port = port.lock();
+        for byte in bytes {
+            port.send(*byte);
+        }
+    });
+    Ok(bytes.len())
+}
+
+/// The serial port takes bytes at any time
+pub fn wait_writable(_fd: u32) -> Result<(), i64> {
+    Ok(())
+}
+
 /// Prints to the host through the serial interface.
 #[macro_export]
 macro_rules! serial_print {
@@ -1,18 +1,15 @@
 //! print via vmsyscall
 
-use vmsyscall::WRITE_BUF_LEN;
+use crate::poll::POLLOUT;
+use linux_errno::ErrNo;
+use vmsyscall::{PollFd, PollKind, WRITE_BUF_LEN};
 
 pub struct DummySerialPort(u32);
 
 impl core::fmt::Write for DummySerialPort {
     #[inline(always)]
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        for c in s.as_bytes().chunks(WRITE_BUF_LEN) {
-            crate::libc::write(self.0, c)
-                .map(|_| ())
-                .map_err(|_| core::fmt::Error)?;
-        }
-        Ok(())
+        write_all(self.0, s.as_bytes()).map_err(|_| core::fmt::Error)
     }
 }
 
@@ -33,6 +30,19 @@ pub fn _eprint(args: ::core::fmt::Arguments) {
         .expect("Printing via vmsyscall fd 2 failed");
 }
 
+/// Write raw bytes to the host `fd`, returns the number of bytes written
+///
+/// At most `WRITE_BUF_LEN` bytes are written at once. Errors are returned as
+/// positive `errno` values.
+pub fn write_bytes(fd: u32, bytes: &[u8]) -> Result<usize, i64> {
+    let len = bytes.len().min(WRITE_BUF_LEN);
+    match crate::libc::write(fd, &bytes[..len]) {
+        Ok(written) => Ok(written as usize),
+        Err(vmsyscall::Error::Errno(errno)) => Err(errno),
+        Err(_) => Err(linux_errno::ErrNo::EIO.into()),
+    }
+}
+
 /// Prints to the host through the serial interface.
 #[macro_export]
 macro_rules! serial_print {
@@ -76,3 +86,34 @@ macro_rules! eprintln {
     ($fmt:expr) => ($crate::serial_eprint!(concat!($fmt, "\n")));
     ($fmt:expr, $($arg:tt)*) => ($crate::serial_eprint!(concat!($fmt, "\n"), $($arg)*));
 }
+
+/// Wait with `VmSyscall::Poll` until the host `fd` takes more bytes
+///
+/// Errors are returned as positive `errno` values.
+pub fn wait_writable(fd: u32) -> Result<(), i64> {
+    let fds = [PollFd {
+        kind: PollKind::Stdio,
+        fd,
+        events: POLLOUT,
+    }];
+    match crate::libc::poll(&fds, -1) {
+        Ok(_) => Ok(()),
+        Err(vmsyscall::Error::Errno(errno)) => Err(errno),
+        Err(_) => Err(ErrNo::EIO.into()),
+    }
+}
+
+/// Write all of `bytes` to the host `fd`, waiting while its pipe is full
+///
+/// Errors are returned as positive `errno` values.
+pub fn write_all(fd: u32, mut bytes: &[u8]) -> Result<(), i64> {
+    while !bytes.is_empty() {
+        match write_bytes(fd, bytes) {
+            Ok(0) => return Err(ErrNo::EIO.into()),
+            Ok(len) => bytes = &bytes[len..],
+            Err(errno) if errno == i64::from(ErrNo::EAGAIN) => wait_writable(fd)?,
+            Err(errno) => return Err(errno),
+        }
+    }
+    Ok(())
+}
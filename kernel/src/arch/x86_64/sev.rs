@@ -0,0 +1,78 @@
+//! AMD SEV guest support
+//!
+//! With SEV the C-bit has to be set in all page table entries mapping private
+//! guest memory. Memory shared with the host, like the syscall page, has to be
+//! mapped without the C-bit.
+
+use crate::arch::x86_64::structures::paging::{
+    mapper::EncryptionUpdateError, FrameAllocator, OffsetPageTable, Page, Size4KiB,
+};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::PageSize;
+use x86_64::VirtAddr;
+
+/// SEV status MSR, bit 0 is set, if SEV is enabled for the guest
+const MSR_SEV_STATUS: u32 = 0xC001_0131;
+
+extern "C" {
+    /// The encryption mask set in the initial page tables by `_setup_pto`
+    static _sev_encryption_mask: u64;
+}
+
+/// Returns the position of the C-bit, if SEV is enabled for this guest.
+pub fn cbit_position() -> Option<u32> {
+    use super::ghcb::cpuid;
+
+    if cpuid(0x8000_0000).eax < 0x8000_001F {
+        return None;
+    }
+
+    let leaf = cpuid(0x8000_001F);
+    // eax bit 1: SEV supported
+    if leaf.eax & 0b10 == 0 {
+        return None;
+    }
+
+    // Reading the SEV status MSR is never intercepted, not even with SEV-ES
+    if unsafe { Msr::new(MSR_SEV_STATUS).read() } & 1 == 0 {
+        return None;
+    }
+
+    Some(leaf.ebx & 0x3F)
+}
+
+/// The encryption mask (C-bit) of all private guest memory mappings, or `0`
+/// if SEV is not enabled.
+pub fn encryption_mask() -> u64 {
+    unsafe { _sev_encryption_mask }
+}
+
+/// Map `size` bytes at `start` unencrypted, so they can be shared with the host.
+///
+/// The guest can't read the previous encrypted contents afterwards.
+pub fn set_host_shared(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    start: VirtAddr,
+    size: u64,
+) -> Result<(), EncryptionUpdateError> {
+    if mapper.encryption_mask() == 0 || size == 0 {
+        return Ok(());
+    }
+
+    let start_page: Page<Size4KiB> = Page::containing_address(start);
+    let end_page: Page<Size4KiB> = Page::containing_address(start + size - 1u64);
+
+    for page in Page::range_inclusive(start_page, end_page) {
+        // write back the cache lines with the encrypted data before changing the C-bit
+        for offset in (0..Size4KiB::SIZE).step_by(64) {
+            unsafe {
+                core::arch::x86_64::_mm_clflush((page.start_address() + offset).as_ptr());
+            }
+        }
+
+        mapper.set_encrypted(page, false, frame_allocator)?.flush();
+    }
+
+    Ok(())
+}
@@ -1,5 +1,8 @@
 use crate::arch::x86_64::PAGESIZE;
-use vmsyscall::bootinfo::BootInfo;
+use crate::debug;
+use vmsyscall::bootinfo::{
+    ArgsBlob, BootInfo, ElfBlob, InitrdBlob, LogLevel, SyscallPolicy, TmpfsLimits,
+};
 use vmsyscall::memory_map::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType};
 use x86_64::PhysAddr;
 
@@ -92,20 +95,20 @@ extern "C" {
 
 #[export_name = "_start_e820"]
 pub unsafe extern "C" fn rust_start_820(hvm_start_info: *const HvmStartInfo) -> ! {
-    eprintln!("rust_start_820, magic={:#X}", (*hvm_start_info).magic);
+    debug!("rust_start_820, magic={:#X}", (*hvm_start_info).magic);
     let kernel_start_ptr = &_kernel_start as *const _ as u64;
     let kernel_end_ptr = &_kernel_end as *const _ as u64;
 
     let e820_count = (*hvm_start_info).memmap_entries;
     let entry = (*hvm_start_info).memmap_paddr as *const HvmMemmapTableEntry;
-    eprintln!("addr={}", (*entry).addr);
+    debug!("addr={}", (*entry).addr);
     let e820_table = core::slice::from_raw_parts(
         (*hvm_start_info).memmap_paddr as *const HvmMemmapTableEntry,
         e820_count as _,
     );
-    eprintln!("e820_table={:#X}", (*hvm_start_info).memmap_paddr);
-    eprintln!("e820_count={}", e820_count);
-    eprintln!("{:#?}", e820_table);
+    debug!("e820_table={:#X}", (*hvm_start_info).memmap_paddr);
+    debug!("e820_count={}", e820_count);
+    debug!("{:#?}", e820_table);
 
     pub const BOOTINFO_PHYS_ADDR: u64 = 0x8000;
 
@@ -113,10 +116,17 @@ pub unsafe extern "C" fn rust_start_820(hvm_start_info: *const HvmStartInfo) ->
         BOOTINFO_PHYS_ADDR as *mut BootInfo,
         BootInfo {
             memory_map: MemoryMap::new(),
-            entry_point: core::ptr::null(),
-            load_addr: core::ptr::null(),
-            elf_phnum: 0,
+            app: ElfBlob::default(),
+            interp: ElfBlob::default(),
+            args: ArgsBlob::default(),
+            initrd: InitrdBlob::default(),
+            tmpfs: TmpfsLimits::default(),
+            syscalls: SyscallPolicy::default(),
+            log_level: LogLevel::default(),
             syscall_trigger_port: 0,
+            ghcb: 0,
+            ghcb_trigger_port: 0,
+            secret: 0,
         },
     );
 
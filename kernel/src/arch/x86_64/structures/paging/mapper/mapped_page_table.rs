@@ -1,7 +1,7 @@
 use super::*;
 use x86_64::structures::paging::{
     frame::PhysFrame,
-    page::{Page, Size1GiB, Size2MiB, Size4KiB},
+    page::{Page, PageSize, Size1GiB, Size2MiB, Size4KiB},
     page_table::{FrameError, PageTable, PageTableEntry, PageTableFlags},
     FrameAllocator,
 };
@@ -30,10 +30,72 @@ impl<'a, P: PhysToVirt> MappedPageTable<'a, P> {
     /// of a valid page table hierarchy. Otherwise this function might break memory safety, e.g.
     /// by writing to an illegal memory location.
     pub unsafe fn new(level_4_table: &'a mut PageTable, phys_to_virt: P) -> Self {
+        Self::with_encryption_mask(level_4_table, phys_to_virt, 0)
+    }
+
+    /// Creates a new `MappedPageTable`, which sets `encryption_mask` in the physical
+    /// address of all newly created entries and strips it from all entries read.
+    ///
+    /// # Safety
+    ///
+    /// See [`MappedPageTable::new`]. Additionally `encryption_mask` must only contain
+    /// the encryption bit (C-bit) of the CPU.
+    pub unsafe fn with_encryption_mask(
+        level_4_table: &'a mut PageTable,
+        phys_to_virt: P,
+        encryption_mask: u64,
+    ) -> Self {
         Self {
             level_4_table,
-            page_table_walker: PageTableWalker::new(phys_to_virt),
+            page_table_walker: PageTableWalker::new(phys_to_virt, encryption_mask),
+        }
+    }
+
+    /// The encryption bit set in the physical addresses of the entries
+    pub fn encryption_mask(&self) -> u64 {
+        self.page_table_walker.encryption_mask
+    }
+
+    /// Sets or clears the encryption bit in the mapping of the given page.
+    ///
+    /// If the page is part of a 2MiB page, the 2MiB page is split into 4KiB pages
+    /// first. The new page table is allocated from `allocator`.
+    pub fn set_encrypted<A>(
+        &mut self,
+        page: Page<Size4KiB>,
+        encrypted: bool,
+        allocator: &mut A,
+    ) -> Result<MapperFlush<Size4KiB>, EncryptionUpdateError>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        let p4 = &mut self.level_4_table;
+        let p3 = self
+            .page_table_walker
+            .next_table_mut(&mut p4[page.p4_index()])?;
+        let p2 = self
+            .page_table_walker
+            .next_table_mut(&mut p3[page.p3_index()])?;
+        let p1 = self
+            .page_table_walker
+            .split_huge_page(&mut p2[page.p2_index()], allocator)?;
+
+        let p1_entry = &mut p1[page.p1_index()];
+
+        if p1_entry.is_unused() {
+            return Err(EncryptionUpdateError::PageNotMapped);
+        }
+
+        let addr = self.page_table_walker.entry_addr(p1_entry);
+        let flags = p1_entry.flags();
+
+        if encrypted {
+            self.page_table_walker.set_entry_addr(p1_entry, addr, flags);
+        } else {
+            p1_entry.set_addr(addr, flags);
         }
+
+        Ok(MapperFlush::new(page))
     }
 
     /// Helper function for implementing Mapper. Safe to limit the scope of unsafe, see
@@ -59,7 +121,11 @@ impl<'a, P: PhysToVirt> MappedPageTable<'a, P> {
         if !p3[page.p3_index()].is_unused() {
             return Err(MapToError::PageAlreadyMapped(frame));
         }
-        p3[page.p3_index()].set_addr(frame.start_address(), flags | PageTableFlags::HUGE_PAGE);
+        self.page_table_walker.set_entry_addr(
+            &mut p3[page.p3_index()],
+            frame.start_address(),
+            flags | PageTableFlags::HUGE_PAGE,
+        );
 
         Ok(MapperFlush::new(page))
     }
@@ -92,7 +158,11 @@ impl<'a, P: PhysToVirt> MappedPageTable<'a, P> {
         if !p2[page.p2_index()].is_unused() {
             return Err(MapToError::PageAlreadyMapped(frame));
         }
-        p2[page.p2_index()].set_addr(frame.start_address(), flags | PageTableFlags::HUGE_PAGE);
+        self.page_table_walker.set_entry_addr(
+            &mut p2[page.p2_index()],
+            frame.start_address(),
+            flags | PageTableFlags::HUGE_PAGE,
+        );
 
         Ok(MapperFlush::new(page))
     }
@@ -130,7 +200,11 @@ impl<'a, P: PhysToVirt> MappedPageTable<'a, P> {
         if !p1[page.p1_index()].is_unused() {
             return Err(MapToError::PageAlreadyMapped(frame));
         }
-        p1[page.p1_index()].set_frame(frame.frame(), flags);
+        self.page_table_walker.set_entry_addr(
+            &mut p1[page.p1_index()],
+            frame.start_address(),
+            flags,
+        );
 
         Ok(MapperFlush::new(page))
     }
@@ -170,8 +244,9 @@ impl<'a, P: PhysToVirt> Mapper<Size1GiB> for MappedPageTable<'a, P> {
             return Err(UnmapError::ParentEntryHugePage);
         }
 
-        let frame = PhysFrame::from_start_address(p3_entry.addr())
-            .map_err(|()| UnmapError::InvalidFrameAddress(p3_entry.addr()))?;
+        let addr = self.page_table_walker.entry_addr(p3_entry);
+        let frame = PhysFrame::from_start_address(addr)
+            .map_err(|()| UnmapError::InvalidFrameAddress(addr))?;
 
         p3_entry.set_unused();
         Ok((frame, MapperFlush::new(page)))
@@ -205,8 +280,8 @@ impl<'a, P: PhysToVirt> Mapper<Size1GiB> for MappedPageTable<'a, P> {
             return Err(TranslateError::PageNotMapped);
         }
 
-        PhysFrame::from_start_address(p3_entry.addr())
-            .map_err(|()| TranslateError::InvalidFrameAddress(p3_entry.addr()))
+        let addr = self.page_table_walker.entry_addr(p3_entry);
+        PhysFrame::from_start_address(addr).map_err(|()| TranslateError::InvalidFrameAddress(addr))
     }
 }
 
@@ -247,8 +322,9 @@ impl<'a, P: PhysToVirt> Mapper<Size2MiB> for MappedPageTable<'a, P> {
             return Err(UnmapError::ParentEntryHugePage);
         }
 
-        let frame = PhysFrame::from_start_address(p2_entry.addr())
-            .map_err(|()| UnmapError::InvalidFrameAddress(p2_entry.addr()))?;
+        let addr = self.page_table_walker.entry_addr(p2_entry);
+        let frame = PhysFrame::from_start_address(addr)
+            .map_err(|()| UnmapError::InvalidFrameAddress(addr))?;
 
         p2_entry.set_unused();
         Ok((frame, MapperFlush::new(page)))
@@ -287,8 +363,8 @@ impl<'a, P: PhysToVirt> Mapper<Size2MiB> for MappedPageTable<'a, P> {
             return Err(TranslateError::PageNotMapped);
         }
 
-        PhysFrame::from_start_address(p2_entry.addr())
-            .map_err(|()| TranslateError::InvalidFrameAddress(p2_entry.addr()))
+        let addr = self.page_table_walker.entry_addr(p2_entry);
+        PhysFrame::from_start_address(addr).map_err(|()| TranslateError::InvalidFrameAddress(addr))
     }
 }
 
@@ -324,10 +400,13 @@ impl<'a, P: PhysToVirt> Mapper<Size4KiB> for MappedPageTable<'a, P> {
 
         let p1_entry = &mut p1[page.p1_index()];
 
-        let frame = p1_entry.frame().map_err(|err| match err {
-            FrameError::FrameNotPresent => UnmapError::PageNotMapped,
-            FrameError::HugeFrame => UnmapError::ParentEntryHugePage,
-        })?;
+        let frame = self
+            .page_table_walker
+            .entry_frame(p1_entry)
+            .map_err(|err| match err {
+                FrameError::FrameNotPresent => UnmapError::PageNotMapped,
+                FrameError::HugeFrame => UnmapError::ParentEntryHugePage,
+            })?;
 
         p1_entry.set_unused();
         Ok((frame, MapperFlush::new(page)))
@@ -370,8 +449,8 @@ impl<'a, P: PhysToVirt> Mapper<Size4KiB> for MappedPageTable<'a, P> {
             return Err(TranslateError::PageNotMapped);
         }
 
-        PhysFrame::from_start_address(p1_entry.addr())
-            .map_err(|()| TranslateError::InvalidFrameAddress(p1_entry.addr()))
+        let addr = self.page_table_walker.entry_addr(p1_entry);
+        PhysFrame::from_start_address(addr).map_err(|()| TranslateError::InvalidFrameAddress(addr))
     }
 }
 
@@ -389,7 +468,9 @@ impl<'a, P: PhysToVirt> MapperAllSizes for MappedPageTable<'a, P> {
             Ok(page_table) => page_table,
             Err(PageTableWalkError::NotMapped) => return TranslateResult::PageNotMapped,
             Err(PageTableWalkError::MappedToHugePage) => {
-                let frame = PhysFrame::containing_address(p3[addr.p3_index()].addr());
+                let frame = PhysFrame::containing_address(
+                    self.page_table_walker.entry_addr(&p3[addr.p3_index()]),
+                );
                 let offset = addr.as_u64() & 0o7_777_777_777;
                 return TranslateResult::Frame1GiB { frame, offset };
             }
@@ -398,7 +479,9 @@ impl<'a, P: PhysToVirt> MapperAllSizes for MappedPageTable<'a, P> {
             Ok(page_table) => page_table,
             Err(PageTableWalkError::NotMapped) => return TranslateResult::PageNotMapped,
             Err(PageTableWalkError::MappedToHugePage) => {
-                let frame = PhysFrame::containing_address(p2[addr.p2_index()].addr());
+                let frame = PhysFrame::containing_address(
+                    self.page_table_walker.entry_addr(&p2[addr.p2_index()]),
+                );
                 let offset = addr.as_u64() & 0o7_777_777;
                 return TranslateResult::Frame2MiB { frame, offset };
             }
@@ -410,9 +493,10 @@ impl<'a, P: PhysToVirt> MapperAllSizes for MappedPageTable<'a, P> {
             return TranslateResult::PageNotMapped;
         }
 
-        let frame = match PhysFrame::from_start_address(p1_entry.addr()) {
+        let frame_addr = self.page_table_walker.entry_addr(p1_entry);
+        let frame = match PhysFrame::from_start_address(frame_addr) {
             Ok(frame) => frame,
-            Err(()) => return TranslateResult::InvalidFrameAddress(p1_entry.addr()),
+            Err(()) => return TranslateResult::InvalidFrameAddress(frame_addr),
         };
         let offset = u64::from(addr.page_offset());
         TranslateResult::Frame4KiB { frame, offset }
@@ -422,11 +506,33 @@ impl<'a, P: PhysToVirt> MapperAllSizes for MappedPageTable<'a, P> {
 #[derive(Debug)]
 struct PageTableWalker<P: PhysToVirt> {
     phys_to_virt: P,
+    encryption_mask: u64,
 }
 
 impl<P: PhysToVirt> PageTableWalker<P> {
-    pub unsafe fn new(phys_to_virt: P) -> Self {
-        Self { phys_to_virt }
+    pub unsafe fn new(phys_to_virt: P, encryption_mask: u64) -> Self {
+        Self {
+            phys_to_virt,
+            encryption_mask,
+        }
+    }
+
+    /// Internal helper function to get the physical address of an entry without the
+    /// encryption bit.
+    fn entry_addr(&self, entry: &PageTableEntry) -> PhysAddr {
+        PhysAddr::new(entry.addr().as_u64() & !self.encryption_mask)
+    }
+
+    /// Internal helper function to get the frame of an entry without the encryption bit.
+    fn entry_frame(&self, entry: &PageTableEntry) -> Result<PhysFrame, FrameError> {
+        entry.frame()?;
+        Ok(PhysFrame::containing_address(self.entry_addr(entry)))
+    }
+
+    /// Internal helper function to set the physical address of an entry with the
+    /// encryption bit.
+    fn set_entry_addr(&self, entry: &mut PageTableEntry, addr: PhysAddr, flags: PageTableFlags) {
+        entry.set_addr(PhysAddr::new(addr.as_u64() | self.encryption_mask), flags);
     }
 
     /// Internal helper function to get a reference to the page table of the next level.
@@ -438,7 +544,7 @@ impl<P: PhysToVirt> PageTableWalker<P> {
         &self,
         entry: &'b PageTableEntry,
     ) -> Result<&'b PageTable, PageTableWalkError> {
-        let page_table_ptr = self.phys_to_virt.phys_to_virt(entry.frame()?);
+        let page_table_ptr = self.phys_to_virt.phys_to_virt(self.entry_frame(entry)?);
         let page_table: &PageTable = unsafe { &*page_table_ptr };
 
         Ok(page_table)
@@ -453,7 +559,7 @@ impl<P: PhysToVirt> PageTableWalker<P> {
         &self,
         entry: &'b mut PageTableEntry,
     ) -> Result<&'b mut PageTable, PageTableWalkError> {
-        let page_table_ptr = self.phys_to_virt.phys_to_virt(entry.frame()?);
+        let page_table_ptr = self.phys_to_virt.phys_to_virt(self.entry_frame(entry)?);
         let page_table: &mut PageTable = unsafe { &mut *page_table_ptr };
 
         Ok(page_table)
@@ -481,8 +587,9 @@ impl<P: PhysToVirt> PageTableWalker<P> {
 
         if entry.is_unused() {
             if let Some(frame) = allocator.allocate_frame() {
-                entry.set_frame(
-                    frame.frame(),
+                self.set_entry_addr(
+                    entry,
+                    frame.start_address(),
                     PageTableFlags::PRESENT | PageTableFlags::WRITABLE | insert_flags,
                 );
                 created = true;
@@ -509,6 +616,47 @@ impl<P: PhysToVirt> PageTableWalker<P> {
         }
         Ok(page_table)
     }
+
+    /// Internal helper function to get the page table of the next level, splitting a 2MiB
+    /// page into 4KiB pages if needed.
+    ///
+    /// The 4KiB pages keep the flags and the encryption bit of the 2MiB page.
+    fn split_huge_page<'b, A>(
+        &self,
+        entry: &'b mut PageTableEntry,
+        allocator: &mut A,
+    ) -> Result<&'b mut PageTable, EncryptionUpdateError>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        let flags = entry.flags();
+
+        if flags.contains(PageTableFlags::PRESENT | PageTableFlags::HUGE_PAGE) {
+            let frame = allocator
+                .allocate_frame()
+                .ok_or(EncryptionUpdateError::FrameAllocationFailed)?;
+            let page_table: &mut PageTable =
+                unsafe { &mut *self.phys_to_virt.phys_to_virt(frame.frame()) };
+
+            // including the encryption bit
+            let start = entry.addr().as_u64();
+            let page_flags = flags - PageTableFlags::HUGE_PAGE;
+            for (i, page_entry) in page_table.iter_mut().enumerate() {
+                page_entry.set_addr(PhysAddr::new(start + i as u64 * Size4KiB::SIZE), page_flags);
+            }
+
+            self.set_entry_addr(
+                entry,
+                frame.start_address(),
+                flags
+                    & (PageTableFlags::PRESENT
+                        | PageTableFlags::WRITABLE
+                        | PageTableFlags::USER_ACCESSIBLE),
+            );
+        }
+
+        Ok(self.next_table_mut(entry)?)
+    }
 }
 
 #[derive(Debug)]
@@ -577,6 +725,15 @@ impl From<PageTableWalkError> for FlagUpdateError {
     }
 }
 
+impl From<PageTableWalkError> for EncryptionUpdateError {
+    fn from(err: PageTableWalkError) -> Self {
+        match err {
+            PageTableWalkError::MappedToHugePage => EncryptionUpdateError::ParentEntryHugePage,
+            PageTableWalkError::NotMapped => EncryptionUpdateError::PageNotMapped,
+        }
+    }
+}
+
 impl From<PageTableWalkError> for TranslateError {
     fn from(err: PageTableWalkError) -> Self {
         match err {
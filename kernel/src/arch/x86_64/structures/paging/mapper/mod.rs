@@ -236,6 +236,19 @@ pub enum FlagUpdateError {
     ParentEntryHugePage,
 }
 
+/// An error indicating that a `set_encrypted` call failed.
+#[derive(Debug)]
+pub enum EncryptionUpdateError {
+    /// The given page is not mapped to a physical frame.
+    PageNotMapped,
+    /// An upper level page table entry has the `HUGE_PAGE` flag set for a 1GiB page,
+    /// which can't be split.
+    ParentEntryHugePage,
+    /// A page table was needed to split a 2MiB page, but the frame allocator
+    /// returned `None`.
+    FrameAllocationFailed,
+}
+
 /// An error indicating that an `translate` call failed.
 #[derive(Debug)]
 pub enum TranslateError {
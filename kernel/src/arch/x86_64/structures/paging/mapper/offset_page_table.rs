@@ -27,13 +27,50 @@ impl<'a> OffsetPageTable<'a> {
     /// of a valid page table hierarchy. Otherwise this function might break memory safety, e.g.
     /// by writing to an illegal memory location.
     pub unsafe fn new(level_4_table: &'a mut PageTable, phys_offset: VirtAddr) -> Self {
+        Self::with_encryption_mask(level_4_table, phys_offset, 0)
+    }
+
+    /// Creates a new `OffsetPageTable`, which sets `encryption_mask` in all physical
+    /// addresses. See [`MappedPageTable::with_encryption_mask`].
+    ///
+    /// # Safety
+    ///
+    /// See [`OffsetPageTable::new`].
+    pub unsafe fn with_encryption_mask(
+        level_4_table: &'a mut PageTable,
+        phys_offset: VirtAddr,
+        encryption_mask: u64,
+    ) -> Self {
         let phys_offset = PhysOffset {
             offset: phys_offset,
         };
         Self {
-            inner: MappedPageTable::new(level_4_table, phys_offset),
+            inner: MappedPageTable::with_encryption_mask(
+                level_4_table,
+                phys_offset,
+                encryption_mask,
+            ),
         }
     }
+
+    /// The encryption bit set in the physical addresses of the entries
+    pub fn encryption_mask(&self) -> u64 {
+        self.inner.encryption_mask()
+    }
+
+    /// Sets or clears the encryption bit in the mapping of the given page.
+    /// See [`MappedPageTable::set_encrypted`].
+    pub fn set_encrypted<A>(
+        &mut self,
+        page: Page<Size4KiB>,
+        encrypted: bool,
+        allocator: &mut A,
+    ) -> Result<MapperFlush<Size4KiB>, EncryptionUpdateError>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        self.inner.set_encrypted(page, encrypted, allocator)
+    }
 }
 
 #[derive(Debug)]
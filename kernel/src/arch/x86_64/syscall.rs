@@ -1,11 +1,12 @@
 use super::gdt;
+use super::tls;
 use x86_64::registers::model_specific::{KernelGsBase, LStar, SFMask, Star};
 use x86_64::registers::rflags::RFlags;
 use x86_64::VirtAddr;
 
 extern "C" {
     fn _syscall_enter() -> !;
-    fn _usermode(ip: usize, sp: usize, arg: usize) -> !;
+    fn _usermode(ip: usize, sp: usize, arg: usize, fs_base: u64, gs_base: u64) -> !;
 }
 
 pub unsafe fn init() {
@@ -37,10 +38,15 @@ pub unsafe extern "C" fn syscall_rust(
     f: usize,
     nr: usize,
 ) -> usize {
-    crate::syscall::handle_syscall(a, b, c, d, e, f, nr)
+    tls::save();
+    let ret = crate::syscall::handle_syscall(a, b, c, d, e, f, nr);
+    tls::restore();
+    ret
 }
 
+/// Enter the app at `ip` with the FS and GS base set by `tls`
 #[inline(always)]
 pub unsafe fn usermode(ip: usize, sp: usize, arg: usize) -> ! {
-    _usermode(ip, sp, arg)
+    let segments = tls::user_segments();
+    _usermode(ip, sp, arg, segments.fs_base, segments.gs_base)
 }
@@ -2,7 +2,8 @@ use lazy_static::lazy_static;
 use pic8259_simple::ChainedPics;
 use spin::Mutex;
 
-use crate::{exit_hypervisor, hlt_loop, HyperVisorExitCode};
+use super::ghcb;
+use crate::{debug, error, exit_hypervisor, hlt_loop, trace, HyperVisorExitCode};
 use x2apic::lapic::{LocalApic, LocalApicBuilder, TimerDivide, TimerMode};
 use x86_64::instructions::port::Port;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
@@ -59,19 +60,15 @@ pub fn timer_init() {
         PICS.lock().initialize();
     };
 
-    let mut cp = Port::new(0x43);
     unsafe {
-        cp.write(0b00110100_u8);
-    }
-    let mut p = Port::new(0x40);
-    unsafe {
-        p.write(0xFF_u8);
-        p.write(0xFF_u8);
+        ghcb::outb(0x43, 0b00110100_u8);
+        ghcb::outb(0x40, 0xFF_u8);
+        ghcb::outb(0x40, 0xFF_u8);
     }
 }
 
 pub fn timer_set_idt(idt: &mut InterruptDescriptorTable) {
-    eprintln!("timer_set_idt");
+    debug!("timer_set_idt");
     idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
     idt[InterruptIndex::LapicTimer.as_usize()].set_handler_fn(lapic_timer_interrupt_handler);
     idt[InterruptIndex::Error.as_usize()].set_handler_fn(error_interrupt_handler);
@@ -80,22 +77,21 @@ pub fn timer_set_idt(idt: &mut InterruptDescriptorTable) {
 }
 
 extern "x86-interrupt" fn spurious_interrupt_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("EXCEPTION: spurious interrupt");
-    eprintln!("{:#?}", stack_frame);
+    error!("EXCEPTION: spurious interrupt");
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 extern "x86-interrupt" fn error_interrupt_handler(stack_frame: &mut InterruptStackFrame) {
-    eprintln!("EXCEPTION: error interrupt");
-    eprintln!("{:#?}", stack_frame);
+    error!("EXCEPTION: error interrupt");
+    error!("{:#?}", stack_frame);
     exit_hypervisor(HyperVisorExitCode::Failed);
     hlt_loop();
 }
 
 extern "x86-interrupt" fn lapic_timer_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
-    #[cfg(debug_assertions)]
-    eprintln!("*");
+    trace!("*");
     unsafe {
         if let Some(l) = LAPIC.lock().as_mut() {
             l.end_of_interrupt();
@@ -104,8 +100,7 @@ extern "x86-interrupt" fn lapic_timer_interrupt_handler(_stack_frame: &mut Inter
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
-    #[cfg(debug_assertions)]
-    eprintln!(".");
+    trace!(".");
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
@@ -118,7 +113,7 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: &mut Interrup
 
     let scancode: u8 = unsafe { port.read() };
 
-    eprintln!("Keyboard scancode {}", scancode);
+    debug!("Keyboard scancode {}", scancode);
     /*
         if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
             if let Some(key) = keyboard.process_keyevent(key_event) {
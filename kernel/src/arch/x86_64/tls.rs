@@ -0,0 +1,82 @@
+//! FS and GS base of the app
+//!
+//! The app sets its thread pointer with `arch_prctl()`. The bases are saved on
+//! syscall entry and restored before returning to the app, so the kernel is
+//! free to use FS and GS in between.
+
+extern "C" {
+    fn _rdfsbase() -> u64;
+    fn _wrfsbase(val: u64);
+    fn _rdgsbase() -> u64;
+    fn _wrgsbase(val: u64);
+}
+
+/// The FS and GS base of the app
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UserSegments {
+    pub fs_base: u64,
+    pub gs_base: u64,
+}
+
+static mut USER: UserSegments = UserSegments {
+    fs_base: 0,
+    gs_base: 0,
+};
+
+/// Save the FS and GS base of the app
+pub fn save() {
+    unsafe {
+        USER = UserSegments {
+            fs_base: _rdfsbase(),
+            gs_base: _rdgsbase(),
+        };
+    }
+}
+
+/// Load the saved FS and GS base of the app
+pub fn restore() {
+    unsafe {
+        _wrfsbase(USER.fs_base);
+        _wrgsbase(USER.gs_base);
+    }
+}
+
+/// The saved FS and GS base of the app
+pub fn user_segments() -> UserSegments {
+    unsafe { USER }
+}
+
+/// Set the FS base of the app, loaded with the next `restore()`
+pub fn set_fs_base(base: u64) {
+    unsafe { USER.fs_base = base };
+}
+
+/// Set the GS base of the app, loaded with the next `restore()`
+pub fn set_gs_base(base: u64) {
+    unsafe { USER.gs_base = base };
+}
+
+#[cfg(test)]
+#[test_case]
+fn test_tls_save_restore() {
+    use crate::{serial_print, serial_println};
+    serial_print!("test_tls_save_restore...");
+    let old = user_segments();
+
+    set_fs_base(0x1000);
+    set_gs_base(0x2000);
+    restore();
+    unsafe { USER = UserSegments::default() };
+    save();
+    assert_eq!(
+        user_segments(),
+        UserSegments {
+            fs_base: 0x1000,
+            gs_base: 0x2000
+        }
+    );
+
+    unsafe { USER = old };
+    restore();
+    serial_println!("[ok]");
+}
@@ -0,0 +1,251 @@
+//! Interest lists of `epoll`
+//!
+//! An epoll instance is a list of watched file descriptors, `epoll_wait()`
+//! checks all of them with one [`crate::poll::poll`]. A watch is dropped when
+//! its file descriptor is closed, not only with the last one of the
+//! description.
+//!
+//! `EPOLLET` is approximated: an event is reported once, until the app reads
+//! or writes the file descriptor again. `EPOLLONESHOT` disables the watch
+//! after its first report, until `EPOLL_CTL_MOD`.
+//!
+//! Errors are returned as positive `errno` values.
+
+use crate::fd;
+use crate::poll::{self, PollFd, POLLNVAL};
+use linux_errno::ErrNo;
+
+/// Maximum number of epoll instances at the same time
+pub const MAX_EPOLLS: usize = 8;
+
+/// Maximum number of watched file descriptors of an epoll instance
+pub const MAX_WATCHES: usize = 64;
+
+pub const EPOLLIN: u32 = 0x1;
+pub const EPOLLPRI: u32 = 0x2;
+pub const EPOLLOUT: u32 = 0x4;
+pub const EPOLLERR: u32 = 0x8;
+pub const EPOLLHUP: u32 = 0x10;
+pub const EPOLLRDHUP: u32 = 0x2000;
+pub const EPOLLEXCLUSIVE: u32 = 1 << 28;
+pub const EPOLLWAKEUP: u32 = 1 << 29;
+pub const EPOLLONESHOT: u32 = 1 << 30;
+pub const EPOLLET: u32 = 1 << 31;
+
+/// The event bits shared with `poll()`
+const POLL_EVENTS: u32 = 0xffff;
+
+pub const EPOLL_CTL_ADD: usize = 1;
+pub const EPOLL_CTL_DEL: usize = 2;
+pub const EPOLL_CTL_MOD: usize = 3;
+
+/// `struct epoll_event` of the app
+#[repr(C, packed)]
+#[derive(Clone, Copy, Default)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Watch {
+    fd: usize,
+    events: u32,
+    data: u64,
+    /// [`fd::io_count`] when `reported` was last reset
+    io: u64,
+    /// The events reported since, for `EPOLLET`
+    reported: u32,
+    /// Cleared by `EPOLLONESHOT`
+    enabled: bool,
+}
+
+type Epoll = [Option<Watch>; MAX_WATCHES];
+
+static mut EPOLLS: [Option<Epoll>; MAX_EPOLLS] = [None; MAX_EPOLLS];
+
+fn err(errno: ErrNo) -> i64 {
+    errno.into()
+}
+
+fn epoll(index: usize) -> Result<&'static mut Epoll, i64> {
+    unsafe { EPOLLS.get_mut(index).and_then(Option::as_mut) }.ok_or_else(|| err(ErrNo::EBADF))
+}
+
+/// Create an empty instance, returns its index
+pub fn create() -> Result<usize, i64> {
+    let index =
+        unsafe { EPOLLS.iter().position(Option::is_none) }.ok_or_else(|| err(ErrNo::ENFILE))?;
+    unsafe { EPOLLS[index].replace([None; MAX_WATCHES]) };
+    Ok(index)
+}
+
+/// Add, change or remove the watch of `fd`
+///
+/// `fd` has to be checked by the caller.
+pub fn ctl(index: usize, op: usize, fd: usize, event: EpollEvent) -> Result<(), i64> {
+    let epoll = epoll(index)?;
+    let pos = epoll
+        .iter()
+        .position(|watch| watch.is_some_and(|watch| watch.fd == fd));
+    let watch = Watch {
+        fd,
+        events: event.events,
+        data: event.data,
+        io: fd::io_count(fd)?,
+        reported: 0,
+        enabled: true,
+    };
+
+    match (op, pos) {
+        (EPOLL_CTL_ADD, Some(_)) => Err(err(ErrNo::EEXIST)),
+        (EPOLL_CTL_ADD, None) => {
+            let free = epoll
+                .iter()
+                .position(Option::is_none)
+                .ok_or_else(|| err(ErrNo::ENOSPC))?;
+            epoll[free] = Some(watch);
+            Ok(())
+        }
+        (EPOLL_CTL_MOD, Some(pos)) => {
+            epoll[pos] = Some(watch);
+            Ok(())
+        }
+        (EPOLL_CTL_DEL, Some(pos)) => {
+            epoll[pos] = None;
+            Ok(())
+        }
+        (EPOLL_CTL_MOD, None) | (EPOLL_CTL_DEL, None) => Err(err(ErrNo::ENOENT)),
+        _ => Err(err(ErrNo::EINVAL)),
+    }
+}
+
+/// Wait up to `timeout` milliseconds for events, returns their number
+pub fn wait(index: usize, events: &mut [EpollEvent], timeout: i32) -> Result<usize, i64> {
+    if events.is_empty() {
+        return Err(err(ErrNo::EINVAL));
+    }
+    let epoll = epoll(index)?;
+
+    let mut fds = [PollFd::default(); MAX_WATCHES];
+    let mut watch_index = [0usize; MAX_WATCHES];
+    let mut n = 0;
+    for (index, watch) in epoll.iter_mut().enumerate() {
+        let watch = match watch {
+            Some(watch) if watch.enabled => watch,
+            _ => continue,
+        };
+        if watch.events & EPOLLET != 0 {
+            let io = fd::io_count(watch.fd)?;
+            if io != watch.io {
+                watch.io = io;
+                watch.reported = 0;
+            }
+            // a hangup stays until the app notices it
+            if watch.reported & (EPOLLERR | EPOLLHUP) != 0 {
+                continue;
+            }
+        }
+        fds[n] = PollFd {
+            fd: watch.fd as _,
+            events: (watch.events & POLL_EVENTS & !watch.reported) as i16,
+            revents: 0,
+        };
+        watch_index[n] = index;
+        n += 1;
+    }
+
+    poll::poll(&mut fds[..n], timeout)?;
+
+    let mut count = 0;
+    for (p, index) in fds[..n].iter().zip(&watch_index[..n]) {
+        if count == events.len() {
+            break;
+        }
+        let watch = epoll[*index].as_mut().unwrap();
+        let revents = p.revents as u16 as u32 & !watch.reported;
+        if revents == 0 || p.revents & POLLNVAL != 0 {
+            continue;
+        }
+
+        events[count] = EpollEvent {
+            events: revents,
+            data: watch.data,
+        };
+        count += 1;
+
+        if watch.events & EPOLLET != 0 {
+            watch.reported |= revents;
+        }
+        if watch.events & EPOLLONESHOT != 0 {
+            watch.enabled = false;
+        }
+    }
+    Ok(count)
+}
+
+/// Drop the watches of the closed file descriptor `fd`
+pub fn forget(fd: usize) {
+    for epoll in unsafe { EPOLLS.iter_mut() }.flatten() {
+        for watch in epoll.iter_mut() {
+            if watch.is_some_and(|watch| watch.fd == fd) {
+                *watch = None;
+            }
+        }
+    }
+}
+
+pub fn close(index: usize) -> Result<(), i64> {
+    epoll(index)?;
+    unsafe { EPOLLS[index].take() };
+    Ok(())
+}
+
+#[cfg(test)]
+#[test_case]
+fn test_epoll() {
+    use crate::{serial_print, serial_println};
+    serial_print!("test_epoll...");
+
+    let (rfd, wfd) = fd::pipe2(0).unwrap();
+    let index = create().unwrap();
+    let event = |events, data| EpollEvent { events, data };
+    let mut out = [EpollEvent::default(); 4];
+
+    ctl(index, EPOLL_CTL_ADD, rfd, event(EPOLLIN | EPOLLET, 7)).unwrap();
+    ctl(index, EPOLL_CTL_ADD, wfd, event(EPOLLOUT | EPOLLONESHOT, 8)).unwrap();
+    assert_eq!(
+        ctl(index, EPOLL_CTL_ADD, rfd, event(EPOLLIN, 0)),
+        Err(err(ErrNo::EEXIST))
+    );
+    assert_eq!(wait(index, &mut out, 0), Ok(1));
+    assert_eq!({ out[0].data }, 8);
+    // one shot
+    assert_eq!(wait(index, &mut out, 0), Ok(0));
+
+    // edge triggered, until the next read
+    fd::write(wfd, b"x").unwrap();
+    assert_eq!(wait(index, &mut out, 0), Ok(1));
+    assert_eq!(({ out[0].events }, { out[0].data }), (EPOLLIN, 7));
+    assert_eq!(wait(index, &mut out, 0), Ok(0));
+    fd::write(wfd, b"y").unwrap();
+    let mut buf = [0u8; 1];
+    fd::read(rfd, &mut buf).unwrap();
+    assert_eq!(wait(index, &mut out, 0), Ok(1));
+
+    ctl(index, EPOLL_CTL_MOD, rfd, event(EPOLLIN, 9)).unwrap();
+    ctl(index, EPOLL_CTL_DEL, wfd, event(0, 0)).unwrap();
+    assert_eq!(
+        ctl(index, EPOLL_CTL_DEL, wfd, event(0, 0)),
+        Err(err(ErrNo::ENOENT))
+    );
+    assert_eq!(wait(index, &mut out, 0), Ok(1));
+    assert_eq!(wait(index, &mut out, 0), Ok(1));
+
+    // closing the file descriptor drops its watch
+    fd::close(rfd).unwrap();
+    assert_eq!(wait(index, &mut out, 0), Ok(0));
+    fd::close(wfd).unwrap();
+    close(index).unwrap();
+    serial_println!("[ok]");
+}
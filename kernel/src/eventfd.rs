@@ -0,0 +1,126 @@
+//! Event counters of `eventfd()`
+//!
+//! An eventfd is a 64-bit counter in the kernel. A read returns and resets
+//! it, or decrements it by one in semaphore mode, a write adds to it. There is
+//! only one thread, so a read of a zero counter or a write overflowing it fail
+//! with `EAGAIN` instead of blocking, like a pipe.
+//!
+//! Errors are returned as positive `errno` values.
+
+use crate::poll::{POLLIN, POLLOUT};
+use linux_errno::ErrNo;
+
+/// Maximum number of eventfds at the same time
+pub const MAX_EVENTFDS: usize = 32;
+
+/// The largest value of a counter
+const MAX_COUNT: u64 = u64::MAX - 1;
+
+#[derive(Clone, Copy)]
+struct EventFd {
+    count: u64,
+    semaphore: bool,
+}
+
+static mut EVENTFDS: [Option<EventFd>; MAX_EVENTFDS] = [None; MAX_EVENTFDS];
+
+fn err(errno: ErrNo) -> i64 {
+    errno.into()
+}
+
+fn eventfd(index: usize) -> Result<&'static mut EventFd, i64> {
+    unsafe { EVENTFDS.get_mut(index).and_then(Option::as_mut) }.ok_or_else(|| err(ErrNo::EBADF))
+}
+
+/// Create a counter starting at `count`, returns its index
+pub fn create(count: u64, semaphore: bool) -> Result<usize, i64> {
+    let index =
+        unsafe { EVENTFDS.iter().position(Option::is_none) }.ok_or_else(|| err(ErrNo::ENFILE))?;
+    unsafe { EVENTFDS[index].replace(EventFd { count, semaphore }) };
+    Ok(index)
+}
+
+/// Read the counter as 8 bytes
+pub fn read(index: usize, buf: &mut [u8]) -> Result<usize, i64> {
+    let eventfd = eventfd(index)?;
+    if buf.len() < 8 {
+        return Err(err(ErrNo::EINVAL));
+    }
+    if eventfd.count == 0 {
+        return Err(err(ErrNo::EAGAIN));
+    }
+
+    let value = if eventfd.semaphore { 1 } else { eventfd.count };
+    eventfd.count -= value;
+    buf[..8].copy_from_slice(&value.to_ne_bytes());
+    Ok(8)
+}
+
+/// Add the 8 bytes of `buf` to the counter
+pub fn write(index: usize, buf: &[u8]) -> Result<usize, i64> {
+    let eventfd = eventfd(index)?;
+    if buf.len() < 8 {
+        return Err(err(ErrNo::EINVAL));
+    }
+
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&buf[..8]);
+    let value = u64::from_ne_bytes(value);
+    if value == u64::MAX {
+        return Err(err(ErrNo::EINVAL));
+    }
+    if value > MAX_COUNT - eventfd.count {
+        return Err(err(ErrNo::EAGAIN));
+    }
+    eventfd.count += value;
+    Ok(8)
+}
+
+/// The ready `poll()` events
+pub fn poll(index: usize) -> Result<i16, i64> {
+    let eventfd = eventfd(index)?;
+    let mut events = 0;
+    if eventfd.count > 0 {
+        events |= POLLIN;
+    }
+    if eventfd.count < MAX_COUNT {
+        events |= POLLOUT;
+    }
+    Ok(events)
+}
+
+pub fn close(index: usize) -> Result<(), i64> {
+    eventfd(index)?;
+    unsafe { EVENTFDS[index].take() };
+    Ok(())
+}
+
+#[cfg(test)]
+#[test_case]
+fn test_eventfd() {
+    use crate::{serial_print, serial_println};
+    serial_print!("test_eventfd...");
+
+    let index = create(0, false).unwrap();
+    let mut buf = [0u8; 8];
+    assert_eq!(read(index, &mut buf), Err(err(ErrNo::EAGAIN)));
+    assert_eq!(poll(index), Ok(POLLOUT));
+    assert_eq!(write(index, &3u64.to_ne_bytes()), Ok(8));
+    assert_eq!(write(index, &4u64.to_ne_bytes()), Ok(8));
+    assert_eq!(poll(index), Ok(POLLIN | POLLOUT));
+    assert_eq!(read(index, &mut buf), Ok(8));
+    assert_eq!(u64::from_ne_bytes(buf), 7);
+    assert_eq!(write(index, &MAX_COUNT.to_ne_bytes()), Ok(8));
+    assert_eq!(write(index, &1u64.to_ne_bytes()), Err(err(ErrNo::EAGAIN)));
+    assert_eq!(write(index, &[1u8; 4]), Err(err(ErrNo::EINVAL)));
+    close(index).unwrap();
+
+    let index = create(2, true).unwrap();
+    assert_eq!(read(index, &mut buf), Ok(8));
+    assert_eq!(u64::from_ne_bytes(buf), 1);
+    assert_eq!(read(index, &mut buf), Ok(8));
+    assert_eq!(read(index, &mut buf), Err(err(ErrNo::EAGAIN)));
+    close(index).unwrap();
+    assert_eq!(close(index), Err(err(ErrNo::EBADF)));
+    serial_println!("[ok]");
+}
@@ -0,0 +1,980 @@
+//! The file descriptor table of the app
+//!
+//! A file descriptor refers to an open file description, which is shared by
+//! the duplicates made with `dup()` and `fcntl(F_DUPFD)`. The description
+//! holds an [`FdObject`], which serves the file: the console, the secret, a
+//! host file, an initrd or tmpfs file, a pipe end, a host socket, an eventfd
+//! or an epoll instance. The filesystem modules keep their own handles for
+//! open files, the file position lives there.
+//!
+//! There is no `exec`, so `FD_CLOEXEC` is only kept for `fcntl(F_GETFD)`.
+//!
+//! Errors are returned as positive `errno` values.
+
+use crate::arch::serial::{wait_writable, write_bytes};
+use crate::poll::{POLLERR, POLLHUP, POLLIN, POLLOUT, POLLRDNORM, POLLWRNORM};
+use crate::syscall::{stat_console, stat_host_file, stat_initrd_file, stat_tmpfs_file, Stat};
+use crate::{epoll, eventfd, hostfs, initrd, pipe, secret, socket, tmpfs};
+use linux_errno::ErrNo;
+use vmsyscall::{PollFd, PollKind, SockAddr};
+
+/// Maximum number of file descriptors
+pub const MAX_FDS: usize = 256;
+
+/// Maximum number of open file descriptions
+pub const MAX_FILES: usize = 128;
+
+pub const O_ACCMODE: usize = 0o3;
+pub const O_RDONLY: usize = 0o0;
+pub const O_WRONLY: usize = 0o1;
+pub const O_RDWR: usize = 0o2;
+pub const O_APPEND: usize = 0o2000;
+pub const O_NONBLOCK: usize = 0o4000;
+pub const O_CLOEXEC: usize = 0o2_000_000;
+
+/// The flags `fcntl(F_SETFL)` can change
+const STATUS_FLAGS: usize = O_APPEND | O_NONBLOCK;
+
+pub const F_DUPFD: usize = 0;
+pub const F_GETFD: usize = 1;
+pub const F_SETFD: usize = 2;
+pub const F_GETFL: usize = 3;
+pub const F_SETFL: usize = 4;
+pub const F_DUPFD_CLOEXEC: usize = 1030;
+pub const FD_CLOEXEC: usize = 1;
+
+pub const CLOSE_RANGE_UNSHARE: usize = 1 << 1;
+pub const CLOSE_RANGE_CLOEXEC: usize = 1 << 2;
+
+pub const EFD_SEMAPHORE: usize = 1;
+
+fn err(errno: ErrNo) -> i64 {
+    errno.into()
+}
+
+/// An open file behind a file descriptor
+///
+/// The access mode is checked before `read` and `write` are called.
+pub trait FdObject {
+    fn read(&mut self, _buf: &mut [u8]) -> Result<usize, i64> {
+        Err(err(ErrNo::EINVAL))
+    }
+
+    fn write(&mut self, _buf: &[u8]) -> Result<usize, i64> {
+        Err(err(ErrNo::EINVAL))
+    }
+
+    fn pread(&mut self, _buf: &mut [u8], _offset: u64) -> Result<usize, i64> {
+        Err(err(ErrNo::ESPIPE))
+    }
+
+    fn pwrite(&mut self, _buf: &[u8], _offset: u64) -> Result<usize, i64> {
+        Err(err(ErrNo::ESPIPE))
+    }
+
+    fn lseek(&mut self, _offset: i64, _whence: usize) -> Result<usize, i64> {
+        Err(err(ErrNo::ESPIPE))
+    }
+
+    fn stat(&mut self, stat: &mut Stat) -> Result<usize, i64>;
+
+    fn getdents64(&mut self, _buf: &mut [u8]) -> Result<usize, i64> {
+        Err(err(ErrNo::ENOTDIR))
+    }
+
+    fn ftruncate(&mut self, _len: u64) -> Result<(), i64> {
+        Err(err(ErrNo::EINVAL))
+    }
+
+    /// The status flags were changed with `fcntl(F_SETFL)`
+    fn set_flags(&mut self, _flags: usize) -> Result<(), i64> {
+        Ok(())
+    }
+
+    fn is_terminal(&self) -> bool {
+        false
+    }
+
+    /// The ready `poll()` events and a host file to ask the hypervisor about
+    ///
+    /// Files are always ready for reading and writing.
+    fn poll(&mut self, _events: i16) -> Result<(i16, Option<PollFd>), i64> {
+        Ok((POLLIN | POLLOUT, None))
+    }
+
+    /// The last file descriptor of the description was closed
+    fn close(&mut self) -> Result<(), i64> {
+        Ok(())
+    }
+}
+
+/// The stdin, stdout or stderr of the host, stdin is always at its end
+#[derive(Clone, Copy)]
+pub struct Console {
+    /// The host file descriptor
+    pub fd: u32,
+    /// `O_NONBLOCK` is set
+    pub nonblock: bool,
+}
+
+impl FdObject for Console {
+    fn read(&mut self, _buf: &mut [u8]) -> Result<usize, i64> {
+        Ok(0)
+    }
+
+    /// The bytes are passed on unchanged, in chunks of the syscall page
+    ///
+    /// A full host pipe is waited for with `VmSyscall::Poll`, unless
+    /// `O_NONBLOCK` is set. After an error the bytes written so far are
+    /// returned.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, i64> {
+        let mut done = 0;
+        while done < buf.len() {
+            match write_bytes(self.fd, &buf[done..]) {
+                Ok(0) => break,
+                Ok(len) => done += len,
+                Err(errno) if errno == err(ErrNo::EAGAIN) && !self.nonblock => {
+                    match wait_writable(self.fd) {
+                        Ok(()) => {}
+                        Err(errno) if done == 0 => return Err(errno),
+                        Err(_) => break,
+                    }
+                }
+                Err(errno) if done == 0 => return Err(errno),
+                Err(_) => break,
+            }
+        }
+        Ok(done)
+    }
+
+    fn stat(&mut self, stat: &mut Stat) -> Result<usize, i64> {
+        stat_console(stat)
+    }
+
+    fn set_flags(&mut self, flags: usize) -> Result<(), i64> {
+        self.nonblock = flags & O_NONBLOCK != 0;
+        Ok(())
+    }
+
+    fn is_terminal(&self) -> bool {
+        true
+    }
+
+    /// Reading is always at the end, the host knows if writing would block
+    fn poll(&mut self, events: i16) -> Result<(i16, Option<PollFd>), i64> {
+        let host = if self.fd != 0 && events & (POLLOUT | POLLWRNORM) != 0 {
+            Some(PollFd {
+                kind: PollKind::Stdio,
+                fd: self.fd,
+                events: events & (POLLOUT | POLLWRNORM),
+            })
+        } else {
+            None
+        };
+        Ok((POLLIN, host))
+    }
+}
+
+/// The file at [`secret::SECRET_PATH`]
+#[derive(Clone, Copy)]
+pub struct SecretFile;
+
+impl FdObject for SecretFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, i64> {
+        secret::read_file(buf).ok_or_else(|| err(ErrNo::EBADF))
+    }
+
+    fn stat(&mut self, stat: &mut Stat) -> Result<usize, i64> {
+        const S_IFREG: u32 = 0o100_000;
+
+        let len = secret::get().map_or(0, <[u8]>::len);
+        *stat = unsafe { core::mem::zeroed() };
+        stat.st_mode = S_IFREG | 0o400;
+        stat.st_nlink = 1;
+        stat.st_size = len as _;
+        stat.st_blksize = 4096;
+        Ok(0)
+    }
+
+    fn close(&mut self) -> Result<(), i64> {
+        secret::close_file();
+        Ok(())
+    }
+}
+
+/// A [`hostfs`] handle
+#[derive(Clone, Copy)]
+pub struct HostFile(pub usize);
+
+impl FdObject for HostFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, i64> {
+        hostfs::read(self.0, buf)
+    }
+
+    fn pread(&mut self, buf: &mut [u8], offset: u64) -> Result<usize, i64> {
+        hostfs::pread(self.0, buf, offset)
+    }
+
+    fn lseek(&mut self, offset: i64, whence: usize) -> Result<usize, i64> {
+        hostfs::lseek(self.0, offset, whence)
+    }
+
+    fn stat(&mut self, stat: &mut Stat) -> Result<usize, i64> {
+        stat_host_file(self.0, stat)
+    }
+
+    fn close(&mut self) -> Result<(), i64> {
+        hostfs::close(self.0)
+    }
+}
+
+/// An [`initrd`] handle
+#[derive(Clone, Copy)]
+pub struct InitrdFile(pub usize);
+
+impl FdObject for InitrdFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, i64> {
+        initrd::read(self.0, buf)
+    }
+
+    fn pread(&mut self, buf: &mut [u8], offset: u64) -> Result<usize, i64> {
+        initrd::pread(self.0, buf, offset)
+    }
+
+    fn lseek(&mut self, offset: i64, whence: usize) -> Result<usize, i64> {
+        initrd::lseek(self.0, offset, whence)
+    }
+
+    fn stat(&mut self, stat: &mut Stat) -> Result<usize, i64> {
+        stat_initrd_file(initrd::stat(self.0)?, stat)
+    }
+
+    fn getdents64(&mut self, buf: &mut [u8]) -> Result<usize, i64> {
+        initrd::getdents64(self.0, buf)
+    }
+
+    fn close(&mut self) -> Result<(), i64> {
+        initrd::close(self.0)
+    }
+}
+
+/// A [`tmpfs`] handle
+#[derive(Clone, Copy)]
+pub struct TmpfsFile(pub usize);
+
+impl FdObject for TmpfsFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, i64> {
+        tmpfs::read(self.0, buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, i64> {
+        tmpfs::write(self.0, buf)
+    }
+
+    fn pread(&mut self, buf: &mut [u8], offset: u64) -> Result<usize, i64> {
+        tmpfs::pread(self.0, buf, offset)
+    }
+
+    fn pwrite(&mut self, buf: &[u8], offset: u64) -> Result<usize, i64> {
+        tmpfs::pwrite(self.0, buf, offset)
+    }
+
+    fn lseek(&mut self, offset: i64, whence: usize) -> Result<usize, i64> {
+        tmpfs::lseek(self.0, offset, whence)
+    }
+
+    fn stat(&mut self, stat: &mut Stat) -> Result<usize, i64> {
+        stat_tmpfs_file(tmpfs::stat(self.0)?, stat)
+    }
+
+    fn getdents64(&mut self, buf: &mut [u8]) -> Result<usize, i64> {
+        tmpfs::getdents64(self.0, buf)
+    }
+
+    fn ftruncate(&mut self, len: u64) -> Result<(), i64> {
+        tmpfs::ftruncate(self.0, len)
+    }
+
+    fn set_flags(&mut self, flags: usize) -> Result<(), i64> {
+        tmpfs::set_append(self.0, flags & O_APPEND != 0)
+    }
+
+    fn close(&mut self) -> Result<(), i64> {
+        tmpfs::close(self.0)
+    }
+}
+
+/// One end of a [`pipe`]
+#[derive(Clone, Copy)]
+pub struct PipeEnd {
+    pub pipe: usize,
+    pub write_end: bool,
+}
+
+impl FdObject for PipeEnd {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, i64> {
+        pipe::read(self.pipe, buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, i64> {
+        pipe::write(self.pipe, buf)
+    }
+
+    fn stat(&mut self, p: &mut Stat) -> Result<usize, i64> {
+        const S_IFIFO: u32 = 0o010_000;
+
+        *p = unsafe { core::mem::zeroed() };
+        p.st_ino = self.pipe as u64 + 1;
+        p.st_mode = S_IFIFO | 0o600;
+        p.st_nlink = 1;
+        p.st_size = pipe::available(self.pipe)? as _;
+        p.st_blksize = 4096;
+        Ok(0)
+    }
+
+    fn poll(&mut self, _events: i16) -> Result<(i16, Option<PollFd>), i64> {
+        Ok((pipe::poll(self.pipe, self.write_end)?, None))
+    }
+
+    fn close(&mut self) -> Result<(), i64> {
+        pipe::close(self.pipe, self.write_end)
+    }
+}
+
+/// A [`socket`] handle of the hypervisor
+#[derive(Clone, Copy)]
+pub struct Socket {
+    pub handle: u32,
+    /// A `SOCK_STREAM` socket, without message boundaries
+    pub stream: bool,
+    /// `O_NONBLOCK` is set
+    pub nonblock: bool,
+}
+
+impl FdObject for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, i64> {
+        let (len, _) = socket::recv(self.handle, buf, 0, self.nonblock)?;
+        Ok(len.min(buf.len()))
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, i64> {
+        socket::send(self.handle, buf, 0, None, self.stream, self.nonblock)
+    }
+
+    fn stat(&mut self, p: &mut Stat) -> Result<usize, i64> {
+        const S_IFSOCK: u32 = 0o140_000;
+
+        *p = unsafe { core::mem::zeroed() };
+        p.st_ino = self.handle as u64 + 1;
+        p.st_mode = S_IFSOCK | 0o777;
+        p.st_nlink = 1;
+        p.st_blksize = 4096;
+        Ok(0)
+    }
+
+    fn set_flags(&mut self, flags: usize) -> Result<(), i64> {
+        self.nonblock = flags & O_NONBLOCK != 0;
+        Ok(())
+    }
+
+    fn poll(&mut self, events: i16) -> Result<(i16, Option<PollFd>), i64> {
+        let host = PollFd {
+            kind: PollKind::Socket,
+            fd: self.handle,
+            events,
+        };
+        Ok((0, Some(host)))
+    }
+
+    fn close(&mut self) -> Result<(), i64> {
+        socket::close(self.handle)
+    }
+}
+
+/// An [`eventfd`] counter
+#[derive(Clone, Copy)]
+pub struct EventFd(pub usize);
+
+impl FdObject for EventFd {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, i64> {
+        eventfd::read(self.0, buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, i64> {
+        eventfd::write(self.0, buf)
+    }
+
+    fn stat(&mut self, p: &mut Stat) -> Result<usize, i64> {
+        *p = unsafe { core::mem::zeroed() };
+        p.st_ino = self.0 as u64 + 1;
+        p.st_mode = 0o600;
+        p.st_nlink = 1;
+        p.st_blksize = 4096;
+        Ok(0)
+    }
+
+    fn poll(&mut self, _events: i16) -> Result<(i16, Option<PollFd>), i64> {
+        Ok((eventfd::poll(self.0)?, None))
+    }
+
+    fn close(&mut self) -> Result<(), i64> {
+        eventfd::close(self.0)
+    }
+}
+
+/// An [`epoll`] instance
+#[derive(Clone, Copy)]
+pub struct Epoll(pub usize);
+
+impl FdObject for Epoll {
+    fn stat(&mut self, p: &mut Stat) -> Result<usize, i64> {
+        *p = unsafe { core::mem::zeroed() };
+        p.st_ino = self.0 as u64 + 1;
+        p.st_mode = 0o600;
+        p.st_nlink = 1;
+        p.st_blksize = 4096;
+        Ok(0)
+    }
+
+    /// Nested epoll instances are not supported, it is never ready
+    fn poll(&mut self, _events: i16) -> Result<(i16, Option<PollFd>), i64> {
+        Ok((0, None))
+    }
+
+    fn close(&mut self) -> Result<(), i64> {
+        epoll::close(self.0)
+    }
+}
+
+/// The objects an open file description can hold
+#[derive(Clone, Copy)]
+pub enum Object {
+    Console(Console),
+    Secret(SecretFile),
+    Host(HostFile),
+    Initrd(InitrdFile),
+    Tmpfs(TmpfsFile),
+    Pipe(PipeEnd),
+    Socket(Socket),
+    EventFd(EventFd),
+    Epoll(Epoll),
+}
+
+impl Object {
+    fn get(&mut self) -> &mut dyn FdObject {
+        match self {
+            Object::Console(o) => o,
+            Object::Secret(o) => o,
+            Object::Host(o) => o,
+            Object::Initrd(o) => o,
+            Object::Tmpfs(o) => o,
+            Object::Pipe(o) => o,
+            Object::Socket(o) => o,
+            Object::EventFd(o) => o,
+            Object::Epoll(o) => o,
+        }
+    }
+}
+
+/// An open file description
+#[derive(Clone, Copy)]
+struct File {
+    object: Object,
+    /// Access mode and status flags
+    flags: usize,
+    /// Number of file descriptors referring to it
+    refs: usize,
+    /// Number of reads and writes, for `EPOLLET`
+    io: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Fd {
+    file: usize,
+    cloexec: bool,
+}
+
+static mut FILES: [Option<File>; MAX_FILES] = [None; MAX_FILES];
+static mut FDS: [Option<Fd>; MAX_FDS] = [None; MAX_FDS];
+
+fn entry(fd: usize) -> Result<&'static mut Fd, i64> {
+    unsafe { FDS.get_mut(fd).and_then(Option::as_mut) }.ok_or_else(|| err(ErrNo::EBADF))
+}
+
+fn file(fd: usize) -> Result<&'static mut File, i64> {
+    let index = entry(fd)?.file;
+    Ok(unsafe { FILES[index].as_mut().unwrap() })
+}
+
+/// The lowest free file descriptor from `min`
+fn free_fd(min: usize) -> Result<usize, i64> {
+    unsafe { FDS.iter().skip(min).position(Option::is_none) }
+        .map(|pos| min + pos)
+        .ok_or_else(|| err(ErrNo::EMFILE))
+}
+
+/// Add a file descriptor for `object`, closes `object` on failure
+fn install(mut object: Object, flags: usize) -> Result<usize, i64> {
+    let slots = free_fd(0).and_then(|fd| {
+        unsafe { FILES.iter().position(Option::is_none) }
+            .map(|file| (fd, file))
+            .ok_or_else(|| err(ErrNo::ENFILE))
+    });
+
+    let (fd, file) = match slots {
+        Ok(slots) => slots,
+        Err(errno) => {
+            object.get().close().ok();
+            return Err(errno);
+        }
+    };
+
+    unsafe {
+        FILES[file].replace(File {
+            object,
+            flags: flags & (O_ACCMODE | STATUS_FLAGS),
+            refs: 1,
+            io: 0,
+        });
+        FDS[fd].replace(Fd {
+            file,
+            cloexec: flags & O_CLOEXEC != 0,
+        });
+    }
+    Ok(fd)
+}
+
+/// Drop the file descriptor `fd`, the description is closed with its last one
+fn release(fd: usize) -> Result<(), i64> {
+    let index = entry(fd)?.file;
+    unsafe { FDS[fd].take() };
+    epoll::forget(fd);
+
+    let file = unsafe { FILES[index].as_mut().unwrap() };
+    file.refs -= 1;
+    if file.refs == 0 {
+        let mut object = file.object;
+        unsafe { FILES[index].take() };
+        return object.get().close();
+    }
+    Ok(())
+}
+
+/// Make `new` refer to the description of `old`
+fn duplicate(old: usize, new: usize, cloexec: bool) -> Result<usize, i64> {
+    let file = entry(old)?.file;
+    if entry(new).is_ok() {
+        // Errors of the implicit close are not reported
+        release(new).ok();
+    }
+
+    unsafe {
+        FILES[file].as_mut().unwrap().refs += 1;
+        FDS[new].replace(Fd { file, cloexec });
+    }
+    Ok(new)
+}
+
+/// Set up stdin, stdout and stderr on the ones of the host
+pub fn init() {
+    unsafe {
+        FDS.iter_mut().for_each(|fd| *fd = None);
+        FILES.iter_mut().for_each(|file| *file = None);
+    }
+
+    for fd in 0..3 {
+        let console = Console {
+            fd,
+            nonblock: false,
+        };
+        install(Object::Console(console), O_RDWR).unwrap();
+    }
+}
+
+/// Is `fd` an open file descriptor
+pub fn is_open(fd: usize) -> bool {
+    entry(fd).is_ok()
+}
+
+/// Is `fd` a terminal
+pub fn is_terminal(fd: usize) -> bool {
+    file(fd).is_some_and(|file| file.object.get().is_terminal())
+}
+
+/// Open `pathname`, from the tmpfs, the initrd or else from the host
+pub fn open(pathname: &str, flags: usize, mode: u32) -> Result<usize, i64> {
+    // Fail before opening anything
+    free_fd(0)?;
+
+    let object = if tmpfs::is_mounted(pathname) {
+        Object::Tmpfs(TmpfsFile(tmpfs::open(pathname, flags, mode)?))
+    } else if pathname.eq(secret::SECRET_PATH) {
+        if flags & O_ACCMODE != O_RDONLY {
+            // the secret is read-only
+            return Err(err(ErrNo::EACCES));
+        }
+        if !secret::open_file() {
+            return Err(err(ErrNo::ENOENT));
+        }
+        Object::Secret(SecretFile)
+    } else {
+        // The initrd hides host files of the same name
+        match initrd::open(pathname, flags) {
+            Err(errno) if errno == err(ErrNo::ENOENT) => {
+                Object::Host(HostFile(hostfs::open(pathname, flags)?))
+            }
+            handle => Object::Initrd(InitrdFile(handle?)),
+        }
+    };
+
+    install(object, flags)
+}
+
+/// Create a pipe, returns the file descriptors of the read and the write end
+pub fn pipe2(flags: usize) -> Result<(usize, usize), i64> {
+    if flags & !(O_CLOEXEC | O_NONBLOCK) != 0 {
+        return Err(err(ErrNo::EINVAL));
+    }
+
+    let index = pipe::create()?;
+    let read_end = PipeEnd {
+        pipe: index,
+        write_end: false,
+    };
+    let write_end = PipeEnd {
+        pipe: index,
+        write_end: true,
+    };
+
+    let rfd = match install(Object::Pipe(read_end), O_RDONLY | flags) {
+        Ok(rfd) => rfd,
+        Err(errno) => {
+            pipe::close(index, true).ok();
+            return Err(errno);
+        }
+    };
+    match install(Object::Pipe(write_end), O_WRONLY | flags) {
+        Ok(wfd) => Ok((rfd, wfd)),
+        Err(errno) => {
+            release(rfd).ok();
+            Err(errno)
+        }
+    }
+}
+
+/// The flags of `socket()` and `accept4()` besides the type
+const SOCKET_FLAGS: usize = socket::SOCK_NONBLOCK | socket::SOCK_CLOEXEC;
+
+/// A description for the socket `handle`, `SOCK_NONBLOCK` and `SOCK_CLOEXEC`
+/// are `O_NONBLOCK` and `O_CLOEXEC`
+fn install_socket(handle: u32, ty: usize, flags: usize) -> Result<usize, i64> {
+    let object = Socket {
+        handle,
+        stream: ty & socket::SOCK_TYPE_MASK == socket::SOCK_STREAM,
+        nonblock: flags & socket::SOCK_NONBLOCK != 0,
+    };
+    install(Object::Socket(object), O_RDWR | (flags & SOCKET_FLAGS))
+}
+
+/// Create a socket of the host
+pub fn socket(domain: usize, ty: usize, protocol: usize) -> Result<usize, i64> {
+    if ty & !(socket::SOCK_TYPE_MASK | SOCKET_FLAGS) != 0 {
+        return Err(err(ErrNo::EINVAL));
+    }
+    free_fd(0)?;
+    let handle = socket::socket(domain, ty, protocol)?;
+    install_socket(handle, ty, ty)
+}
+
+/// Create a pair of connected sockets
+pub fn socketpair(domain: usize, ty: usize, protocol: usize) -> Result<(usize, usize), i64> {
+    if ty & !(socket::SOCK_TYPE_MASK | SOCKET_FLAGS) != 0 {
+        return Err(err(ErrNo::EINVAL));
+    }
+    free_fd(0)?;
+    let (first, second) = socket::socketpair(domain, ty, protocol)?;
+
+    let fd = match install_socket(first, ty, ty) {
+        Ok(fd) => fd,
+        Err(errno) => {
+            socket::close(second).ok();
+            return Err(errno);
+        }
+    };
+    match install_socket(second, ty, ty) {
+        Ok(other) => Ok((fd, other)),
+        Err(errno) => {
+            release(fd).ok();
+            Err(errno)
+        }
+    }
+}
+
+/// The socket of `fd`, `ENOTSOCK` for other files
+///
+/// Counts as a read or write of `fd`, for `EPOLLET`.
+pub fn socket_of(fd: usize) -> Result<Socket, i64> {
+    let file = file(fd)?;
+    match file.object {
+        Object::Socket(socket) => {
+            file.io += 1;
+            Ok(socket)
+        }
+        _ => Err(err(ErrNo::ENOTSOCK)),
+    }
+}
+
+/// Accept a connection on the listening socket `fd`
+///
+/// Returns the new file descriptor and the address of the peer.
+pub fn accept4(fd: usize, flags: usize) -> Result<(usize, SockAddr), i64> {
+    if flags & !SOCKET_FLAGS != 0 {
+        return Err(err(ErrNo::EINVAL));
+    }
+    let listener = socket_of(fd)?;
+    free_fd(0)?;
+    let (handle, addr) = socket::accept(listener.handle, listener.nonblock)?;
+    let ty = if listener.stream {
+        socket::SOCK_STREAM
+    } else {
+        socket::SOCK_SEQPACKET
+    };
+    Ok((install_socket(handle, ty, flags)?, addr))
+}
+
+/// Create an eventfd counter starting at `initval`
+pub fn eventfd(initval: usize, flags: usize) -> Result<usize, i64> {
+    if flags & !(EFD_SEMAPHORE | O_NONBLOCK | O_CLOEXEC) != 0 {
+        return Err(err(ErrNo::EINVAL));
+    }
+    free_fd(0)?;
+    let index = eventfd::create(initval as _, flags & EFD_SEMAPHORE != 0)?;
+    install(Object::EventFd(EventFd(index)), O_RDWR | flags)
+}
+
+/// Create an epoll instance
+pub fn epoll_create(flags: usize) -> Result<usize, i64> {
+    if flags & !O_CLOEXEC != 0 {
+        return Err(err(ErrNo::EINVAL));
+    }
+    free_fd(0)?;
+    let index = epoll::create()?;
+    install(Object::Epoll(Epoll(index)), O_RDWR | flags)
+}
+
+/// The epoll instance of `fd`, `EINVAL` for other files
+pub fn epoll_of(fd: usize) -> Result<usize, i64> {
+    match file(fd)?.object {
+        Object::Epoll(Epoll(index)) => Ok(index),
+        _ => Err(err(ErrNo::EINVAL)),
+    }
+}
+
+/// Change the watch of `fd` in the epoll instance `epfd`
+///
+/// Files are always ready, they can not be watched, like on Linux.
+pub fn epoll_ctl(epfd: usize, op: usize, fd: usize, event: epoll::EpollEvent) -> Result<(), i64> {
+    let index = epoll_of(epfd)?;
+    match file(fd)?.object {
+        Object::Secret(_) | Object::Host(_) | Object::Initrd(_) | Object::Tmpfs(_) => {
+            Err(err(ErrNo::EPERM))
+        }
+        Object::Epoll(_) => Err(err(ErrNo::EINVAL)),
+        _ => epoll::ctl(index, op, fd, event),
+    }
+}
+
+/// The ready `poll()` events of `fd` and a host file to ask the hypervisor
+/// about
+///
+/// Only `events`, `POLLERR` and `POLLHUP` are returned.
+pub fn poll(fd: usize, events: i16) -> Result<(i16, Option<PollFd>), i64> {
+    let (mut ready, host) = file(fd)?.object.get().poll(events)?;
+    if ready & POLLIN != 0 {
+        ready |= POLLRDNORM;
+    }
+    if ready & POLLOUT != 0 {
+        ready |= POLLWRNORM;
+    }
+    Ok((ready & (events | POLLERR | POLLHUP), host))
+}
+
+/// Number of reads and writes of the description of `fd`
+pub fn io_count(fd: usize) -> Result<u64, i64> {
+    Ok(file(fd)?.io)
+}
+
+pub fn read(fd: usize, buf: &mut [u8]) -> Result<usize, i64> {
+    let file = file(fd)?;
+    if file.flags & O_ACCMODE == O_WRONLY {
+        return Err(err(ErrNo::EBADF));
+    }
+    file.io += 1;
+    file.object.get().read(buf)
+}
+
+pub fn write(fd: usize, buf: &[u8]) -> Result<usize, i64> {
+    let file = file(fd)?;
+    if file.flags & O_ACCMODE == O_RDONLY {
+        return Err(err(ErrNo::EBADF));
+    }
+    file.io += 1;
+    file.object.get().write(buf)
+}
+
+pub fn pread(fd: usize, buf: &mut [u8], offset: u64) -> Result<usize, i64> {
+    let file = file(fd)?;
+    if file.flags & O_ACCMODE == O_WRONLY {
+        return Err(err(ErrNo::EBADF));
+    }
+    file.io += 1;
+    file.object.get().pread(buf, offset)
+}
+
+pub fn pwrite(fd: usize, buf: &[u8], offset: u64) -> Result<usize, i64> {
+    let file = file(fd)?;
+    if file.flags & O_ACCMODE == O_RDONLY {
+        return Err(err(ErrNo::EBADF));
+    }
+    file.io += 1;
+    file.object.get().pwrite(buf, offset)
+}
+
+pub fn lseek(fd: usize, offset: i64, whence: usize) -> Result<usize, i64> {
+    file(fd)?.object.get().lseek(offset, whence)
+}
+
+/// Fill `stat` for `fd`
+pub fn stat(fd: usize, stat: &mut Stat) -> Result<usize, i64> {
+    file(fd)?.object.get().stat(stat)
+}
+
+pub fn getdents64(fd: usize, buf: &mut [u8]) -> Result<usize, i64> {
+    file(fd)?.object.get().getdents64(buf)
+}
+
+pub fn ftruncate(fd: usize, len: u64) -> Result<(), i64> {
+    let file = file(fd)?;
+    if file.flags & O_ACCMODE == O_RDONLY {
+        return Err(err(ErrNo::EINVAL));
+    }
+    file.object.get().ftruncate(len)
+}
+
+pub fn close(fd: usize) -> Result<(), i64> {
+    release(fd)
+}
+
+/// Duplicate `fd` to the lowest free file descriptor
+pub fn dup(old: usize) -> Result<usize, i64> {
+    entry(old)?;
+    duplicate(old, free_fd(0)?, false)
+}
+
+/// Duplicate `old` to `new`, closing `new` first
+pub fn dup2(old: usize, new: usize) -> Result<usize, i64> {
+    entry(old)?;
+    if new >= MAX_FDS {
+        return Err(err(ErrNo::EBADF));
+    }
+    if old == new {
+        return Ok(new);
+    }
+    duplicate(old, new, false)
+}
+
+/// `dup2()` with `O_CLOEXEC`, `old` and `new` have to differ
+pub fn dup3(old: usize, new: usize, flags: usize) -> Result<usize, i64> {
+    if old == new || flags & !O_CLOEXEC != 0 {
+        return Err(err(ErrNo::EINVAL));
+    }
+    entry(old)?;
+    if new >= MAX_FDS {
+        return Err(err(ErrNo::EBADF));
+    }
+    duplicate(old, new, flags & O_CLOEXEC != 0)
+}
+
+pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> Result<usize, i64> {
+    entry(fd)?;
+    match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            if arg >= MAX_FDS {
+                return Err(err(ErrNo::EINVAL));
+            }
+            duplicate(fd, free_fd(arg)?, cmd == F_DUPFD_CLOEXEC)
+        }
+        F_GETFD => Ok(if entry(fd)?.cloexec { FD_CLOEXEC } else { 0 }),
+        F_SETFD => {
+            entry(fd)?.cloexec = arg & FD_CLOEXEC != 0;
+            Ok(0)
+        }
+        F_GETFL => Ok(file(fd)?.flags),
+        F_SETFL => {
+            let file = file(fd)?;
+            file.object.get().set_flags(arg & STATUS_FLAGS)?;
+            file.flags = (file.flags & !STATUS_FLAGS) | (arg & STATUS_FLAGS);
+            Ok(0)
+        }
+        _ => Err(err(ErrNo::EINVAL)),
+    }
+}
+
+/// Close the file descriptors from `first` to `last` or mark them `FD_CLOEXEC`
+pub fn close_range(first: usize, last: usize, flags: usize) -> Result<(), i64> {
+    if first > last || flags & !(CLOSE_RANGE_UNSHARE | CLOSE_RANGE_CLOEXEC) != 0 {
+        return Err(err(ErrNo::EINVAL));
+    }
+
+    for fd in first..=last.min(MAX_FDS - 1) {
+        if flags & CLOSE_RANGE_CLOEXEC != 0 {
+            if let Ok(entry) = entry(fd) {
+                entry.cloexec = true;
+            }
+        } else if is_open(fd) {
+            release(fd).ok();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[test_case]
+fn test_fd_table() {
+    use crate::{serial_print, serial_println};
+    serial_print!("test_fd_table...");
+
+    init();
+    let (rfd, wfd) = pipe2(O_CLOEXEC).unwrap();
+    assert_eq!((rfd, wfd), (3, 4));
+    assert_eq!(fcntl(rfd, F_GETFD, 0), Ok(FD_CLOEXEC));
+    assert_eq!(fcntl(wfd, F_GETFL, 0), Ok(O_WRONLY));
+    assert_eq!(write(rfd, b"x"), Err(err(ErrNo::EBADF)));
+
+    // stdout redirected into the pipe
+    assert_eq!(dup2(wfd, 1), Ok(1));
+    assert_eq!(fcntl(1, F_GETFD, 0), Ok(0));
+    assert_eq!(write(1, b"hi"), Ok(2));
+    let mut buf = [0u8; 4];
+    assert_eq!(read(rfd, &mut buf), Ok(2));
+    assert_eq!(&buf[..2], b"hi");
+
+    // The pipe stays open while a duplicate exists
+    close(wfd).unwrap();
+    assert_eq!(read(rfd, &mut buf), Err(err(ErrNo::EAGAIN)));
+    assert_eq!(fcntl(rfd, F_DUPFD_CLOEXEC, 10), Ok(10));
+    assert_eq!(dup3(1, 1, 0), Err(err(ErrNo::EINVAL)));
+    close_range(1, 1, 0).unwrap();
+    assert_eq!(read(rfd, &mut buf), Ok(0));
+
+    fcntl(rfd, F_SETFL, O_NONBLOCK | O_APPEND).unwrap();
+    assert_eq!(fcntl(10, F_GETFL, 0), Ok(O_NONBLOCK | O_APPEND));
+    close_range(3, !0, CLOSE_RANGE_CLOEXEC).unwrap();
+    assert_eq!(fcntl(rfd, F_GETFD, 0), Ok(FD_CLOEXEC));
+    close_range(3, !0, 0).unwrap();
+    assert!(!is_open(rfd) && !is_open(10));
+    assert_eq!(dup(2), Ok(1));
+
+    init();
+    serial_println!("[ok]");
+}
@@ -0,0 +1,173 @@
+//! Read-only files of the host
+//!
+//! The ELF interpreter of a dynamically linked app loads the shared libraries
+//! with `open`, `read` and `mmap`. The files are served by the hypervisor from
+//! its sysroot. The kernel keeps the file position, every read is a `pread`.
+//!
+//! Errors are returned as positive `errno` values.
+
+use linux_errno::ErrNo;
+
+/// Maximum number of host files opened at the same time
+pub const MAX_FILES: usize = 16;
+
+#[derive(Clone, Copy)]
+struct HostFile {
+    fd: u32,
+    pos: u64,
+}
+
+static mut FILES: [Option<HostFile>; MAX_FILES] = [None; MAX_FILES];
+
+fn err(errno: ErrNo) -> i64 {
+    errno.into()
+}
+
+fn file(handle: usize) -> Result<&'static mut HostFile, i64> {
+    unsafe { FILES.get_mut(handle).and_then(Option::as_mut) }.ok_or_else(|| err(ErrNo::EBADF))
+}
+
+/// Is `handle` an open host file
+pub fn is_open(handle: usize) -> bool {
+    file(handle).is_ok()
+}
+
+/// Open the host file `pathname` read-only, returns its handle
+pub fn open(pathname: &str, flags: usize) -> Result<usize, i64> {
+    let handle =
+        unsafe { FILES.iter().position(Option::is_none) }.ok_or_else(|| err(ErrNo::EMFILE))?;
+    let fd = host::open(pathname, flags as _)?;
+    unsafe { FILES[handle].replace(HostFile { fd, pos: 0 }) };
+    Ok(handle)
+}
+
+/// Read from the file position of `handle`
+pub fn read(handle: usize, buf: &mut [u8]) -> Result<usize, i64> {
+    let file = file(handle)?;
+    let len = pread_all(file.fd, buf, file.pos)?;
+    file.pos += len as u64;
+    Ok(len)
+}
+
+/// Read at `offset` of `handle`, without changing the file position
+pub fn pread(handle: usize, buf: &mut [u8], offset: u64) -> Result<usize, i64> {
+    pread_all(file(handle)?.fd, buf, offset)
+}
+
+/// Set the file position of `handle`, returns the new position
+pub fn lseek(handle: usize, offset: i64, whence: usize) -> Result<usize, i64> {
+    const SEEK_SET: usize = 0;
+    const SEEK_CUR: usize = 1;
+    const SEEK_END: usize = 2;
+
+    let file = file(handle)?;
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => file.pos as i64,
+        SEEK_END => host::fstat(file.fd)?.1 as i64,
+        _ => return Err(err(ErrNo::EINVAL)),
+    };
+
+    let pos = base
+        .checked_add(offset)
+        .ok_or_else(|| err(ErrNo::EOVERFLOW))?;
+    if pos < 0 {
+        return Err(err(ErrNo::EINVAL));
+    }
+    file.pos = pos as u64;
+    Ok(pos as usize)
+}
+
+/// The mode and size of `handle`
+pub fn stat(handle: usize) -> Result<(u32, u64), i64> {
+    host::fstat(file(handle)?.fd)
+}
+
+/// Close `handle`
+pub fn close(handle: usize) -> Result<(), i64> {
+    let host_fd = file(handle)?.fd;
+    unsafe { FILES[handle].take() };
+    host::close(host_fd)
+}
+
+/// A read is split in chunks fitting the syscall page
+fn pread_all(fd: u32, buf: &mut [u8], offset: u64) -> Result<usize, i64> {
+    let mut done = 0;
+    while done < buf.len() {
+        let len = host::pread(fd, &mut buf[done..], offset + done as u64)?;
+        if len == 0 {
+            break;
+        }
+        done += len;
+    }
+    Ok(done)
+}
+
+#[cfg(not(feature = "qemu"))]
+mod host {
+    use crate::libc;
+    use linux_errno::ErrNo;
+    use vmsyscall::Error;
+
+    fn errno(e: Error) -> i64 {
+        match e {
+            Error::Errno(e) => e,
+            _ => ErrNo::EIO.into(),
+        }
+    }
+
+    pub fn open(pathname: &str, flags: i32) -> Result<u32, i64> {
+        if pathname.len() > vmsyscall::PATH_BUF_LEN {
+            return Err(ErrNo::ENAMETOOLONG.into());
+        }
+        libc::open(pathname, flags).map(|fd| fd as _).map_err(errno)
+    }
+
+    pub fn pread(fd: u32, buf: &mut [u8], offset: u64) -> Result<usize, i64> {
+        libc::pread(fd, buf, offset as _).map_err(errno)
+    }
+
+    pub fn fstat(fd: u32) -> Result<(u32, u64), i64> {
+        libc::fstat(fd)
+            .map(|stat| (stat.st_mode, stat.st_size))
+            .map_err(errno)
+    }
+
+    pub fn close(fd: u32) -> Result<(), i64> {
+        libc::close(fd).map(|_| ()).map_err(errno)
+    }
+}
+
+#[cfg(feature = "qemu")]
+mod host {
+    use linux_errno::ErrNo;
+
+    pub fn open(_pathname: &str, _flags: i32) -> Result<u32, i64> {
+        Err(ErrNo::ENOENT.into())
+    }
+
+    pub fn pread(_fd: u32, _buf: &mut [u8], _offset: u64) -> Result<usize, i64> {
+        Err(ErrNo::EBADF.into())
+    }
+
+    pub fn fstat(_fd: u32) -> Result<(u32, u64), i64> {
+        Err(ErrNo::EBADF.into())
+    }
+
+    pub fn close(_fd: u32) -> Result<(), i64> {
+        Err(ErrNo::EBADF.into())
+    }
+}
+
+#[cfg(test)]
+#[test_case]
+fn test_hostfs_bad_handle() {
+    use crate::{serial_print, serial_println};
+    serial_print!("test_hostfs_bad_handle...");
+    let mut buf = [0u8; 8];
+    assert!(!is_open(0));
+    assert_eq!(read(0, &mut buf), Err(err(ErrNo::EBADF)));
+    assert_eq!(pread(MAX_FILES, &mut buf, 0), Err(err(ErrNo::EBADF)));
+    assert_eq!(close(1), Err(err(ErrNo::EBADF)));
+    serial_println!("[ok]");
+}
@@ -0,0 +1,96 @@
+//! Log messages and trace lines for the hypervisor
+//!
+//! Both are formatted into a fixed buffer, without an allocation, and handed
+//! to the hypervisor with one `VmSyscall`. Without a hypervisor they go to the
+//! serial port.
+
+use core::fmt::{self, Write};
+use vmsyscall::bootinfo::LogLevel;
+use vmsyscall::LOG_MSG_LEN;
+
+/// The longest message, a log message, trace lines are shorter
+const MSG_BUF_LEN: usize = LOG_MSG_LEN;
+
+/// A message, cut at `cap` bytes
+pub struct MsgBuf {
+    buf: [u8; MSG_BUF_LEN],
+    len: usize,
+    cap: usize,
+}
+
+impl MsgBuf {
+    /// An empty message of at most `cap` bytes
+    pub fn new(cap: usize) -> Self {
+        MsgBuf {
+            buf: [0u8; MSG_BUF_LEN],
+            len: 0,
+            cap: cap.min(MSG_BUF_LEN),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl Write for MsgBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.cap - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// What a message is sent as
+pub enum Kind<'a> {
+    /// A kernel log message
+    Log(LogLevel),
+    /// The trace line of a syscall, with its raw values
+    Trace {
+        nr: usize,
+        args: &'a [usize; 6],
+        ret: Option<usize>,
+    },
+}
+
+/// Hand `msg` to the hypervisor as `kind`
+#[cfg(not(feature = "qemu"))]
+pub fn send(kind: Kind, msg: &MsgBuf) {
+    use crate::libc;
+
+    // there is nobody to tell about a failed message, and a trace can not
+    // fail the syscall
+    let _ = match kind {
+        Kind::Log(level) => libc::log(level, msg.as_bytes()),
+        Kind::Trace { nr, args, ret } => libc::trace(nr, args, ret, msg.as_bytes()),
+    };
+}
+
+/// Without a hypervisor the messages go to the serial port
+#[cfg(feature = "qemu")]
+pub fn send(kind: Kind, msg: &MsgBuf) {
+    use crate::eprintln;
+
+    let text = core::str::from_utf8(msg.as_bytes()).unwrap_or("<invalid UTF-8>");
+    match kind {
+        Kind::Log(level) => eprintln!("{}: {}", level.name(), text),
+        Kind::Trace { .. } => eprintln!("{}", text),
+    }
+}
+
+#[cfg(test)]
+#[test_case]
+fn test_msg_buf() {
+    use crate::{serial_print, serial_println};
+    serial_print!("test_msg_buf...");
+
+    let mut msg = MsgBuf::new(16);
+    write!(msg, "heap {:#X}", 0x1000).unwrap();
+    assert_eq!(msg.as_bytes(), &b"heap 0x1000"[..]);
+    for _ in 0..16 {
+        msg.write_str("x").unwrap();
+    }
+    assert_eq!(msg.as_bytes(), &b"heap 0x1000xxxxx"[..]);
+    serial_println!("[ok]");
+}
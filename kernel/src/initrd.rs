@@ -0,0 +1,632 @@
+//! Read-only files of the initial RAM filesystem
+//!
+//! The hypervisor places a tar (ustar) or cpio (newc) archive in guest memory
+//! and describes it in the `BootInfo`. Its regular files and directories are
+//! served to the app from the archive in place, without asking the host.
+//! Other entries, like symlinks and hard links, are skipped.
+//!
+//! Paths are looked up from the root, `.` components are ignored. Directories
+//! missing in the archive exist implicitly, if they contain an entry. The
+//! archive is indexed once, lookups and directory listings do not scan it.
+//!
+//! Errors are returned as positive `errno` values.
+
+use crate::arch::x86_64::PHYSICAL_MEMORY_OFFSET;
+use crate::syscall::{put_dirent64, DT_DIR, DT_REG};
+use linux_errno::ErrNo;
+use vmsyscall::bootinfo::BootInfo;
+
+/// Maximum number of initrd files opened at the same time
+pub const MAX_FILES: usize = 16;
+
+/// File type bits of `st_mode`
+pub const S_IFMT: u32 = 0o170_000;
+/// Directory
+pub const S_IFDIR: u32 = 0o040_000;
+/// Regular file
+pub const S_IFREG: u32 = 0o100_000;
+
+const O_ACCMODE: usize = 0o3;
+const O_CREAT: usize = 0o100;
+const O_TRUNC: usize = 0o1000;
+const O_DIRECTORY: usize = 0o200_000;
+
+const SEEK_SET: usize = 0;
+const SEEK_CUR: usize = 1;
+const SEEK_END: usize = 2;
+
+const TAR_BLOCK: usize = 512;
+const CPIO_HEADER: usize = 110;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Tar,
+    Cpio,
+}
+
+static mut ARCHIVE: Option<(Format, &'static [u8])> = None;
+
+/// One regular file or directory of the archive
+#[derive(Clone, Copy)]
+struct Entry {
+    /// The ustar prefix, prepended to `name`
+    prefix: &'static [u8],
+    name: &'static [u8],
+    mode: u32,
+    mtime: u64,
+    data: &'static [u8],
+}
+
+/// The non-empty components of a path, without `.`
+fn components(path: &[u8]) -> impl Iterator<Item = &[u8]> + Clone {
+    path.split(|&b| b == b'/')
+        .filter(|c| !c.is_empty() && *c != b".")
+}
+
+impl Entry {
+    fn components(&self) -> impl Iterator<Item = &'static [u8]> + Clone {
+        components(self.prefix).chain(components(self.name))
+    }
+}
+
+/// The number in `field`, in ASCII digits of `radix`
+fn parse_num(field: &[u8], radix: u32) -> Option<u64> {
+    let digits = field
+        .iter()
+        .skip_while(|&&b| b == b' ')
+        .take_while(|&&b| b != 0 && b != b' ');
+
+    let mut num: u64 = 0;
+    for &b in digits {
+        let digit = (b as char).to_digit(radix)?;
+        num = num.checked_mul(radix as u64)?.checked_add(digit as u64)?;
+    }
+    Some(num)
+}
+
+/// `field` up to the first NUL byte
+fn c_field(field: &[u8]) -> &[u8] {
+    match field.iter().position(|&b| b == 0) {
+        Some(len) => &field[..len],
+        None => field,
+    }
+}
+
+fn align_up(val: usize, align: usize) -> usize {
+    (val + align - 1) / align * align
+}
+
+/// Iterator over the regular files and directories of the archive
+#[derive(Clone)]
+struct Entries {
+    format: Format,
+    archive: &'static [u8],
+    pos: usize,
+}
+
+impl Entries {
+    /// The record at `pos`, `None` for an entry to skip, advances `pos`
+    fn next_tar(&mut self) -> Option<Option<Entry>> {
+        let header = self.archive.get(self.pos..self.pos + TAR_BLOCK)?;
+        if header.iter().all(|&b| b == 0) {
+            return None;
+        }
+
+        let size = parse_num(&header[124..136], 8)? as usize;
+        let start = self.pos + TAR_BLOCK;
+        let data = self.archive.get(start..start.checked_add(size)?)?;
+        self.pos = start + align_up(size, TAR_BLOCK);
+
+        let mode = parse_num(&header[100..108], 8)? as u32 & !S_IFMT;
+        let mode = match header[156] {
+            b'0' | b'7' | 0 => mode | S_IFREG,
+            b'5' => mode | S_IFDIR,
+            _ => return Some(None),
+        };
+
+        // GNU tar has other fields in place of the POSIX prefix
+        let prefix = match &header[257..263] {
+            b"ustar\0" => c_field(&header[345..500]),
+            _ => &[],
+        };
+
+        Some(Some(Entry {
+            prefix,
+            name: c_field(&header[..100]),
+            mode,
+            mtime: parse_num(&header[136..148], 8)?,
+            data,
+        }))
+    }
+
+    /// Like `next_tar()` for a newc cpio archive
+    fn next_cpio(&mut self) -> Option<Option<Entry>> {
+        let header = self.archive.get(self.pos..self.pos + CPIO_HEADER)?;
+        if &header[..6] != b"070701" && &header[..6] != b"070702" {
+            return None;
+        }
+
+        let field = |n: usize| parse_num(&header[6 + n * 8..14 + n * 8], 16);
+        let mode = field(1)? as u32;
+        let mtime = field(5)?;
+        let size = field(6)? as usize;
+        let name_size = field(11)? as usize;
+
+        let name_start = self.pos + CPIO_HEADER;
+        let name = c_field(self.archive.get(name_start..name_start + name_size)?);
+        if name == b"TRAILER!!!" {
+            return None;
+        }
+
+        let start = align_up(name_start + name_size, 4);
+        let data = self.archive.get(start..start.checked_add(size)?)?;
+        self.pos = align_up(start + size, 4);
+
+        match mode & S_IFMT {
+            S_IFREG | S_IFDIR => Some(Some(Entry {
+                prefix: &[],
+                name,
+                mode,
+                mtime,
+                data,
+            })),
+            _ => Some(None),
+        }
+    }
+}
+
+impl Iterator for Entries {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        loop {
+            let entry = match self.format {
+                Format::Tar => self.next_tar()?,
+                Format::Cpio => self.next_cpio()?,
+            };
+            // the root directory itself is implicit
+            match entry {
+                Some(entry) if entry.components().next().is_some() => return Some(entry),
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// Maximum number of files and directories of the archive, the implicit
+/// directories included
+pub const MAX_NODES: usize = 4096;
+
+/// The end of a list of nodes
+const NO_NODE: u32 = !0;
+
+/// A file or directory of the index, node `0` is the root
+///
+/// The index is built once by `set_archive()`. A directory without an entry
+/// of its own exists implicitly.
+#[derive(Clone, Copy)]
+struct Node {
+    /// The parent directory, the root is its own parent
+    parent: u32,
+    /// The last path component, empty for the root
+    name: &'static [u8],
+    /// The node has an entry in the archive
+    explicit: bool,
+    mode: u32,
+    mtime: u64,
+    /// The contents of a regular file
+    data: &'static [u8],
+    /// The children as a list, while the index is built
+    first_child: u32,
+    next_sibling: u32,
+    /// The first child in `CHILDREN` and the number of children
+    children: (u32, u32),
+}
+
+const IMPLICIT_DIR: Node = Node {
+    parent: 0,
+    name: &[],
+    explicit: false,
+    mode: S_IFDIR | 0o755,
+    mtime: 0,
+    data: &[],
+    first_child: NO_NODE,
+    next_sibling: NO_NODE,
+    children: (0, 0),
+};
+
+static mut NODES: [Node; MAX_NODES] = [IMPLICIT_DIR; MAX_NODES];
+static mut NODE_COUNT: usize = 0;
+/// The children of all directories, in archive order, grouped by directory
+static mut CHILDREN: [u32; MAX_NODES] = [0; MAX_NODES];
+
+/// The metadata of an initrd file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub ino: u64,
+    /// File type and permissions
+    pub mode: u32,
+    pub size: u64,
+    /// Modification time in seconds since the epoch
+    pub mtime: u64,
+}
+
+fn node(index: usize) -> &'static Node {
+    unsafe { &NODES[index] }
+}
+
+fn is_dir(index: usize) -> bool {
+    node(index).mode & S_IFMT == S_IFDIR
+}
+
+fn metadata(index: usize) -> Metadata {
+    let node = node(index);
+    Metadata {
+        ino: index as u64 + 1,
+        mode: node.mode,
+        size: if is_dir(index) {
+            0
+        } else {
+            node.data.len() as _
+        },
+        mtime: node.mtime,
+    }
+}
+
+/// The children of the directory `dir`
+fn children(dir: usize) -> &'static [u32] {
+    let (start, len) = node(dir).children;
+    unsafe { &CHILDREN[start as usize..(start + len) as usize] }
+}
+
+/// Add `entry` and the directories above it to the index
+///
+/// Returns `false` if there is no room for another node. The first entry of
+/// a path wins, a directory entry may come after the contents.
+fn index_entry(entry: &Entry) -> bool {
+    let nodes = unsafe { &mut NODES };
+    let count = unsafe { &mut NODE_COUNT };
+    let mut dir = 0;
+    let mut comps = entry.components().peekable();
+
+    while let Some(name) = comps.next() {
+        let mut last = NO_NODE;
+        let mut child = nodes[dir].first_child;
+        while child != NO_NODE && nodes[child as usize].name != name {
+            last = child;
+            child = nodes[child as usize].next_sibling;
+        }
+
+        if child == NO_NODE {
+            if *count == MAX_NODES {
+                return false;
+            }
+            child = *count as u32;
+            *count += 1;
+            nodes[child as usize] = Node {
+                parent: dir as u32,
+                name,
+                ..IMPLICIT_DIR
+            };
+            match last {
+                NO_NODE => nodes[dir].first_child = child,
+                last => nodes[last as usize].next_sibling = child,
+            }
+        }
+
+        let node = &mut nodes[child as usize];
+        if comps.peek().is_none() && !node.explicit {
+            node.explicit = true;
+            node.mode = entry.mode;
+            node.mtime = entry.mtime;
+            node.data = entry.data;
+        }
+        dir = child as usize;
+    }
+    true
+}
+
+/// Index the entries of `archive`, returns `false` if there are too many
+fn build_index(format: Format, archive: &'static [u8]) -> bool {
+    let entries = Entries {
+        format,
+        archive,
+        pos: 0,
+    };
+
+    unsafe {
+        NODES[0] = IMPLICIT_DIR;
+        NODE_COUNT = 1;
+    }
+    for entry in entries {
+        if !index_entry(&entry) {
+            return false;
+        }
+    }
+
+    // Lay out the children of each directory next to each other
+    let nodes = unsafe { &mut NODES[..NODE_COUNT] };
+    let mut next = 0;
+    for dir in 0..nodes.len() {
+        let start = next;
+        let mut child = nodes[dir].first_child;
+        while child != NO_NODE {
+            unsafe { CHILDREN[next] = child };
+            next += 1;
+            child = nodes[child as usize].next_sibling;
+        }
+        nodes[dir].children = (start as u32, (next - start) as u32);
+    }
+    true
+}
+
+/// The entry of `name` in the directory `dir`
+fn lookup_child(dir: usize, name: &[u8]) -> Option<usize> {
+    children(dir)
+        .iter()
+        .map(|&child| child as usize)
+        .find(|&child| node(child).name == name)
+}
+
+/// Find `path` in the archive
+fn lookup(path: &str) -> Option<usize> {
+    components(path.as_bytes()).try_fold(0, lookup_child)
+}
+
+/// The `n`th entry of directory `dir`, without `.` and `..`
+///
+/// Returns the name, the node and if it is a directory.
+fn dir_entry(dir: usize, n: usize) -> Option<(&'static [u8], usize, bool)> {
+    let child = *children(dir).get(n)? as usize;
+    Some((node(child).name, child, is_dir(child)))
+}
+
+#[derive(Clone, Copy)]
+struct InitrdFile {
+    node: usize,
+    /// Read position of a file, number of listed entries of a directory
+    pos: u64,
+}
+
+static mut FILES: [Option<InitrdFile>; MAX_FILES] = [None; MAX_FILES];
+
+fn err(errno: ErrNo) -> i64 {
+    errno.into()
+}
+
+fn file(handle: usize) -> Result<&'static mut InitrdFile, i64> {
+    unsafe { FILES.get_mut(handle).and_then(Option::as_mut) }.ok_or_else(|| err(ErrNo::EBADF))
+}
+
+/// Serve the archive passed by the hypervisor
+pub fn init(boot_info: &BootInfo) {
+    if boot_info.initrd.len == 0 {
+        return;
+    }
+
+    let archive = unsafe {
+        core::slice::from_raw_parts(
+            (PHYSICAL_MEMORY_OFFSET + boot_info.initrd.start) as *const u8,
+            boot_info.initrd.len as usize,
+        )
+    };
+
+    if let Err(e) = set_archive(archive) {
+        crate::warn!("{}", e);
+    }
+}
+
+/// Serve the files of the tar or cpio `archive`
+pub fn set_archive(archive: &'static [u8]) -> Result<(), &'static str> {
+    let format = if archive.starts_with(b"070701") || archive.starts_with(b"070702") {
+        Format::Cpio
+    } else if archive.get(257..262) == Some(&b"ustar"[..]) {
+        Format::Tar
+    } else {
+        return Err("initrd is not a tar or cpio (newc) archive");
+    };
+
+    unsafe {
+        FILES = [None; MAX_FILES];
+        ARCHIVE = None;
+    }
+    if !build_index(format, archive) {
+        return Err("initrd has more than 4096 files and directories");
+    }
+    unsafe { ARCHIVE.replace((format, archive)) };
+    Ok(())
+}
+
+/// Is there an initial RAM filesystem
+pub fn is_present() -> bool {
+    unsafe { ARCHIVE.is_some() }
+}
+
+/// Is `handle` an open initrd file
+pub fn is_open(handle: usize) -> bool {
+    file(handle).is_ok()
+}
+
+/// Open `pathname` read-only, returns its handle
+pub fn open(pathname: &str, flags: usize) -> Result<usize, i64> {
+    if !is_present() {
+        return Err(err(ErrNo::ENOENT));
+    }
+
+    let node = lookup(pathname).ok_or_else(|| err(ErrNo::ENOENT))?;
+
+    if flags & O_ACCMODE != 0 || flags & (O_CREAT | O_TRUNC) != 0 {
+        return Err(err(ErrNo::EROFS));
+    }
+    if flags & O_DIRECTORY != 0 && !is_dir(node) {
+        return Err(err(ErrNo::ENOTDIR));
+    }
+
+    let handle =
+        unsafe { FILES.iter().position(Option::is_none) }.ok_or_else(|| err(ErrNo::EMFILE))?;
+    unsafe { FILES[handle].replace(InitrdFile { node, pos: 0 }) };
+    Ok(handle)
+}
+
+/// The contents of the regular file `file`
+fn file_data(file: &InitrdFile) -> Result<&'static [u8], i64> {
+    match is_dir(file.node) {
+        false => Ok(node(file.node).data),
+        true => Err(err(ErrNo::EISDIR)),
+    }
+}
+
+fn read_at(file: &InitrdFile, buf: &mut [u8], offset: u64) -> Result<usize, i64> {
+    let data = file_data(file)?;
+    let start = data.len().min(offset as usize);
+    let len = buf.len().min(data.len() - start);
+    buf[..len].copy_from_slice(&data[start..start + len]);
+    Ok(len)
+}
+
+/// Read from the file position of `handle`
+pub fn read(handle: usize, buf: &mut [u8]) -> Result<usize, i64> {
+    let file = file(handle)?;
+    let len = read_at(file, buf, file.pos)?;
+    file.pos += len as u64;
+    Ok(len)
+}
+
+/// Read at `offset` of `handle`, without changing the file position
+pub fn pread(handle: usize, buf: &mut [u8], offset: u64) -> Result<usize, i64> {
+    read_at(file(handle)?, buf, offset)
+}
+
+/// Set the file position of `handle`, returns the new position
+pub fn lseek(handle: usize, offset: i64, whence: usize) -> Result<usize, i64> {
+    let file = file(handle)?;
+
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => file.pos as i64,
+        SEEK_END => file_data(file).map_or(0, |data| data.len() as i64),
+        _ => return Err(err(ErrNo::EINVAL)),
+    };
+
+    let pos = base
+        .checked_add(offset)
+        .ok_or_else(|| err(ErrNo::EOVERFLOW))?;
+    if pos < 0 {
+        return Err(err(ErrNo::EINVAL));
+    }
+    file.pos = pos as u64;
+    Ok(pos as usize)
+}
+
+/// The metadata of `handle`
+pub fn stat(handle: usize) -> Result<Metadata, i64> {
+    Ok(metadata(file(handle)?.node))
+}
+
+/// The metadata of `pathname`
+pub fn stat_path(pathname: &str) -> Result<Metadata, i64> {
+    if !is_present() {
+        return Err(err(ErrNo::ENOENT));
+    }
+    lookup(pathname)
+        .map(metadata)
+        .ok_or_else(|| err(ErrNo::ENOENT))
+}
+
+/// Fill `buf` with `struct linux_dirent64` records of the directory `handle`
+pub fn getdents64(handle: usize, buf: &mut [u8]) -> Result<usize, i64> {
+    let file = file(handle)?;
+    let dir = file.node;
+    if !is_dir(dir) {
+        return Err(err(ErrNo::ENOTDIR));
+    }
+
+    let mut len = 0;
+    loop {
+        let (name, ino, d_type): (&[u8], u64, u8) = match file.pos {
+            0 => (&b"."[..], metadata(dir).ino, DT_DIR),
+            // The parent of the root is the root itself
+            1 => (&b".."[..], metadata(node(dir).parent as usize).ino, DT_DIR),
+            n => match dir_entry(dir, n as usize - 2) {
+                Some((name, node, true)) => (name, metadata(node).ino, DT_DIR),
+                Some((name, node, false)) => (name, metadata(node).ino, DT_REG),
+                None => break,
+            },
+        };
+
+        match put_dirent64(&mut buf[len..], ino, file.pos as i64 + 1, d_type, name) {
+            Some(reclen) => len += reclen,
+            None if len == 0 => return Err(err(ErrNo::EINVAL)),
+            None => break,
+        }
+        file.pos += 1;
+    }
+    Ok(len)
+}
+
+/// Close `handle`
+pub fn close(handle: usize) -> Result<(), i64> {
+    file(handle)?;
+    unsafe { FILES[handle].take() };
+    Ok(())
+}
+
+#[cfg(test)]
+#[test_case]
+fn test_initrd_tar() {
+    use crate::{serial_print, serial_println};
+    serial_print!("test_initrd_tar...");
+
+    static mut TAR: [u8; 5 * TAR_BLOCK] = [0u8; 5 * TAR_BLOCK];
+
+    fn header(block: &mut [u8], name: &[u8], size: &[u8], typeflag: u8) {
+        block[..name.len()].copy_from_slice(name);
+        block[100..107].copy_from_slice(b"0000644");
+        block[124..124 + size.len()].copy_from_slice(size);
+        block[136..147].copy_from_slice(b"13606240000");
+        block[156] = typeflag;
+        block[257..263].copy_from_slice(b"ustar\0");
+    }
+
+    let tar = unsafe { &mut TAR };
+    header(&mut tar[..TAR_BLOCK], b"./etc/", b"00000000000", b'5');
+    header(&mut tar[TAR_BLOCK..], b"./etc/hosts", b"00000000017", b'0');
+    tar[2 * TAR_BLOCK..2 * TAR_BLOCK + 15].copy_from_slice(b"127.0.0.1 host\n");
+    header(&mut tar[3 * TAR_BLOCK..], b"data/a", b"00000000000", b'0');
+
+    let old = unsafe { ARCHIVE.take() };
+    assert_eq!(set_archive(unsafe { &TAR }), Ok(()));
+
+    assert_eq!(stat_path("/etc").unwrap().mode, S_IFDIR | 0o644);
+    assert_eq!(stat_path("/data").unwrap().mode, S_IFDIR | 0o755);
+    assert_eq!(stat_path("/etc/hosts").unwrap().size, 15);
+    assert_eq!(stat_path("/etc/passwd"), Err(err(ErrNo::ENOENT)));
+    assert_eq!(open("/etc/hosts", 0o1), Err(err(ErrNo::EROFS)));
+
+    let handle = open("/etc//hosts", 0).unwrap();
+    let mut buf = [0u8; 9];
+    assert_eq!(read(handle, &mut buf), Ok(9));
+    assert_eq!(&buf, b"127.0.0.1");
+    assert_eq!(lseek(handle, -4, SEEK_END), Ok(11));
+    assert_eq!(read(handle, &mut buf), Ok(4));
+    assert_eq!(&buf[..4], b"ost\n");
+    assert_eq!(read(handle, &mut buf), Ok(0));
+    close(handle).unwrap();
+
+    let handle = open("/", O_DIRECTORY).unwrap();
+    let mut dirents = [0u8; 256];
+    let len = getdents64(handle, &mut dirents).unwrap();
+    // ".", "..", "etc" and "data"
+    assert_eq!(len, 4 * 24);
+    assert_eq!(&dirents[2 * 24 + 19..2 * 24 + 23], b"etc\0");
+    assert_eq!(dirents[3 * 24 + 18], DT_DIR);
+    assert_eq!(&dirents[3 * 24 + 19..3 * 24 + 24], b"data\0");
+    assert_eq!(getdents64(handle, &mut dirents), Ok(0));
+    close(handle).unwrap();
+
+    match old {
+        Some((_, archive)) => set_archive(archive).unwrap(),
+        None => unsafe { ARCHIVE = None },
+    }
+    serial_println!("[ok]");
+}
@@ -26,11 +26,24 @@ use core::panic::PanicInfo;
 use linked_list_allocator::LockedHeap;
 
 pub mod arch;
+pub mod epoll;
+pub mod eventfd;
+pub mod fd;
+pub mod hostfs;
+pub mod hostmsg;
+pub mod initrd;
 #[cfg(not(feature = "qemu"))]
 pub mod libc;
+pub mod log;
 pub mod memory;
+pub mod pipe;
+pub mod poll;
+pub mod secret;
+pub mod socket;
 pub mod strlen;
 pub mod syscall;
+pub mod tmpfs;
+pub mod trace;
 
 #[cfg(any(feature = "nightly", test))]
 #[lang = "eh_personality"]
@@ -80,11 +93,8 @@ pub enum HyperVisorExitCode {
 }
 
 pub fn exit_hypervisor(exit_code: HyperVisorExitCode) {
-    use x86_64::instructions::port::PortWriteOnly;
-
     unsafe {
-        let mut port = PortWriteOnly::new(0xf4);
-        port.write(exit_code as u32);
+        arch::x86_64::ghcb::outl(0xf4, exit_code as u32);
     }
 }
 
@@ -0,0 +1,60 @@
+use super::vm_syscall;
+pub use vmsyscall::Error;
+use vmsyscall::{FileStat, VmSyscall, VmSyscallRet, PATH_BUF_LEN, READ_BUF_LEN};
+
+pub fn open(pathname: &str, flags: i32) -> Result<i32, Error> {
+    let bytes = pathname.as_bytes();
+    if bytes.len() > PATH_BUF_LEN {
+        return Err(Error::SerializeError);
+    }
+
+    let mut path = [0u8; PATH_BUF_LEN];
+    path[..bytes.len()].copy_from_slice(bytes);
+
+    let s = VmSyscall::Open {
+        path,
+        len: bytes.len(),
+        flags,
+    };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Open(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn pread(fd: u32, buf: &mut [u8], offset: i64) -> Result<usize, Error> {
+    let s = VmSyscall::Pread {
+        fd,
+        count: buf.len().min(READ_BUF_LEN),
+        offset,
+    };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Pread(res) => {
+            let (len, data) = res?;
+            let len = (len as usize).min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok(len)
+        }
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn fstat(fd: u32) -> Result<FileStat, Error> {
+    let s = VmSyscall::Fstat { fd };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Fstat(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn close(fd: u32) -> Result<i32, Error> {
+    let s = VmSyscall::Close { fd };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Close(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
@@ -0,0 +1,21 @@
+use super::{copy_cut, vm_syscall};
+pub use vmsyscall::Error;
+use vmsyscall::bootinfo::LogLevel;
+use vmsyscall::{VmSyscall, VmSyscallRet, LOG_MSG_LEN};
+
+/// Hands a kernel log message to the hypervisor, `msg` is cut to `LOG_MSG_LEN`
+pub fn log(level: LogLevel, msg: &[u8]) -> Result<(), Error> {
+    let mut buf = [0u8; LOG_MSG_LEN];
+    let len = copy_cut(&mut buf, msg);
+
+    let s = VmSyscall::Log {
+        level,
+        len,
+        msg: buf,
+    };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Log(res) => res.map(|_| ()),
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
@@ -1,11 +1,21 @@
 pub use vmsyscall::Error;
 use vmsyscall::{VmSyscall, VmSyscallRet, WRITE_BUF_LEN};
-use x86_64::instructions::port::Port;
 use x86_64::VirtAddr;
 
+mod file;
+mod log;
 mod mmap;
+mod poll;
+mod socket;
+mod trace;
+pub use file::*;
+pub use log::*;
 pub use mmap::*;
+pub use poll::*;
+pub use socket::*;
+pub use trace::*;
 
+use crate::arch::x86_64::ghcb::outw;
 use crate::arch::{SYSCALL_PHYS_ADDR, SYSCALL_TRIGGER_PORT};
 
 #[cfg(test)]
@@ -25,8 +35,7 @@ pub fn write(fd: u32, bytes: &[u8]) -> Result<i32, Error> {
             data,
         });
 
-        let mut port = Port::<u16>::new(SYSCALL_TRIGGER_PORT);
-        port.write(1 as u16);
+        outw(SYSCALL_TRIGGER_PORT, 1);
         let reply = syscall_page.as_u64() as *mut VmSyscallRet;
 
         match reply.read_volatile() {
@@ -44,12 +53,20 @@ pub fn vm_syscall(syscall: VmSyscall) -> Result<VmSyscallRet, Error> {
 
     unsafe {
         request.write_volatile(syscall);
-        let mut port = Port::<u16>::new(SYSCALL_TRIGGER_PORT);
-        port.write(1 as u16);
+        outw(SYSCALL_TRIGGER_PORT, 1);
         Ok(reply.read_volatile())
     }
 }
 
+/// Copy `bytes` into the fixed `buf` of a `VmSyscall`, cut at its end
+///
+/// Returns the length copied.
+fn copy_cut(buf: &mut [u8], bytes: &[u8]) -> usize {
+    let len = bytes.len().min(buf.len());
+    buf[..len].copy_from_slice(&bytes[..len]);
+    len
+}
+
 #[allow(non_camel_case_types)]
 #[repr(u8)]
 pub enum c_void {
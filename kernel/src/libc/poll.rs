@@ -0,0 +1,24 @@
+use super::vm_syscall;
+pub use vmsyscall::Error;
+use vmsyscall::{PollFd, VmSyscall, VmSyscallRet, MAX_POLL_FDS};
+
+/// Returns the number of ready files and the `revents` of `fds`
+pub fn poll(pollfds: &[PollFd], timeout: i32) -> Result<(usize, [i16; MAX_POLL_FDS]), Error> {
+    if pollfds.len() > MAX_POLL_FDS {
+        return Err(Error::SerializeError);
+    }
+
+    let mut fds = [PollFd::default(); MAX_POLL_FDS];
+    fds[..pollfds.len()].copy_from_slice(pollfds);
+
+    let s = VmSyscall::Poll {
+        fds,
+        nfds: pollfds.len(),
+        timeout,
+    };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Poll(res) => res.map(|(ready, revents)| (ready as _, revents)),
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
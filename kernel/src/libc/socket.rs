@@ -0,0 +1,192 @@
+use super::vm_syscall;
+pub use vmsyscall::Error;
+use vmsyscall::{SockAddr, VmSyscall, VmSyscallRet, SOCKET_BUF_LEN, SOCKOPT_LEN};
+
+pub fn socket(domain: i32, ty: i32, protocol: i32) -> Result<i32, Error> {
+    let s = VmSyscall::Socket {
+        domain,
+        ty,
+        protocol,
+    };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Socket(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn socketpair(domain: i32, ty: i32, protocol: i32) -> Result<(i32, i32), Error> {
+    let s = VmSyscall::Socketpair {
+        domain,
+        ty,
+        protocol,
+    };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Socketpair(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn bind(fd: u32, addr: SockAddr) -> Result<i32, Error> {
+    let s = VmSyscall::Bind { fd, addr };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Bind(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn listen(fd: u32, backlog: i32) -> Result<i32, Error> {
+    let s = VmSyscall::Listen { fd, backlog };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Listen(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn accept(fd: u32, nonblock: bool) -> Result<(i32, SockAddr), Error> {
+    let s = VmSyscall::Accept { fd, nonblock };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Accept(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn connect(fd: u32, addr: SockAddr, nonblock: bool) -> Result<i32, Error> {
+    let s = VmSyscall::Connect { fd, addr, nonblock };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Connect(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn sendto(
+    fd: u32,
+    buf: &[u8],
+    flags: i32,
+    addr: Option<SockAddr>,
+    nonblock: bool,
+) -> Result<usize, Error> {
+    let count = buf.len().min(SOCKET_BUF_LEN);
+    let mut data = [0u8; SOCKET_BUF_LEN];
+    data[..count].copy_from_slice(&buf[..count]);
+
+    let s = VmSyscall::Sendto {
+        fd,
+        count,
+        data,
+        flags,
+        addr,
+        nonblock,
+    };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Sendto(res) => res.map(|len| len as _),
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn recvfrom(
+    fd: u32,
+    buf: &mut [u8],
+    flags: i32,
+    nonblock: bool,
+) -> Result<(usize, SockAddr), Error> {
+    let s = VmSyscall::Recvfrom {
+        fd,
+        count: buf.len().min(SOCKET_BUF_LEN),
+        flags,
+        nonblock,
+    };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Recvfrom(res) => {
+            let (len, data, addr) = res?;
+            let copied = (len as usize).min(buf.len()).min(SOCKET_BUF_LEN);
+            buf[..copied].copy_from_slice(&data[..copied]);
+            Ok((len as usize, addr))
+        }
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn getsockname(fd: u32) -> Result<SockAddr, Error> {
+    let s = VmSyscall::Getsockname { fd };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Getsockname(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn getpeername(fd: u32) -> Result<SockAddr, Error> {
+    let s = VmSyscall::Getpeername { fd };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Getpeername(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn setsockopt(fd: u32, level: i32, name: i32, optval: &[u8]) -> Result<i32, Error> {
+    if optval.len() > SOCKOPT_LEN {
+        return Err(Error::SerializeError);
+    }
+
+    let mut value = [0u8; SOCKOPT_LEN];
+    value[..optval.len()].copy_from_slice(optval);
+
+    let s = VmSyscall::Setsockopt {
+        fd,
+        level,
+        name,
+        value,
+        len: optval.len() as _,
+    };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Setsockopt(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn getsockopt(fd: u32, level: i32, name: i32, optval: &mut [u8]) -> Result<usize, Error> {
+    let s = VmSyscall::Getsockopt {
+        fd,
+        level,
+        name,
+        len: optval.len().min(SOCKOPT_LEN) as _,
+    };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Getsockopt(res) => {
+            let (len, value) = res?;
+            let len = (len as usize).min(optval.len()).min(SOCKOPT_LEN);
+            optval[..len].copy_from_slice(&value[..len]);
+            Ok(len)
+        }
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn shutdown(fd: u32, how: i32) -> Result<i32, Error> {
+    let s = VmSyscall::Shutdown { fd, how };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Shutdown(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
+
+pub fn close_socket(fd: u32) -> Result<i32, Error> {
+    let s = VmSyscall::CloseSocket { fd };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::CloseSocket(res) => res,
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
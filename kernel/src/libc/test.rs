@@ -1,4 +1,7 @@
+use super::file::*;
 use super::mmap::*;
+use super::poll::*;
+use super::socket::*;
 use crate::{serial_print, serial_println};
 use linux_errno::ErrNo;
 pub use vmsyscall::Error;
@@ -42,3 +45,52 @@ fn test_mprotect() {
     assert_eq!(ret, Error::Errno(ErrNo::ENOSYS.into()));
     serial_println!("[ok]");
 }
+
+#[test_case]
+fn test_open_read_only() {
+    serial_print!("test_open_read_only...");
+    const O_RDWR: i32 = 2;
+    let ret = open("/etc/passwd", O_RDWR).unwrap_err();
+    assert_eq!(ret, Error::Errno(ErrNo::EACCES.into()));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_close_bad_fd() {
+    serial_print!("test_close_bad_fd...");
+    let ret = close(1000).unwrap_err();
+    assert_eq!(ret, Error::Errno(ErrNo::EBADF.into()));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_socket_policy() {
+    serial_print!("test_socket_policy...");
+    const AF_INET: i32 = 2;
+    const SOCK_STREAM: i32 = 1;
+    let fd = socket(AF_INET, SOCK_STREAM, 0).unwrap() as u32;
+    // 127.0.0.1:80, not allowed without --allow-listen
+    let addr =
+        vmsyscall::SockAddr::new(&[2, 0, 0, 80, 127, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+    let ret = bind(fd, addr).unwrap_err();
+    assert_eq!(ret, Error::Errno(ErrNo::EACCES.into()));
+    assert_eq!(close_socket(fd), Ok(0));
+    let ret = close_socket(fd).unwrap_err();
+    assert_eq!(ret, Error::Errno(ErrNo::EBADF.into()));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_poll_bad_socket() {
+    serial_print!("test_poll_bad_socket...");
+    let fd = vmsyscall::PollFd {
+        kind: vmsyscall::PollKind::Socket,
+        fd: 1000,
+        events: 1,
+    };
+    let (ready, revents) = poll(&[fd], -1).unwrap();
+    assert_eq!(ready, 1);
+    // POLLNVAL
+    assert_eq!(revents[0], 0x20);
+    serial_println!("[ok]");
+}
@@ -0,0 +1,27 @@
+use super::{copy_cut, vm_syscall};
+pub use vmsyscall::Error;
+use vmsyscall::{VmSyscall, VmSyscallRet, TRACE_LINE_LEN};
+
+/// Hands a traced syscall to the hypervisor, `line` is cut to `TRACE_LINE_LEN`
+pub fn trace(nr: usize, args: &[usize; 6], ret: Option<usize>, line: &[u8]) -> Result<(), Error> {
+    let mut buf = [0u8; TRACE_LINE_LEN];
+    let len = copy_cut(&mut buf, line);
+
+    let mut raw = [0u64; 6];
+    for (raw, arg) in raw.iter_mut().zip(args) {
+        *raw = *arg as _;
+    }
+
+    let s = VmSyscall::Trace {
+        nr: nr as _,
+        args: raw,
+        ret: ret.map(|ret| ret as i64),
+        len,
+        line: buf,
+    };
+    let ret = vm_syscall(s)?;
+    match ret {
+        VmSyscallRet::Trace(res) => res.map(|_| ()),
+        _ => panic!("Unknown KvmSyscallRet"),
+    }
+}
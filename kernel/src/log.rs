@@ -0,0 +1,95 @@
+//! Leveled log messages of the kernel
+//!
+//! A message goes to the hypervisor with `VmSyscall::Log`, apart from the
+//! output of the app on its stdout and stderr. Messages more verbose than the
+//! level of the `BootInfo` are dropped in the kernel already, until the
+//! `BootInfo` is read the default level applies.
+//!
+//! ```ignore
+//! use crate::{debug, warn};
+//!
+//! debug!("NEXT_MMAP = {:#X}", next_mmap);
+//! warn!("No secret provided by the hypervisor");
+//! ```
+
+use crate::hostmsg::{self, Kind, MsgBuf};
+use core::fmt::{self, Write};
+pub use vmsyscall::bootinfo::LogLevel;
+use vmsyscall::LOG_MSG_LEN;
+
+static mut LEVEL: LogLevel = LogLevel::Warn;
+
+/// Set the most verbose level sent to the hypervisor
+pub fn set_level(level: LogLevel) {
+    unsafe { LEVEL = level };
+}
+
+/// Is a message of `level` sent to the hypervisor
+pub fn enabled(level: LogLevel) -> bool {
+    unsafe { LEVEL }.allows(level)
+}
+
+#[doc(hidden)]
+pub fn _log(level: LogLevel, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let mut msg = MsgBuf::new(LOG_MSG_LEN);
+    // the message is cut at its end
+    let _ = msg.write_fmt(args);
+    hostmsg::send(Kind::Log(level), &msg);
+}
+
+/// Log a message with the [`LogLevel`]
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::log::_log($level, format_args!($($arg)*))
+    };
+}
+
+/// Log a message about a failure the kernel or the app can not continue from
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Error, $($arg)*));
+}
+
+/// Log a message the user should know about
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Warn, $($arg)*));
+}
+
+/// Log a step of the boot
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Info, $($arg)*));
+}
+
+/// Log a detail of the boot
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Debug, $($arg)*));
+}
+
+/// Log a frequent event, like a timer tick
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Trace, $($arg)*));
+}
+
+#[cfg(test)]
+#[test_case]
+fn test_log() {
+    use crate::{serial_print, serial_println};
+    serial_print!("test_log...");
+
+    set_level(LogLevel::Info);
+    assert!(enabled(LogLevel::Error));
+    assert!(enabled(LogLevel::Info));
+    assert!(!enabled(LogLevel::Debug));
+    set_level(LogLevel::Off);
+    assert!(!enabled(LogLevel::Error));
+    set_level(LogLevel::default());
+    serial_println!("[ok]");
+}
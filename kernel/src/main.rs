@@ -12,7 +12,7 @@
 use core::panic::PanicInfo;
 use kernel::arch::OffsetPageTable;
 use kernel::memory::BootInfoFrameAllocator;
-use kernel::{entry_point, exit_hypervisor, println, HyperVisorExitCode};
+use kernel::{entry_point, exit_hypervisor, HyperVisorExitCode};
 use vmsyscall::bootinfo::BootInfo;
 
 entry_point!(kernel_main);
@@ -47,7 +47,7 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
         _app_phnum: usize,
     ) -> ! {
         test_main();
-        println!("It did not crash!");
+        kernel::println!("It did not crash!");
         exit_hypervisor(HyperVisorExitCode::Success);
         kernel::hlt_loop()
     }
@@ -59,7 +59,7 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
+    kernel::error!("{}", info);
     exit_hypervisor(HyperVisorExitCode::Failed);
     kernel::hlt_loop()
 }
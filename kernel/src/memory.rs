@@ -12,12 +12,18 @@ use x86_64::structures::paging::{FrameDeallocator, UnusedPhysFrame};
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 ///
+/// `encryption_mask` is the SEV C-bit, which is set in all new mappings,
+/// or `0` without memory encryption.
+///
 /// # Safety
 /// FIXME
 ///
-pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
-    let level_4_table = active_level_4_table(physical_memory_offset);
-    OffsetPageTable::new(level_4_table, physical_memory_offset)
+pub unsafe fn init(
+    physical_memory_offset: VirtAddr,
+    encryption_mask: u64,
+) -> OffsetPageTable<'static> {
+    let level_4_table = active_level_4_table(physical_memory_offset, encryption_mask);
+    OffsetPageTable::with_encryption_mask(level_4_table, physical_memory_offset, encryption_mask)
 }
 
 /// Returns a mutable reference to the active level 4 table.
@@ -26,13 +32,17 @@ pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static>
 /// complete physical memory is mapped to virtual memory at the passed
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
-unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+unsafe fn active_level_4_table(
+    physical_memory_offset: VirtAddr,
+    encryption_mask: u64,
+) -> &'static mut PageTable {
     use x86_64::registers::control::Cr3;
 
     let (level_4_table_frame, _) = Cr3::read();
 
-    let phys = level_4_table_frame.start_address();
-    let virt = physical_memory_offset + phys.as_u64();
+    // CR3 contains the C-bit, if the level 4 table is encrypted
+    let phys = level_4_table_frame.start_address().as_u64() & !encryption_mask;
+    let virt = physical_memory_offset + phys;
     let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
 
     &mut *page_table_ptr // unsafe
@@ -42,6 +52,7 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
 pub struct BootInfoFrameAllocator {
     memory_map: MemoryMap,
     next: usize,
+    encryption_mask: u64,
 }
 
 impl BootInfoFrameAllocator {
@@ -51,15 +62,23 @@ impl BootInfoFrameAllocator {
     /// memory map is valid. The main requirement is that all frames that are marked
     /// as `USABLE` in it are really unused.
     ///
+    /// `encryption_mask` is the SEV C-bit the frames are mapped with.
+    ///
     /// # Safety
     /// FIXME
-    pub unsafe fn init(memory_map: MemoryMap) -> Self {
+    pub unsafe fn init(memory_map: MemoryMap, encryption_mask: u64) -> Self {
         BootInfoFrameAllocator {
             memory_map,
             next: 0,
+            encryption_mask,
         }
     }
 
+    /// The SEV C-bit the frames are mapped with, or `0` without memory encryption
+    pub fn encryption_mask(&self) -> u64 {
+        self.encryption_mask
+    }
+
     pub fn set_region_type_usable(&mut self, region_type: MemoryRegionType) {
         self.memory_map.iter_mut().for_each(|r| {
             if r.region_type == region_type {
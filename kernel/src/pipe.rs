@@ -0,0 +1,169 @@
+//! Pipes between file descriptors of the app
+//!
+//! A pipe is a ring buffer in the kernel. There is only one thread, so a
+//! read of an empty pipe or a write to a full pipe could wait forever. They
+//! fail with `EAGAIN` instead of blocking.
+//!
+//! Errors are returned as positive `errno` values.
+
+use crate::poll::{POLLERR, POLLHUP, POLLIN, POLLOUT};
+use linux_errno::ErrNo;
+
+/// Maximum number of pipes at the same time
+pub const MAX_PIPES: usize = 8;
+
+/// Capacity of a pipe in bytes
+pub const PIPE_SIZE: usize = 16 * 1024;
+
+#[derive(Clone, Copy)]
+struct Pipe {
+    buf: [u8; PIPE_SIZE],
+    start: usize,
+    len: usize,
+    reader: bool,
+    writer: bool,
+}
+
+const EMPTY: Pipe = Pipe {
+    buf: [0u8; PIPE_SIZE],
+    start: 0,
+    len: 0,
+    reader: false,
+    writer: false,
+};
+
+static mut PIPES: [Pipe; MAX_PIPES] = [EMPTY; MAX_PIPES];
+
+fn err(errno: ErrNo) -> i64 {
+    errno.into()
+}
+
+fn pipe(pipe: usize) -> Result<&'static mut Pipe, i64> {
+    unsafe { PIPES.get_mut(pipe) }
+        .filter(|p| p.reader || p.writer)
+        .ok_or_else(|| err(ErrNo::EBADF))
+}
+
+/// Create a pipe with both ends open, returns its index
+pub fn create() -> Result<usize, i64> {
+    let index = unsafe { PIPES.iter().position(|p| !p.reader && !p.writer) }
+        .ok_or_else(|| err(ErrNo::ENFILE))?;
+    unsafe {
+        PIPES[index] = Pipe {
+            reader: true,
+            writer: true,
+            ..EMPTY
+        };
+    }
+    Ok(index)
+}
+
+/// Number of bytes in `pipe`
+pub fn available(index: usize) -> Result<usize, i64> {
+    Ok(pipe(index)?.len)
+}
+
+/// Read from `pipe`, `0` at the end of the data of a closed writer
+pub fn read(index: usize, buf: &mut [u8]) -> Result<usize, i64> {
+    let p = pipe(index)?;
+    if p.len == 0 {
+        if p.writer && !buf.is_empty() {
+            return Err(err(ErrNo::EAGAIN));
+        }
+        return Ok(0);
+    }
+
+    let len = buf.len().min(p.len);
+    for (i, b) in buf[..len].iter_mut().enumerate() {
+        *b = p.buf[(p.start + i) % PIPE_SIZE];
+    }
+    p.start = (p.start + len) % PIPE_SIZE;
+    p.len -= len;
+    Ok(len)
+}
+
+/// Write to `pipe`, as much as fits
+pub fn write(index: usize, buf: &[u8]) -> Result<usize, i64> {
+    let p = pipe(index)?;
+    if !p.reader {
+        return Err(err(ErrNo::EPIPE));
+    }
+
+    let len = buf.len().min(PIPE_SIZE - p.len);
+    if len == 0 && !buf.is_empty() {
+        return Err(err(ErrNo::EAGAIN));
+    }
+
+    let end = p.start + p.len;
+    for (i, b) in buf[..len].iter().enumerate() {
+        p.buf[(end + i) % PIPE_SIZE] = *b;
+    }
+    p.len += len;
+    Ok(len)
+}
+
+/// The ready `poll()` events of the read or the write end of `pipe`
+pub fn poll(index: usize, write_end: bool) -> Result<i16, i64> {
+    let p = pipe(index)?;
+    let mut events = 0;
+    if write_end {
+        if !p.reader {
+            events |= POLLERR;
+        } else if p.len < PIPE_SIZE {
+            events |= POLLOUT;
+        }
+    } else {
+        if p.len > 0 {
+            events |= POLLIN;
+        }
+        if !p.writer {
+            events |= POLLHUP;
+        }
+    }
+    Ok(events)
+}
+
+/// Close the read or the write end of `pipe`, the pipe is freed with both
+pub fn close(index: usize, write_end: bool) -> Result<(), i64> {
+    let p = pipe(index)?;
+    if write_end {
+        p.writer = false;
+    } else {
+        p.reader = false;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[test_case]
+fn test_pipe() {
+    use crate::{serial_print, serial_println};
+    serial_print!("test_pipe...");
+
+    let index = create().unwrap();
+    let mut buf = [0u8; 8];
+    assert_eq!(read(index, &mut buf), Err(err(ErrNo::EAGAIN)));
+    assert_eq!(poll(index, false), Ok(0));
+    assert_eq!(poll(index, true), Ok(POLLOUT));
+    assert_eq!(write(index, b"hello"), Ok(5));
+    assert_eq!(poll(index, false), Ok(POLLIN));
+    assert_eq!(available(index), Ok(5));
+    assert_eq!(read(index, &mut buf[..3]), Ok(3));
+    assert_eq!(&buf[..3], b"hel");
+
+    // Wraps around the end of the buffer
+    let big = [1u8; PIPE_SIZE];
+    assert_eq!(write(index, &big), Ok(PIPE_SIZE - 2));
+    assert_eq!(write(index, b"x"), Err(err(ErrNo::EAGAIN)));
+    assert_eq!(poll(index, true), Ok(0));
+    assert_eq!(read(index, &mut buf[..2]), Ok(2));
+    assert_eq!(&buf[..2], b"lo");
+    assert_eq!(write(index, b"ab"), Ok(2));
+
+    close(index, true).unwrap();
+    assert_eq!(available(index), Ok(PIPE_SIZE));
+    assert_eq!(poll(index, false), Ok(POLLIN | POLLHUP));
+    close(index, false).unwrap();
+    assert_eq!(read(index, &mut buf), Err(err(ErrNo::EBADF)));
+    serial_println!("[ok]");
+}
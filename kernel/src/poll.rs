@@ -0,0 +1,267 @@
+//! Readiness of file descriptors for `poll()`, `select()` and `epoll`
+//!
+//! Every [`crate::fd::FdObject`] reports the events the kernel knows: pipes
+//! and eventfds are checked in the kernel and files are always ready. Sockets
+//! and the console are asked from the hypervisor with one `VmSyscall::Poll`,
+//! which also does the waiting. There is only one thread, so nothing in the
+//! kernel can become ready while the hypervisor waits.
+//!
+//! Errors are returned as positive `errno` values.
+
+use crate::fd::{self, MAX_FDS};
+use linux_errno::ErrNo;
+use vmsyscall::MAX_POLL_FDS;
+
+pub const POLLIN: i16 = 0x1;
+pub const POLLPRI: i16 = 0x2;
+pub const POLLOUT: i16 = 0x4;
+pub const POLLERR: i16 = 0x8;
+pub const POLLHUP: i16 = 0x10;
+pub const POLLNVAL: i16 = 0x20;
+pub const POLLRDNORM: i16 = 0x40;
+pub const POLLWRNORM: i16 = 0x100;
+
+/// Maximum number of file descriptors of `select()`, the bits of an `fd_set`
+pub const FD_SETSIZE: usize = 1024;
+
+/// `struct pollfd` of the app
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+
+/// `fd_set` of `select()`, a bit per file descriptor
+pub type FdSet = [u64; FD_SETSIZE / 64];
+
+fn err(errno: ErrNo) -> i64 {
+    errno.into()
+}
+
+/// Set the `revents` of `fds`, returns the number of ready ones
+///
+/// Waits up to `timeout` milliseconds if none is ready, `-1` waits forever.
+/// A negative `fd` is ignored, an unknown one is `POLLNVAL`.
+pub fn poll(fds: &mut [PollFd], timeout: i32) -> Result<usize, i64> {
+    if fds.len() > MAX_FDS {
+        return Err(err(ErrNo::EINVAL));
+    }
+
+    let mut host = [vmsyscall::PollFd::default(); MAX_POLL_FDS];
+    let mut host_index = [0usize; MAX_POLL_FDS];
+    let mut nhost = 0;
+
+    for (index, p) in fds.iter_mut().enumerate() {
+        p.revents = 0;
+        if p.fd < 0 {
+            continue;
+        }
+        match fd::poll(p.fd as _, p.events) {
+            Ok((revents, remote)) => {
+                p.revents = revents;
+                if let Some(remote) = remote {
+                    if nhost == MAX_POLL_FDS {
+                        return Err(err(ErrNo::ENOMEM));
+                    }
+                    host[nhost] = remote;
+                    host_index[nhost] = index;
+                    nhost += 1;
+                }
+            }
+            Err(_) => p.revents = POLLNVAL,
+        }
+    }
+
+    let ready = fds.iter().filter(|p| p.revents != 0).count();
+    if nhost == 0 && (ready > 0 || timeout == 0) {
+        return Ok(ready);
+    }
+
+    // Ask the hypervisor without waiting, if the kernel has something ready
+    let timeout = if ready > 0 { 0 } else { timeout };
+    let revents = host::poll(&host[..nhost], timeout)?;
+    for (revents, index) in revents[..nhost].iter().zip(&host_index[..nhost]) {
+        fds[*index].revents |= revents;
+    }
+    Ok(fds.iter().filter(|p| p.revents != 0).count())
+}
+
+fn is_set(set: &Option<&mut FdSet>, fd: usize) -> bool {
+    set.as_ref()
+        .is_some_and(|set| set[fd / 64] & (1 << (fd % 64)) != 0)
+}
+
+fn set(set: &mut Option<&mut FdSet>, fd: usize) -> usize {
+    match set {
+        Some(set) => {
+            set[fd / 64] |= 1 << (fd % 64);
+            1
+        }
+        None => 0,
+    }
+}
+
+/// Clear the first `words` of `set`, the sets of the app may be shorter
+fn clear(set: &mut Option<&mut FdSet>, words: usize) {
+    if let Some(set) = set {
+        set[..words].iter_mut().for_each(|word| *word = 0);
+    }
+}
+
+/// `select()` on top of [`poll`], returns the number of set bits
+pub fn select(
+    nfds: usize,
+    mut read: Option<&mut FdSet>,
+    mut write: Option<&mut FdSet>,
+    mut except: Option<&mut FdSet>,
+    timeout: i32,
+) -> Result<usize, i64> {
+    if nfds > FD_SETSIZE {
+        return Err(err(ErrNo::EINVAL));
+    }
+
+    let mut fds = [PollFd::default(); MAX_FDS];
+    let mut n = 0;
+    for fd in 0..nfds {
+        let mut events = 0;
+        if is_set(&read, fd) {
+            events |= POLLIN;
+        }
+        if is_set(&write, fd) {
+            events |= POLLOUT;
+        }
+        if is_set(&except, fd) {
+            events |= POLLPRI;
+        }
+        if events == 0 {
+            continue;
+        }
+        if fd >= MAX_FDS {
+            return Err(err(ErrNo::EBADF));
+        }
+        fds[n] = PollFd {
+            fd: fd as _,
+            events,
+            revents: 0,
+        };
+        n += 1;
+    }
+
+    poll(&mut fds[..n], timeout)?;
+    if fds[..n].iter().any(|p| p.revents & POLLNVAL != 0) {
+        return Err(err(ErrNo::EBADF));
+    }
+
+    let words = (nfds + 63) / 64;
+    clear(&mut read, words);
+    clear(&mut write, words);
+    clear(&mut except, words);
+
+    let mut count = 0;
+    for p in &fds[..n] {
+        let fd = p.fd as usize;
+        if p.revents & (POLLIN | POLLHUP | POLLERR) != 0 && p.events & POLLIN != 0 {
+            count += set(&mut read, fd);
+        }
+        if p.revents & (POLLOUT | POLLERR) != 0 && p.events & POLLOUT != 0 {
+            count += set(&mut write, fd);
+        }
+        if p.revents & POLLPRI != 0 {
+            count += set(&mut except, fd);
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(not(feature = "qemu"))]
+mod host {
+    use crate::libc;
+    use linux_errno::ErrNo;
+    use vmsyscall::{Error, PollFd, MAX_POLL_FDS};
+
+    pub fn poll(fds: &[PollFd], timeout: i32) -> Result<[i16; MAX_POLL_FDS], i64> {
+        match libc::poll(fds, timeout) {
+            Ok((_, revents)) => Ok(revents),
+            Err(Error::Errno(e)) => Err(e),
+            Err(_) => Err(ErrNo::EIO.into()),
+        }
+    }
+}
+
+/// Without a hypervisor the console is the always writable serial port and
+/// there are no sockets
+#[cfg(feature = "qemu")]
+mod host {
+    use super::{POLLNVAL, POLLOUT, POLLWRNORM};
+    use vmsyscall::{PollFd, PollKind, MAX_POLL_FDS};
+
+    pub fn poll(fds: &[PollFd], _timeout: i32) -> Result<[i16; MAX_POLL_FDS], i64> {
+        let mut revents = [0i16; MAX_POLL_FDS];
+        for (revents, fd) in revents.iter_mut().zip(fds) {
+            *revents = match fd.kind {
+                PollKind::Stdio => fd.events & (POLLOUT | POLLWRNORM),
+                PollKind::Socket => POLLNVAL,
+            };
+        }
+        Ok(revents)
+    }
+}
+
+#[cfg(test)]
+#[test_case]
+fn test_poll_pipes() {
+    use crate::{serial_print, serial_println};
+    serial_print!("test_poll_pipes...");
+
+    let (rfd, wfd) = fd::pipe2(0).unwrap();
+    let mut fds = [
+        PollFd {
+            fd: rfd as _,
+            events: POLLIN,
+            revents: 0,
+        },
+        PollFd {
+            fd: wfd as _,
+            events: POLLOUT,
+            revents: 0,
+        },
+        PollFd {
+            fd: -1,
+            events: POLLIN,
+            revents: 0,
+        },
+        PollFd {
+            fd: (MAX_FDS - 1) as _,
+            events: POLLIN,
+            revents: 0,
+        },
+    ];
+    assert_eq!(poll(&mut fds[..3], 0), Ok(1));
+    assert_eq!((fds[0].revents, fds[1].revents), (0, POLLOUT));
+    assert_eq!(poll(&mut fds, 0), Ok(2));
+    assert_eq!(fds[3].revents, POLLNVAL);
+
+    fd::write(wfd, b"x").unwrap();
+    let mut read = [0u64; FD_SETSIZE / 64];
+    let mut write = [0u64; FD_SETSIZE / 64];
+    read[0] = 1 << rfd;
+    write[0] = 1 << wfd | 1 << rfd;
+    assert_eq!(
+        select(wfd + 1, Some(&mut read), Some(&mut write), None, 0),
+        Ok(2)
+    );
+    assert_eq!((read[0], write[0]), (1 << rfd, 1 << wfd));
+
+    fd::close(wfd).unwrap();
+    assert_eq!(poll(&mut fds[..1], 0), Ok(1));
+    assert_eq!(fds[0].revents, POLLIN | POLLHUP);
+    fd::close(rfd).unwrap();
+    read[0] = 1 << rfd;
+    assert_eq!(
+        select(rfd + 1, Some(&mut read), None, None, 0),
+        Err(err(ErrNo::EBADF))
+    );
+    serial_println!("[ok]");
+}
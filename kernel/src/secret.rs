@@ -0,0 +1,157 @@
+//! The secret of the workload
+//!
+//! The key broker of the guest owner injects the key into the private launch
+//! secret page, before the guest runs. The sealed secret is fetched from the
+//! hypervisor with `VmSyscall::GetSecret` and opened with that key.
+//!
+//! The app can read the secret from the file [`SECRET_PATH`].
+
+use crate::{error, warn};
+use vmsyscall::bootinfo::BootInfo;
+use vmsyscall::secret::{LaunchSecret, SecretBlob, KEY_LEN};
+
+/// Path of the secret file for the app
+pub const SECRET_PATH: &str = "/run/enarx/secret";
+
+static mut SECRET: Option<SecretBlob> = None;
+static mut PLAIN_TEXT: Option<&'static [u8]> = None;
+static mut FILE_POS: Option<usize> = None;
+
+/// Unwrap the secret with the key injected at launch
+///
+/// Has to be called after the syscall page is shared with the host.
+pub fn init(boot_info: &BootInfo) {
+    if boot_info.secret == 0 {
+        return;
+    }
+
+    let mut key = match take_key(boot_info.secret) {
+        Some(key) => key,
+        None => {
+            warn!("No key in the launch secret page");
+            return;
+        }
+    };
+
+    match fetch() {
+        Some(blob) => {
+            if unwrap(blob, &key).is_none() {
+                error!("Failed to unwrap the secret");
+            }
+        }
+        None => warn!("No secret provided by the hypervisor"),
+    }
+
+    wipe(&mut key);
+}
+
+/// Read the key from the launch secret page at `addr` and wipe the page
+pub fn take_key(addr: u64) -> Option<[u8; KEY_LEN]> {
+    let page = addr as *mut LaunchSecret;
+    let mut launch_secret = unsafe { page.read_volatile() };
+    unsafe { page.write_volatile(core::mem::zeroed()) };
+
+    let key = launch_secret.key().copied();
+    wipe(&mut launch_secret.key);
+    key
+}
+
+/// Open `blob` with `key` and keep the secret for the app
+pub fn unwrap(blob: SecretBlob, key: &[u8; KEY_LEN]) -> Option<&'static [u8]> {
+    unsafe {
+        PLAIN_TEXT.take();
+        if let Some(old) = SECRET.as_mut() {
+            wipe_blob(old);
+        }
+
+        SECRET.replace(blob);
+        PLAIN_TEXT = SECRET.as_mut().unwrap().open(key).ok();
+        PLAIN_TEXT
+    }
+}
+
+/// The unwrapped secret, if there is one
+pub fn get() -> Option<&'static [u8]> {
+    unsafe { PLAIN_TEXT }
+}
+
+#[cfg(not(feature = "qemu"))]
+fn fetch() -> Option<SecretBlob> {
+    use vmsyscall::{VmSyscall, VmSyscallRet};
+
+    match crate::libc::vm_syscall(VmSyscall::GetSecret) {
+        Ok(VmSyscallRet::GetSecret(Ok(blob))) => Some(blob),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "qemu")]
+fn fetch() -> Option<SecretBlob> {
+    None
+}
+
+fn wipe(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        unsafe { (b as *mut u8).write_volatile(0) };
+    }
+}
+
+fn wipe_blob(blob: &mut SecretBlob) {
+    unsafe { (blob as *mut SecretBlob).write_volatile(core::mem::zeroed()) };
+}
+
+/// Open the secret file
+///
+/// `false`, if there is no secret or the file is already open.
+pub fn open_file() -> bool {
+    unsafe {
+        if get().is_none() || FILE_POS.is_some() {
+            return false;
+        }
+        FILE_POS.replace(0);
+    }
+    true
+}
+
+/// Read from the secret file, `None` if it is not open
+pub fn read_file(buf: &mut [u8]) -> Option<usize> {
+    let secret = get()?;
+    let pos = unsafe { FILE_POS.as_mut()? };
+
+    let len = buf.len().min(secret.len() - *pos);
+    buf[..len].copy_from_slice(&secret[*pos..*pos + len]);
+    *pos += len;
+    Some(len)
+}
+
+/// Close the secret file, `false` if it is not open
+pub fn close_file() -> bool {
+    unsafe { FILE_POS.take().is_some() }
+}
+
+#[cfg(test)]
+#[test_case]
+fn test_unwrap_secret() {
+    use crate::{serial_print, serial_println};
+    serial_print!("test_unwrap_secret...");
+    let key = [0x42u8; KEY_LEN];
+    let blob = SecretBlob::seal(&key, &[1u8; 12], b"kernel secret").unwrap();
+
+    assert!(unwrap(blob, &[0x43u8; KEY_LEN]).is_none());
+    assert_eq!(get(), None);
+
+    assert_eq!(unwrap(blob, &key), Some(&b"kernel secret"[..]));
+    assert_eq!(get(), Some(&b"kernel secret"[..]));
+
+    assert!(open_file());
+    assert!(!open_file());
+    let mut buf = [0u8; 8];
+    assert_eq!(read_file(&mut buf), Some(8));
+    assert_eq!(&buf, b"kernel s");
+    assert_eq!(read_file(&mut buf), Some(5));
+    assert_eq!(&buf[..5], b"ecret");
+    assert_eq!(read_file(&mut buf), Some(0));
+    assert!(close_file());
+    assert_eq!(read_file(&mut buf), None);
+    serial_println!("[ok]");
+}
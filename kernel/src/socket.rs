@@ -0,0 +1,320 @@
+//! Sockets of the host
+//!
+//! The socket syscalls of the app are forwarded to the hypervisor, which
+//! creates real sockets on the host. It checks the addresses of `bind`,
+//! `listen`, `connect` and `sendto` against its `--allow-listen` and
+//! `--allow-connect` rules. A socket is a handle of the hypervisor, the file
+//! descriptor table holds it in a [`crate::fd::Socket`].
+//!
+//! Addresses are passed on as the `struct sockaddr` bytes of the app.
+//!
+//! Errors are returned as positive `errno` values.
+
+use linux_errno::ErrNo;
+use vmsyscall::{SockAddr, SOCKET_BUF_LEN};
+
+pub const AF_UNIX: usize = 1;
+pub const AF_INET: usize = 2;
+pub const AF_INET6: usize = 10;
+
+pub const SOCK_STREAM: usize = 1;
+pub const SOCK_DGRAM: usize = 2;
+pub const SOCK_SEQPACKET: usize = 5;
+pub const SOCK_TYPE_MASK: usize = 0xf;
+pub const SOCK_NONBLOCK: usize = 0o4000;
+pub const SOCK_CLOEXEC: usize = 0o2_000_000;
+
+pub const MSG_DONTWAIT: usize = 0x40;
+
+fn err(errno: ErrNo) -> i64 {
+    errno.into()
+}
+
+/// The `struct sockaddr` bytes of the app
+fn sockaddr(bytes: &[u8]) -> Result<SockAddr, i64> {
+    SockAddr::new(bytes).ok_or_else(|| err(ErrNo::EINVAL))
+}
+
+/// Create a socket of `ty` without the flags, returns its handle
+pub fn socket(domain: usize, ty: usize, protocol: usize) -> Result<u32, i64> {
+    host::socket(domain as _, (ty & SOCK_TYPE_MASK) as _, protocol as _)
+}
+
+/// Create a pair of connected Unix sockets
+pub fn socketpair(domain: usize, ty: usize, protocol: usize) -> Result<(u32, u32), i64> {
+    host::socketpair(domain as _, (ty & SOCK_TYPE_MASK) as _, protocol as _)
+}
+
+pub fn bind(handle: u32, addr: &[u8]) -> Result<(), i64> {
+    host::bind(handle, sockaddr(addr)?)
+}
+
+pub fn listen(handle: u32, backlog: usize) -> Result<(), i64> {
+    host::listen(handle, backlog.min(i32::MAX as usize) as _)
+}
+
+/// Accept a connection, returns its handle and the address of the peer
+pub fn accept(handle: u32, nonblock: bool) -> Result<(u32, SockAddr), i64> {
+    host::accept(handle, nonblock)
+}
+
+pub fn connect(handle: u32, addr: &[u8], nonblock: bool) -> Result<(), i64> {
+    host::connect(handle, sockaddr(addr)?, nonblock)
+}
+
+/// Send `buf` in chunks fitting the syscall page
+///
+/// A datagram has to fit in one chunk. After an error the bytes sent so far
+/// are returned.
+pub fn send(
+    handle: u32,
+    buf: &[u8],
+    flags: usize,
+    addr: Option<&[u8]>,
+    stream: bool,
+    nonblock: bool,
+) -> Result<usize, i64> {
+    if !stream && buf.len() > SOCKET_BUF_LEN {
+        return Err(err(ErrNo::EMSGSIZE));
+    }
+    let addr = addr.map(sockaddr).transpose()?;
+    let flags = (flags & !MSG_DONTWAIT) as i32;
+
+    let mut done = 0;
+    loop {
+        match host::sendto(handle, &buf[done..], flags, addr, nonblock) {
+            Ok(0) => break,
+            Ok(len) => done += len,
+            Err(errno) if done == 0 => return Err(errno),
+            Err(_) => break,
+        }
+        if done >= buf.len() {
+            break;
+        }
+    }
+    Ok(done)
+}
+
+/// Receive into `buf`, returns the length and the address of the sender
+///
+/// At most one chunk fitting the syscall page is received.
+pub fn recv(
+    handle: u32,
+    buf: &mut [u8],
+    flags: usize,
+    nonblock: bool,
+) -> Result<(usize, SockAddr), i64> {
+    host::recvfrom(handle, buf, (flags & !MSG_DONTWAIT) as _, nonblock)
+}
+
+pub fn getsockname(handle: u32) -> Result<SockAddr, i64> {
+    host::getsockname(handle)
+}
+
+pub fn getpeername(handle: u32) -> Result<SockAddr, i64> {
+    host::getpeername(handle)
+}
+
+pub fn setsockopt(handle: u32, level: usize, name: usize, value: &[u8]) -> Result<(), i64> {
+    if value.len() > vmsyscall::SOCKOPT_LEN {
+        return Err(err(ErrNo::EINVAL));
+    }
+    host::setsockopt(handle, level as _, name as _, value)
+}
+
+/// Read a socket option into `value`, returns its length
+pub fn getsockopt(handle: u32, level: usize, name: usize, value: &mut [u8]) -> Result<usize, i64> {
+    host::getsockopt(handle, level as _, name as _, value)
+}
+
+pub fn shutdown(handle: u32, how: usize) -> Result<(), i64> {
+    host::shutdown(handle, how as _)
+}
+
+pub fn close(handle: u32) -> Result<(), i64> {
+    host::close(handle)
+}
+
+#[cfg(not(feature = "qemu"))]
+mod host {
+    use crate::libc;
+    use linux_errno::ErrNo;
+    use vmsyscall::{Error, SockAddr};
+
+    fn errno(e: Error) -> i64 {
+        match e {
+            Error::Errno(e) => e,
+            _ => ErrNo::EIO.into(),
+        }
+    }
+
+    pub fn socket(domain: i32, ty: i32, protocol: i32) -> Result<u32, i64> {
+        libc::socket(domain, ty, protocol)
+            .map(|fd| fd as _)
+            .map_err(errno)
+    }
+
+    pub fn socketpair(domain: i32, ty: i32, protocol: i32) -> Result<(u32, u32), i64> {
+        libc::socketpair(domain, ty, protocol)
+            .map(|(a, b)| (a as _, b as _))
+            .map_err(errno)
+    }
+
+    pub fn bind(fd: u32, addr: SockAddr) -> Result<(), i64> {
+        libc::bind(fd, addr).map(|_| ()).map_err(errno)
+    }
+
+    pub fn listen(fd: u32, backlog: i32) -> Result<(), i64> {
+        libc::listen(fd, backlog).map(|_| ()).map_err(errno)
+    }
+
+    pub fn accept(fd: u32, nonblock: bool) -> Result<(u32, SockAddr), i64> {
+        libc::accept(fd, nonblock)
+            .map(|(fd, addr)| (fd as _, addr))
+            .map_err(errno)
+    }
+
+    pub fn connect(fd: u32, addr: SockAddr, nonblock: bool) -> Result<(), i64> {
+        libc::connect(fd, addr, nonblock).map(|_| ()).map_err(errno)
+    }
+
+    pub fn sendto(
+        fd: u32,
+        buf: &[u8],
+        flags: i32,
+        addr: Option<SockAddr>,
+        nonblock: bool,
+    ) -> Result<usize, i64> {
+        libc::sendto(fd, buf, flags, addr, nonblock).map_err(errno)
+    }
+
+    pub fn recvfrom(
+        fd: u32,
+        buf: &mut [u8],
+        flags: i32,
+        nonblock: bool,
+    ) -> Result<(usize, SockAddr), i64> {
+        libc::recvfrom(fd, buf, flags, nonblock).map_err(errno)
+    }
+
+    pub fn getsockname(fd: u32) -> Result<SockAddr, i64> {
+        libc::getsockname(fd).map_err(errno)
+    }
+
+    pub fn getpeername(fd: u32) -> Result<SockAddr, i64> {
+        libc::getpeername(fd).map_err(errno)
+    }
+
+    pub fn setsockopt(fd: u32, level: i32, name: i32, value: &[u8]) -> Result<(), i64> {
+        libc::setsockopt(fd, level, name, value)
+            .map(|_| ())
+            .map_err(errno)
+    }
+
+    pub fn getsockopt(fd: u32, level: i32, name: i32, value: &mut [u8]) -> Result<usize, i64> {
+        libc::getsockopt(fd, level, name, value).map_err(errno)
+    }
+
+    pub fn shutdown(fd: u32, how: i32) -> Result<(), i64> {
+        libc::shutdown(fd, how).map(|_| ()).map_err(errno)
+    }
+
+    pub fn close(fd: u32) -> Result<(), i64> {
+        libc::close_socket(fd).map(|_| ()).map_err(errno)
+    }
+}
+
+/// There is no hypervisor to create sockets, so there are no handles
+#[cfg(feature = "qemu")]
+mod host {
+    use linux_errno::ErrNo;
+    use vmsyscall::SockAddr;
+
+    pub fn socket(_domain: i32, _ty: i32, _protocol: i32) -> Result<u32, i64> {
+        Err(ErrNo::EAFNOSUPPORT.into())
+    }
+
+    pub fn socketpair(_domain: i32, _ty: i32, _protocol: i32) -> Result<(u32, u32), i64> {
+        Err(ErrNo::EAFNOSUPPORT.into())
+    }
+
+    pub fn bind(_fd: u32, _addr: SockAddr) -> Result<(), i64> {
+        Err(ErrNo::EBADF.into())
+    }
+
+    pub fn listen(_fd: u32, _backlog: i32) -> Result<(), i64> {
+        Err(ErrNo::EBADF.into())
+    }
+
+    pub fn accept(_fd: u32, _nonblock: bool) -> Result<(u32, SockAddr), i64> {
+        Err(ErrNo::EBADF.into())
+    }
+
+    pub fn connect(_fd: u32, _addr: SockAddr, _nonblock: bool) -> Result<(), i64> {
+        Err(ErrNo::EBADF.into())
+    }
+
+    pub fn sendto(
+        _fd: u32,
+        _buf: &[u8],
+        _flags: i32,
+        _addr: Option<SockAddr>,
+        _nonblock: bool,
+    ) -> Result<usize, i64> {
+        Err(ErrNo::EBADF.into())
+    }
+
+    pub fn recvfrom(
+        _fd: u32,
+        _buf: &mut [u8],
+        _flags: i32,
+        _nonblock: bool,
+    ) -> Result<(usize, SockAddr), i64> {
+        Err(ErrNo::EBADF.into())
+    }
+
+    pub fn getsockname(_fd: u32) -> Result<SockAddr, i64> {
+        Err(ErrNo::EBADF.into())
+    }
+
+    pub fn getpeername(_fd: u32) -> Result<SockAddr, i64> {
+        Err(ErrNo::EBADF.into())
+    }
+
+    pub fn setsockopt(_fd: u32, _level: i32, _name: i32, _value: &[u8]) -> Result<(), i64> {
+        Err(ErrNo::EBADF.into())
+    }
+
+    pub fn getsockopt(_fd: u32, _level: i32, _name: i32, _value: &mut [u8]) -> Result<usize, i64> {
+        Err(ErrNo::EBADF.into())
+    }
+
+    pub fn shutdown(_fd: u32, _how: i32) -> Result<(), i64> {
+        Err(ErrNo::EBADF.into())
+    }
+
+    pub fn close(_fd: u32) -> Result<(), i64> {
+        Err(ErrNo::EBADF.into())
+    }
+}
+
+#[cfg(test)]
+#[test_case]
+fn test_socket_args() {
+    use crate::{serial_print, serial_println};
+    serial_print!("test_socket_args...");
+    let big = [0u8; SOCKET_BUF_LEN + 1];
+    assert_eq!(
+        send(0, &big, 0, None, false, false),
+        Err(err(ErrNo::EMSGSIZE))
+    );
+    assert_eq!(
+        bind(0, &[0u8; vmsyscall::SOCKADDR_LEN + 1]),
+        Err(err(ErrNo::EINVAL))
+    );
+    assert_eq!(
+        setsockopt(0, 1, 1, &[0u8; vmsyscall::SOCKOPT_LEN + 1]),
+        Err(err(ErrNo::EINVAL))
+    );
+    serial_println!("[ok]");
+}
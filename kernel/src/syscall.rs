@@ -1,9 +1,22 @@
-use crate::arch::x86_64::{brk_user, mmap_user, NEXT_MMAP};
+use crate::arch::x86_64::{
+    brk_user, mmap_user, mmap_user_fixed, mprotect_user, protect_pending_relro, tls, NEXT_MMAP,
+    PAGESIZE,
+};
 //use crate::arch::SyscallStack;
-use crate::{eprintln, exit_hypervisor, print, HyperVisorExitCode};
+use crate::epoll;
+use crate::fd;
+use crate::hostfs;
+use crate::initrd;
+use crate::poll;
+use crate::socket;
+use crate::tmpfs;
+use crate::trace;
+use crate::{error, exit_hypervisor, warn, HyperVisorExitCode};
 //use vmbootspec::layout::USER_HEAP_OFFSET;
 use linux_errno::ErrNo;
 use linux_syscall::SysCall;
+use vmsyscall::bootinfo::{SyscallPolicy, UnknownSyscall};
+use vmsyscall::{syscalls, SockAddr, SOCKET_BUF_LEN};
 
 trait NegAsUsize {
     fn neg_as_usize(self) -> usize;
@@ -15,6 +28,17 @@ impl NegAsUsize for ErrNo {
     }
 }
 
+/// `close_range()` is matched by number, it is newer than the `linux_syscall` table
+const SYS_CLOSE_RANGE: usize = 436;
+
+/// The syscall policy of the `BootInfo`, the defaults until it is set
+static mut POLICY: Option<SyscallPolicy> = None;
+
+/// Set the policy for unknown and denied syscalls
+pub fn set_policy(policy: &SyscallPolicy) {
+    unsafe { POLICY.replace(*policy) };
+}
+
 extern "C" {
     fn _read_rsp() -> u64;
 }
@@ -24,9 +48,302 @@ pub fn read_rsp() -> u64 {
     unsafe { _read_rsp() }
 }
 
-extern "C" {
-    fn _rdfsbase() -> u64;
-    fn _wrfsbase(val: u64);
+/// The syscall return value of a `hostfs` result
+fn host_ret(ret: Result<usize, i64>) -> usize {
+    match ret {
+        Ok(v) => v,
+        Err(errno) => -errno as _,
+    }
+}
+
+fn makedev(x: u64, y: u64) -> u64 {
+    (((x) & 0xffff_f000u64) << 32)
+        | (((x) & 0x0000_0fffu64) << 8)
+        | (((y) & 0xffff_ff00u64) << 12)
+        | ((y) & 0x0000_00ffu64)
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Stat {
+    pub st_dev: u64,
+    pub st_ino: u64,
+    pub st_nlink: u64,
+    pub st_mode: u32,
+    pub st_uid: u32,
+    pub st_gid: u32,
+    pub __pad0: i32,
+    pub st_rdev: u64,
+    pub st_size: i64,
+    pub st_blksize: i64,
+    pub st_blocks: i64,
+    pub st_atime: timespec,
+    pub st_mtime: timespec,
+    pub st_ctime: timespec,
+    pub __glibc_reserved: [i64; 3usize],
+}
+
+/// Fill `stat` for the serial console
+pub fn stat_console(p: &mut Stat) -> Result<usize, i64> {
+    *p = unsafe { core::mem::zeroed() };
+    p.st_dev = makedev(0, 0x17);
+    p.st_ino = 3;
+    p.st_mode = 0o020_000 | 0o620; // S_IFCHR
+    p.st_nlink = 1;
+    p.st_uid = 1000;
+    p.st_gid = 5;
+    p.st_blksize = 1024;
+    p.st_blocks = 0;
+    p.st_rdev = makedev(0x88, 0);
+    p.st_atime.tv_sec = 1_579_507_218; /* 2020-01-21T11:45:08.467721685+0100 */
+    p.st_mtime.tv_sec = 1_579_507_218; /* 2020-01-21T11:45:07.467721685+0100 */
+    p.st_ctime.tv_sec = 1_579_507_218; /* 2020-01-20T09:00:18.467721685+0100 */
+    Ok(0)
+}
+
+/// Fill `stat` for the [`hostfs`] handle
+pub fn stat_host_file(handle: usize, p: &mut Stat) -> Result<usize, i64> {
+    let (mode, size) = hostfs::stat(handle)?;
+    *p = unsafe { core::mem::zeroed() };
+    p.st_dev = makedev(0, 0x18);
+    p.st_ino = handle as u64 + 1;
+    p.st_mode = mode;
+    p.st_nlink = 1;
+    p.st_size = size as _;
+    p.st_blksize = 4096;
+    p.st_blocks = ((size + 511) / 512) as _;
+    Ok(0)
+}
+
+/// Fill `stat` for an initrd file
+pub fn stat_initrd_file(meta: initrd::Metadata, p: &mut Stat) -> Result<usize, i64> {
+    *p = unsafe { core::mem::zeroed() };
+    p.st_dev = makedev(0, 0x19);
+    p.st_ino = meta.ino;
+    p.st_mode = meta.mode;
+    p.st_nlink = if meta.mode & initrd::S_IFMT == initrd::S_IFDIR {
+        2
+    } else {
+        1
+    };
+    p.st_size = meta.size as _;
+    p.st_blksize = 4096;
+    p.st_blocks = ((meta.size + 511) / 512) as _;
+    p.st_atime.tv_sec = meta.mtime as _;
+    p.st_mtime.tv_sec = meta.mtime as _;
+    p.st_ctime.tv_sec = meta.mtime as _;
+    Ok(0)
+}
+
+/// Fill `stat` for a tmpfs file
+pub fn stat_tmpfs_file(meta: tmpfs::Metadata, p: &mut Stat) -> Result<usize, i64> {
+    *p = unsafe { core::mem::zeroed() };
+    p.st_dev = makedev(0, 0x1a);
+    p.st_ino = meta.ino;
+    p.st_mode = meta.mode;
+    p.st_nlink = meta.nlink as _;
+    p.st_size = meta.size as _;
+    p.st_blksize = 4096;
+    p.st_blocks = meta.blocks as _;
+    Ok(0)
+}
+
+/// Fill `stat` for `pathname`, from the tmpfs, the initrd or else from the host
+fn stat_path(pathname: &str, stat: &mut Stat) -> Result<usize, i64> {
+    if tmpfs::is_mounted(pathname) {
+        return stat_tmpfs_file(tmpfs::stat_path(pathname)?, stat);
+    }
+    match initrd::stat_path(pathname) {
+        Ok(meta) => stat_initrd_file(meta, stat),
+        Err(errno) if errno == Into::<i64>::into(ErrNo::ENOENT) => {
+            let handle = hostfs::open(pathname, 0)?;
+            let ret = stat_host_file(handle, stat);
+            hostfs::close(handle)?;
+            ret
+        }
+        Err(errno) => Err(errno),
+    }
+}
+
+/// `d_type` of a directory
+pub const DT_DIR: u8 = 4;
+/// `d_type` of a regular file
+pub const DT_REG: u8 = 8;
+
+/// Write a `struct linux_dirent64` to the start of `buf`, returns its length
+///
+/// Returns `None`, if the record does not fit in `buf`.
+pub fn put_dirent64(buf: &mut [u8], ino: u64, off: i64, d_type: u8, name: &[u8]) -> Option<usize> {
+    // d_ino, d_off, d_reclen, d_type, d_name with NUL, 8 byte aligned
+    let reclen = (19 + name.len() + 1 + 7) / 8 * 8;
+    let rec = buf.get_mut(..reclen)?;
+
+    rec.iter_mut().for_each(|b| *b = 0);
+    rec[0..8].copy_from_slice(&ino.to_ne_bytes());
+    rec[8..16].copy_from_slice(&off.to_ne_bytes());
+    rec[16..18].copy_from_slice(&(reclen as u16).to_ne_bytes());
+    rec[18] = d_type;
+    rec[19..19 + name.len()].copy_from_slice(name);
+    Some(reclen)
+}
+
+/// Only the tmpfs is writable, a rename must stay in it
+fn rename_path(oldpath: &str, newpath: &str) -> Result<usize, i64> {
+    match (tmpfs::is_mounted(oldpath), tmpfs::is_mounted(newpath)) {
+        (true, true) => tmpfs::rename(oldpath, newpath).map(|_| 0),
+        (true, false) | (false, true) => Err(ErrNo::EXDEV.into()),
+        (false, false) => Err(ErrNo::EROFS.into()),
+    }
+}
+
+#[repr(C)]
+struct Iovec {
+    iov_base: u64,  /* Starting address */
+    iov_len: usize, /* Number of bytes to transfer */
+}
+
+/// `struct msghdr` of `sendmsg()` and `recvmsg()`
+#[repr(C)]
+struct MsgHdr {
+    msg_name: usize,
+    msg_namelen: u32,
+    msg_iov: usize,
+    msg_iovlen: usize,
+    msg_control: usize,
+    msg_controllen: usize,
+    msg_flags: i32,
+}
+
+const MSG_TRUNC: i32 = 0x20;
+
+/// The `struct sockaddr` of the app at `ptr`, `None` for NULL
+unsafe fn get_sockaddr<'a>(ptr: usize, len: usize) -> Option<&'a [u8]> {
+    if ptr == 0 {
+        None
+    } else {
+        Some(core::slice::from_raw_parts(ptr as *const u8, len))
+    }
+}
+
+/// Copy `addr` to the `struct sockaddr` of the app at `ptr`
+///
+/// The address is truncated to the `socklen_t` at `len_ptr`, which is set to
+/// the full length. Nothing is copied for a NULL `ptr`.
+unsafe fn put_sockaddr(addr: &SockAddr, ptr: usize, len_ptr: usize) {
+    if ptr == 0 || len_ptr == 0 {
+        return;
+    }
+    let len_ptr = len_ptr as *mut u32;
+    let bytes = addr.as_bytes();
+    let len = bytes.len().min(len_ptr.read() as usize);
+    core::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr as *mut u8, len);
+    len_ptr.write(bytes.len() as _);
+}
+
+/// `sendmsg()` without ancillary data, a datagram is gathered in one buffer
+fn sendmsg(fd: usize, msg: &MsgHdr, flags: usize) -> Result<usize, i64> {
+    let sock = fd::socket_of(fd)?;
+    if msg.msg_controllen != 0 {
+        return Err(ErrNo::EOPNOTSUPP.into());
+    }
+
+    let nonblock = sock.nonblock || flags & socket::MSG_DONTWAIT != 0;
+    let addr = unsafe { get_sockaddr(msg.msg_name, msg.msg_namelen as _) };
+    let iovec = unsafe { core::slice::from_raw_parts(msg.msg_iov as *const Iovec, msg.msg_iovlen) };
+    let iov_buf = |iov: &Iovec| unsafe {
+        core::slice::from_raw_parts(iov.iov_base as *const u8, iov.iov_len)
+    };
+
+    if !sock.stream {
+        let mut buf = [0u8; SOCKET_BUF_LEN];
+        let mut len = 0;
+        for iov in iovec {
+            if iov.iov_len > SOCKET_BUF_LEN - len {
+                return Err(ErrNo::EMSGSIZE.into());
+            }
+            buf[len..len + iov.iov_len].copy_from_slice(iov_buf(iov));
+            len += iov.iov_len;
+        }
+        return socket::send(sock.handle, &buf[..len], flags, addr, false, nonblock);
+    }
+
+    let mut sent = 0;
+    for iov in iovec {
+        let buf = iov_buf(iov);
+        match socket::send(sock.handle, buf, flags, addr, true, nonblock) {
+            Ok(len) => {
+                sent += len;
+                if len < buf.len() {
+                    break;
+                }
+            }
+            Err(errno) if sent == 0 => return Err(errno),
+            Err(_) => break,
+        }
+    }
+    Ok(sent)
+}
+
+/// `recvmsg()` without ancillary data, one chunk is scattered over the buffers
+fn recvmsg(fd: usize, msg: &mut MsgHdr, flags: usize) -> Result<usize, i64> {
+    let sock = fd::socket_of(fd)?;
+    let nonblock = sock.nonblock || flags & socket::MSG_DONTWAIT != 0;
+    let iovec = unsafe { core::slice::from_raw_parts(msg.msg_iov as *const Iovec, msg.msg_iovlen) };
+    let total = iovec
+        .iter()
+        .fold(0usize, |total, iov| total.saturating_add(iov.iov_len));
+
+    let mut buf = [0u8; SOCKET_BUF_LEN];
+    let want = total.min(SOCKET_BUF_LEN);
+    let (len, addr) = socket::recv(sock.handle, &mut buf[..want], flags, nonblock)?;
+
+    let copied = len.min(want);
+    let mut done = 0;
+    for iov in iovec {
+        let n = iov.iov_len.min(copied - done);
+        let dst = unsafe { core::slice::from_raw_parts_mut(iov.iov_base as *mut u8, n) };
+        dst.copy_from_slice(&buf[done..done + n]);
+        done += n;
+    }
+
+    let namelen = &mut msg.msg_namelen as *mut u32 as usize;
+    unsafe { put_sockaddr(&addr, msg.msg_name, namelen) };
+    msg.msg_controllen = 0;
+    msg.msg_flags = if len > copied { MSG_TRUNC } else { 0 };
+    Ok(len)
+}
+
+/// The `struct timespec` at `ptr`, or with `frac_ns` 1000 the `struct timeval`,
+/// in milliseconds rounded up, `-1` for NULL
+unsafe fn timeout_ms(ptr: usize, frac_ns: i64) -> Result<i32, i64> {
+    if ptr == 0 {
+        return Ok(-1);
+    }
+    let [sec, frac] = (ptr as *const [i64; 2]).read();
+    if sec < 0 || frac < 0 || frac >= 1_000_000_000 / frac_ns {
+        return Err(ErrNo::EINVAL.into());
+    }
+    let ms = sec
+        .saturating_mul(1000)
+        .saturating_add((frac * frac_ns + 999_999) / 1_000_000);
+    Ok(ms.min(i32::MAX as i64) as i32)
+}
+
+/// The NUL terminated string at `ptr`
+unsafe fn c_str<'a>(ptr: usize) -> &'a str {
+    let ptr = ptr as *const u8;
+    let mut len = 0;
+    while ptr.add(len).read() != 0 {
+        len += 1;
+    }
+    core::str::from_utf8_unchecked(core::slice::from_raw_parts(ptr, len))
 }
 
 #[allow(clippy::many_single_char_names)]
@@ -40,18 +357,41 @@ pub extern "C" fn handle_syscall(
     f: usize,
     nr: usize,
 ) -> usize {
-    #[cfg(debug_assertions)]
-    eprintln!(
-        "SC> raw: syscall({}, {:#X}, {:#X}, {:#X}, {}, {}, {:#X})",
-        nr, a, b, c, d, e, f
-    );
-
     //eprintln!("stackpointer: {:#X}", read_rsp());
     //eprintln!("stackpointer initial: {:#X}", f);
 
+    // a static-PIE app has relocated itself by now
+    protect_pending_relro();
+
+    let policy = unsafe { POLICY.unwrap_or_default() };
+    let args = [a, b, c, d, e, f];
+    let traced = policy.is_traced(nr as _);
+
+    // exit() does not return, it is traced without a return value
+    let exits = matches!(
+        SysCall::from(nr as u64),
+        SysCall::EXIT | SysCall::EXIT_GROUP
+    );
+    if traced && exits {
+        trace::syscall(nr, &args, None);
+    }
+
+    let ret = if policy.is_denied(nr as _) {
+        ErrNo::EPERM.neg_as_usize()
+    } else {
+        dispatch(nr, args, policy.unknown)
+    };
+
+    if traced {
+        trace::syscall(nr, &args, Some(ret));
+    }
+    ret
+}
+
+#[allow(clippy::many_single_char_names)]
+fn dispatch(nr: usize, [a, b, c, d, e, f]: [usize; 6], unknown: UnknownSyscall) -> usize {
     match SysCall::from(nr as u64) {
         SysCall::EXIT => {
-            eprintln!("SC> exit({})", a);
             exit_hypervisor(if a == 0 {
                 HyperVisorExitCode::Success
             } else {
@@ -60,7 +400,6 @@ pub extern "C" fn handle_syscall(
             loop {}
         }
         SysCall::EXIT_GROUP => {
-            eprintln!("SC> exit_group({})", a);
             exit_hypervisor(if a == 0 {
                 HyperVisorExitCode::Success
             } else {
@@ -69,68 +408,32 @@ pub extern "C" fn handle_syscall(
             loop {}
         }
         SysCall::WRITE => {
-            let fd = a;
-            let data = b as *const u8;
-            let len = c;
-            match fd {
-                1 | 2 => {
-                    let cstr = unsafe { core::slice::from_raw_parts(data, len) };
-                    match core::str::from_utf8(cstr) {
-                        Ok(s) => {
-                            eprintln!("SC> write({}, {:#?}) = {}", fd, s, len);
-                            print!("{}", s);
-                            len
-                        }
-                        Err(_) => {
-                            eprintln!("SC> write({}, …) = -EINVAL", fd);
-                            ErrNo::EINVAL.neg_as_usize()
-                        }
-                    }
-                }
-                _ => {
-                    eprintln!("SC> write({}, \"…\") = -EBADFD", a);
-                    ErrNo::EBADFD.neg_as_usize()
-                }
-            }
+            let buf = unsafe { core::slice::from_raw_parts(b as *const u8, c) };
+            host_ret(fd::write(a, buf))
         }
         SysCall::WRITEV => {
-            struct Iovec {
-                iov_base: u64,  /* Starting address */
-                iov_len: usize, /* Number of bytes to transfer */
-            };
             let fd = a;
             let iov = b as *const Iovec;
             let iovcnt = c;
             let iovec = unsafe { core::slice::from_raw_parts(iov, iovcnt) };
             let mut written: usize = 0;
-            match fd {
-                1 | 2 => {
-                    for iov in iovec {
-                        let data = iov.iov_base as *const u8;
-                        let len = iov.iov_len;
-                        if len == 0 {
-                            continue;
-                        }
-                        let cstr = unsafe { core::slice::from_raw_parts(data, len) };
-                        match core::str::from_utf8(cstr) {
-                            Ok(s) => {
-                                eprintln!("SC> writev({}, {:#?}) = {}", fd, s, len);
-                                print!("{}", s);
-                                written += len;
-                            }
-                            Err(_) => {
-                                eprintln!("SC> writev({}, …) = -EINVAL", fd);
-                                return ErrNo::EINVAL.neg_as_usize();
-                            }
+            for iov in iovec {
+                let buf =
+                    unsafe { core::slice::from_raw_parts(iov.iov_base as *const u8, iov.iov_len) };
+                match fd::write(fd, buf) {
+                    Ok(len) => {
+                        written += len;
+                        if len < buf.len() {
+                            break;
                         }
                     }
-                    written
-                }
-                _ => {
-                    eprintln!("SC> write({}, \"…\") = -EBADFD", a);
-                    ErrNo::EBADFD.neg_as_usize()
+                    Err(errno) if written == 0 => {
+                        return host_ret(Err(errno));
+                    }
+                    Err(_) => break,
                 }
             }
+            written
         }
         SysCall::ARCH_PRCTL => {
             const ARCH_SET_GS: usize = 0x1001;
@@ -138,61 +441,92 @@ pub extern "C" fn handle_syscall(
             const ARCH_GET_FS: usize = 0x1003;
             const ARCH_GET_GS: usize = 0x1004;
 
+            // Addresses from the upper half can't be loaded by the app
+            const USER_END: usize = 0x0000_8000_0000_0000;
+
             match a {
+                ARCH_SET_FS | ARCH_SET_GS if b >= USER_END => ErrNo::EPERM.neg_as_usize(),
                 ARCH_SET_FS => {
-                    eprintln!("SC> arch_prctl(ARCH_SET_FS, {:#X}) = 0", b);
-                    let value: u64 = b as _;
-                    unsafe {
-                        _wrfsbase(value);
-                    }
+                    tls::set_fs_base(b as _);
                     0
                 }
-                ARCH_GET_FS => unimplemented!(),
-                ARCH_SET_GS => unimplemented!(),
-                ARCH_GET_GS => unimplemented!(),
-                x => {
-                    eprintln!("SC> arch_prctl({:#X}, {:#X}) = -EINVAL", x, b);
-                    ErrNo::EINVAL.neg_as_usize()
+                ARCH_SET_GS => {
+                    tls::set_gs_base(b as _);
+                    0
+                }
+                ARCH_GET_FS | ARCH_GET_GS if b == 0 => ErrNo::EFAULT.neg_as_usize(),
+                ARCH_GET_FS => {
+                    let base = tls::user_segments().fs_base;
+                    unsafe { (b as *mut u64).write(base) };
+                    0
                 }
+                ARCH_GET_GS => {
+                    let base = tls::user_segments().gs_base;
+                    unsafe { (b as *mut u64).write(base) };
+                    0
+                }
+                _ => ErrNo::EINVAL.neg_as_usize(),
             }
         }
-        SysCall::MUNMAP => {
-            let ret = 0;
-            eprintln!("SC> dummy munmap({:#X}, {}, …) = {:#?}", a, b, ret);
-            ret
-        }
+        SysCall::MUNMAP => 0,
         SysCall::MMAP => {
-            if a == 0 {
-                let ret = mmap_user(b);
-                eprintln!("SC> mmap({:#X}, {}, …) = {:#?}", a, b, ret);
-                ret as _
+            const MAP_FIXED: usize = 0x10;
+            const MAP_ANONYMOUS: usize = 0x20;
+
+            let (addr, len, flags, fd, offset) = (a, b, d, e, f);
+
+            if len == 0 {
+                return ErrNo::EINVAL.neg_as_usize();
+            }
+
+            if flags & MAP_FIXED != 0 && addr % PAGESIZE != 0 {
+                return ErrNo::EINVAL.neg_as_usize();
+            }
+
+            if flags & MAP_ANONYMOUS == 0 && !fd::is_open(fd) {
+                return ErrNo::EBADF.neg_as_usize();
+            }
+
+            // A hint for the address without MAP_FIXED is ignored
+            let ret = if flags & MAP_FIXED != 0 {
+                mmap_user_fixed(addr, len)
             } else {
-                eprintln!("SC> mmap({:#X}, {}, …)", a, b);
-                todo!();
+                mmap_user(len)
+            };
+
+            if flags & MAP_ANONYMOUS == 0 {
+                // Private file mappings are copied, the rest of the last page stays zero
+                let buf = unsafe { core::slice::from_raw_parts_mut(ret, len) };
+                if let Err(errno) = fd::pread(fd, buf, offset as _) {
+                    return -errno as _;
+                }
+            }
+
+            // The pages are writable until filled, apply the requested protection now
+            let prot = c;
+            if let Err(errno) = mprotect_user(ret as _, len, prot) {
+                return errno.neg_as_usize();
             }
+
+            ret as _
         }
         SysCall::BRK => unsafe {
             match a {
-                0 => {
-                    eprintln!("SC> brk({:#X}) = {:#X}", a, NEXT_MMAP);
-                    NEXT_MMAP as _
-                }
+                0 => NEXT_MMAP as _,
                 n => {
                     brk_user(n - NEXT_MMAP as usize);
-                    eprintln!("SC> brk({:#X}) = {:#X}", a, NEXT_MMAP);
                     n as _
                 }
             }
         },
         SysCall::MPROTECT => {
-            let ret = 0;
-            eprintln!("SC> mprotect({:#X}, {}, {}) = {:#?}", a, b, c, ret);
-            ret as _
+            // ld.so and glibc make RELRO read-only after relocating
+            match mprotect_user(a, b, c) {
+                Ok(()) => 0,
+                Err(errno) => errno.neg_as_usize(),
+            }
         }
         SysCall::UNAME => {
-            eprintln!(
-                r##"SC> uname({{sysname="Linux", nodename="enarx", release="5.4.8", version="1", machine="x86_64", domainname="(none)"}}) = 0"##
-            );
             #[repr(C)]
             struct NewUtsname {
                 sysname: [u8; 65],
@@ -214,17 +548,7 @@ pub extern "C" fn handle_syscall(
             0
         }
         SysCall::READLINK => {
-            let pathname = unsafe {
-                let mut len = 0;
-                let ptr: *const u8 = a as _;
-                loop {
-                    if ptr.offset(len).read() == 0 {
-                        break;
-                    }
-                    len += 1;
-                }
-                core::str::from_utf8_unchecked(core::slice::from_raw_parts(a as _, len as _))
-            };
+            let pathname = unsafe { c_str(a) };
 
             if !pathname.eq("/proc/self/exe") {
                 return ErrNo::ENOENT.neg_as_usize();
@@ -232,118 +556,332 @@ pub extern "C" fn handle_syscall(
 
             let outbuf = unsafe { core::slice::from_raw_parts_mut(b as _, c as _) };
             outbuf[..6].copy_from_slice(b"/init\0");
-            eprintln!("SC> readlink({:#?}, \"/init\", {}) = 5", pathname, c);
             5
         }
 
-        SysCall::RT_SIGACTION => {
-            eprintln!("SC> rt_sigaction(…) = 0");
-            0
+        SysCall::OPEN | SysCall::OPENAT => {
+            let (pathname, flags, mode) = match SysCall::from(nr as u64) {
+                SysCall::OPEN => (unsafe { c_str(a) }, b, c),
+                _ => (unsafe { c_str(b) }, c, d),
+            };
+            host_ret(fd::open(pathname, flags, mode as _))
         }
-        SysCall::RT_SIGPROCMASK => {
-            eprintln!("SC> rt_sigprocmask(…) = 0");
-            0
+        SysCall::READ => {
+            let buf = unsafe { core::slice::from_raw_parts_mut(b as *mut u8, c) };
+            host_ret(fd::read(a, buf))
         }
-        SysCall::SIGALTSTACK => {
-            eprintln!("SC> sigaltstack(…) = 0");
-            0
+        SysCall::PREAD64 => {
+            let buf = unsafe { core::slice::from_raw_parts_mut(b as *mut u8, c) };
+            host_ret(fd::pread(a, buf, d as _))
         }
-        SysCall::SET_TID_ADDRESS => {
-            eprintln!("SC> set_tid_address(…) = 63618");
-            63618
-        }
-        SysCall::IOCTL => match a {
-            1 => {
-                match b {
-                    0x5413 /* TIOCGWINSZ */ => {
-                        #[repr(C, packed)]
-                        struct WinSize {
-                            ws_row: u16,
-                            ws_col: u16,
-                            ws_xpixel: u16,
-                            ws_ypixel: u16,
-                        };
-                        let p: *mut WinSize = c as _;
-                        let winsize = WinSize {
-                            ws_row: 40,
-                            ws_col: 80,
-                            ws_xpixel: 0,
-                            ws_ypixel: 0
-                        };
-                        unsafe {
-                            p.write_volatile(winsize);
-                        }
-                        eprintln!("SC> ioctl(1, TIOCGWINSZ, {{ws_row=40, ws_col=80, ws_xpixel=0, ws_ypixel=0}}) = 0");
-                        0
-                    },
-                    _ => ErrNo::EINVAL.neg_as_usize(),
+        SysCall::PWRITE64 => {
+            let buf = unsafe { core::slice::from_raw_parts(b as *const u8, c) };
+            host_ret(fd::pwrite(a, buf, d as _))
+        }
+        SysCall::CLOSE => host_ret(fd::close(a).map(|_| 0)),
+        SysCall::ACCESS => {
+            const W_OK: usize = 2;
+
+            let pathname = unsafe { c_str(a) };
+            if tmpfs::is_mounted(pathname) {
+                host_ret(tmpfs::stat_path(pathname).map(|_| 0))
+            } else if b & W_OK != 0 {
+                ErrNo::EACCES.neg_as_usize()
+            } else if initrd::stat_path(pathname).is_ok() {
+                0
+            } else {
+                host_ret(hostfs::open(pathname, 0).and_then(hostfs::close).map(|_| 0))
+            }
+        }
+        SysCall::PIPE | SysCall::PIPE2 => {
+            let flags = match SysCall::from(nr as u64) {
+                SysCall::PIPE => 0,
+                _ => b,
+            };
+            match fd::pipe2(flags) {
+                Ok((rfd, wfd)) => {
+                    let fds = a as *mut [i32; 2];
+                    unsafe { fds.write([rfd as i32, wfd as i32]) };
+                    0
                 }
+                Err(errno) => host_ret(Err(errno)),
+            }
+        }
+        SysCall::DUP => host_ret(fd::dup(a)),
+        SysCall::DUP2 => host_ret(fd::dup2(a, b)),
+        SysCall::DUP3 => host_ret(fd::dup3(a, b, c)),
+        SysCall::FCNTL => host_ret(fd::fcntl(a, b, c)),
+        _ if nr == SYS_CLOSE_RANGE => host_ret(fd::close_range(a, b, c).map(|_| 0)),
+        SysCall::SOCKET => host_ret(fd::socket(a, b, c)),
+        SysCall::SOCKETPAIR => match fd::socketpair(a, b, c) {
+            Ok((first, second)) => {
+                let fds = d as *mut [i32; 2];
+                unsafe { fds.write([first as i32, second as i32]) };
+                0
             }
-            _ => ErrNo::EINVAL.neg_as_usize(),
+            Err(errno) => host_ret(Err(errno)),
         },
-        SysCall::FSTAT => match a {
-            1 => {
-                fn makedev(x: u64, y: u64) -> u64 {
-                    (((x) & 0xffff_f000u64) << 32)
-                        | (((x) & 0x0000_0fffu64) << 8)
-                        | (((y) & 0xffff_ff00u64) << 12)
-                        | ((y) & 0x0000_00ffu64)
+        SysCall::BIND => {
+            let addr = unsafe { core::slice::from_raw_parts(b as *const u8, c) };
+            host_ret(
+                fd::socket_of(a)
+                    .and_then(|sock| socket::bind(sock.handle, addr))
+                    .map(|_| 0),
+            )
+        }
+        SysCall::LISTEN => host_ret(
+            fd::socket_of(a)
+                .and_then(|sock| socket::listen(sock.handle, b))
+                .map(|_| 0),
+        ),
+        SysCall::ACCEPT | SysCall::ACCEPT4 => {
+            let flags = match SysCall::from(nr as u64) {
+                SysCall::ACCEPT => 0,
+                _ => d,
+            };
+            match fd::accept4(a, flags) {
+                Ok((fd, addr)) => {
+                    unsafe { put_sockaddr(&addr, b, c) };
+                    fd
                 }
+                Err(errno) => host_ret(Err(errno)),
+            }
+        }
+        SysCall::CONNECT => {
+            let addr = unsafe { core::slice::from_raw_parts(b as *const u8, c) };
+            host_ret(
+                fd::socket_of(a)
+                    .and_then(|sock| socket::connect(sock.handle, addr, sock.nonblock))
+                    .map(|_| 0),
+            )
+        }
+        SysCall::SENDTO => {
+            let buf = unsafe { core::slice::from_raw_parts(b as *const u8, c) };
+            let addr = unsafe { get_sockaddr(e, f) };
+            host_ret(fd::socket_of(a).and_then(|sock| {
+                let nonblock = sock.nonblock || d & socket::MSG_DONTWAIT != 0;
+                socket::send(sock.handle, buf, d, addr, sock.stream, nonblock)
+            }))
+        }
+        SysCall::RECVFROM => {
+            let buf = unsafe { core::slice::from_raw_parts_mut(b as *mut u8, c) };
+            match fd::socket_of(a).and_then(|sock| {
+                let nonblock = sock.nonblock || d & socket::MSG_DONTWAIT != 0;
+                socket::recv(sock.handle, buf, d, nonblock)
+            }) {
+                Ok((len, addr)) => {
+                    unsafe { put_sockaddr(&addr, e, f) };
+                    len
+                }
+                Err(errno) => host_ret(Err(errno)),
+            }
+        }
+        SysCall::SENDMSG => {
+            let msg = unsafe { &*(b as *const MsgHdr) };
+            host_ret(sendmsg(a, msg, c))
+        }
+        SysCall::RECVMSG => {
+            let msg = unsafe { &mut *(b as *mut MsgHdr) };
+            host_ret(recvmsg(a, msg, c))
+        }
+        SysCall::GETSOCKNAME | SysCall::GETPEERNAME => {
+            let peer = matches!(SysCall::from(nr as u64), SysCall::GETPEERNAME);
+            match fd::socket_of(a).and_then(|sock| {
+                if peer {
+                    socket::getpeername(sock.handle)
+                } else {
+                    socket::getsockname(sock.handle)
+                }
+            }) {
+                Ok(addr) => {
+                    unsafe { put_sockaddr(&addr, b, c) };
+                    0
+                }
+                Err(errno) => host_ret(Err(errno)),
+            }
+        }
+        SysCall::SETSOCKOPT => {
+            let value = unsafe { core::slice::from_raw_parts(d as *const u8, e) };
+            host_ret(
+                fd::socket_of(a)
+                    .and_then(|sock| socket::setsockopt(sock.handle, b, c, value))
+                    .map(|_| 0),
+            )
+        }
+        SysCall::GETSOCKOPT => {
+            let len_ptr = e as *mut u32;
+            let len = unsafe { len_ptr.read() } as usize;
+            let value = unsafe { core::slice::from_raw_parts_mut(d as *mut u8, len) };
+            match fd::socket_of(a).and_then(|sock| socket::getsockopt(sock.handle, b, c, value)) {
+                Ok(len) => {
+                    unsafe { len_ptr.write(len as _) };
+                    0
+                }
+                Err(errno) => host_ret(Err(errno)),
+            }
+        }
+        SysCall::SHUTDOWN => host_ret(
+            fd::socket_of(a)
+                .and_then(|sock| socket::shutdown(sock.handle, b))
+                .map(|_| 0),
+        ),
+        // There are no signals, the signal masks of `ppoll()`, `pselect6()`
+        // and `epoll_pwait()` are ignored
+        SysCall::POLL | SysCall::PPOLL => {
+            let timeout = match SysCall::from(nr as u64) {
+                SysCall::POLL => Ok(c as i32),
+                _ => unsafe { timeout_ms(c, 1) },
+            };
+            host_ret(timeout.and_then(|timeout| {
+                if b > fd::MAX_FDS {
+                    return Err(ErrNo::EINVAL.into());
+                }
+                let fds = unsafe { core::slice::from_raw_parts_mut(a as *mut poll::PollFd, b) };
+                poll::poll(fds, timeout)
+            }))
+        }
+        SysCall::SELECT | SysCall::PSELECT6 => {
+            let timeout = match SysCall::from(nr as u64) {
+                SysCall::SELECT => unsafe { timeout_ms(e, 1000) },
+                _ => unsafe { timeout_ms(e, 1) },
+            };
+            let set = |ptr: usize| unsafe { (ptr as *mut poll::FdSet).as_mut() };
+            host_ret(timeout.and_then(|timeout| poll::select(a, set(b), set(c), set(d), timeout)))
+        }
+        SysCall::EPOLL_CREATE | SysCall::EPOLL_CREATE1 => {
+            let flags = match SysCall::from(nr as u64) {
+                SysCall::EPOLL_CREATE if a as i32 <= 0 => Err(ErrNo::EINVAL.into()),
+                SysCall::EPOLL_CREATE => Ok(0),
+                _ => Ok(a),
+            };
+            host_ret(flags.and_then(fd::epoll_create))
+        }
+        SysCall::EPOLL_CTL => {
+            let event = match d {
+                0 => epoll::EpollEvent::default(),
+                _ => unsafe { (d as *const epoll::EpollEvent).read() },
+            };
+            host_ret(fd::epoll_ctl(a, b, c, event).map(|_| 0))
+        }
+        SysCall::EPOLL_WAIT | SysCall::EPOLL_PWAIT => {
+            // There can not be more events than watches
+            let maxevents = (c as i32).max(0).min(epoll::MAX_WATCHES as i32) as usize;
+            let events =
+                unsafe { core::slice::from_raw_parts_mut(b as *mut epoll::EpollEvent, maxevents) };
+            host_ret(fd::epoll_of(a).and_then(|index| epoll::wait(index, events, d as i32)))
+        }
+        SysCall::EVENTFD | SysCall::EVENTFD2 => {
+            let flags = match SysCall::from(nr as u64) {
+                SysCall::EVENTFD => 0,
+                _ => b,
+            };
+            host_ret(fd::eventfd(a, flags))
+        }
 
-                #[repr(C)]
-                #[derive(Debug, Copy, Clone)]
-                pub struct timespec {
-                    pub tv_sec: i64,
-                    pub tv_nsec: i64,
+        SysCall::RT_SIGACTION => 0,
+        SysCall::RT_SIGPROCMASK => 0,
+        SysCall::SIGALTSTACK => 0,
+        SysCall::SET_TID_ADDRESS => 63618,
+        SysCall::IOCTL => match b {
+            0x5413 /* TIOCGWINSZ */ if fd::is_terminal(a) => {
+                #[repr(C, packed)]
+                struct WinSize {
+                    ws_row: u16,
+                    ws_col: u16,
+                    ws_xpixel: u16,
+                    ws_ypixel: u16,
                 };
-
-                #[repr(C)]
-                #[derive(Debug, Copy, Clone)]
-                pub struct Stat {
-                    pub st_dev: u64,
-                    pub st_ino: u64,
-                    pub st_nlink: u64,
-                    pub st_mode: u32,
-                    pub st_uid: u32,
-                    pub st_gid: u32,
-                    pub __pad0: i32,
-                    pub st_rdev: u64,
-                    pub st_size: i64,
-                    pub st_blksize: i64,
-                    pub st_blocks: i64,
-                    pub st_atime: timespec,
-                    pub st_mtime: timespec,
-                    pub st_ctime: timespec,
-                    pub __glibc_reserved: [i64; 3usize],
+                let p: *mut WinSize = c as _;
+                let winsize = WinSize {
+                    ws_row: 40,
+                    ws_col: 80,
+                    ws_xpixel: 0,
+                    ws_ypixel: 0
                 };
-
-                let p: &mut Stat = &mut unsafe { *(c as *mut Stat) };
-                p.st_dev = makedev(0, 0x17);
-                p.st_ino = 3;
-                p.st_mode = 0o020_000 | 0o620; // S_IFCHR
-                p.st_nlink = 1;
-                p.st_uid = 1000;
-                p.st_gid = 5;
-                p.st_blksize = 1024;
-                p.st_blocks = 0;
-                p.st_rdev = makedev(0x88, 0);
-                p.st_atime.tv_sec=1_579_507_218 /* 2020-01-21T11:45:08.467721685+0100 */;
-                p.st_atime.tv_nsec = 0;
-                p.st_mtime.tv_sec=1_579_507_218 /* 2020-01-21T11:45:07.467721685+0100 */;
-                p.st_mtime.tv_nsec = 0;
-                p.st_ctime.tv_sec=1_579_507_218 /* 2020-01-20T09:00:18.467721685+0100 */;
-                p.st_ctime.tv_nsec = 0;
-                eprintln!("SC> fstat(1, {{st_dev=makedev(0, 0x17), st_ino=3, st_mode=S_IFCHR|0620, st_nlink=1, st_uid=1000, st_gid=5, st_blksize=1024, st_blocks=0, st_rdev=makedev(0x88, 0), st_atime=1579507218 /* 2020-01-21T11:45:08.467721685+0100 */, st_atime_nsec=0, st_mtime=1579507218 /* 2020-01-21T11:45:08.467721685+0100 */, st_mtime_nsec=0, st_ctime=1579507218 /* 2020-01-21T11:45:08.467721685+0100 */, st_ctime_nsec=0}}) = 0
- = 0");
+                unsafe {
+                    p.write_volatile(winsize);
+                }
                 0
-            }
+            },
+            _ if fd::is_terminal(a) => ErrNo::EINVAL.neg_as_usize(),
+            _ if fd::is_open(a) => ErrNo::ENOTTY.neg_as_usize(),
             _ => ErrNo::EBADF.neg_as_usize(),
         },
-        _ => {
-            eprintln!("syscall({}, {}, {}, {}, {}, {}, {})", nr, a, b, c, d, e, f);
-            //stack.dump();
-            panic!("syscall {} not yet implemented", nr)
-            // ENOSYS.neg_as_usize(),
+        SysCall::FSTAT => host_ret(fd::stat(a, unsafe { &mut *(c as *mut Stat) })),
+        SysCall::NEWFSTATAT => {
+            const AT_EMPTY_PATH: usize = 0x1000;
+
+            let pathname = unsafe { c_str(b) };
+            if d & AT_EMPTY_PATH != 0 && pathname.is_empty() {
+                host_ret(fd::stat(a, unsafe { &mut *(c as *mut Stat) }))
+            } else {
+                host_ret(stat_path(pathname, unsafe { &mut *(c as *mut Stat) }))
+            }
+        }
+        SysCall::STAT | SysCall::LSTAT => {
+            let pathname = unsafe { c_str(a) };
+            host_ret(stat_path(pathname, unsafe { &mut *(b as *mut Stat) }))
+        }
+        SysCall::LSEEK => host_ret(fd::lseek(a, b as _, c)),
+        SysCall::GETDENTS64 => {
+            let buf = unsafe { core::slice::from_raw_parts_mut(b as *mut u8, c) };
+            host_ret(fd::getdents64(a, buf))
+        }
+        SysCall::FTRUNCATE => host_ret(fd::ftruncate(a, b as _).map(|_| 0)),
+        SysCall::MKDIR | SysCall::MKDIRAT => {
+            let (pathname, mode) = match SysCall::from(nr as u64) {
+                SysCall::MKDIR => (unsafe { c_str(a) }, b),
+                _ => (unsafe { c_str(b) }, c),
+            };
+            if tmpfs::is_mounted(pathname) {
+                host_ret(tmpfs::mkdir(pathname, mode as _).map(|_| 0))
+            } else {
+                ErrNo::EROFS.neg_as_usize()
+            }
+        }
+        SysCall::UNLINK | SysCall::UNLINKAT | SysCall::RMDIR => {
+            const AT_REMOVEDIR: usize = 0x200;
+
+            let (pathname, rmdir) = match SysCall::from(nr as u64) {
+                SysCall::UNLINK => (unsafe { c_str(a) }, false),
+                SysCall::RMDIR => (unsafe { c_str(a) }, true),
+                _ => (unsafe { c_str(b) }, c & AT_REMOVEDIR != 0),
+            };
+            if !tmpfs::is_mounted(pathname) {
+                ErrNo::EROFS.neg_as_usize()
+            } else if rmdir {
+                host_ret(tmpfs::rmdir(pathname).map(|_| 0))
+            } else {
+                host_ret(tmpfs::unlink(pathname).map(|_| 0))
+            }
+        }
+        SysCall::RENAME | SysCall::RENAMEAT => {
+            let (oldpath, newpath) = match SysCall::from(nr as u64) {
+                SysCall::RENAME => unsafe { (c_str(a), c_str(b)) },
+                _ => unsafe { (c_str(b), c_str(d)) },
+            };
+            host_ret(rename_path(oldpath, newpath))
         }
+        _ => match unknown {
+            UnknownSyscall::Panic => {
+                error!("syscall({}, {}, {}, {}, {}, {}, {})", nr, a, b, c, d, e, f);
+                //stack.dump();
+                panic!("syscall {} not yet implemented", nr)
+            }
+            UnknownSyscall::LogAndEnosys => {
+                warn!(
+                    "{}({:#X}, {:#X}, {:#X}, {:#X}, {:#X}, {:#X}) = -ENOSYS, not implemented",
+                    syscalls::name(nr as _).unwrap_or("unknown"),
+                    a,
+                    b,
+                    c,
+                    d,
+                    e,
+                    f
+                );
+                ErrNo::ENOSYS.neg_as_usize()
+            }
+            UnknownSyscall::Enosys => ErrNo::ENOSYS.neg_as_usize(),
+        },
     }
 }
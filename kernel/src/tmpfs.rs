@@ -0,0 +1,894 @@
+//! Writable in-memory filesystem at `/tmp`
+//!
+//! The files live on the kernel heap, which is enlarged for the limits
+//! configured by the hypervisor. File contents are kept in pages, which are
+//! allocated on the first write, so holes do not count against the size
+//! limit. No file is larger than the size limit, though. There are no
+//! timestamps and no owners.
+//!
+//! Without the `allocator` feature there is no heap and no tmpfs, all paths
+//! are left to the other filesystems.
+//!
+//! Errors are returned as positive `errno` values.
+
+use linux_errno::ErrNo;
+
+pub use imp::*;
+
+/// The mount point
+pub const MOUNT_POINT: &str = "tmp";
+
+/// Maximum number of tmpfs files opened at the same time
+pub const MAX_FILES: usize = 32;
+
+/// The metadata of a tmpfs file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub ino: u64,
+    /// File type and permissions
+    pub mode: u32,
+    pub nlink: u32,
+    pub size: u64,
+    /// Allocated size in 512 byte blocks
+    pub blocks: u64,
+}
+
+fn err(errno: ErrNo) -> i64 {
+    errno.into()
+}
+
+#[cfg(feature = "allocator")]
+mod imp {
+    use super::{err, Metadata, MAX_FILES, MOUNT_POINT};
+    use crate::initrd::{S_IFDIR, S_IFREG};
+    use crate::syscall::{put_dirent64, DT_DIR, DT_REG};
+    use alloc::boxed::Box;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use linux_errno::ErrNo;
+    use vmsyscall::bootinfo::TmpfsLimits;
+
+    const PAGE: usize = 4096;
+
+    const O_ACCMODE: usize = 0o3;
+    const O_RDONLY: usize = 0o0;
+    const O_WRONLY: usize = 0o1;
+    const O_CREAT: usize = 0o100;
+    const O_EXCL: usize = 0o200;
+    const O_TRUNC: usize = 0o1000;
+    const O_APPEND: usize = 0o2000;
+    const O_DIRECTORY: usize = 0o200_000;
+
+    const SEEK_SET: usize = 0;
+    const SEEK_CUR: usize = 1;
+    const SEEK_END: usize = 2;
+
+    const NAME_MAX: usize = 255;
+    const UMASK: u32 = 0o022;
+
+    type Page = Box<[u8; PAGE]>;
+
+    enum Node {
+        File {
+            size: u64,
+            pages: Vec<Option<Page>>,
+        },
+        Dir {
+            parent: usize,
+            entries: Vec<(String, usize)>,
+        },
+    }
+
+    struct Inode {
+        node: Node,
+        mode: u32,
+        nlink: u32,
+        /// Number of open file descriptors
+        open: u32,
+    }
+
+    #[derive(Clone, Copy)]
+    struct OpenFile {
+        ino: usize,
+        /// File position, number of listed entries of a directory
+        pos: u64,
+        flags: usize,
+    }
+
+    struct Tmpfs {
+        limits: TmpfsLimits,
+        /// Inode number `n` is at index `n - 1`, the root is inode 1
+        inodes: Vec<Option<Inode>>,
+        /// Number of allocated file pages
+        pages: u64,
+        files: [Option<OpenFile>; MAX_FILES],
+    }
+
+    static mut TMPFS: Option<Tmpfs> = None;
+
+    /// The components of `pathname` below the mount point, `None` if it is outside
+    fn components(pathname: &str) -> Option<impl Iterator<Item = &str>> {
+        let mut comps = pathname.split('/').filter(|c| !c.is_empty() && *c != ".");
+        match comps.next() {
+            Some(MOUNT_POINT) => Some(comps),
+            _ => None,
+        }
+    }
+
+    fn fs() -> Result<&'static mut Tmpfs, i64> {
+        unsafe { TMPFS.as_mut() }.ok_or_else(|| err(ErrNo::ENOENT))
+    }
+
+    impl Tmpfs {
+        fn inode(&self, ino: usize) -> &Inode {
+            self.inodes[ino].as_ref().unwrap()
+        }
+
+        fn inode_mut(&mut self, ino: usize) -> &mut Inode {
+            self.inodes[ino].as_mut().unwrap()
+        }
+
+        fn is_dir(&self, ino: usize) -> bool {
+            matches!(self.inode(ino).node, Node::Dir { .. })
+        }
+
+        fn lookup(&self, dir: usize, name: &str) -> Option<usize> {
+            match &self.inode(dir).node {
+                Node::Dir { entries, .. } => {
+                    entries.iter().find(|(n, _)| n == name).map(|(_, ino)| *ino)
+                }
+                Node::File { .. } => None,
+            }
+        }
+
+        fn resolve<'a>(&self, comps: impl Iterator<Item = &'a str>) -> Result<usize, i64> {
+            let mut ino = 0;
+            for comp in comps {
+                ino = match &self.inode(ino).node {
+                    Node::Dir { parent, .. } if comp == ".." => *parent,
+                    Node::Dir { .. } => self.lookup(ino, comp).ok_or_else(|| err(ErrNo::ENOENT))?,
+                    Node::File { .. } => return Err(err(ErrNo::ENOTDIR)),
+                };
+            }
+            Ok(ino)
+        }
+
+        /// The directory of `pathname` and the last component
+        fn resolve_parent<'a>(&self, pathname: &'a str) -> Result<(usize, &'a str), i64> {
+            let comps = components(pathname).ok_or_else(|| err(ErrNo::ENOENT))?;
+            let comps: Vec<&str> = comps.collect();
+            let (name, dirs) = comps.split_last().ok_or_else(|| err(ErrNo::EBUSY))?;
+
+            if *name == ".." {
+                return Err(err(ErrNo::EINVAL));
+            }
+            if name.len() > NAME_MAX {
+                return Err(err(ErrNo::ENAMETOOLONG));
+            }
+
+            let dir = self.resolve(dirs.iter().copied())?;
+            if !self.is_dir(dir) {
+                return Err(err(ErrNo::ENOTDIR));
+            }
+            Ok((dir, name))
+        }
+
+        fn max_pages(&self) -> u64 {
+            self.limits.size / PAGE as u64
+        }
+
+        /// Past the size limit even a sparse file would need a too long page list
+        fn check_size(&self, size: Option<u64>) -> Result<u64, i64> {
+            size.filter(|size| *size <= self.limits.size)
+                .ok_or_else(|| err(ErrNo::EFBIG))
+        }
+
+        /// Add a new inode `name` to `dir`
+        fn create(&mut self, dir: usize, name: &str, node: Node, mode: u32) -> Result<usize, i64> {
+            if self.lookup(dir, name).is_some() {
+                return Err(err(ErrNo::EEXIST));
+            }
+
+            let used = self.inodes.iter().filter(|i| i.is_some()).count() as u64;
+            if used >= self.limits.max_inodes {
+                return Err(err(ErrNo::ENOSPC));
+            }
+
+            let is_dir = matches!(node, Node::Dir { .. });
+            let inode = Inode {
+                node,
+                mode,
+                nlink: if is_dir { 2 } else { 1 },
+                open: 0,
+            };
+            let ino = match self.inodes.iter().position(Option::is_none) {
+                Some(ino) => {
+                    self.inodes[ino].replace(inode);
+                    ino
+                }
+                None => {
+                    self.inodes.push(Some(inode));
+                    self.inodes.len() - 1
+                }
+            };
+
+            if is_dir {
+                self.inode_mut(dir).nlink += 1;
+            }
+            if let Node::Dir { entries, .. } = &mut self.inode_mut(dir).node {
+                entries.push((name.to_string(), ino));
+            }
+            Ok(ino)
+        }
+
+        /// Remove `name` from `dir`, the inode is freed once it is closed
+        fn remove(&mut self, dir: usize, name: &str) {
+            let mut removed = None;
+            if let Node::Dir { entries, .. } = &mut self.inode_mut(dir).node {
+                if let Some(pos) = entries.iter().position(|(n, _)| n == name) {
+                    removed = Some(entries.remove(pos).1);
+                }
+            }
+
+            if let Some(ino) = removed {
+                if self.is_dir(ino) {
+                    self.inode_mut(dir).nlink -= 1;
+                    self.inode_mut(ino).nlink = 0;
+                } else {
+                    self.inode_mut(ino).nlink -= 1;
+                }
+                self.release(ino);
+            }
+        }
+
+        /// Free `ino`, if it is neither linked nor open
+        fn release(&mut self, ino: usize) {
+            let inode = self.inode(ino);
+            if inode.nlink != 0 || inode.open != 0 {
+                return;
+            }
+            if let Some(Inode {
+                node: Node::File { pages, .. },
+                ..
+            }) = self.inodes[ino].take()
+            {
+                self.pages -= pages.iter().filter(|p| p.is_some()).count() as u64;
+            }
+        }
+
+        fn truncate(&mut self, ino: usize, len: u64) {
+            let mut freed = 0;
+            if let Node::File { size, pages } = &mut self.inode_mut(ino).node {
+                if len < *size {
+                    let keep = (len as usize + PAGE - 1) / PAGE;
+                    freed = pages.iter().skip(keep).filter(|p| p.is_some()).count() as u64;
+                    pages.truncate(keep);
+
+                    // Growing again must read zeros, the last page might be a hole
+                    let tail = len as usize % PAGE;
+                    if tail != 0 {
+                        if let Some(Some(page)) = pages.get_mut(keep - 1) {
+                            page[tail..].iter_mut().for_each(|b| *b = 0);
+                        }
+                    }
+                }
+                *size = len;
+            }
+            self.pages -= freed;
+        }
+
+        fn read_at(&self, ino: usize, buf: &mut [u8], offset: u64) -> Result<usize, i64> {
+            let (size, pages) = match &self.inode(ino).node {
+                Node::File { size, pages } => (*size, pages),
+                Node::Dir { .. } => return Err(err(ErrNo::EISDIR)),
+            };
+
+            if offset >= size {
+                return Ok(0);
+            }
+            let len = buf.len().min((size - offset) as usize);
+
+            let mut done = 0;
+            while done < len {
+                let pos = offset as usize + done;
+                let in_page = pos % PAGE;
+                let chunk = (PAGE - in_page).min(len - done);
+                let dst = &mut buf[done..done + chunk];
+                match pages.get(pos / PAGE) {
+                    Some(Some(page)) => dst.copy_from_slice(&page[in_page..in_page + chunk]),
+                    _ => dst.iter_mut().for_each(|b| *b = 0),
+                }
+                done += chunk;
+            }
+            Ok(len)
+        }
+
+        fn write_at(&mut self, ino: usize, buf: &[u8], offset: u64) -> Result<usize, i64> {
+            self.check_size(offset.checked_add(buf.len() as u64))?;
+
+            let max_pages = self.max_pages();
+            let used = &mut self.pages;
+            let (size, pages) = match &mut self.inodes[ino].as_mut().unwrap().node {
+                Node::File { size, pages } => (size, pages),
+                Node::Dir { .. } => return Err(err(ErrNo::EISDIR)),
+            };
+
+            let mut done = 0;
+            while done < buf.len() {
+                let pos = offset as usize + done;
+                let in_page = pos % PAGE;
+                let chunk = (PAGE - in_page).min(buf.len() - done);
+
+                let index = pos / PAGE;
+                if pages.len() <= index {
+                    pages.resize_with(index + 1, || None);
+                }
+                if pages[index].is_none() {
+                    if *used >= max_pages {
+                        break;
+                    }
+                    pages[index].replace(Box::new([0u8; PAGE]));
+                    *used += 1;
+                }
+
+                pages[index].as_mut().unwrap()[in_page..in_page + chunk]
+                    .copy_from_slice(&buf[done..done + chunk]);
+                done += chunk;
+            }
+
+            *size = (*size).max(offset + done as u64);
+            if done == 0 && !buf.is_empty() {
+                return Err(err(ErrNo::ENOSPC));
+            }
+            Ok(done)
+        }
+
+        fn metadata(&self, ino: usize) -> Metadata {
+            let inode = self.inode(ino);
+            let (size, blocks) = match &inode.node {
+                Node::File { size, pages } => (
+                    *size,
+                    pages.iter().filter(|p| p.is_some()).count() as u64 * (PAGE as u64 / 512),
+                ),
+                Node::Dir { entries, .. } => (entries.len() as u64 * 32, 0),
+            };
+            Metadata {
+                ino: ino as u64 + 1,
+                mode: inode.mode,
+                nlink: inode.nlink,
+                size,
+                blocks,
+            }
+        }
+
+        fn file(&mut self, handle: usize) -> Result<&mut OpenFile, i64> {
+            self.files
+                .get_mut(handle)
+                .and_then(Option::as_mut)
+                .ok_or_else(|| err(ErrNo::EBADF))
+        }
+    }
+
+    /// Heap needed for `limits`: the file pages, their page lists and the inodes
+    pub fn heap_size(limits: &TmpfsLimits) -> usize {
+        if limits.size == 0 {
+            return 0;
+        }
+        (limits.size + limits.size / 256 + limits.max_inodes * 256) as usize
+    }
+
+    /// Mount an empty tmpfs, after the heap is initialized
+    pub fn init(limits: TmpfsLimits) {
+        if limits.size == 0 || limits.max_inodes == 0 {
+            return;
+        }
+
+        let mut inodes = Vec::new();
+        inodes.push(Some(Inode {
+            node: Node::Dir {
+                parent: 0,
+                entries: Vec::new(),
+            },
+            mode: S_IFDIR | 0o1777,
+            nlink: 2,
+            open: 0,
+        }));
+
+        unsafe {
+            TMPFS.replace(Tmpfs {
+                limits,
+                inodes,
+                pages: 0,
+                files: [None; MAX_FILES],
+            });
+        }
+    }
+
+    /// Is `pathname` in the tmpfs
+    pub fn is_mounted(pathname: &str) -> bool {
+        fs().is_ok() && components(pathname).is_some()
+    }
+
+    /// Is `handle` an open tmpfs file
+    pub fn is_open(handle: usize) -> bool {
+        fs().and_then(|fs| fs.file(handle).map(|_| ())).is_ok()
+    }
+
+    /// Open or create `pathname`, returns its handle
+    pub fn open(pathname: &str, flags: usize, mode: u32) -> Result<usize, i64> {
+        let fs = fs()?;
+        let comps = components(pathname).ok_or_else(|| err(ErrNo::ENOENT))?;
+        let handle = fs
+            .files
+            .iter()
+            .position(Option::is_none)
+            .ok_or_else(|| err(ErrNo::EMFILE))?;
+
+        let ino = match fs.resolve(comps) {
+            Ok(_) if flags & O_CREAT != 0 && flags & O_EXCL != 0 => return Err(err(ErrNo::EEXIST)),
+            Ok(ino) => ino,
+            Err(errno) if errno == err(ErrNo::ENOENT) && flags & O_CREAT != 0 => {
+                let (dir, name) = fs.resolve_parent(pathname)?;
+                let node = Node::File {
+                    size: 0,
+                    pages: Vec::new(),
+                };
+                fs.create(dir, name, node, S_IFREG | (mode & 0o7777 & !UMASK))?
+            }
+            Err(errno) => return Err(errno),
+        };
+
+        let writable = flags & O_ACCMODE != O_RDONLY;
+        if fs.is_dir(ino) {
+            if writable {
+                return Err(err(ErrNo::EISDIR));
+            }
+        } else if flags & O_DIRECTORY != 0 {
+            return Err(err(ErrNo::ENOTDIR));
+        } else if writable && flags & O_TRUNC != 0 {
+            fs.truncate(ino, 0);
+        }
+
+        fs.inode_mut(ino).open += 1;
+        fs.files[handle].replace(OpenFile { ino, pos: 0, flags });
+        Ok(handle)
+    }
+
+    /// Read from the file position of `handle`
+    pub fn read(handle: usize, buf: &mut [u8]) -> Result<usize, i64> {
+        let fs = fs()?;
+        let file = *fs.file(handle)?;
+        if file.flags & O_ACCMODE == O_WRONLY {
+            return Err(err(ErrNo::EBADF));
+        }
+        let len = fs.read_at(file.ino, buf, file.pos)?;
+        fs.file(handle)?.pos += len as u64;
+        Ok(len)
+    }
+
+    /// Read at `offset` of `handle`, without changing the file position
+    pub fn pread(handle: usize, buf: &mut [u8], offset: u64) -> Result<usize, i64> {
+        let fs = fs()?;
+        let file = *fs.file(handle)?;
+        if file.flags & O_ACCMODE == O_WRONLY {
+            return Err(err(ErrNo::EBADF));
+        }
+        fs.read_at(file.ino, buf, offset)
+    }
+
+    /// Write at the file position of `handle`, or at the end with `O_APPEND`
+    pub fn write(handle: usize, buf: &[u8]) -> Result<usize, i64> {
+        let fs = fs()?;
+        let file = *fs.file(handle)?;
+        if file.flags & O_ACCMODE == O_RDONLY {
+            return Err(err(ErrNo::EBADF));
+        }
+
+        let pos = if file.flags & O_APPEND != 0 {
+            fs.metadata(file.ino).size
+        } else {
+            file.pos
+        };
+        let len = fs.write_at(file.ino, buf, pos)?;
+        fs.file(handle)?.pos = pos + len as u64;
+        Ok(len)
+    }
+
+    /// Write at `offset` of `handle`, without changing the file position
+    pub fn pwrite(handle: usize, buf: &[u8], offset: u64) -> Result<usize, i64> {
+        let fs = fs()?;
+        let file = *fs.file(handle)?;
+        if file.flags & O_ACCMODE == O_RDONLY {
+            return Err(err(ErrNo::EBADF));
+        }
+        fs.write_at(file.ino, buf, offset)
+    }
+
+    /// Set the file position of `handle`, returns the new position
+    pub fn lseek(handle: usize, offset: i64, whence: usize) -> Result<usize, i64> {
+        let fs = fs()?;
+        let file = *fs.file(handle)?;
+
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => file.pos as i64,
+            SEEK_END => fs.metadata(file.ino).size as i64,
+            _ => return Err(err(ErrNo::EINVAL)),
+        };
+
+        let pos = base
+            .checked_add(offset)
+            .ok_or_else(|| err(ErrNo::EOVERFLOW))?;
+        if pos < 0 {
+            return Err(err(ErrNo::EINVAL));
+        }
+        fs.file(handle)?.pos = pos as u64;
+        Ok(pos as usize)
+    }
+
+    /// Switch `O_APPEND` of `handle` on or off
+    pub fn set_append(handle: usize, append: bool) -> Result<(), i64> {
+        let file = fs()?.file(handle)?;
+        if append {
+            file.flags |= O_APPEND;
+        } else {
+            file.flags &= !O_APPEND;
+        }
+        Ok(())
+    }
+
+    /// Set the size of the regular file `handle`
+    pub fn ftruncate(handle: usize, len: u64) -> Result<(), i64> {
+        let fs = fs()?;
+        let file = *fs.file(handle)?;
+        if fs.is_dir(file.ino) {
+            return Err(err(ErrNo::EISDIR));
+        }
+        if file.flags & O_ACCMODE == O_RDONLY {
+            return Err(err(ErrNo::EINVAL));
+        }
+        fs.check_size(Some(len))?;
+        fs.truncate(file.ino, len);
+        Ok(())
+    }
+
+    /// The metadata of `handle`
+    pub fn stat(handle: usize) -> Result<Metadata, i64> {
+        let fs = fs()?;
+        let ino = fs.file(handle)?.ino;
+        Ok(fs.metadata(ino))
+    }
+
+    /// The metadata of `pathname`
+    pub fn stat_path(pathname: &str) -> Result<Metadata, i64> {
+        let fs = fs()?;
+        let comps = components(pathname).ok_or_else(|| err(ErrNo::ENOENT))?;
+        let ino = fs.resolve(comps)?;
+        Ok(fs.metadata(ino))
+    }
+
+    /// Fill `buf` with `struct linux_dirent64` records of the directory `handle`
+    pub fn getdents64(handle: usize, buf: &mut [u8]) -> Result<usize, i64> {
+        let fs = fs()?;
+        let file = *fs.file(handle)?;
+        let (parent, entries) = match &fs.inode(file.ino).node {
+            Node::Dir { parent, entries } => (*parent, entries),
+            Node::File { .. } => return Err(err(ErrNo::ENOTDIR)),
+        };
+
+        let mut len = 0;
+        let mut pos = file.pos;
+        loop {
+            let (name, ino, d_type) = match pos {
+                0 => (".", file.ino, DT_DIR),
+                1 => ("..", parent, DT_DIR),
+                n => match entries.get(n as usize - 2) {
+                    Some((name, ino)) if fs.is_dir(*ino) => (name.as_str(), *ino, DT_DIR),
+                    Some((name, ino)) => (name.as_str(), *ino, DT_REG),
+                    None => break,
+                },
+            };
+
+            let ino = ino as u64 + 1;
+            match put_dirent64(
+                &mut buf[len..],
+                ino,
+                pos as i64 + 1,
+                d_type,
+                name.as_bytes(),
+            ) {
+                Some(reclen) => len += reclen,
+                None if len == 0 => return Err(err(ErrNo::EINVAL)),
+                None => break,
+            }
+            pos += 1;
+        }
+
+        fs.file(handle)?.pos = pos;
+        Ok(len)
+    }
+
+    /// Create the directory `pathname`
+    pub fn mkdir(pathname: &str, mode: u32) -> Result<(), i64> {
+        let fs = fs()?;
+        let (dir, name) = fs.resolve_parent(pathname)?;
+        let node = Node::Dir {
+            parent: dir,
+            entries: Vec::new(),
+        };
+        fs.create(dir, name, node, S_IFDIR | (mode & 0o7777 & !UMASK))?;
+        Ok(())
+    }
+
+    /// Remove the file `pathname`
+    pub fn unlink(pathname: &str) -> Result<(), i64> {
+        let fs = fs()?;
+        let (dir, name) = fs.resolve_parent(pathname)?;
+        let ino = fs.lookup(dir, name).ok_or_else(|| err(ErrNo::ENOENT))?;
+        if fs.is_dir(ino) {
+            return Err(err(ErrNo::EISDIR));
+        }
+        fs.remove(dir, name);
+        Ok(())
+    }
+
+    /// Remove the empty directory `pathname`
+    pub fn rmdir(pathname: &str) -> Result<(), i64> {
+        let fs = fs()?;
+        let (dir, name) = fs.resolve_parent(pathname)?;
+        let ino = fs.lookup(dir, name).ok_or_else(|| err(ErrNo::ENOENT))?;
+        match &fs.inode(ino).node {
+            Node::Dir { entries, .. } if !entries.is_empty() => Err(err(ErrNo::ENOTEMPTY)),
+            Node::Dir { .. } => {
+                fs.remove(dir, name);
+                Ok(())
+            }
+            Node::File { .. } => Err(err(ErrNo::ENOTDIR)),
+        }
+    }
+
+    /// Rename `oldpath` to `newpath`, replacing `newpath`
+    pub fn rename(oldpath: &str, newpath: &str) -> Result<(), i64> {
+        let fs = fs()?;
+        let (old_dir, old_name) = fs.resolve_parent(oldpath)?;
+        let ino = fs
+            .lookup(old_dir, old_name)
+            .ok_or_else(|| err(ErrNo::ENOENT))?;
+        let (new_dir, new_name) = fs.resolve_parent(newpath)?;
+
+        // A directory can't be moved below itself
+        if fs.is_dir(ino) {
+            let mut dir = new_dir;
+            while dir != 0 {
+                if dir == ino {
+                    return Err(err(ErrNo::EINVAL));
+                }
+                dir = match fs.inode(dir).node {
+                    Node::Dir { parent, .. } => parent,
+                    Node::File { .. } => 0,
+                };
+            }
+        }
+
+        if let Some(target) = fs.lookup(new_dir, new_name) {
+            if target == ino {
+                return Ok(());
+            }
+            match (fs.is_dir(ino), &fs.inode(target).node) {
+                (false, Node::Dir { .. }) => return Err(err(ErrNo::EISDIR)),
+                (true, Node::File { .. }) => return Err(err(ErrNo::ENOTDIR)),
+                (true, Node::Dir { entries, .. }) if !entries.is_empty() => {
+                    return Err(err(ErrNo::ENOTEMPTY))
+                }
+                _ => {}
+            }
+            fs.remove(new_dir, new_name);
+        }
+
+        if let Node::Dir { entries, .. } = &mut fs.inode_mut(old_dir).node {
+            entries.retain(|(n, _)| n != old_name);
+        }
+        if let Node::Dir { entries, .. } = &mut fs.inode_mut(new_dir).node {
+            entries.push((new_name.to_string(), ino));
+        }
+
+        let is_dir = fs.is_dir(ino);
+        if let Node::Dir { parent, .. } = &mut fs.inode_mut(ino).node {
+            *parent = new_dir;
+        }
+        if is_dir {
+            fs.inode_mut(old_dir).nlink -= 1;
+            fs.inode_mut(new_dir).nlink += 1;
+        }
+        Ok(())
+    }
+
+    /// Close `handle`
+    pub fn close(handle: usize) -> Result<(), i64> {
+        let fs = fs()?;
+        let ino = fs.file(handle)?.ino;
+        fs.files[handle].take();
+        fs.inode_mut(ino).open -= 1;
+        fs.release(ino);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    #[test_case]
+    fn test_tmpfs_files() {
+        use crate::{serial_print, serial_println};
+        serial_print!("test_tmpfs_files...");
+
+        const O_RDWR: usize = 0o2;
+
+        let old = unsafe { TMPFS.take() };
+        init(TmpfsLimits {
+            size: 3 * PAGE as u64,
+            max_inodes: 4,
+        });
+
+        assert!(is_mounted("/tmp/a"));
+        assert!(!is_mounted("/tmpa"));
+        assert_eq!(open("/tmp/a", 0, 0), Err(err(ErrNo::ENOENT)));
+
+        let handle = open("/tmp/a", O_CREAT | O_RDWR, 0o666).unwrap();
+        assert_eq!(write(handle, b"hello"), Ok(5));
+        assert_eq!(lseek(handle, 0, SEEK_SET), Ok(0));
+        let mut buf = [0u8; 8];
+        assert_eq!(read(handle, &mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(stat(handle).unwrap().mode, S_IFREG | 0o644);
+
+        // A hole does not count, no file is larger than three pages
+        assert_eq!(pwrite(handle, &[1u8; PAGE], 2 * PAGE as u64), Ok(PAGE));
+        assert_eq!(
+            pwrite(handle, &[1u8; 1], 3 * PAGE as u64),
+            Err(err(ErrNo::EFBIG))
+        );
+        assert_eq!(
+            pwrite(handle, &[1u8; 1], u64::MAX / 2),
+            Err(err(ErrNo::EFBIG))
+        );
+        assert_eq!(
+            ftruncate(handle, 3 * PAGE as u64 + 1),
+            Err(err(ErrNo::EFBIG))
+        );
+        assert_eq!(pread(handle, &mut buf, PAGE as u64), Ok(8));
+        assert_eq!(buf, [0u8; 8]);
+
+        // The third page is left for another file
+        let other = open("/tmp/c", O_CREAT | O_RDWR, 0o666).unwrap();
+        assert_eq!(pwrite(other, &[1u8; PAGE], 0), Ok(PAGE));
+        assert_eq!(
+            pwrite(other, &[1u8; 1], PAGE as u64),
+            Err(err(ErrNo::ENOSPC))
+        );
+        unlink("/tmp/c").unwrap();
+        close(other).unwrap();
+
+        ftruncate(handle, 3).unwrap();
+        assert_eq!(stat(handle).unwrap().size, 3);
+        ftruncate(handle, 5).unwrap();
+        assert_eq!(pread(handle, &mut buf, 0), Ok(5));
+        assert_eq!(&buf[..5], b"hel\0\0");
+
+        // Shrinking into a hole keeps the data of the pages before it
+        assert_eq!(pwrite(handle, &[7u8; 8], 200), Ok(8));
+        ftruncate(handle, 3 * PAGE as u64).unwrap();
+        ftruncate(handle, 2 * PAGE as u64 + 100).unwrap();
+        assert_eq!(pread(handle, &mut buf, 200), Ok(8));
+        assert_eq!(buf, [7u8; 8]);
+        ftruncate(handle, 5).unwrap();
+
+        mkdir("/tmp/d", 0o777).unwrap();
+        assert_eq!(mkdir("/tmp/d", 0o777), Err(err(ErrNo::EEXIST)));
+        rename("/tmp/a", "/tmp/d/b").unwrap();
+        assert_eq!(stat_path("/tmp/a"), Err(err(ErrNo::ENOENT)));
+        assert_eq!(stat_path("/tmp/d/../d/b").unwrap().size, 5);
+        assert_eq!(rename("/tmp/d", "/tmp/d/e"), Err(err(ErrNo::EINVAL)));
+        assert_eq!(rmdir("/tmp/d"), Err(err(ErrNo::ENOTEMPTY)));
+
+        // The unlinked file is still open
+        unlink("/tmp/d/b").unwrap();
+        assert_eq!(pread(handle, &mut buf, 0), Ok(5));
+        close(handle).unwrap();
+        assert_eq!(fs().unwrap().pages, 0);
+        rmdir("/tmp/d").unwrap();
+
+        let handle = open("/tmp", O_DIRECTORY, 0).unwrap();
+        let mut dirents = [0u8; 64];
+        assert_eq!(getdents64(handle, &mut dirents), Ok(48));
+        assert_eq!(getdents64(handle, &mut dirents), Ok(0));
+        close(handle).unwrap();
+
+        unsafe {
+            TMPFS = old;
+        }
+        serial_println!("[ok]");
+    }
+}
+
+#[cfg(not(feature = "allocator"))]
+mod imp {
+    use super::{err, Metadata};
+    use linux_errno::ErrNo;
+    use vmsyscall::bootinfo::TmpfsLimits;
+
+    pub fn heap_size(_limits: &TmpfsLimits) -> usize {
+        0
+    }
+
+    pub fn init(_limits: TmpfsLimits) {}
+
+    pub fn is_mounted(_pathname: &str) -> bool {
+        false
+    }
+
+    pub fn is_open(_handle: usize) -> bool {
+        false
+    }
+
+    pub fn open(_pathname: &str, _flags: usize, _mode: u32) -> Result<usize, i64> {
+        Err(err(ErrNo::ENOENT))
+    }
+
+    pub fn read(_handle: usize, _buf: &mut [u8]) -> Result<usize, i64> {
+        Err(err(ErrNo::EBADF))
+    }
+
+    pub fn pread(_handle: usize, _buf: &mut [u8], _offset: u64) -> Result<usize, i64> {
+        Err(err(ErrNo::EBADF))
+    }
+
+    pub fn write(_handle: usize, _buf: &[u8]) -> Result<usize, i64> {
+        Err(err(ErrNo::EBADF))
+    }
+
+    pub fn pwrite(_handle: usize, _buf: &[u8], _offset: u64) -> Result<usize, i64> {
+        Err(err(ErrNo::EBADF))
+    }
+
+    pub fn lseek(_handle: usize, _offset: i64, _whence: usize) -> Result<usize, i64> {
+        Err(err(ErrNo::EBADF))
+    }
+
+    pub fn set_append(_handle: usize, _append: bool) -> Result<(), i64> {
+        Err(err(ErrNo::EBADF))
+    }
+
+    pub fn ftruncate(_handle: usize, _len: u64) -> Result<(), i64> {
+        Err(err(ErrNo::EBADF))
+    }
+
+    pub fn stat(_handle: usize) -> Result<Metadata, i64> {
+        Err(err(ErrNo::EBADF))
+    }
+
+    pub fn stat_path(_pathname: &str) -> Result<Metadata, i64> {
+        Err(err(ErrNo::ENOENT))
+    }
+
+    pub fn getdents64(_handle: usize, _buf: &mut [u8]) -> Result<usize, i64> {
+        Err(err(ErrNo::EBADF))
+    }
+
+    pub fn mkdir(_pathname: &str, _mode: u32) -> Result<(), i64> {
+        Err(err(ErrNo::EROFS))
+    }
+
+    pub fn unlink(_pathname: &str) -> Result<(), i64> {
+        Err(err(ErrNo::EROFS))
+    }
+
+    pub fn rmdir(_pathname: &str) -> Result<(), i64> {
+        Err(err(ErrNo::EROFS))
+    }
+
+    pub fn rename(_oldpath: &str, _newpath: &str) -> Result<(), i64> {
+        Err(err(ErrNo::EROFS))
+    }
+
+    pub fn close(_handle: usize) -> Result<(), i64> {
+        Err(err(ErrNo::EBADF))
+    }
+}
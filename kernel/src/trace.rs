@@ -0,0 +1,423 @@
+//! strace-like decoding of the syscalls of the app
+//!
+//! `vmrun --trace-syscalls` sets the traced syscalls in the `BootInfo`. After
+//! a traced syscall returned, its arguments and return value are decoded into
+//! one line, like `openat(AT_FDCWD, "/etc/hosts", O_RDONLY|O_CLOEXEC) = 3`,
+//! and handed with the raw values to the hypervisor. The hypervisor writes
+//! them apart from the output of the app.
+//!
+//! Memory of the app is only read where the syscall itself has read or
+//! filled it, buffers filled by the kernel are decoded after a success.
+
+use crate::hostmsg::{self, Kind, MsgBuf};
+use core::fmt::{self, Write};
+use vmsyscall::syscalls;
+use vmsyscall::TRACE_LINE_LEN;
+
+/// Bytes of a buffer shown in a trace line
+const BUF_PREVIEW: usize = 32;
+
+/// Maximum length of a path read from the app
+const PATH_MAX: usize = 4096;
+
+const AT_FDCWD: i32 = -100;
+
+/// How a syscall argument is decoded
+#[derive(Clone, Copy)]
+enum Arg {
+    /// a signed `int`
+    Int,
+    /// a signed `off_t`
+    Off,
+    /// an unsigned size
+    Size,
+    /// a pointer or a bit mask
+    Hex,
+    Fd,
+    /// a file descriptor or `AT_FDCWD`
+    DirFd,
+    /// a NUL terminated string
+    Path,
+    /// a buffer of the app, its length is the next argument
+    Out,
+    /// a buffer filled by the kernel, its length is the return value
+    In,
+    /// `open()` flags with the access mode
+    OpenFlags,
+    /// `O_CLOEXEC` and `O_NONBLOCK` of `pipe2()` and friends
+    Flags,
+    /// an octal file mode
+    Mode,
+    /// `mmap()` and `mprotect()` protection
+    Prot,
+    /// `mmap()` flags
+    MapFlags,
+    /// two file descriptors filled by the kernel
+    FdPair,
+    /// a `struct sockaddr` of the app, its length is the next argument
+    SockAddr,
+    /// a `struct stat` filled by the kernel
+    Stat,
+}
+
+/// The arguments of the syscall `name`, `None` to show all raw
+fn signature(name: &str) -> Option<&'static [Arg]> {
+    use Arg::*;
+    Some(match name {
+        "read" => &[Fd, In, Size],
+        "write" => &[Fd, Out, Size],
+        "pread64" => &[Fd, In, Size, Off],
+        "pwrite64" => &[Fd, Out, Size, Off],
+        "readv" | "writev" => &[Fd, Hex, Int],
+        "open" => &[Path, OpenFlags, Mode],
+        "openat" => &[DirFd, Path, OpenFlags, Mode],
+        "close" | "dup" | "fsync" | "fdatasync" => &[Fd],
+        "dup2" => &[Fd, Fd],
+        "dup3" => &[Fd, Fd, Flags],
+        "fcntl" => &[Fd, Int, Hex],
+        "close_range" => &[Fd, Fd, Hex],
+        "access" => &[Path, Int],
+        "stat" | "lstat" => &[Path, Stat],
+        "fstat" => &[Fd, Stat],
+        "newfstatat" => &[DirFd, Path, Stat, Hex],
+        "lseek" => &[Fd, Off, Int],
+        "getdents64" => &[Fd, Hex, Size],
+        "ftruncate" => &[Fd, Off],
+        "mkdir" => &[Path, Mode],
+        "mkdirat" => &[DirFd, Path, Mode],
+        "unlink" | "rmdir" | "chdir" => &[Path],
+        "unlinkat" => &[DirFd, Path, Hex],
+        "rename" => &[Path, Path],
+        "renameat" => &[DirFd, Path, DirFd, Path],
+        "readlink" => &[Path, Hex, Size],
+        "mmap" => &[Hex, Size, Prot, MapFlags, Fd, Hex],
+        "mprotect" => &[Hex, Size, Prot],
+        "munmap" => &[Hex, Size],
+        "brk" | "uname" | "set_tid_address" => &[Hex],
+        "arch_prctl" => &[Hex, Hex],
+        "ioctl" => &[Fd, Hex, Hex],
+        "pipe" => &[FdPair],
+        "pipe2" => &[FdPair, Flags],
+        "socket" => &[Int, Hex, Int],
+        "socketpair" => &[Int, Hex, Int, FdPair],
+        "bind" | "connect" => &[Fd, SockAddr, Size],
+        "listen" | "shutdown" => &[Fd, Int],
+        "accept" | "getsockname" | "getpeername" => &[Fd, Hex, Hex],
+        "accept4" => &[Fd, Hex, Hex, Flags],
+        "sendto" => &[Fd, Out, Size, Hex, SockAddr, Size],
+        "recvfrom" => &[Fd, In, Size, Hex, Hex, Hex],
+        "sendmsg" | "recvmsg" => &[Fd, Hex, Hex],
+        "setsockopt" | "getsockopt" => &[Fd, Int, Int, Hex, Hex],
+        "poll" => &[Hex, Size, Int],
+        "ppoll" => &[Hex, Size, Hex, Hex],
+        "select" | "pselect6" => &[Int, Hex, Hex, Hex, Hex],
+        "epoll_create" => &[Int],
+        "epoll_create1" => &[Flags],
+        "epoll_ctl" => &[Fd, Int, Fd, Hex],
+        "epoll_wait" => &[Fd, Hex, Int, Int],
+        "epoll_pwait" => &[Fd, Hex, Int, Int, Hex],
+        "eventfd" => &[Size],
+        "eventfd2" => &[Size, Flags],
+        "rt_sigaction" | "rt_sigprocmask" => &[Int, Hex, Hex],
+        "sigaltstack" => &[Hex, Hex],
+        "exit" | "exit_group" => &[Int],
+        _ => return None,
+    })
+}
+
+const OPEN_FLAGS: &[(usize, &str)] = &[
+    (0o100, "O_CREAT"),
+    (0o200, "O_EXCL"),
+    (0o400, "O_NOCTTY"),
+    (0o1000, "O_TRUNC"),
+    (0o2000, "O_APPEND"),
+    (0o4000, "O_NONBLOCK"),
+    (0o10000, "O_DSYNC"),
+    (0o20000, "O_ASYNC"),
+    (0o40000, "O_DIRECT"),
+    (0o100000, "O_LARGEFILE"),
+    (0o200000, "O_DIRECTORY"),
+    (0o400000, "O_NOFOLLOW"),
+    (0o1000000, "O_NOATIME"),
+    (0o2000000, "O_CLOEXEC"),
+    (0o10000000, "O_PATH"),
+];
+
+const PROT_FLAGS: &[(usize, &str)] = &[(1, "PROT_READ"), (2, "PROT_WRITE"), (4, "PROT_EXEC")];
+
+const MAP_FLAGS: &[(usize, &str)] = &[
+    (0x1, "MAP_SHARED"),
+    (0x2, "MAP_PRIVATE"),
+    (0x10, "MAP_FIXED"),
+    (0x20, "MAP_ANONYMOUS"),
+    (0x100, "MAP_GROWSDOWN"),
+    (0x4000, "MAP_NORESERVE"),
+    (0x8000, "MAP_POPULATE"),
+    (0x20000, "MAP_STACK"),
+];
+
+const FILE_TYPES: &[(u32, &str)] = &[
+    (0o140000, "S_IFSOCK"),
+    (0o120000, "S_IFLNK"),
+    (0o100000, "S_IFREG"),
+    (0o060000, "S_IFBLK"),
+    (0o040000, "S_IFDIR"),
+    (0o020000, "S_IFCHR"),
+    (0o010000, "S_IFIFO"),
+];
+
+/// Is the syscall return value an `errno`
+fn is_error(ret: usize) -> bool {
+    (ret as isize) < 0 && (ret as isize) >= -4095
+}
+
+unsafe fn bytes<'a>(ptr: usize, len: usize) -> &'a [u8] {
+    core::slice::from_raw_parts(ptr as *const u8, len)
+}
+
+/// The NUL terminated string at `ptr`, without the NUL
+unsafe fn c_str<'a>(ptr: usize) -> &'a [u8] {
+    let mut len = 0;
+    while len < PATH_MAX && (ptr as *const u8).add(len).read() != 0 {
+        len += 1;
+    }
+    bytes(ptr, len)
+}
+
+/// Write `bytes` as a C string literal, with `...` if longer than `max`
+fn quote(w: &mut MsgBuf, bytes: &[u8], max: usize) -> fmt::Result {
+    w.write_char('"')?;
+    for &b in bytes.iter().take(max) {
+        match b {
+            b'"' => w.write_str("\\\"")?,
+            b'\\' => w.write_str("\\\\")?,
+            b'\n' => w.write_str("\\n")?,
+            b'\r' => w.write_str("\\r")?,
+            b'\t' => w.write_str("\\t")?,
+            0x20..=0x7e => w.write_char(b as char)?,
+            _ => write!(w, "\\x{:02x}", b)?,
+        }
+    }
+    w.write_char('"')?;
+    if bytes.len() > max {
+        w.write_str("...")?;
+    }
+    Ok(())
+}
+
+/// Write the names of the bits of `value`, after `sep` if something is
+/// written before
+fn bits(w: &mut MsgBuf, value: usize, names: &[(usize, &str)], mut sep: &str) -> fmt::Result {
+    let mut rest = value;
+    for (bit, name) in names {
+        if value & bit == *bit {
+            write!(w, "{}{}", sep, name)?;
+            sep = "|";
+            rest &= !bit;
+        }
+    }
+    match (rest, sep) {
+        (0, "|") => Ok(()),
+        (rest, sep) => write!(w, "{}{:#x}", sep, rest),
+    }
+}
+
+fn open_flags(w: &mut MsgBuf, flags: usize) -> fmt::Result {
+    w.write_str(match flags & 0o3 {
+        0 => "O_RDONLY",
+        1 => "O_WRONLY",
+        2 => "O_RDWR",
+        _ => "O_ACCMODE",
+    })?;
+    bits(w, flags & !0o3, OPEN_FLAGS, "|")
+}
+
+fn mode(w: &mut MsgBuf, mode: u32) -> fmt::Result {
+    match FILE_TYPES.iter().find(|(ty, _)| mode & 0o170000 == *ty) {
+        Some((_, name)) => write!(w, "{}|{:04o}", name, mode & 0o7777),
+        None => write!(w, "{:04o}", mode),
+    }
+}
+
+fn sockaddr(w: &mut MsgBuf, addr: &[u8]) -> fmt::Result {
+    if addr.len() < 2 {
+        return w.write_str("{}");
+    }
+    let family = u16::from_ne_bytes([addr[0], addr[1]]);
+    let port = |addr: &[u8]| u16::from_be_bytes([addr[2], addr[3]]);
+    match family {
+        1 => {
+            let path = &addr[2..];
+            let path = &path[..path.iter().position(|b| *b == 0).unwrap_or(path.len())];
+            w.write_str("{sa_family=AF_UNIX, sun_path=")?;
+            quote(w, path, PATH_MAX)?;
+            w.write_char('}')
+        }
+        2 if addr.len() >= 8 => write!(
+            w,
+            "{{sa_family=AF_INET, sin_port=htons({}), sin_addr=inet_addr(\"{}.{}.{}.{}\")}}",
+            port(addr),
+            addr[4],
+            addr[5],
+            addr[6],
+            addr[7]
+        ),
+        10 if addr.len() >= 24 => {
+            write!(w, "{{sa_family=AF_INET6, sin6_port=htons({}), ", port(addr))?;
+            w.write_str("sin6_addr=inet_pton(\"")?;
+            for (i, group) in addr[8..24].chunks(2).enumerate() {
+                let sep = if i == 0 { "" } else { ":" };
+                write!(w, "{}{:x}", sep, u16::from_be_bytes([group[0], group[1]]))?;
+            }
+            w.write_str("\")}")
+        }
+        family => write!(w, "{{sa_family={}}}", family),
+    }
+}
+
+/// Write the argument `i` of `args`, `ok` is the return value of a success
+fn arg(w: &mut MsgBuf, arg: Arg, args: &[usize; 6], i: usize, ok: Option<usize>) -> fmt::Result {
+    let value = args[i];
+    let next = args.get(i + 1).copied().unwrap_or(0);
+    let filled = ok.is_some() && value != 0;
+    match arg {
+        Arg::Int | Arg::Fd => write!(w, "{}", value as i32),
+        Arg::DirFd if value as i32 == AT_FDCWD => w.write_str("AT_FDCWD"),
+        Arg::DirFd => write!(w, "{}", value as i32),
+        Arg::Off => write!(w, "{}", value as i64),
+        Arg::Size => write!(w, "{}", value),
+        Arg::Path | Arg::Out | Arg::SockAddr if value == 0 => w.write_str("NULL"),
+        Arg::Path => quote(w, unsafe { c_str(value) }, PATH_MAX),
+        Arg::Out => quote(
+            w,
+            unsafe { bytes(value, next.min(BUF_PREVIEW + 1)) },
+            BUF_PREVIEW,
+        ),
+        Arg::In if filled => {
+            let len = ok.unwrap().min(next);
+            quote(
+                w,
+                unsafe { bytes(value, len.min(BUF_PREVIEW + 1)) },
+                BUF_PREVIEW,
+            )
+        }
+        Arg::OpenFlags => open_flags(w, value),
+        Arg::Flags => bits(w, value, OPEN_FLAGS, ""),
+        Arg::Mode => write!(w, "{:04o}", value),
+        Arg::Prot if value == 0 => w.write_str("PROT_NONE"),
+        Arg::Prot => bits(w, value, PROT_FLAGS, ""),
+        Arg::MapFlags => bits(w, value, MAP_FLAGS, ""),
+        Arg::FdPair if filled => {
+            let [first, second] = unsafe { (value as *const [i32; 2]).read() };
+            write!(w, "[{}, {}]", first, second)
+        }
+        Arg::SockAddr => sockaddr(w, unsafe { bytes(value, next.min(128)) }),
+        Arg::Stat if filled => {
+            let stat = unsafe { &*(value as *const crate::syscall::Stat) };
+            w.write_str("{st_mode=")?;
+            mode(w, stat.st_mode)?;
+            write!(w, ", st_size={}, ...}}", stat.st_size)
+        }
+        Arg::Hex | Arg::In | Arg::FdPair | Arg::Stat => write!(w, "{:#x}", value),
+    }
+}
+
+fn decode(w: &mut MsgBuf, nr: usize, args: &[usize; 6], ret: Option<usize>) -> fmt::Result {
+    let name = syscalls::name(nr as _);
+    let ok = ret.filter(|ret| !is_error(*ret));
+
+    match name {
+        Some(name) => write!(w, "{}(", name)?,
+        None => write!(w, "syscall_{}(", nr)?,
+    }
+    match name.and_then(signature) {
+        Some(signature) => {
+            for (i, a) in signature.iter().enumerate() {
+                if i > 0 {
+                    w.write_str(", ")?;
+                }
+                arg(w, *a, args, i, ok)?;
+            }
+        }
+        None => {
+            for (i, value) in args.iter().enumerate() {
+                let sep = if i == 0 { "" } else { ", " };
+                write!(w, "{}{:#x}", sep, value)?;
+            }
+        }
+    }
+    w.write_char(')')?;
+
+    match ret {
+        None => w.write_str(" = ?"),
+        Some(ret) if is_error(ret) => {
+            let errno = -(ret as i64);
+            match syscalls::errno_name(errno) {
+                Some(errno) => write!(w, " = -1 {}", errno),
+                None => write!(w, " = -1 E{}", errno),
+            }
+        }
+        Some(ret) if matches!(name, Some("mmap") | Some("brk")) => write!(w, " = {:#x}", ret),
+        Some(ret) => write!(w, " = {}", ret as isize),
+    }
+}
+
+/// Decode the syscall `nr` and hand it to the hypervisor
+///
+/// `ret` is `None` for a syscall that does not return.
+pub fn syscall(nr: usize, args: &[usize; 6], ret: Option<usize>) {
+    let mut line = MsgBuf::new(TRACE_LINE_LEN);
+    // the line is cut at its end
+    let _ = decode(&mut line, nr, args, ret);
+    hostmsg::send(Kind::Trace { nr, args, ret }, &line);
+}
+
+#[cfg(test)]
+#[test_case]
+fn test_trace() {
+    use crate::{serial_print, serial_println};
+    serial_print!("test_trace...");
+
+    let line = |nr, args: [usize; 6], ret| {
+        let mut line = MsgBuf::new(TRACE_LINE_LEN);
+        decode(&mut line, nr, &args, ret).unwrap();
+        line
+    };
+
+    let path = b"/etc/hosts\0";
+    let l = line(
+        257,
+        [-100isize as _, path.as_ptr() as _, 0o2000000, 0, 0, 0],
+        Some(3),
+    );
+    assert_eq!(
+        l.as_bytes(),
+        &b"openat(AT_FDCWD, \"/etc/hosts\", O_RDONLY|O_CLOEXEC, 0000) = 3"[..]
+    );
+
+    let buf = b"hi\n";
+    let l = line(1, [7, buf.as_ptr() as _, 3, 0, 0, 0], Some(-9isize as _));
+    assert_eq!(l.as_bytes(), &b"write(7, \"hi\\n\", 3) = -1 EBADF"[..]);
+
+    let l = line(9, [0, 4096, 3, 0x22, -1isize as _, 0], Some(0x1000));
+    assert_eq!(
+        l.as_bytes(),
+        &b"mmap(0x0, 4096, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0x0) = 0x1000"[..]
+    );
+
+    let addr = [2u8, 0, 0x1f, 0x90, 127, 0, 0, 1];
+    let l = line(42, [3, addr.as_ptr() as _, 16, 0, 0, 0], Some(0));
+    assert_eq!(
+        l.as_bytes(),
+        &b"connect(3, {sa_family=AF_INET, sin_port=htons(8080), sin_addr=inet_addr(\"127.0.0.1\")}, 16) = 0"[..]
+    );
+
+    let l = line(231, [1, 0, 0, 0, 0, 0], None);
+    assert_eq!(l.as_bytes(), &b"exit_group(1) = ?"[..]);
+    let l = line(1000, [1, 2, 3, 4, 5, 6], Some(-38isize as _));
+    assert_eq!(
+        l.as_bytes(),
+        &b"syscall_1000(0x1, 0x2, 0x3, 0x4, 0x5, 0x6) = -1 ENOSYS"[..]
+    );
+    serial_println!("[ok]");
+}
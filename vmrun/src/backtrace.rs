@@ -0,0 +1,300 @@
+//! Symbolized backtraces of failed guests
+//!
+//! After a failure exit, vmrun follows the frame pointer chain of the first
+//! vCPU in guest memory. The ISR and syscall entry stubs of the kernel push a
+//! stack frame of the interrupted code, so the chain continues from the kernel
+//! into the app. Addresses are looked up in the symbol tables of the kernel,
+//! the app and its ELF interpreter, frames in user accessible pages are ring 3
+//! frames.
+//!
+//! Code built without frame pointers, like the C parts of musl, ends the chain
+//! early.
+
+use crate::arch::x86_64::VirtAddr;
+use crate::error::*;
+use crate::kvmvm::KvmVm;
+use crate::map_context;
+use std::fmt::Write as _;
+use xmas_elf::sections::SectionData;
+use xmas_elf::symbol_table::{self, Entry};
+use xmas_elf::ElfFile;
+
+/// The most frames of a backtrace, a corrupted chain might be a loop
+pub const MAX_FRAMES: usize = 64;
+
+struct Symbol {
+    start: u64,
+    size: u64,
+    name: String,
+}
+
+/// The function symbols of an ELF file
+pub struct Symbols {
+    name: String,
+    symbols: Vec<Symbol>,
+}
+
+impl Symbols {
+    /// Read the symbol tables of the ELF file `elf`, loaded at `base`
+    pub fn new(name: &str, elf: &[u8], base: u64) -> Result<Self, Error> {
+        let elf_file = ElfFile::new(elf).map_err(map_context!())?;
+        let mut symbols = Vec::new();
+        for section in elf_file.section_iter() {
+            let entries = match section.get_data(&elf_file) {
+                Ok(SectionData::SymbolTable64(entries)) => entries,
+                Ok(SectionData::DynSymbolTable64(entries)) => entries,
+                _ => continue,
+            };
+            for entry in entries {
+                if entry.get_type() != Ok(symbol_table::Type::Func) || entry.value() == 0 {
+                    continue;
+                }
+                if let Ok(name) = entry.get_name(&elf_file) {
+                    symbols.push(Symbol {
+                        start: base + entry.value(),
+                        size: entry.size(),
+                        name: demangle(name),
+                    });
+                }
+            }
+        }
+        symbols.sort_by_key(|symbol| symbol.start);
+        symbols.dedup_by_key(|symbol| symbol.start);
+        Ok(Symbols {
+            name: name.to_string(),
+            symbols,
+        })
+    }
+
+    /// The function at `addr` and the offset into it
+    pub fn lookup(&self, addr: u64) -> Option<(&str, u64)> {
+        let index = match self.symbols.binary_search_by_key(&addr, |s| s.start) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let symbol = &self.symbols[index];
+        let offset = addr - symbol.start;
+        match symbol.size {
+            0 => Some((&symbol.name, offset)),
+            size if offset < size => Some((&symbol.name, offset)),
+            _ => None,
+        }
+    }
+}
+
+/// Demangle a Rust symbol without its hash, other symbols are returned as
+/// they are
+fn demangle(symbol: &str) -> String {
+    format!("{:#}", rustc_demangle::demangle(symbol))
+}
+
+/// A frame of a backtrace
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// The instruction pointer of the first frame, else the return address
+    pub addr: u64,
+    /// The code is in a user accessible page, run in ring 3
+    pub user: bool,
+}
+
+/// The instruction pointer `rip` and the return addresses of the frame
+/// pointer chain at `rbp`, `read` reads a `u64` of guest virtual memory
+pub fn walk(rip: u64, mut rbp: u64, mut read: impl FnMut(u64) -> Option<u64>) -> Vec<u64> {
+    let mut addrs = vec![rip];
+    while addrs.len() < MAX_FRAMES && rbp != 0 && rbp % 8 == 0 {
+        let (next, ret) = match (read(rbp), read(rbp.wrapping_add(8))) {
+            (Some(next), Some(ret)) if ret != 0 => (next, ret),
+            _ => break,
+        };
+        addrs.push(ret);
+        if next == rbp {
+            break;
+        }
+        rbp = next;
+    }
+    addrs
+}
+
+/// The backtrace of vCPU `vcpuid`, up to the first address not mapped
+pub fn backtrace(vm: &KvmVm, vcpuid: u8) -> Result<Vec<Frame>, Error> {
+    let vcpu = &vm.cpu_fd[vcpuid as usize];
+    let regs = vcpu.get_regs().map_err(|e| ErrorKind::from(&e))?;
+    let cr3 = vcpu.get_sregs().map_err(|e| ErrorKind::from(&e))?.cr3;
+
+    let addrs = walk(regs.rip, regs.rbp, |addr| {
+        let mut buf = [0u8; 8];
+        let addr = VirtAddr::try_new(addr).ok()?;
+        vm.mem.read_virt(cr3, addr, &mut buf).ok()?;
+        Some(u64::from_le_bytes(buf))
+    });
+
+    let mut frames = Vec::new();
+    for addr in addrs {
+        let translation = match VirtAddr::try_new(addr) {
+            Ok(vaddr) => vm.mem.gva2gpa(cr3, vaddr),
+            Err(_) => break,
+        };
+        match translation {
+            Ok(translation) => frames.push(Frame {
+                addr,
+                user: translation.user,
+            }),
+            Err(_) => break,
+        }
+    }
+    Ok(frames)
+}
+
+/// The symbols of the kernel, the app and the ELF interpreter of `vm`
+///
+/// Files without a valid symbol table are left out. An empty `app` is read
+/// from guest memory, as for a restored guest.
+pub fn guest_symbols(vm: &KvmVm, kernel: &[u8], app: &[u8]) -> Vec<Symbols> {
+    let (app_blob, interp_blob) = vm.mem.elf_blobs();
+    let mut symbols = Vec::new();
+    if !kernel.is_empty() {
+        symbols.extend(Symbols::new("kernel", kernel, 0));
+    }
+    let app = match app {
+        [] => vm.mem.blob_data(&app_blob).unwrap_or(&[]),
+        app => app,
+    };
+    if !app.is_empty() {
+        symbols.extend(Symbols::new("app", app, app_blob.base));
+    }
+    if let Ok(interp) = vm.mem.blob_data(&interp_blob) {
+        if !interp.is_empty() {
+            symbols.extend(Symbols::new("interp", interp, interp_blob.base));
+        }
+    }
+    symbols
+}
+
+/// Format the frames, one per line
+///
+/// A return address is looked up one byte before, a call might be the last
+/// instruction of a function.
+pub fn format(frames: &[Frame], symbols: &[Symbols]) -> String {
+    let mut out = String::new();
+    for (index, frame) in frames.iter().enumerate() {
+        let lookup = frame.addr - if index == 0 { 0 } else { 1 };
+        let ring = if frame.user { 3 } else { 0 };
+        let _ = write!(out, "#{:<2} ring {} {:#018x}", index, ring, frame.addr);
+        match symbols.iter().find_map(|s| {
+            s.lookup(lookup)
+                .map(|(name, offset)| (&s.name, name, offset))
+        }) {
+            Some((file, name, offset)) => {
+                let offset = offset + frame.addr - lookup;
+                let _ = writeln!(out, " {}+{:#x} ({})", name, offset, file);
+            }
+            None => {
+                let _ = writeln!(out, " ??");
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn frame_pointer_chain() {
+        // kernel frames, the frame of the ISR stub and the app frames
+        let stack: HashMap<u64, u64> = vec![
+            (0xFFFF_8000_0010_0000, 0xFFFF_8000_0010_0040),
+            (0xFFFF_8000_0010_0008, 0x20_1234),
+            (0xFFFF_8000_0010_0040, 0x7FFF_0000),
+            (0xFFFF_8000_0010_0048, 0x40_1000),
+            (0x7FFF_0000, 0x7FFF_0100),
+            (0x7FFF_0008, 0x40_2000),
+            (0x7FFF_0100, 0),
+            (0x7FFF_0108, 0x40_3000),
+        ]
+        .into_iter()
+        .collect();
+        let read = |addr| stack.get(&addr).copied();
+
+        assert_eq!(
+            walk(0x20_0000, 0xFFFF_8000_0010_0000, read),
+            vec![0x20_0000, 0x20_1234, 0x40_1000, 0x40_2000, 0x40_3000]
+        );
+        // unmapped and misaligned frame pointers
+        assert_eq!(walk(0x20_0000, 0x1000, read), vec![0x20_0000]);
+        assert_eq!(walk(0x20_0000, 0x7FFF_0001, read), vec![0x20_0000]);
+        // a loop
+        assert_eq!(
+            walk(0x20_0000, 0x10, |addr| Some(addr + 0x10)).len(),
+            MAX_FRAMES
+        );
+    }
+
+    #[test]
+    fn demangle_symbols() {
+        assert_eq!(
+            demangle("_ZN6kernel4arch6x86_6410interrupts18page_fault_handler17h0123456789abcdefE"),
+            "kernel::arch::x86_64::interrupts::page_fault_handler"
+        );
+        assert_eq!(
+            demangle(
+                "_ZN4core3ptr46drop_in_place$LT$alloc..vec..Vec$LT$u8$GT$$GT$17h0123456789abcdefE"
+            ),
+            "core::ptr::drop_in_place<alloc::vec::Vec<u8>>"
+        );
+        assert_eq!(
+            demangle("_ZN59_$LT$core..fmt..Arguments$u20$as$u20$core..fmt..Display$GT$3fmt17h0123456789abcdefE"),
+            "<core::fmt::Arguments as core::fmt::Display>::fmt"
+        );
+        assert_eq!(demangle("memcpy"), "memcpy");
+        assert_eq!(demangle("_ZN99brokenE"), "_ZN99brokenE");
+    }
+
+    #[test]
+    fn symbolize() {
+        let exe = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let symbols = Symbols::new("vmrun", &exe, 0x1000_0000).unwrap();
+        let function = symbols
+            .symbols
+            .iter()
+            .find(|s| s.name == "vmrun::backtrace::format" && s.size > 1)
+            .expect("no symbol of format()");
+        let start = function.start;
+
+        assert_eq!(symbols.lookup(start), Some(("vmrun::backtrace::format", 0)));
+        assert_eq!(
+            symbols.lookup(start + 1),
+            Some(("vmrun::backtrace::format", 1))
+        );
+        assert_eq!(symbols.lookup(0x1000), None);
+
+        let frames = [
+            Frame {
+                addr: start,
+                user: false,
+            },
+            // the return address right after the end of the function
+            Frame {
+                addr: start + function.size,
+                user: true,
+            },
+        ];
+        let text = format(&frames, &[symbols]);
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            format!(
+                "#0  ring 0 {:#018x} vmrun::backtrace::format+0x0 (vmrun)",
+                start
+            )
+        );
+        assert!(lines[1].starts_with("#1  ring 3 "));
+        assert!(lines[1].contains(&format!(
+            "vmrun::backtrace::format+{:#x} (vmrun)",
+            function.size
+        )));
+    }
+}
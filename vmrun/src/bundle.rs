@@ -0,0 +1,508 @@
+//! Signed bundles of a kernel, an app and its configuration
+//!
+//! A bundle is a single file to run a workload with `vmrun run`. It starts
+//! with the magic `ENARXBND` and a `u32` version, followed by sections:
+//!
+//! ```text
+//! kind: u32 | len: u64 | data: [u8; len]
+//! ```
+//!
+//! All integers are little-endian. The kernel and app sections are required,
+//! the initrd and the manifest are optional. The last section is the
+//! signature: the ed25519 public key of the signer and the signature over all
+//! bytes of the bundle before the signature section.
+
+use crate::context;
+use crate::error::*;
+use crate::guest::{tmpfs_fits, DEFAULT_GUEST_MEM};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// The magic at the start of every bundle
+pub const BUNDLE_MAGIC: &[u8; 8] = b"ENARXBND";
+/// The version of the bundle format
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Length of an ed25519 secret key
+pub const SECRET_KEY_LEN: usize = 32;
+/// Length of an ed25519 public key
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+const SIGNATURE_LEN: usize = 64;
+const SECTION_HEADER_LEN: usize = 12;
+
+/// Smallest guest memory the kernel boots with
+const MIN_MEMORY_MIB: u64 = 64;
+/// Largest guest memory, 1 TiB
+const MAX_MEMORY_MIB: u64 = 1024 * 1024;
+
+/// Largest `/tmp`, 1 TiB
+pub const MAX_TMPFS_MIB: u64 = 1024 * 1024;
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SectionKind {
+    Kernel = 1,
+    App = 2,
+    Initrd = 3,
+    Manifest = 4,
+    Signature = 5,
+}
+
+impl SectionKind {
+    fn from_u32(kind: u32) -> Option<Self> {
+        match kind {
+            1 => Some(SectionKind::Kernel),
+            2 => Some(SectionKind::App),
+            3 => Some(SectionKind::Initrd),
+            4 => Some(SectionKind::Manifest),
+            5 => Some(SectionKind::Signature),
+            _ => None,
+        }
+    }
+}
+
+/// The configuration of the workload
+///
+/// The text format has one `key = value` per line, a line starting with `#`
+/// is a comment. Values in double quotes keep their whitespace and may have
+/// the escapes of Rust strings:
+///
+/// ```text
+/// memory_mib = 512
+/// tmpfs_mib = 16
+/// vcpus = 1
+/// arg = /init
+/// arg = "--color=#fff "
+/// env = LANG=C
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    /// Guest memory in MiB, the default of `vmrun` if `None`
+    pub memory_mib: Option<u64>,
+    /// Size of `/tmp` in MiB, `0` disables it, the default of `vmrun` if `None`
+    pub tmpfs_mib: Option<u64>,
+    /// Number of vCPUs, only `1` is supported
+    pub vcpus: u32,
+    /// Arguments of the app, starting with `argv[0]`
+    pub args: Vec<String>,
+    /// Environment of the app as `KEY=value`
+    pub env: Vec<String>,
+}
+
+impl Manifest {
+    /// Parse the text format of a manifest
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut manifest = Manifest {
+            vcpus: 1,
+            ..Default::default()
+        };
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut kv = line.splitn(2, '=');
+            let key = kv.next().unwrap().trim();
+            let value = kv
+                .next()
+                .ok_or_else(|| context!(ErrorKind::InvalidManifest("expected `key = value`")))?
+                .trim();
+
+            match key {
+                "memory_mib" => {
+                    let mib = value
+                        .parse::<u64>()
+                        .map_err(|_| context!(ErrorKind::InvalidManifest("invalid memory_mib")))?;
+                    if mib < MIN_MEMORY_MIB {
+                        return Err(context!(ErrorKind::InvalidManifest(
+                            "memory_mib below 64 MiB"
+                        )));
+                    }
+                    if mib > MAX_MEMORY_MIB {
+                        return Err(context!(ErrorKind::InvalidManifest(
+                            "memory_mib above 1 TiB"
+                        )));
+                    }
+                    manifest.memory_mib.replace(mib);
+                }
+                "tmpfs_mib" => {
+                    let mib = value
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|mib| *mib <= MAX_TMPFS_MIB)
+                        .ok_or_else(|| context!(ErrorKind::InvalidManifest("invalid tmpfs_mib")))?;
+                    manifest.tmpfs_mib.replace(mib);
+                }
+                "vcpus" => {
+                    manifest.vcpus = match value.parse::<u32>() {
+                        Ok(1) => 1,
+                        Ok(_) => {
+                            return Err(context!(ErrorKind::InvalidManifest(
+                                "only one vCPU is supported"
+                            )))
+                        }
+                        Err(_) => {
+                            return Err(context!(ErrorKind::InvalidManifest("invalid vcpus")))
+                        }
+                    };
+                }
+                "arg" => manifest.args.push(unquote(value)?),
+                "env" => {
+                    let var = unquote(value)?;
+                    if !var.contains('=') {
+                        return Err(context!(ErrorKind::InvalidManifest(
+                            "env expects `KEY=value`"
+                        )));
+                    }
+                    manifest.env.push(var);
+                }
+                _ => return Err(context!(ErrorKind::InvalidManifest("unknown key"))),
+            }
+        }
+
+        let mem_size = manifest.mem_size().unwrap_or(DEFAULT_GUEST_MEM);
+        if !tmpfs_fits(manifest.tmpfs_size().unwrap_or(0), mem_size) {
+            return Err(context!(ErrorKind::InvalidManifest(
+                "tmpfs_mib does not fit in the guest memory"
+            )));
+        }
+
+        Ok(manifest)
+    }
+
+    /// Size of the guest memory in bytes
+    pub fn mem_size(&self) -> Option<u64> {
+        self.memory_mib.and_then(|mib| mib.checked_mul(1024 * 1024))
+    }
+
+    /// Size of `/tmp` in bytes
+    pub fn tmpfs_size(&self) -> Option<u64> {
+        self.tmpfs_mib.and_then(|mib| mib.checked_mul(1024 * 1024))
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(mib) = self.memory_mib {
+            writeln!(f, "memory_mib = {}", mib)?;
+        }
+        if let Some(mib) = self.tmpfs_mib {
+            writeln!(f, "tmpfs_mib = {}", mib)?;
+        }
+        writeln!(f, "vcpus = {}", self.vcpus)?;
+        for arg in &self.args {
+            writeln!(f, "arg = \"{}\"", arg.escape_default())?;
+        }
+        for var in &self.env {
+            writeln!(f, "env = \"{}\"", var.escape_default())?;
+        }
+        Ok(())
+    }
+}
+
+/// A value as it is, or the string in double quotes with its escapes resolved
+fn unquote(value: &str) -> Result<String, Error> {
+    let invalid = || context!(ErrorKind::InvalidManifest("invalid quoted value"));
+
+    let quoted = match value.strip_prefix('"') {
+        Some(quoted) => quoted.strip_suffix('"').ok_or_else(invalid)?,
+        None => return Ok(value.to_string()),
+    };
+
+    let mut out = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '"' => return Err(invalid()),
+            '\\' => match chars.next().ok_or_else(invalid)? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                c @ '\\' | c @ '"' | c @ '\'' => c,
+                'u' => {
+                    let rest = chars.as_str().strip_prefix('{').ok_or_else(invalid)?;
+                    let end = rest.find('}').ok_or_else(invalid)?;
+                    let c = u32::from_str_radix(&rest[..end], 16)
+                        .ok()
+                        .and_then(std::char::from_u32)
+                        .ok_or_else(invalid)?;
+                    chars = rest[end + 1..].chars();
+                    c
+                }
+                _ => return Err(invalid()),
+            },
+            c => c,
+        };
+        out.push(c);
+    }
+    Ok(out)
+}
+
+/// The contents of a bundle
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bundle {
+    /// The kernel ELF file
+    pub kernel: Vec<u8>,
+    /// The app ELF file
+    pub app: Vec<u8>,
+    /// The initial filesystem image
+    pub initrd: Option<Vec<u8>>,
+    pub manifest: Manifest,
+}
+
+fn push_section(out: &mut Vec<u8>, kind: SectionKind, data: &[u8]) {
+    out.extend_from_slice(&(kind as u32).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+fn keypair(secret_key: &[u8]) -> Result<Keypair, Error> {
+    let secret = SecretKey::from_bytes(secret_key)
+        .map_err(|_| context!(ErrorKind::InvalidBundle("invalid secret key")))?;
+    let public = PublicKey::from(&secret);
+    Ok(Keypair { secret, public })
+}
+
+/// The public key of the ed25519 `secret_key`
+pub fn public_key(secret_key: &[u8]) -> Result<[u8; PUBLIC_KEY_LEN], Error> {
+    Ok(keypair(secret_key)?.public.to_bytes())
+}
+
+impl Bundle {
+    /// Serialize the bundle, signed with the ed25519 `secret_key`
+    pub fn pack(&self, secret_key: &[u8]) -> Result<Vec<u8>, Error> {
+        let keypair = keypair(secret_key)?;
+
+        let mut out = Vec::new();
+        out.extend_from_slice(BUNDLE_MAGIC);
+        out.extend_from_slice(&BUNDLE_VERSION.to_le_bytes());
+        push_section(&mut out, SectionKind::Kernel, &self.kernel);
+        push_section(&mut out, SectionKind::App, &self.app);
+        if let Some(initrd) = &self.initrd {
+            push_section(&mut out, SectionKind::Initrd, initrd);
+        }
+        push_section(
+            &mut out,
+            SectionKind::Manifest,
+            self.manifest.to_string().as_bytes(),
+        );
+
+        let signature = keypair.sign(&out);
+        let mut sig = Vec::with_capacity(PUBLIC_KEY_LEN + SIGNATURE_LEN);
+        sig.extend_from_slice(keypair.public.as_bytes());
+        sig.extend_from_slice(&signature.to_bytes());
+        push_section(&mut out, SectionKind::Signature, &sig);
+
+        Ok(out)
+    }
+
+    /// Verify and parse a bundle, returns it with the public key of the signer
+    ///
+    /// With `trusted`, the bundle has to be signed with this public key.
+    /// Otherwise any valid signature is accepted and the caller has to check
+    /// the returned key.
+    pub fn open(
+        data: &[u8],
+        trusted: Option<&[u8; PUBLIC_KEY_LEN]>,
+    ) -> Result<(Self, [u8; PUBLIC_KEY_LEN]), Error> {
+        if data.len() < BUNDLE_MAGIC.len() + 4 || &data[..BUNDLE_MAGIC.len()] != BUNDLE_MAGIC {
+            return Err(context!(ErrorKind::InvalidBundle("not a bundle")));
+        }
+
+        let mut version = [0u8; 4];
+        version.copy_from_slice(&data[8..12]);
+        if u32::from_le_bytes(version) != BUNDLE_VERSION {
+            return Err(context!(ErrorKind::InvalidBundle("unsupported version")));
+        }
+
+        let mut kernel = None;
+        let mut app = None;
+        let mut initrd = None;
+        let mut manifest = None;
+        let mut pos = 12;
+
+        let (signed, key, signature) = loop {
+            if data.len() - pos < SECTION_HEADER_LEN {
+                return Err(context!(ErrorKind::InvalidBundle("missing signature")));
+            }
+
+            let mut kind = [0u8; 4];
+            kind.copy_from_slice(&data[pos..pos + 4]);
+            let mut len = [0u8; 8];
+            len.copy_from_slice(&data[pos + 4..pos + SECTION_HEADER_LEN]);
+            let len = u64::from_le_bytes(len);
+
+            let start = pos + SECTION_HEADER_LEN;
+            if len > (data.len() - start) as u64 {
+                return Err(context!(ErrorKind::InvalidBundle("truncated section")));
+            }
+            let section = &data[start..start + len as usize];
+
+            let slot = match SectionKind::from_u32(u32::from_le_bytes(kind)) {
+                Some(SectionKind::Kernel) => &mut kernel,
+                Some(SectionKind::App) => &mut app,
+                Some(SectionKind::Initrd) => &mut initrd,
+                Some(SectionKind::Manifest) => &mut manifest,
+                Some(SectionKind::Signature) => {
+                    if section.len() != PUBLIC_KEY_LEN + SIGNATURE_LEN
+                        || start + section.len() != data.len()
+                    {
+                        return Err(context!(ErrorKind::InvalidBundle("invalid signature")));
+                    }
+                    break (
+                        &data[..pos],
+                        &section[..PUBLIC_KEY_LEN],
+                        &section[PUBLIC_KEY_LEN..],
+                    );
+                }
+                None => return Err(context!(ErrorKind::InvalidBundle("unknown section"))),
+            };
+
+            if slot.replace(section).is_some() {
+                return Err(context!(ErrorKind::InvalidBundle("duplicate section")));
+            }
+            pos = start + section.len();
+        };
+
+        let mut public_key = [0u8; PUBLIC_KEY_LEN];
+        public_key.copy_from_slice(key);
+
+        if let Some(trusted) = trusted {
+            if trusted != &public_key {
+                return Err(context!(ErrorKind::BundleKeyUntrusted));
+            }
+        }
+
+        let public =
+            PublicKey::from_bytes(key).map_err(|_| context!(ErrorKind::BundleSignatureInvalid))?;
+        let signature = Signature::try_from(signature)
+            .map_err(|_| context!(ErrorKind::BundleSignatureInvalid))?;
+        public
+            .verify(signed, &signature)
+            .map_err(|_| context!(ErrorKind::BundleSignatureInvalid))?;
+
+        let manifest = match manifest {
+            Some(text) => Manifest::parse(
+                std::str::from_utf8(text)
+                    .map_err(|_| context!(ErrorKind::InvalidManifest("not UTF-8")))?,
+            )?,
+            None => Manifest::parse("")?,
+        };
+
+        let bundle = Bundle {
+            kernel: kernel
+                .ok_or_else(|| context!(ErrorKind::InvalidBundle("missing kernel")))?
+                .to_vec(),
+            app: app
+                .ok_or_else(|| context!(ErrorKind::InvalidBundle("missing app")))?
+                .to_vec(),
+            initrd: initrd.map(<[u8]>::to_vec),
+            manifest,
+        };
+
+        Ok((bundle, public_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; SECRET_KEY_LEN] = [7u8; SECRET_KEY_LEN];
+
+    fn bundle() -> Bundle {
+        Bundle {
+            kernel: b"kernel".to_vec(),
+            app: b"app".to_vec(),
+            initrd: Some(b"initrd".to_vec()),
+            manifest: Manifest {
+                memory_mib: Some(128),
+                tmpfs_mib: Some(4),
+                vcpus: 1,
+                args: vec!["/init".into(), "--verbose".into()],
+                env: vec!["LANG=C".into()],
+            },
+        }
+    }
+
+    #[test]
+    fn pack_open() {
+        let data = bundle().pack(&KEY).unwrap();
+        let (opened, key) = Bundle::open(&data, None).unwrap();
+
+        assert_eq!(opened, bundle());
+        assert_eq!(key, public_key(&KEY).unwrap());
+        assert!(Bundle::open(&data, Some(&key)).is_ok());
+    }
+
+    #[test]
+    fn tampered() {
+        let mut data = bundle().pack(&KEY).unwrap();
+        // The first byte of the kernel
+        data[24] ^= 1;
+
+        let err = Bundle::open(&data, None).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::BundleSignatureInvalid);
+
+        let data = bundle().pack(&KEY).unwrap();
+        let err = Bundle::open(&data[..data.len() - 1], None).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::InvalidBundle("truncated section"));
+    }
+
+    #[test]
+    fn untrusted_key() {
+        let data = bundle().pack(&KEY).unwrap();
+        let other = public_key(&[8u8; SECRET_KEY_LEN]).unwrap();
+
+        let err = Bundle::open(&data, Some(&other)).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::BundleKeyUntrusted);
+    }
+
+    #[test]
+    fn manifest_parse() {
+        let text = "# workload\nmemory_mib = 128\ntmpfs_mib = 4\nvcpus = 1\narg = /init\n  # flag\narg = \"--verbose\"\nenv = LANG=C\n";
+        assert_eq!(Manifest::parse(text).unwrap(), bundle().manifest);
+        assert_eq!(
+            Manifest::parse(&bundle().manifest.to_string()).unwrap(),
+            bundle().manifest
+        );
+
+        assert!(Manifest::parse("memory_mib = 16").is_err());
+        assert!(Manifest::parse("memory_mib = 18446744073709551615").is_err());
+        assert!(Manifest::parse("tmpfs_mib = -1").is_err());
+        assert!(Manifest::parse("memory_mib = 64\ntmpfs_mib = 64").is_err());
+        assert!(Manifest::parse("tmpfs_mib = 4096").is_err());
+        assert!(Manifest::parse("vcpus = 2").is_err());
+        assert!(Manifest::parse("env = LANG").is_err());
+        assert!(Manifest::parse("cpus = 1").is_err());
+        assert!(Manifest::parse("arg = \"open").is_err());
+        assert!(Manifest::parse("arg = \"a\"b\"").is_err());
+        assert!(Manifest::parse("arg = \"\\x41\"").is_err());
+        assert_eq!(
+            Manifest::parse("arg = --color=#fff").unwrap().args,
+            vec!["--color=#fff".to_string()]
+        );
+    }
+
+    #[test]
+    fn pack_open_special_values() {
+        let mut bundle = bundle();
+        bundle.manifest.args = vec![
+            "--color=#fff".into(),
+            " padded\t".into(),
+            "line\nenv = INJECTED=1".into(),
+            "\"quoted\" \\ \u{e9}\u{1}".into(),
+            String::new(),
+        ];
+        bundle.manifest.env = vec!["X=a#b".into(), "Y= y ".into()];
+
+        let data = bundle.pack(&KEY).unwrap();
+        let (opened, _) = Bundle::open(&data, None).unwrap();
+        assert_eq!(opened.manifest, bundle.manifest);
+    }
+}
@@ -0,0 +1,401 @@
+//! ELF core files of failed guests
+//!
+//! `vmrun --core-dump <file>` writes a core file, if the guest exits with a
+//! failure or unexpectedly. Every mapping of the page tables of the first vCPU
+//! is a `PT_LOAD` segment at its guest virtual address, pages outside of the
+//! guest memory are zero. Every vCPU has a `NT_PRSTATUS` note with its
+//! registers, so gdb shows the guest with the symbols of the kernel and the
+//! app:
+//!
+//! ```text
+//! gdb -ex 'core-file core' -ex 'add-symbol-file app' kernel
+//! ```
+//!
+//! The mappings of all physical memory are part of the file, the core file is
+//! about as large as the guest memory times the number of its mappings.
+
+use crate::arch::x86_64::{PageSize, PhysAddr, Size4KiB};
+use crate::error::*;
+use crate::guest::{GuestMemory, Mapping};
+use crate::kvmvm::KvmVm;
+use kvm_bindings::{kvm_regs, kvm_sregs};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// see linux/elf.h
+const ET_CORE: u16 = 4;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+/// `e_phnum` of a file with more program headers, the number is in the
+/// `sh_info` of section header 0
+const PN_XNUM: u64 = 0xFFFF;
+
+const EHDR_SIZE: u64 = 64;
+const PHDR_SIZE: u64 = 56;
+const SHDR_SIZE: u64 = 64;
+/// The `struct elf_prstatus` of x86_64
+const PRSTATUS_SIZE: usize = 336;
+/// The offset of `pr_reg` in `struct elf_prstatus`
+const PRSTATUS_REG: usize = 112;
+const NOTE_NAME: &[u8; 8] = b"CORE\0\0\0\0";
+
+const PAGE_SIZE: u64 = Size4KiB::SIZE;
+
+/// Write the core file of `vm` to `path`, the vCPUs stopped with `signal`
+pub fn write(vm: &KvmVm, path: &Path, signal: u8) -> Result<(), Error> {
+    let mut cpus = Vec::new();
+    for vcpu in &vm.cpu_fd {
+        let regs = vcpu.get_regs().map_err(|e| ErrorKind::from(&e))?;
+        let sregs = vcpu.get_sregs().map_err(|e| ErrorKind::from(&e))?;
+        cpus.push((regs, sregs));
+    }
+    let cr3 = cpus.first().map_or(0, |(_, sregs)| sregs.cr3);
+    let mappings = vm.mem.mappings(cr3);
+
+    let file = File::create(path).map_err(|e| ErrorKind::from(&e))?;
+    let mut out = BufWriter::new(file);
+    write_core(&mut out, &vm.mem, &mappings, &cpus, signal)
+        .and_then(|_| out.flush())
+        .map_err(|e| ErrorKind::from(&e))?;
+    Ok(())
+}
+
+fn write_core(
+    out: &mut impl Write,
+    mem: &GuestMemory,
+    mappings: &[Mapping],
+    cpus: &[(kvm_regs, kvm_sregs)],
+    signal: u8,
+) -> io::Result<()> {
+    let mut notes = Vec::new();
+    for (pid, (regs, sregs)) in cpus.iter().enumerate() {
+        note(
+            &mut notes,
+            NT_PRSTATUS,
+            &prstatus(pid as u32 + 1, regs, sregs, signal),
+        );
+    }
+
+    let phnum = 1 + mappings.len() as u64;
+    let shoff = EHDR_SIZE + phnum * PHDR_SIZE;
+    let notes_offset = if phnum >= PN_XNUM {
+        shoff + SHDR_SIZE
+    } else {
+        shoff
+    };
+    let data_offset = align_up(notes_offset + notes.len() as u64, PAGE_SIZE);
+
+    let mut headers = Vec::new();
+    ehdr(&mut headers, phnum, shoff);
+    phdr(
+        &mut headers,
+        PT_NOTE,
+        PF_R,
+        notes_offset,
+        0,
+        0,
+        notes.len() as u64,
+        1,
+    );
+    let mut offset = data_offset;
+    for mapping in mappings {
+        let mut flags = PF_R;
+        if mapping.writable {
+            flags |= PF_W;
+        }
+        if mapping.executable {
+            flags |= PF_X;
+        }
+        phdr(
+            &mut headers,
+            PT_LOAD,
+            flags,
+            offset,
+            mapping.virt.as_u64(),
+            mapping.phys.as_u64(),
+            mapping.size,
+            PAGE_SIZE,
+        );
+        offset += mapping.size;
+    }
+    if phnum >= PN_XNUM {
+        shdr_xnum(&mut headers, phnum);
+    }
+    headers.extend_from_slice(&notes);
+    headers.resize(data_offset as usize, 0);
+    out.write_all(&headers)?;
+
+    let zero = [0u8; PAGE_SIZE as usize];
+    for mapping in mappings {
+        for page in (0..mapping.size).step_by(PAGE_SIZE as usize) {
+            match mem.addr_gpa2hva(mapping.phys + page) {
+                Ok(hva) => {
+                    let page = unsafe {
+                        std::slice::from_raw_parts(hva.as_ptr::<u8>(), PAGE_SIZE as usize)
+                    };
+                    out.write_all(page)?
+                }
+                Err(_) => out.write_all(&zero)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    (value + align - 1) & !(align - 1)
+}
+
+/// The ELF header, with `PN_XNUM` and one section header at `shoff` for
+/// `phnum` of `PN_XNUM` and more
+fn ehdr(buf: &mut Vec<u8>, phnum: u64, shoff: u64) {
+    let xnum = phnum >= PN_XNUM;
+    // ELFCLASS64, ELFDATA2LSB, EV_CURRENT, ELFOSABI_NONE
+    buf.extend_from_slice(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0");
+    buf.extend_from_slice(&ET_CORE.to_le_bytes());
+    buf.extend_from_slice(&EM_X86_64.to_le_bytes());
+    buf.extend_from_slice(&1u32.to_le_bytes());
+    // e_entry, e_phoff and e_shoff
+    buf.extend_from_slice(&0u64.to_le_bytes());
+    buf.extend_from_slice(&EHDR_SIZE.to_le_bytes());
+    buf.extend_from_slice(&(if xnum { shoff } else { 0 }).to_le_bytes());
+    // e_flags
+    buf.extend_from_slice(&0u32.to_le_bytes());
+    buf.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+    buf.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+    buf.extend_from_slice(&(phnum.min(PN_XNUM) as u16).to_le_bytes());
+    // e_shentsize, e_shnum and e_shstrndx
+    if xnum {
+        buf.extend_from_slice(&(SHDR_SIZE as u16).to_le_bytes());
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+    } else {
+        buf.extend_from_slice(&[0u8; 6]);
+    }
+}
+
+/// The `SHT_NULL` section header 0 with the number of program headers
+fn shdr_xnum(buf: &mut Vec<u8>, phnum: u64) {
+    // sh_name, sh_type, sh_flags, sh_addr and sh_offset
+    buf.extend_from_slice(&[0u8; 32]);
+    // sh_size is e_shnum, sh_link is e_shstrndx
+    buf.extend_from_slice(&1u64.to_le_bytes());
+    buf.extend_from_slice(&0u32.to_le_bytes());
+    buf.extend_from_slice(&(phnum as u32).to_le_bytes());
+    // sh_addralign and sh_entsize
+    buf.extend_from_slice(&[0u8; 16]);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn phdr(
+    buf: &mut Vec<u8>,
+    p_type: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    size: u64,
+    align: u64,
+) {
+    buf.extend_from_slice(&p_type.to_le_bytes());
+    buf.extend_from_slice(&flags.to_le_bytes());
+    buf.extend_from_slice(&offset.to_le_bytes());
+    buf.extend_from_slice(&vaddr.to_le_bytes());
+    buf.extend_from_slice(&paddr.to_le_bytes());
+    // p_filesz and p_memsz
+    buf.extend_from_slice(&size.to_le_bytes());
+    buf.extend_from_slice(&size.to_le_bytes());
+    buf.extend_from_slice(&align.to_le_bytes());
+}
+
+fn note(buf: &mut Vec<u8>, n_type: u32, desc: &[u8]) {
+    // the name without padding, the descriptor is a multiple of 4 bytes
+    buf.extend_from_slice(&5u32.to_le_bytes());
+    buf.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&n_type.to_le_bytes());
+    buf.extend_from_slice(NOTE_NAME);
+    buf.extend_from_slice(desc);
+}
+
+/// The `struct elf_prstatus` of a vCPU, with the registers in the order of
+/// `struct user_regs_struct`
+fn prstatus(pid: u32, regs: &kvm_regs, sregs: &kvm_sregs, signal: u8) -> Vec<u8> {
+    let mut buf = vec![0u8; PRSTATUS_SIZE];
+    // pr_info.si_signo and pr_cursig
+    buf[0..4].copy_from_slice(&(signal as u32).to_le_bytes());
+    buf[12..14].copy_from_slice(&(signal as u16).to_le_bytes());
+    // pr_pid
+    buf[32..36].copy_from_slice(&pid.to_le_bytes());
+
+    let r = regs;
+    let s = sregs;
+    let user_regs = [
+        r.r15,
+        r.r14,
+        r.r13,
+        r.r12,
+        r.rbp,
+        r.rbx,
+        r.r11,
+        r.r10,
+        r.r9,
+        r.r8,
+        r.rax,
+        r.rcx,
+        r.rdx,
+        r.rsi,
+        r.rdi,
+        // orig_rax
+        u64::MAX,
+        r.rip,
+        s.cs.selector as u64,
+        r.rflags,
+        r.rsp,
+        s.ss.selector as u64,
+        s.fs.base,
+        s.gs.base,
+        s.ds.selector as u64,
+        s.es.selector as u64,
+        s.fs.selector as u64,
+        s.gs.selector as u64,
+    ];
+    for (index, reg) in user_regs.iter().enumerate() {
+        let offset = PRSTATUS_REG + index * 8;
+        buf[offset..offset + 8].copy_from_slice(&reg.to_le_bytes());
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::x86_64::VirtAddr;
+    use xmas_elf::program::Type;
+    use xmas_elf::ElfFile;
+
+    #[test]
+    fn core_file() {
+        let mut mem = GuestMemory::new(0);
+        mem.region_add(PhysAddr::new(0), 0, 16).unwrap();
+        unsafe {
+            let page = mem.addr_gpa2hva(PhysAddr::new(0x3000)).unwrap();
+            page.as_mut_ptr::<u64>().write(0xDEAD_BEEF);
+        }
+        let mappings = [
+            Mapping {
+                virt: VirtAddr::new(0xFFFF_8000_0000_0000),
+                phys: PhysAddr::new(0x2000),
+                size: 0x2000,
+                writable: true,
+                user: false,
+                executable: false,
+            },
+            // outside of the guest memory
+            Mapping {
+                virt: VirtAddr::new(0x40_0000),
+                phys: PhysAddr::new(0x100_0000),
+                size: 0x1000,
+                writable: false,
+                user: true,
+                executable: true,
+            },
+        ];
+        let regs = kvm_regs {
+            rip: 0x40_0010,
+            rsp: 0x7FFF_F000,
+            rax: 42,
+            ..Default::default()
+        };
+        let mut sregs = kvm_sregs::default();
+        sregs.cs.selector = 0x2B;
+
+        let mut core = Vec::new();
+        write_core(&mut core, &mem, &mappings, &[(regs, sregs)], 11).unwrap();
+
+        let elf = ElfFile::new(&core).unwrap();
+        assert_eq!(
+            elf.header.pt2.type_().as_type(),
+            xmas_elf::header::Type::Core
+        );
+        let headers: Vec<_> = elf.program_iter().collect();
+        assert_eq!(headers.len(), 3);
+
+        assert_eq!(headers[0].get_type(), Ok(Type::Note));
+        let note = &core[headers[0].offset() as usize..][..headers[0].file_size() as usize];
+        let word = |offset: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&note[offset..offset + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        // namesz and descsz, n_type and the name
+        assert_eq!(word(0), 5 | (PRSTATUS_SIZE as u64) << 32);
+        assert_eq!(word(8) as u32, NT_PRSTATUS);
+        assert_eq!(&note[12..20], NOTE_NAME);
+        assert_eq!(note.len(), 20 + PRSTATUS_SIZE);
+        let desc = 20;
+        assert_eq!(note[desc + 12], 11);
+        assert_eq!(note[desc + 32], 1);
+        let reg = |index: usize| word(desc + PRSTATUS_REG + index * 8);
+        assert_eq!(reg(10), 42);
+        assert_eq!(reg(16), 0x40_0010);
+        assert_eq!(reg(17), 0x2B);
+        assert_eq!(reg(19), 0x7FFF_F000);
+
+        let load = headers[1];
+        assert_eq!(load.get_type(), Ok(Type::Load));
+        assert_eq!(load.virtual_addr(), 0xFFFF_8000_0000_0000);
+        assert_eq!(load.physical_addr(), 0x2000);
+        assert_eq!(load.file_size(), 0x2000);
+        assert_eq!(load.offset() % PAGE_SIZE, 0);
+        assert!(load.flags().is_write() && !load.flags().is_execute());
+        let offset = load.offset() as usize + 0x1000;
+        assert_eq!(&core[offset..offset + 4], &[0xEF, 0xBE, 0xAD, 0xDE]);
+
+        let load = headers[2];
+        assert_eq!(load.virtual_addr(), 0x40_0000);
+        assert_eq!(load.offset(), headers[1].offset() + 0x2000);
+        assert!(!load.flags().is_write() && load.flags().is_execute());
+        let offset = load.offset() as usize;
+        assert!(core[offset..offset + 0x1000].iter().all(|b| *b == 0));
+        assert_eq!(core.len(), offset + 0x1000);
+    }
+
+    #[test]
+    fn extended_phnum() {
+        let mem = GuestMemory::new(0);
+        let mapping = Mapping {
+            virt: VirtAddr::new(0x40_0000),
+            phys: PhysAddr::new(0),
+            size: 0,
+            writable: false,
+            user: true,
+            executable: false,
+        };
+        let mappings = vec![mapping; PN_XNUM as usize];
+
+        let mut core = Vec::new();
+        write_core(&mut core, &mem, &mappings, &[], 11).unwrap();
+
+        let field = |offset: usize, len: usize| {
+            let mut bytes = [0u8; 8];
+            bytes[..len].copy_from_slice(&core[offset..offset + len]);
+            u64::from_le_bytes(bytes)
+        };
+        // e_phnum, e_shentsize and e_shnum
+        assert_eq!(field(56, 2), PN_XNUM);
+        assert_eq!(field(58, 2), SHDR_SIZE);
+        assert_eq!(field(60, 2), 1);
+        // sh_info of section header 0 at e_shoff
+        let shoff = field(40, 8);
+        assert_eq!(shoff, EHDR_SIZE + (PN_XNUM + 1) * PHDR_SIZE);
+        assert_eq!(field(shoff as usize + 44, 4), PN_XNUM + 1);
+        // the notes follow the section header
+        assert_eq!(field(EHDR_SIZE as usize + 8, 8), shoff + SHDR_SIZE);
+    }
+}
@@ -13,6 +13,15 @@ pub enum ErrorKind {
     NoVirtualAddressAvailable,
     GuestCodeNotFound,
     NotAStaticBinary,
+    SevInvalidState,
+    SevFirmware(u32),
+    GhcbInvalidRequest(u64),
+    KeyBrokerRejected,
+    SecretTooLong,
+    InvalidBundle(&'static str),
+    InvalidManifest(&'static str),
+    BundleSignatureInvalid,
+    BundleKeyUntrusted,
     Errno(i32),
     Io(::std::io::ErrorKind),
     Str(&'static str),
@@ -41,6 +50,19 @@ impl ::std::fmt::Display for ErrorKind {
             ErrorKind::NoMappingForVirtualAddress => write!(f, "no mapping for virtual address"),
             ErrorKind::GuestCodeNotFound => write!(f, "guest code not found"),
             ErrorKind::NotAStaticBinary => write!(f, "not a static binary"),
+            ErrorKind::SevInvalidState => write!(f, "SEV command invalid in current launch state"),
+            ErrorKind::SevFirmware(e) => write!(f, "SEV firmware error {:#x}", e),
+            ErrorKind::GhcbInvalidRequest(code) => {
+                write!(f, "invalid GHCB request, exit code {:#x}", code)
+            }
+            ErrorKind::KeyBrokerRejected => {
+                write!(f, "launch measurement rejected by the key broker")
+            }
+            ErrorKind::SecretTooLong => write!(f, "secret too long"),
+            ErrorKind::InvalidBundle(s) => write!(f, "invalid bundle: {}", s),
+            ErrorKind::InvalidManifest(s) => write!(f, "invalid manifest: {}", s),
+            ErrorKind::BundleSignatureInvalid => write!(f, "bundle signature invalid"),
+            ErrorKind::BundleKeyUntrusted => write!(f, "bundle signed with an untrusted key"),
             ErrorKind::NoVirtualAddressAvailable => {
                 write!(f, "No vaddr of specified pages available")
             }
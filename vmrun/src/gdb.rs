@@ -0,0 +1,701 @@
+//! GDB remote serial protocol stub for KVM guests
+//!
+//! `vmrun --gdb <addr>` waits for gdb on `addr` before the first instruction
+//! of the guest runs. Registers are read and written with `get_regs` and
+//! `get_sregs`, memory is accessed through the page tables of the guest.
+//! Software breakpoints and single-stepping use `KVM_SET_GUEST_DEBUG`, so
+//! every `int3` of the guest stops in gdb.
+//!
+//! Only the first vCPU is debugged and a running guest can not be interrupted
+//! with Ctrl-C, breakpoints have to be set before continuing.
+
+use crate::arch::x86_64::VirtAddr;
+use crate::context;
+use crate::error::*;
+use crate::kvmvm::KvmVm;
+use kvm_ioctls::VcpuFd;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use vmm_sys_util::errno;
+use vmm_sys_util::ioctl::ioctl_with_ref;
+use vmm_sys_util::{ioctl_ioc_nr, ioctl_iow_nr};
+
+const KVMIO: u32 = 0xAE;
+
+ioctl_iow_nr!(KVM_SET_GUEST_DEBUG, KVMIO, 0x9b, KvmGuestDebug);
+
+// see linux/kvm.h
+const KVM_GUESTDBG_ENABLE: u32 = 0x1;
+const KVM_GUESTDBG_SINGLESTEP: u32 = 0x2;
+const KVM_GUESTDBG_USE_SW_BP: u32 = 0x1_0000;
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmGuestDebug {
+    control: u32,
+    pad: u32,
+    debugreg: [u64; 8],
+}
+
+/// The signal of a breakpoint or a finished step
+pub const SIGTRAP: u8 = 5;
+/// The signal of a guest that exited unexpectedly
+pub const SIGSEGV: u8 = 11;
+
+const INT3: u8 = 0xCC;
+
+// gdb's errno values of the `E` replies
+const EFAULT: u8 = 14;
+const EINVAL: u8 = 22;
+
+/// The registers of the `g` packet, in the order of gdb's amd64 description
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Registers {
+    /// rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8 to r15
+    pub gpr: [u64; 16],
+    pub rip: u64,
+    pub eflags: u32,
+    /// cs, ss, ds, es, fs and gs
+    pub segments: [u32; 6],
+}
+
+/// The number of registers of the `g` packet
+const NUM_REGS: usize = 24;
+
+impl Registers {
+    /// The value and the size in bytes of register `nr`
+    fn get(&self, nr: usize) -> Option<(u64, usize)> {
+        match nr {
+            0..=15 => Some((self.gpr[nr], 8)),
+            16 => Some((self.rip, 8)),
+            17 => Some((self.eflags as u64, 4)),
+            18..=23 => Some((self.segments[nr - 18] as u64, 4)),
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, nr: usize, value: u64) -> Option<()> {
+        match nr {
+            0..=15 => self.gpr[nr] = value,
+            16 => self.rip = value,
+            17 => self.eflags = value as u32,
+            18..=23 => self.segments[nr - 18] = value as u32,
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn to_hex(self) -> String {
+        let mut s = String::new();
+        for nr in 0..NUM_REGS {
+            let (value, size) = self.get(nr).unwrap();
+            s.push_str(&hex(&value.to_le_bytes()[..size]));
+        }
+        s
+    }
+
+    fn from_hex(s: &str) -> Option<Self> {
+        let bytes = unhex(s)?;
+        let mut regs = Registers::default();
+        let mut rest = &bytes[..];
+        for nr in 0..NUM_REGS {
+            let size = regs.get(nr).unwrap().1;
+            if rest.len() < size {
+                return None;
+            }
+            let mut value = [0u8; 8];
+            value[..size].copy_from_slice(&rest[..size]);
+            regs.set(nr, u64::from_le_bytes(value));
+            rest = &rest[size..];
+        }
+        Some(regs)
+    }
+}
+
+/// The guest as seen by gdb
+pub trait Target {
+    fn registers(&mut self) -> Result<Registers, Error>;
+    fn set_registers(&mut self, regs: &Registers) -> Result<(), Error>;
+    /// Read the guest virtual `addr`
+    fn read_memory(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), Error>;
+    /// Write the guest virtual `addr`
+    fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<(), Error>;
+}
+
+/// How gdb lets the guest go on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resume {
+    Continue,
+    Step,
+    /// Run without gdb
+    Detach,
+    /// Stop the guest
+    Kill,
+}
+
+/// What to do after a packet of gdb
+enum Action {
+    Reply(String),
+    Resume(Resume),
+}
+
+/// A gdb connection
+pub struct GdbStub<S = TcpStream> {
+    stream: S,
+    input: Vec<u8>,
+    no_ack: bool,
+    /// The guest runs and gdb waits for a stop reply
+    running: bool,
+    /// The original byte of each inserted breakpoint
+    breakpoints: BTreeMap<u64, u8>,
+}
+
+impl GdbStub<TcpStream> {
+    /// Wait for gdb to connect on `addr`
+    pub fn listen(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        eprintln!(
+            "Hypervisor: waiting for gdb on {}, `target remote {}`",
+            listener.local_addr()?,
+            addr
+        );
+        let (stream, peer) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        eprintln!("Hypervisor: gdb connected from {}", peer);
+        Ok(GdbStub::new(stream))
+    }
+}
+
+impl<S: Read + Write> GdbStub<S> {
+    pub fn new(stream: S) -> Self {
+        GdbStub {
+            stream,
+            input: Vec::new(),
+            no_ack: false,
+            running: false,
+            breakpoints: BTreeMap::new(),
+        }
+    }
+
+    /// Report the stop of the guest with `signal` and serve gdb until it
+    /// resumes the guest
+    pub fn stop(&mut self, target: &mut dyn Target, signal: u8) -> io::Result<Resume> {
+        if self.running {
+            self.running = false;
+            self.send(&format!("S{:02x}", signal))?;
+        }
+        loop {
+            let packet = self.receive()?;
+            let reply = match self.command(target, &packet, signal) {
+                Action::Reply(reply) => reply,
+                Action::Resume(resume) => {
+                    if resume == Resume::Detach {
+                        self.remove_breakpoints(target);
+                        self.send("OK")?;
+                    }
+                    self.running = resume != Resume::Detach;
+                    return Ok(resume);
+                }
+            };
+            self.send(&reply)?;
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+        }
+    }
+
+    /// Report the exit of the guest with `code`
+    pub fn exited(&mut self, code: i32) -> io::Result<()> {
+        if self.running {
+            self.running = false;
+            self.send(&format!("W{:02x}", code as u8))?;
+        }
+        Ok(())
+    }
+
+    /// The reply to `packet`, or how to resume the guest
+    fn command(&mut self, target: &mut dyn Target, packet: &str, signal: u8) -> Action {
+        let cmd = packet.get(..1).unwrap_or_default();
+        let args = packet.get(1..).unwrap_or_default();
+        let reply = match cmd {
+            "?" => format!("S{:02x}", signal),
+            "g" => match target.registers() {
+                Ok(regs) => regs.to_hex(),
+                Err(_) => error(EFAULT),
+            },
+            "G" => match Registers::from_hex(args) {
+                Some(regs) => ok(target.set_registers(&regs).is_ok()),
+                None => error(EINVAL),
+            },
+            "p" => {
+                let reg = usize::from_str_radix(args, 16)
+                    .ok()
+                    .and_then(|nr| Some((target.registers().ok()?, nr)))
+                    .and_then(|(regs, nr)| regs.get(nr));
+                match reg {
+                    Some((value, size)) => hex(&value.to_le_bytes()[..size]),
+                    None => error(EINVAL),
+                }
+            }
+            "P" => {
+                let done = split2(args, '=')
+                    .and_then(|(nr, value)| {
+                        let nr = usize::from_str_radix(nr, 16).ok()?;
+                        let mut bytes = [0u8; 8];
+                        let value = unhex(value)?;
+                        bytes.get_mut(..value.len())?.copy_from_slice(&value);
+                        let mut regs = target.registers().ok()?;
+                        regs.set(nr, u64::from_le_bytes(bytes))?;
+                        target.set_registers(&regs).ok()
+                    })
+                    .is_some();
+                ok(done)
+            }
+            "m" => match parse_range(args) {
+                Some((addr, len)) => {
+                    let mut buf = vec![0u8; len];
+                    match target.read_memory(addr, &mut buf) {
+                        Ok(()) => hex(&buf),
+                        Err(_) => error(EFAULT),
+                    }
+                }
+                None => error(EINVAL),
+            },
+            "M" => {
+                let data = split2(args, ':')
+                    .and_then(|(range, data)| Some((parse_range(range)?, unhex(data)?)));
+                match data {
+                    Some(((addr, len), data)) if data.len() == len => {
+                        match target.write_memory(addr, &data) {
+                            Ok(()) => "OK".into(),
+                            Err(_) => error(EFAULT),
+                        }
+                    }
+                    _ => error(EINVAL),
+                }
+            }
+            "Z" | "z" => match args.strip_prefix("0,").and_then(parse_range) {
+                Some((addr, _kind)) if cmd == "Z" => ok(self.insert_breakpoint(target, addr)),
+                Some((addr, _kind)) => ok(self.remove_breakpoint(target, addr)),
+                // only software breakpoints
+                None => String::new(),
+            },
+            "c" | "s" => {
+                if let Ok(addr) = u64::from_str_radix(args, 16) {
+                    let moved = target.registers().and_then(|mut regs| {
+                        regs.rip = addr;
+                        target.set_registers(&regs)
+                    });
+                    if moved.is_err() {
+                        return Action::Reply(error(EFAULT));
+                    }
+                }
+                return Action::Resume(if cmd == "c" {
+                    Resume::Continue
+                } else {
+                    Resume::Step
+                });
+            }
+            "D" => return Action::Resume(Resume::Detach),
+            "k" => return Action::Resume(Resume::Kill),
+            "H" | "T" => "OK".into(),
+            _ => match packet {
+                "qAttached" => "1".into(),
+                "qC" => "QC1".into(),
+                "qfThreadInfo" => "m1".into(),
+                "qsThreadInfo" => "l".into(),
+                "QStartNoAckMode" => "OK".into(),
+                _ if packet.starts_with("qSupported") => {
+                    "PacketSize=1000;QStartNoAckMode+;swbreak+".into()
+                }
+                // unsupported, like `vCont?`
+                _ => String::new(),
+            },
+        };
+        Action::Reply(reply)
+    }
+
+    fn insert_breakpoint(&mut self, target: &mut dyn Target, addr: u64) -> bool {
+        if self.breakpoints.contains_key(&addr) {
+            return true;
+        }
+        let mut orig = [0u8];
+        if target.read_memory(addr, &mut orig).is_err()
+            || target.write_memory(addr, &[INT3]).is_err()
+        {
+            return false;
+        }
+        self.breakpoints.insert(addr, orig[0]);
+        true
+    }
+
+    fn remove_breakpoint(&mut self, target: &mut dyn Target, addr: u64) -> bool {
+        match self.breakpoints.remove(&addr) {
+            Some(orig) => target.write_memory(addr, &[orig]).is_ok(),
+            None => true,
+        }
+    }
+
+    fn remove_breakpoints(&mut self, target: &mut dyn Target) {
+        let addrs: Vec<u64> = self.breakpoints.keys().copied().collect();
+        for addr in addrs {
+            self.remove_breakpoint(target, addr);
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<u8> {
+        if self.input.is_empty() {
+            let mut buf = [0u8; 4096];
+            let len = self.stream.read(&mut buf)?;
+            if len == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.input.extend_from_slice(&buf[..len]);
+        }
+        Ok(self.input.remove(0))
+    }
+
+    /// The next packet of gdb, acknowledged
+    fn receive(&mut self) -> io::Result<String> {
+        loop {
+            // skip acks and interrupts of a stopped guest
+            while self.next_byte()? != b'$' {}
+            let mut data = Vec::new();
+            let mut sum = 0u8;
+            loop {
+                match self.next_byte()? {
+                    b'#' => break,
+                    b => {
+                        sum = sum.wrapping_add(b);
+                        data.push(b);
+                    }
+                }
+            }
+            let checksum = [self.next_byte()?, self.next_byte()?];
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                == Some(sum);
+            if self.no_ack {
+                if valid {
+                    return Ok(String::from_utf8_lossy(&data).into_owned());
+                }
+                continue;
+            }
+            if valid {
+                self.stream.write_all(b"+")?;
+                return Ok(String::from_utf8_lossy(&data).into_owned());
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    /// Send a packet, until gdb acknowledges it
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let packet = format!("${}#{:02x}", data, sum);
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            self.stream.flush()?;
+            if self.no_ack {
+                return Ok(());
+            }
+            match self.next_byte()? {
+                b'+' => return Ok(()),
+                b'-' => continue,
+                // a packet without an ack of ours, take it as acked
+                b => {
+                    self.input.insert(0, b);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn ok(done: bool) -> String {
+    if done {
+        "OK".into()
+    } else {
+        error(EFAULT)
+    }
+}
+
+fn error(errno: u8) -> String {
+    format!("E{:02x}", errno)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(s, "{:02x}", b).unwrap();
+    }
+    s
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn split2(s: &str, sep: char) -> Option<(&str, &str)> {
+    let i = s.find(sep)?;
+    Some((&s[..i], &s[i + 1..]))
+}
+
+/// `addr,len` of the `m`, `M` and `Z` packets
+fn parse_range(s: &str) -> Option<(u64, usize)> {
+    let (addr, len) = split2(s, ',')?;
+    Some((
+        u64::from_str_radix(addr, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+/// Stop the vCPU at breakpoints, and after one instruction for `Resume::Step`
+pub fn set_guest_debug(vcpu: &VcpuFd, resume: Resume) -> Result<(), Error> {
+    let control = match resume {
+        Resume::Continue => KVM_GUESTDBG_ENABLE | KVM_GUESTDBG_USE_SW_BP,
+        Resume::Step => KVM_GUESTDBG_ENABLE | KVM_GUESTDBG_USE_SW_BP | KVM_GUESTDBG_SINGLESTEP,
+        Resume::Detach | Resume::Kill => 0,
+    };
+    let debug = KvmGuestDebug {
+        control,
+        ..Default::default()
+    };
+    let ret = unsafe { ioctl_with_ref(vcpu, KVM_SET_GUEST_DEBUG(), &debug) };
+    if ret < 0 {
+        return Err(context!(ErrorKind::from(&errno::Error::last())));
+    }
+    Ok(())
+}
+
+impl Target for KvmVm {
+    fn registers(&mut self) -> Result<Registers, Error> {
+        let vcpu = &self.cpu_fd[0];
+        let r = vcpu.get_regs().map_err(|e| ErrorKind::from(&e))?;
+        let s = vcpu.get_sregs().map_err(|e| ErrorKind::from(&e))?;
+        Ok(Registers {
+            gpr: [
+                r.rax, r.rbx, r.rcx, r.rdx, r.rsi, r.rdi, r.rbp, r.rsp, r.r8, r.r9, r.r10, r.r11,
+                r.r12, r.r13, r.r14, r.r15,
+            ],
+            rip: r.rip,
+            eflags: r.rflags as u32,
+            segments: [
+                s.cs.selector as u32,
+                s.ss.selector as u32,
+                s.ds.selector as u32,
+                s.es.selector as u32,
+                s.fs.selector as u32,
+                s.gs.selector as u32,
+            ],
+        })
+    }
+
+    /// The segment registers are not written
+    fn set_registers(&mut self, regs: &Registers) -> Result<(), Error> {
+        let vcpu = &self.cpu_fd[0];
+        let mut r = vcpu.get_regs().map_err(|e| ErrorKind::from(&e))?;
+        let [rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8, r9, r10, r11, r12, r13, r14, r15] =
+            regs.gpr;
+        r.rax = rax;
+        r.rbx = rbx;
+        r.rcx = rcx;
+        r.rdx = rdx;
+        r.rsi = rsi;
+        r.rdi = rdi;
+        r.rbp = rbp;
+        r.rsp = rsp;
+        r.r8 = r8;
+        r.r9 = r9;
+        r.r10 = r10;
+        r.r11 = r11;
+        r.r12 = r12;
+        r.r13 = r13;
+        r.r14 = r14;
+        r.r15 = r15;
+        r.rip = regs.rip;
+        r.rflags = (r.rflags & !0xFFFF_FFFF) | regs.eflags as u64;
+        vcpu.set_regs(&r).map_err(|e| ErrorKind::from(&e))?;
+        Ok(())
+    }
+
+    fn read_memory(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), Error> {
+        self.read_guest_virt(0, virt_addr(addr)?, buf)
+    }
+
+    fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<(), Error> {
+        self.write_guest_virt(0, virt_addr(addr)?, data)
+    }
+}
+
+fn virt_addr(addr: u64) -> Result<VirtAddr, Error> {
+    VirtAddr::try_new(addr).map_err(|_| context!(ErrorKind::NoMappingForVirtualAddress))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// gdb's side of the connection
+    #[derive(Default)]
+    struct Pipe {
+        input: Vec<u8>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.input.len());
+            buf[..len].copy_from_slice(&self.input[..len]);
+            self.input.drain(..len);
+            Ok(len)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A guest with 64 bytes of memory at 0x1000
+    struct Guest {
+        regs: Registers,
+        mem: [u8; 64],
+    }
+
+    impl Target for Guest {
+        fn registers(&mut self) -> Result<Registers, Error> {
+            Ok(self.regs)
+        }
+
+        fn set_registers(&mut self, regs: &Registers) -> Result<(), Error> {
+            self.regs = *regs;
+            Ok(())
+        }
+
+        fn read_memory(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), Error> {
+            let start = addr.checked_sub(0x1000).ok_or(ErrorKind::Generic)? as usize;
+            let mem = self
+                .mem
+                .get(start..start + buf.len())
+                .ok_or(ErrorKind::Generic)?;
+            buf.copy_from_slice(mem);
+            Ok(())
+        }
+
+        fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<(), Error> {
+            let start = addr.checked_sub(0x1000).ok_or(ErrorKind::Generic)? as usize;
+            self.mem
+                .get_mut(start..start + data.len())
+                .ok_or(ErrorKind::Generic)?
+                .copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        format!("${}#{:02x}", data, sum)
+    }
+
+    /// Run the stub with the packets of gdb, the replies and the resume
+    fn session(guest: &mut Guest, packets: &[&str]) -> (String, Resume) {
+        let mut pipe = Pipe::default();
+        for p in packets {
+            pipe.input.extend_from_slice(packet(p).as_bytes());
+            pipe.input.push(b'+');
+        }
+        let mut stub = GdbStub::new(pipe);
+        let resume = stub.stop(guest, SIGTRAP).unwrap();
+        (String::from_utf8(stub.stream.output).unwrap(), resume)
+    }
+
+    fn guest() -> Guest {
+        let mut guest = Guest {
+            regs: Registers::default(),
+            mem: [0; 64],
+        };
+        guest.regs.gpr[0] = 0x1122_3344_5566_7788;
+        guest.regs.rip = 0x1000;
+        guest.regs.eflags = 0x202;
+        guest.regs.segments[0] = 0x8;
+        guest.mem[..4].copy_from_slice(&[0x55, 0x48, 0x89, 0xE5]);
+        guest
+    }
+
+    #[test]
+    fn registers_hex() {
+        let regs = guest().regs;
+        let s = regs.to_hex();
+        assert_eq!(s.len(), (16 * 8 + 8 + 4 + 6 * 4) * 2);
+        assert!(s.starts_with("8877665544332211"));
+        assert_eq!(Registers::from_hex(&s), Some(regs));
+        assert_eq!(Registers::from_hex(&s[..s.len() - 2]), None);
+    }
+
+    #[test]
+    fn packets() {
+        let mut guest = guest();
+        let (out, resume) = session(
+            &mut guest,
+            &[
+                "?",
+                "p10",
+                "m1000,4",
+                "M1002,2:cc90",
+                "m2000,1",
+                "Z0,1000,1",
+                "c",
+            ],
+        );
+        assert_eq!(resume, Resume::Continue);
+        let replies = [
+            packet("S05"),
+            packet("0010000000000000"),
+            packet("554889e5"),
+            packet("OK"),
+            packet("E0e"),
+            packet("OK"),
+        ];
+        assert_eq!(out, "+".to_owned() + &replies.join("+") + "+");
+        assert_eq!(&guest.mem[..4], &[INT3, 0x48, 0xCC, 0x90]);
+
+        // detaching removes the breakpoints
+        let (_, resume) = session(&mut guest, &["Z0,1001,1", "D"]);
+        assert_eq!(resume, Resume::Detach);
+        assert_eq!(guest.mem[1], 0x48);
+    }
+
+    #[test]
+    fn write_registers() {
+        let mut guest = guest();
+        let (_, resume) = session(&mut guest, &["P0=efbeadde00000000", "s2000"]);
+        assert_eq!(resume, Resume::Step);
+        assert_eq!(guest.regs.gpr[0], 0xDEAD_BEEF);
+        assert_eq!(guest.regs.rip, 0x2000);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut pipe = Pipe::default();
+        pipe.input.extend_from_slice(b"$?#00$?#3f+$k#6b+");
+        let mut stub = GdbStub::new(pipe);
+        assert_eq!(stub.stop(&mut guest(), SIGTRAP).unwrap(), Resume::Kill);
+        assert_eq!(stub.stream.output, b"-+$S05#b8+");
+    }
+}
@@ -0,0 +1,1506 @@
+//! Guest physical memory and the initial memory image
+//!
+//! [`GuestMemory`] builds the memory image a guest starts with: the initial
+//! page tables, the kernel ELF segments, the raw app ELF file and the
+//! [`BootInfo`] page. It does not depend on KVM, so the same image can be
+//! built offline to compute the launch measurement.
+
+use crate::arch::x86_64::{
+    structures::paging::{
+        frame::PhysFrameRange, PageSize, PageTable, PageTableFlags, PhysFrame, Size1GiB, Size2MiB,
+        Size4KiB,
+    },
+    HostVirtAddr, PhysAddr, VirtAddr,
+};
+use crate::error::*;
+use crate::{context, map_context};
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::PathBuf;
+use vmsyscall::bootinfo::{
+    ArgsBlob, BootInfo, ElfBlob, InitrdBlob, LogLevel, SyscallPolicy, TmpfsLimits,
+};
+use vmsyscall::ghcb::{GHCB_PHYS_ADDR, GHCB_TRIGGER_PORT};
+use vmsyscall::memory_map::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType};
+use vmsyscall::secret::LAUNCH_SECRET_PHYS_ADDR;
+
+pub const DEFAULT_GUEST_MEM: u64 = 2 * 1024 * 1024 * 1024; // 2GiB
+pub const DEFAULT_GUEST_PAGE_SIZE: usize = 4096;
+pub const DEFAULT_TMPFS_SIZE: u64 = 16 * 1024 * 1024; // 16MiB
+pub const DEFAULT_TMPFS_INODES: u64 = 1024;
+/// Guest memory the kernel and the app need besides the tmpfs
+const TMPFS_RESERVE: u64 = 32 * 1024 * 1024; // 32MiB
+
+pub const HIMEM_START: usize = 0x0010_0000; //1 MB.
+
+pub const SYSCALL_PHYS_ADDR: u64 = 0x1000;
+pub const SYSCALL_TRIGGER_PORT: u16 = 0xFF;
+
+// Initial pagetables.
+pub const PML4_START: usize = 0x9000;
+pub const PDPTE_START: usize = 0xA000;
+pub const PDE_START: usize = 0xB000;
+pub const PAGETABLE_LEN: u64 = core::mem::size_of::<PageTables>() as _;
+
+/// Guest physical address of the raw ELF file of the app, above the kernel
+pub const APP_BLOB_START: u64 = 0x0040_0000; // 4 MiB
+
+/// Lowest virtual load base of static-PIE apps, above non-PIE apps
+pub const PIE_BASE_MIN: u64 = 0x0100_0000; // 16 MiB
+/// End of the virtual load area of static-PIE apps
+pub const PIE_BASE_END: u64 = 0x2000_0000; // 512 MiB
+/// Lowest virtual load base of the ELF interpreter of dynamically linked apps
+pub const INTERP_BASE_MIN: u64 = PIE_BASE_END;
+/// End of the load area of the ELF interpreter
+pub const INTERP_BASE_END: u64 = 0x3000_0000; // 768 MiB
+
+/// A guest virtual address translated with the page tables of the guest
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Translation {
+    /// The guest physical address
+    pub phys: PhysAddr,
+    /// The size of the page, 4 KiB, 2 MiB or 1 GiB
+    pub page_size: u64,
+    /// Writable at all levels of the page tables
+    pub writable: bool,
+    /// Accessible from ring 3 at all levels of the page tables
+    pub user: bool,
+    /// Not `NO_EXECUTE` at any level of the page tables
+    pub executable: bool,
+}
+
+/// A range of guest virtual memory, mapped to contiguous guest physical memory
+/// with the same permissions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mapping {
+    pub virt: VirtAddr,
+    pub phys: PhysAddr,
+    pub size: u64,
+    pub writable: bool,
+    pub user: bool,
+    pub executable: bool,
+}
+
+/// How the guest talks to the hypervisor for intercepted instructions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GhcbMode {
+    /// Plain `in`/`out`, `cpuid` and MSR instructions
+    Disabled,
+    /// SEV-ES: the kernel #VC handler fills the GHCB and exits with `VMGEXIT`
+    Vmgexit,
+    /// The kernel fills the GHCB and signals it on `GHCB_TRIGGER_PORT`,
+    /// to exercise the GHCB protocol without SEV-ES
+    Software,
+}
+
+impl Default for GhcbMode {
+    fn default() -> Self {
+        GhcbMode::Disabled
+    }
+}
+
+/// The kind of data placed into guest memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadedRegionKind {
+    PageTables,
+    App,
+    Interp,
+    Args,
+    Initrd,
+    Kernel,
+    BootInfo,
+}
+
+impl LoadedRegionKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LoadedRegionKind::PageTables => "page_tables",
+            LoadedRegionKind::App => "app",
+            LoadedRegionKind::Interp => "interp",
+            LoadedRegionKind::Args => "args",
+            LoadedRegionKind::Initrd => "initrd",
+            LoadedRegionKind::Kernel => "kernel",
+            LoadedRegionKind::BootInfo => "boot_info",
+        }
+    }
+}
+
+/// A page aligned range of guest memory written before the guest starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoadedRegion {
+    pub kind: LoadedRegionKind,
+    pub start: PhysAddr,
+    pub size: u64,
+}
+
+/// Split the loaded regions into chunks of pages, which are not
+/// covered by a previous region, keeping the load order.
+///
+/// Pages shared between regions (e.g. two ELF segments in one page) must only
+/// be encrypted and measured once.
+pub fn launch_chunks(regions: &[LoadedRegion]) -> Vec<LoadedRegion> {
+    let page_size = DEFAULT_GUEST_PAGE_SIZE as u64;
+    let mut seen = HashSet::new();
+    let mut chunks: Vec<LoadedRegion> = Vec::new();
+
+    for region in regions {
+        let start = region.start.as_u64() / page_size;
+        let end = (region.start.as_u64() + region.size + page_size - 1) / page_size;
+
+        for frame in start..end {
+            if !seen.insert(frame) {
+                continue;
+            }
+            match chunks.last_mut() {
+                Some(chunk)
+                    if chunk.kind == region.kind
+                        && chunk.start.as_u64() + chunk.size == frame * page_size =>
+                {
+                    chunk.size += page_size
+                }
+                _ => chunks.push(LoadedRegion {
+                    kind: region.kind,
+                    start: PhysAddr::new(frame * page_size),
+                    size: page_size,
+                }),
+            }
+        }
+    }
+    chunks
+}
+
+#[repr(C)]
+pub struct PageTables {
+    pub pml4t: [u64; 512],
+    pub pml3t_ident: [u64; 512],
+    pub pml2t_ident: [u64; 512],
+}
+
+impl Default for PageTables {
+    fn default() -> Self {
+        PageTables {
+            pml4t: [0u64; 512],
+            pml3t_ident: [0u64; 512],
+            pml2t_ident: [0u64; 512],
+        }
+    }
+}
+
+/// The load base of a static-PIE app spanning `size` bytes from its base
+///
+/// Without `aslr_seed` the app is loaded at `PIE_BASE_MIN`. The base only
+/// depends on the seed, so the launch measurement can be computed offline.
+pub fn pie_base(aslr_seed: Option<u64>, size: u64, align: u64) -> Result<u64, Error> {
+    load_base(PIE_BASE_MIN..PIE_BASE_END, aslr_seed, size, align)
+        .ok_or_else(|| context!(ErrorKind::Str("static-PIE app too large")))
+}
+
+/// The load base of the ELF interpreter spanning `size` bytes from its base
+///
+/// Like `pie_base()`, but in the range from `INTERP_BASE_MIN`, so the app
+/// and the interpreter never overlap.
+pub fn interp_base(aslr_seed: Option<u64>, size: u64, align: u64) -> Result<u64, Error> {
+    load_base(
+        INTERP_BASE_MIN..INTERP_BASE_END,
+        aslr_seed.map(splitmix64),
+        size,
+        align,
+    )
+    .ok_or_else(|| context!(ErrorKind::Str("ELF interpreter too large")))
+}
+
+fn load_base(range: Range<u64>, aslr_seed: Option<u64>, size: u64, align: u64) -> Option<u64> {
+    let align = align.max(DEFAULT_GUEST_PAGE_SIZE as u64);
+    let min = (range.start + align - 1) / align * align;
+
+    if min + size > range.end {
+        return None;
+    }
+
+    let slots = (range.end - min - size) / align + 1;
+    let slot = aslr_seed.map_or(0, |seed| splitmix64(seed) % slots);
+    Some(min + slot * align)
+}
+
+/// SplitMix64, to spread consecutive seeds
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// A range of guest physical memory backed by anonymous host memory
+pub struct GuestRegion {
+    pub slot: u32,
+    pub guest_phys_addr: PhysAddr,
+    pub host_mem: HostVirtAddr,
+    pub size: usize,
+}
+
+/// Entry points of the loaded guest image
+#[derive(Clone, Copy, Debug)]
+pub struct GuestEntry {
+    /// The kernel entry point
+    pub kernel_entry: VirtAddr,
+    /// Guest physical address of the `BootInfo` passed to the kernel
+    pub boot_info: PhysAddr,
+}
+
+fn frame_range(range: PhysFrameRange) -> FrameRange {
+    FrameRange::new(
+        range.start.start_address().as_u64(),
+        range.end.start_address().as_u64(),
+    )
+}
+
+/// A tmpfs of `size` bytes leaves enough of `mem_size` bytes of guest memory
+///
+/// The kernel backs all files of the tmpfs with heap pages when it boots, plus
+/// the page lists and the inodes, like `tmpfs::heap_size()` of the kernel.
+pub fn tmpfs_fits(size: u64, mem_size: u64) -> bool {
+    if size == 0 {
+        return true;
+    }
+    let needed = size
+        .checked_add(size / 256)
+        .and_then(|heap| heap.checked_add(DEFAULT_TMPFS_INODES * 256))
+        .and_then(|heap| heap.checked_add(TMPFS_RESERVE));
+    matches!(needed, Some(needed) if needed <= mem_size)
+}
+
+/// The guest physical memory and the log of what was loaded into it
+pub struct GuestMemory {
+    page_size: usize,
+    memory_map: MemoryMap,
+    regions: Vec<GuestRegion>,
+    loaded_regions: Vec<LoadedRegion>,
+    cbit_mask: u64,
+    ghcb_mode: GhcbMode,
+    launch_secret: bool,
+    aslr_seed: Option<u64>,
+    sysroot: PathBuf,
+    args: Option<Vec<u8>>,
+    initrd: Option<Vec<u8>>,
+    tmpfs: TmpfsLimits,
+    syscalls: SyscallPolicy,
+    log_level: LogLevel,
+    app: ElfBlob,
+    interp: ElfBlob,
+}
+
+impl GuestMemory {
+    /// Create an empty guest memory
+    ///
+    /// `cbit_mask` is set in all initial page table entries to map the
+    /// guest memory encrypted.
+    pub fn new(cbit_mask: u64) -> Self {
+        GuestMemory {
+            page_size: DEFAULT_GUEST_PAGE_SIZE,
+            memory_map: MemoryMap::new(),
+            regions: vec![],
+            loaded_regions: vec![],
+            cbit_mask,
+            ghcb_mode: GhcbMode::Disabled,
+            launch_secret: false,
+            aslr_seed: None,
+            sysroot: PathBuf::from("/"),
+            args: None,
+            initrd: None,
+            tmpfs: TmpfsLimits {
+                size: DEFAULT_TMPFS_SIZE,
+                max_inodes: DEFAULT_TMPFS_INODES,
+            },
+            syscalls: SyscallPolicy::default(),
+            log_level: LogLevel::default(),
+            app: ElfBlob::default(),
+            interp: ElfBlob::default(),
+        }
+    }
+
+    /// Set the GHCB mode, before `setup_boot_memory()`
+    pub fn set_ghcb_mode(&mut self, ghcb_mode: GhcbMode) {
+        self.ghcb_mode = ghcb_mode;
+    }
+
+    pub fn ghcb_mode(&self) -> GhcbMode {
+        self.ghcb_mode
+    }
+
+    /// Reserve the launch secret page, before `setup_boot_memory()`
+    pub fn set_launch_secret(&mut self, launch_secret: bool) {
+        self.launch_secret = launch_secret;
+    }
+
+    pub fn has_launch_secret(&self) -> bool {
+        self.launch_secret
+    }
+
+    /// Set the seed for the load base of static-PIE apps, before loading them
+    pub fn set_aslr_seed(&mut self, aslr_seed: Option<u64>) {
+        self.aslr_seed = aslr_seed;
+    }
+
+    /// Set the directory the ELF interpreter is read from, `/` by default
+    pub fn set_sysroot(&mut self, sysroot: impl Into<PathBuf>) {
+        self.sysroot = sysroot.into();
+    }
+
+    /// Set the arguments and environment of the app, before loading it
+    ///
+    /// Without them the kernel starts the app with its defaults. Empty strings
+    /// are skipped.
+    pub fn set_args(&mut self, args: &[String], env: &[String]) {
+        let mut data = Vec::new();
+        for arg in args.iter().filter(|s| !s.is_empty()) {
+            data.extend_from_slice(arg.as_bytes());
+            data.push(0);
+        }
+        data.push(0);
+        for var in env.iter().filter(|s| !s.is_empty()) {
+            data.extend_from_slice(var.as_bytes());
+            data.push(0);
+        }
+        self.args.replace(data);
+    }
+
+    /// Set the initial RAM filesystem of the app, a tar or cpio (newc) archive
+    pub fn set_initrd(&mut self, archive: Vec<u8>) -> Result<(), Error> {
+        let is_cpio = archive.starts_with(b"070701");
+        let is_tar = archive.get(257..262) == Some(&b"ustar"[..]);
+
+        if !is_cpio && !is_tar {
+            return Err(context!(ErrorKind::Str(
+                "initrd is not a tar or cpio (newc) archive"
+            )));
+        }
+        self.initrd.replace(archive);
+        Ok(())
+    }
+
+    /// Set the limits of the kernel's `/tmp`, a `size` of `0` disables it
+    ///
+    /// The kernel takes the memory for the files from the guest memory.
+    pub fn set_tmpfs(&mut self, size: u64, max_inodes: u64) {
+        self.tmpfs = TmpfsLimits { size, max_inodes };
+    }
+
+    /// Set what the kernel does with unknown and denied syscalls of the app
+    pub fn set_syscall_policy(&mut self, policy: SyscallPolicy) {
+        self.syscalls = policy;
+    }
+
+    /// Set the most verbose level of the kernel log messages
+    pub fn set_log_level(&mut self, level: LogLevel) {
+        self.log_level = level;
+    }
+
+    /// The launch secret page, not part of the measured image
+    pub fn launch_secret_page(&mut self) -> Result<&mut [u8], Error> {
+        if !self.launch_secret {
+            return Err(context!(ErrorKind::Str("no launch secret page")));
+        }
+        let page = self.addr_gpa2hva(PhysAddr::new(LAUNCH_SECRET_PHYS_ADDR))?;
+        Ok(unsafe { core::slice::from_raw_parts_mut(page.as_mut_ptr::<u8>(), self.page_size) })
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub fn memory_map(&self) -> &MemoryMap {
+        &self.memory_map
+    }
+
+    /// The regions of guest memory written so far, in load order
+    pub fn loaded_regions(&self) -> &[LoadedRegion] {
+        &self.loaded_regions
+    }
+
+    /// The app and its ELF interpreter loaded with `load_default()`
+    pub fn elf_blobs(&self) -> (ElfBlob, ElfBlob) {
+        (self.app, self.interp)
+    }
+
+    /// Set the app and its ELF interpreter of a restored guest
+    pub fn set_elf_blobs(&mut self, app: ElfBlob, interp: ElfBlob) {
+        self.app = app;
+        self.interp = interp;
+    }
+
+    /// The regions of guest memory backed by host memory
+    pub fn regions(&self) -> &[GuestRegion] {
+        &self.regions
+    }
+
+    /// The contents of a blob loaded to guest memory
+    pub fn blob_data(&self, blob: &ElfBlob) -> Result<&[u8], Error> {
+        if blob.len == 0 {
+            return Ok(&[]);
+        }
+        let start = self.addr_gpa2hva(PhysAddr::new(blob.start))?;
+        let end = self.addr_gpa2hva(PhysAddr::new(blob.start + blob.len - 1))?;
+        if end.as_u64() - start.as_u64() != blob.len - 1 {
+            return Err(context!(ErrorKind::Str("blob spans guest memory regions")));
+        }
+        Ok(unsafe { std::slice::from_raw_parts(start.as_ptr::<u8>(), blob.len as usize) })
+    }
+
+    /// Back `npages` of guest memory at `guest_paddr` with host memory
+    pub fn region_add(
+        &mut self,
+        guest_paddr: PhysAddr,
+        slot: u32,
+        npages: u64,
+    ) -> Result<&GuestRegion, Error> {
+        let size = npages * self.page_size as u64;
+
+        for r in self.regions.iter() {
+            if r.slot == slot {
+                return Err(context!(ErrorKind::MemRegionWithSlotAlreadyExists));
+            }
+
+            if guest_paddr.as_u64() <= (r.guest_phys_addr.as_u64() + r.size as u64)
+                && (guest_paddr.as_u64() + size) >= r.guest_phys_addr.as_u64()
+            {
+                return Err(context!(ErrorKind::OverlappingUserspaceMemRegionExists));
+            }
+        }
+
+        let mm = mmap::MemoryMap::new(
+            size as usize,
+            &[mmap::MapOption::MapReadable, mmap::MapOption::MapWritable],
+        )
+        .map_err(|_| context!(ErrorKind::MmapFailed))?;
+        let mmap_start = mm.data();
+        // FIXME: No drop for mm
+        std::mem::forget(mm);
+
+        self.memory_map.add_region(MemoryRegion {
+            range: FrameRange::new(guest_paddr.as_u64(), guest_paddr.as_u64() + size),
+            region_type: MemoryRegionType::Usable,
+        });
+
+        self.regions.push(GuestRegion {
+            slot,
+            guest_phys_addr: guest_paddr,
+            host_mem: HostVirtAddr::new(mmap_start as u64),
+            size: size as usize,
+        });
+
+        Ok(self.regions.last().unwrap())
+    }
+
+    pub fn addr_gpa2hva(&self, guest_phys_addr: PhysAddr) -> Result<HostVirtAddr, Error> {
+        for region in &self.regions {
+            if (guest_phys_addr.as_u64() >= region.guest_phys_addr.as_u64())
+                && (guest_phys_addr.as_u64()
+                    <= (region.guest_phys_addr.as_u64() + region.size as u64 - 1))
+            {
+                return Ok(HostVirtAddr::new(
+                    region.host_mem.as_u64()
+                        + (guest_phys_addr.as_u64() - region.guest_phys_addr.as_u64()),
+                ));
+            }
+        }
+        Err(context!(ErrorKind::NoMappingForVirtualAddress))
+    }
+
+    /// Translate the guest virtual `vaddr` with the 4-level page tables at `cr3`
+    pub fn gva2gpa(&self, cr3: u64, vaddr: VirtAddr) -> Result<Translation, Error> {
+        let indices = [
+            vaddr.p4_index(),
+            vaddr.p3_index(),
+            vaddr.p2_index(),
+            vaddr.p1_index(),
+        ];
+        // the C-bit is not part of the address
+        let addr_mask = self.pte_addr_mask();
+        let mut table_addr = cr3 & addr_mask;
+        let mut flags = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        let mut executable = true;
+
+        for (level, index) in indices.iter().enumerate() {
+            let table = self.addr_gpa2hva(PhysAddr::new(table_addr))?;
+            let entry = unsafe { &(*table.as_ptr::<PageTable>())[*index] };
+            let entry_flags = entry.flags();
+            if !entry_flags.contains(PageTableFlags::PRESENT) {
+                return Err(context!(ErrorKind::NoMappingForVirtualAddress));
+            }
+            flags &= entry_flags;
+            executable &= !entry_flags.contains(PageTableFlags::NO_EXECUTE);
+
+            let huge = entry_flags.contains(PageTableFlags::HUGE_PAGE);
+            let page_size = match level {
+                1 if huge => Size1GiB::SIZE,
+                2 if huge => Size2MiB::SIZE,
+                3 => Size4KiB::SIZE,
+                _ => {
+                    table_addr = entry.addr().as_u64() & addr_mask;
+                    continue;
+                }
+            };
+            let offset = vaddr.as_u64() & (page_size - 1);
+            return Ok(Translation {
+                phys: PhysAddr::new(
+                    (entry.addr().as_u64() & addr_mask & !(page_size - 1)) + offset,
+                ),
+                page_size,
+                writable: flags.contains(PageTableFlags::WRITABLE),
+                user: flags.contains(PageTableFlags::USER_ACCESSIBLE),
+                executable,
+            });
+        }
+        unreachable!("the last level maps 4 KiB pages")
+    }
+
+    /// All mappings of the 4-level page tables at `cr3`, ordered by the virtual
+    /// address
+    ///
+    /// Page tables outside of the guest memory are skipped.
+    pub fn mappings(&self, cr3: u64) -> Vec<Mapping> {
+        let mut mappings = Vec::new();
+        let flags = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        self.walk_table(cr3 & self.pte_addr_mask(), 0, 0, flags, true, &mut mappings);
+        mappings
+    }
+
+    /// The bits of a page table entry with the physical address
+    fn pte_addr_mask(&self) -> u64 {
+        0x000F_FFFF_FFFF_F000 & !self.cbit_mask
+    }
+
+    fn walk_table(
+        &self,
+        table_addr: u64,
+        level: usize,
+        virt: u64,
+        flags: PageTableFlags,
+        executable: bool,
+        mappings: &mut Vec<Mapping>,
+    ) {
+        let table = match self.addr_gpa2hva(PhysAddr::new(table_addr)) {
+            Ok(table) => unsafe { &*table.as_ptr::<PageTable>() },
+            Err(_) => return,
+        };
+        let shift = 39 - 9 * level;
+        for (index, entry) in table.iter().enumerate() {
+            let entry_flags = entry.flags();
+            if !entry_flags.contains(PageTableFlags::PRESENT) {
+                continue;
+            }
+            // sign extend the canonical address
+            let virt = (((virt | (index as u64) << shift) << 16) as i64 >> 16) as u64;
+            let flags = flags & entry_flags;
+            let executable = executable && !entry_flags.contains(PageTableFlags::NO_EXECUTE);
+            let addr = entry.addr().as_u64() & self.pte_addr_mask();
+
+            let huge = level > 0 && entry_flags.contains(PageTableFlags::HUGE_PAGE);
+            if level < 3 && !huge {
+                self.walk_table(addr, level + 1, virt, flags, executable, mappings);
+                continue;
+            }
+            let size = 1u64 << shift;
+            let mapping = Mapping {
+                virt: VirtAddr::new(virt),
+                phys: PhysAddr::new(addr & !(size - 1)),
+                size,
+                writable: flags.contains(PageTableFlags::WRITABLE),
+                user: flags.contains(PageTableFlags::USER_ACCESSIBLE),
+                executable,
+            };
+            match mappings.last_mut() {
+                Some(last)
+                    if last.virt.as_u64().wrapping_add(last.size) == virt
+                        && last.phys + last.size == mapping.phys
+                        && (last.writable, last.user, last.executable)
+                            == (mapping.writable, mapping.user, mapping.executable) =>
+                {
+                    last.size += size
+                }
+                _ => mappings.push(mapping),
+            }
+        }
+    }
+
+    /// The host memory of `len` bytes from the guest virtual `vaddr` on, a
+    /// page at a time with the offset into the whole range
+    fn virt_chunks(
+        &self,
+        cr3: u64,
+        vaddr: VirtAddr,
+        len: usize,
+        mut f: impl FnMut(HostVirtAddr, Range<usize>),
+    ) -> Result<(), Error> {
+        let mut done = 0;
+        while done < len {
+            let addr = VirtAddr::try_new(vaddr.as_u64().wrapping_add(done as u64))
+                .map_err(|_| context!(ErrorKind::NoMappingForVirtualAddress))?;
+            let page_left = Size4KiB::SIZE - addr.as_u64() % Size4KiB::SIZE;
+            let chunk = (page_left as usize).min(len - done);
+            let hva = self.addr_gpa2hva(self.gva2gpa(cr3, addr)?.phys)?;
+            f(hva, done..done + chunk);
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Read the guest virtual memory at `vaddr`, mapped by the page tables at `cr3`
+    pub fn read_virt(&self, cr3: u64, vaddr: VirtAddr, buf: &mut [u8]) -> Result<(), Error> {
+        self.virt_chunks(cr3, vaddr, buf.len(), |hva, range| unsafe {
+            core::ptr::copy_nonoverlapping(
+                hva.as_ptr::<u8>(),
+                buf[range.clone()].as_mut_ptr(),
+                range.len(),
+            )
+        })
+    }
+
+    /// Write the guest virtual memory at `vaddr`, mapped by the page tables at
+    /// `cr3`
+    ///
+    /// Read-only pages are written, too, like a debugger does.
+    pub fn write_virt(&self, cr3: u64, vaddr: VirtAddr, data: &[u8]) -> Result<(), Error> {
+        self.virt_chunks(cr3, vaddr, data.len(), |hva, range| unsafe {
+            core::ptr::copy_nonoverlapping(
+                data[range.clone()].as_ptr(),
+                hva.as_mut_ptr::<u8>(),
+                range.len(),
+            )
+        })
+    }
+
+    /// The host memory backing a loaded region
+    ///
+    /// # Safety
+    ///
+    /// The caller must not create overlapping mutable slices.
+    pub unsafe fn host_slice(&self, region: &LoadedRegion) -> Result<&mut [u8], Error> {
+        self.guest_slice(region.start, region.size)
+    }
+
+    /// The host memory backing `size` bytes of guest memory at `start`
+    ///
+    /// # Safety
+    ///
+    /// The caller must not create overlapping mutable slices.
+    unsafe fn guest_slice(&self, start: PhysAddr, size: u64) -> Result<&mut [u8], Error> {
+        if size == 0 {
+            return Ok(&mut []);
+        }
+        let last = start
+            .as_u64()
+            .checked_add(size - 1)
+            .ok_or_else(|| context!(ErrorKind::NoMappingForVirtualAddress))?;
+        let hva = self.addr_gpa2hva(start)?;
+        // the whole range has to be backed by the same host mapping
+        let end = self.addr_gpa2hva(PhysAddr::new(last))?;
+        if end.as_u64() - hva.as_u64() != size - 1 {
+            return Err(context!(ErrorKind::NoMappingForVirtualAddress));
+        }
+        Ok(core::slice::from_raw_parts_mut(
+            hva.as_mut_ptr::<u8>(),
+            size as usize,
+        ))
+    }
+
+    /// Reserve the zero frame, the syscall page, the GHCB page and the launch
+    /// secret page and write the initial page tables
+    pub fn setup_boot_memory(&mut self) -> Result<(), Error> {
+        let zero_frame: PhysFrame = PhysFrame::from_start_address(PhysAddr::new(0)).unwrap();
+
+        self.memory_map.mark_allocated_region(MemoryRegion {
+            range: frame_range(PhysFrame::range(zero_frame, zero_frame + 1)),
+            region_type: MemoryRegionType::FrameZero,
+        });
+
+        let syscall_frame: PhysFrame =
+            PhysFrame::from_start_address(PhysAddr::new(SYSCALL_PHYS_ADDR)).unwrap();
+        self.memory_map.mark_allocated_region(MemoryRegion {
+            range: frame_range(PhysFrame::range(syscall_frame, syscall_frame + 1)),
+            region_type: MemoryRegionType::InUse,
+        });
+
+        if self.ghcb_mode != GhcbMode::Disabled {
+            let ghcb_frame: PhysFrame =
+                PhysFrame::from_start_address(PhysAddr::new(GHCB_PHYS_ADDR)).unwrap();
+            self.memory_map.mark_allocated_region(MemoryRegion {
+                range: frame_range(PhysFrame::range(ghcb_frame, ghcb_frame + 1)),
+                region_type: MemoryRegionType::InUse,
+            });
+        }
+
+        if self.launch_secret {
+            let secret_frame: PhysFrame =
+                PhysFrame::from_start_address(PhysAddr::new(LAUNCH_SECRET_PHYS_ADDR)).unwrap();
+            self.memory_map.mark_allocated_region(MemoryRegion {
+                range: frame_range(PhysFrame::range(secret_frame, secret_frame + 1)),
+                region_type: MemoryRegionType::InUse,
+            });
+        }
+
+        self.setup_page_tables()
+    }
+
+    fn setup_page_tables(&mut self) -> Result<(), Error> {
+        let mut page_tables = PageTables::default();
+
+        // Note we are assuming CPU supports 2MB pages. All modern CPUs do.
+        page_tables.pml4t[0] = PDPTE_START as u64 | 0x3 | self.cbit_mask;
+        page_tables.pml3t_ident[0] = PDE_START as u64 | 0x3 | self.cbit_mask;
+        page_tables.pml2t_ident[0] = 0x183u64 | self.cbit_mask;
+
+        let guest_pg_addr: *mut PageTables = self
+            .addr_gpa2hva(PhysAddr::new(PML4_START as _))?
+            .as_mut_ptr();
+
+        unsafe {
+            guest_pg_addr.write(page_tables);
+        }
+
+        self.loaded_regions.push(LoadedRegion {
+            kind: LoadedRegionKind::PageTables,
+            start: PhysAddr::new(PML4_START as _),
+            size: PAGETABLE_LEN,
+        });
+
+        Ok(())
+    }
+
+    /// Load the segments of the kernel ELF file `data` at their physical
+    /// addresses, returns the entry point
+    pub fn elf_load(
+        &mut self,
+        data: &[u8],
+        region_type: MemoryRegionType,
+    ) -> Result<VirtAddr, Error> {
+        use xmas_elf::program::{self, ProgramHeader};
+        use xmas_elf::ElfFile;
+
+        let elf_file = ElfFile::new(data).map_err(map_context!())?;
+
+        xmas_elf::header::sanity_check(&elf_file).map_err(map_context!())?;
+
+        let mut loaded = false;
+
+        for program_header in elf_file.program_iter() {
+            match program_header {
+                ProgramHeader::Ph64(header) => {
+                    let segment = *header;
+                    match segment.get_type().unwrap() {
+                        program::Type::Load => {}
+                        program::Type::Interp => {
+                            return Err(ErrorKind::NotAStaticBinary.into());
+                        }
+                        _ => continue,
+                    }
+
+                    loaded = true;
+
+                    // dbg!(segment);
+
+                    if segment.mem_size == 0 {
+                        continue;
+                    }
+
+                    let invalid = || context!(ErrorKind::Str("invalid kernel ELF segment"));
+                    let file_end = segment
+                        .offset
+                        .checked_add(segment.file_size)
+                        .filter(|end| {
+                            *end <= data.len() as u64 && segment.file_size <= segment.mem_size
+                        })
+                        .ok_or_else(invalid)?;
+                    let mem_end = segment
+                        .physical_addr
+                        .checked_add(segment.mem_size)
+                        .ok_or_else(invalid)?;
+                    let start_phys = PhysAddr::new(segment.physical_addr);
+                    let start_frame: PhysFrame =
+                        PhysFrame::from_start_address(start_phys.align_down(self.page_size as u64))
+                            .unwrap();
+
+                    let end_frame: PhysFrame = PhysFrame::from_start_address(
+                        PhysAddr::new(mem_end - 1).align_up(self.page_size as u64),
+                    )
+                    .unwrap();
+
+                    let region = MemoryRegion {
+                        range: frame_range(PhysFrame::range(start_frame, end_frame)),
+                        region_type,
+                    };
+
+                    //dbg!(region);
+                    //dbg!(&self.memory_map);
+                    self.memory_map.mark_allocated_region(region);
+
+                    let load_end = PhysAddr::new(mem_end).align_up(self.page_size as u64);
+                    self.loaded_regions.push(LoadedRegion {
+                        kind: LoadedRegionKind::Kernel,
+                        start: start_frame.start_address(),
+                        size: load_end.as_u64() - start_frame.start_address().as_u64(),
+                    });
+
+                    let host_slice = unsafe { self.guest_slice(start_phys, segment.mem_size)? };
+                    let (file, bss) = host_slice.split_at_mut(segment.file_size as usize);
+                    file.copy_from_slice(&data[segment.offset as usize..file_end as usize]);
+                    bss.iter_mut().for_each(|b| *b = 0);
+                }
+                ProgramHeader::Ph32(_) => {
+                    return Err(context!(ErrorKind::Str(
+                        "32 bit ELF files are not supported"
+                    )))
+                }
+            }
+        }
+
+        if !loaded {
+            return Err(context!(ErrorKind::GuestCodeNotFound));
+        }
+
+        Ok(VirtAddr::new(elf_file.header.pt2.entry_point()))
+    }
+
+    /// Copy the app ELF file `app` and its ELF interpreter to guest memory
+    ///
+    /// The kernel parses and maps the raw ELF files itself. The app is placed
+    /// at `APP_BLOB_START`, the interpreter named in `PT_INTERP`, read from the
+    /// sysroot, right after it. A PIE app (`ET_DYN`) is loaded by the kernel at
+    /// the base returned by `pie_base()`, the interpreter at the base returned
+    /// by `interp_base()`.
+    pub fn app_load(&mut self, app: &[u8]) -> Result<(ElfBlob, ElfBlob), Error> {
+        let (app, interp) = self.elf_blob_load(
+            app,
+            PhysAddr::new(APP_BLOB_START),
+            LoadedRegionKind::App,
+            pie_base,
+        )?;
+
+        let interp = match interp {
+            Some(interp) => interp,
+            None => return Ok((app, ElfBlob::default())),
+        };
+
+        let interp_path = crate::sysroot::resolve(&self.sysroot, &interp)
+            .ok_or_else(|| context!(ErrorKind::Str("invalid ELF interpreter path")))?;
+        let interp_data = std::fs::read(&interp_path).map_err(map_context!())?;
+        let start = PhysAddr::new(app.start + app.len).align_up(self.page_size as u64);
+        let (ld, ld_interp) =
+            self.elf_blob_load(&interp_data, start, LoadedRegionKind::Interp, interp_base)?;
+
+        // The interpreter has to be a static PIE itself
+        if ld_interp.is_some() || ld.base == 0 {
+            return Err(context!(ErrorKind::Str("invalid ELF interpreter")));
+        }
+
+        Ok((app, ld))
+    }
+
+    /// Copy one raw ELF file to `start`, returns the blob and its `PT_INTERP`
+    fn elf_blob_load(
+        &mut self,
+        data: &[u8],
+        start: PhysAddr,
+        kind: LoadedRegionKind,
+        place: fn(Option<u64>, u64, u64) -> Result<u64, Error>,
+    ) -> Result<(ElfBlob, Option<String>), Error> {
+        use xmas_elf::header;
+        use xmas_elf::program::{self, ProgramHeader};
+        use xmas_elf::ElfFile;
+
+        let elf_file = ElfFile::new(data).map_err(map_context!())?;
+
+        xmas_elf::header::sanity_check(&elf_file).map_err(map_context!())?;
+
+        let mut end: u64 = 0;
+        let mut align: u64 = 0;
+        let mut interp: Option<String> = None;
+
+        for program_header in elf_file.program_iter() {
+            match program_header {
+                ProgramHeader::Ph64(header) => match header.get_type().unwrap() {
+                    program::Type::Load => {
+                        let segment_end = header
+                            .virtual_addr
+                            .checked_add(header.mem_size)
+                            .ok_or_else(|| context!(ErrorKind::Str("invalid PT_LOAD")))?;
+                        end = end.max(segment_end);
+                        align = align.max(header.align);
+                    }
+                    program::Type::Interp => {
+                        let invalid = || context!(ErrorKind::Str("invalid PT_INTERP"));
+                        let start = header.offset as usize;
+                        let path = (header.file_size as usize)
+                            .checked_add(start)
+                            .and_then(|end| data.get(start..end))
+                            .and_then(|path| path.split(|&b| b == 0).next())
+                            .ok_or_else(invalid)?;
+                        let path = std::str::from_utf8(path).map_err(|_| invalid())?;
+                        interp.replace(path.to_string());
+                    }
+                    _ => {}
+                },
+                ProgramHeader::Ph32(_) => {
+                    return Err(context!(ErrorKind::Str(
+                        "32 bit ELF files are not supported"
+                    )))
+                }
+            }
+        }
+
+        if end == 0 {
+            return Err(context!(ErrorKind::GuestCodeNotFound));
+        }
+
+        let base = match elf_file.header.pt2.type_().as_type() {
+            header::Type::SharedObject => place(self.aslr_seed, end, align)?,
+            _ => 0,
+        };
+
+        self.blob_load(data, start, kind)?;
+
+        let blob = ElfBlob {
+            start: start.as_u64(),
+            len: data.len() as u64,
+            base,
+        };
+        Ok((blob, interp))
+    }
+
+    /// Copy `data` to the page aligned `start`, reserved as a region of `kind`
+    fn blob_load(
+        &mut self,
+        data: &[u8],
+        start: PhysAddr,
+        kind: LoadedRegionKind,
+    ) -> Result<(), Error> {
+        // fails before anything is reserved, if the blob is not in guest memory
+        let host_slice = unsafe { self.guest_slice(start, data.len() as u64) }
+            .map_err(|_| context!(ErrorKind::Str("not enough guest memory for the blob")))?;
+        host_slice.copy_from_slice(data);
+
+        let start_frame: PhysFrame = PhysFrame::from_start_address(start).unwrap();
+        let end_frame: PhysFrame = PhysFrame::from_start_address(
+            PhysAddr::new(start.as_u64() + data.len() as u64).align_up(self.page_size as u64),
+        )
+        .unwrap();
+
+        let region_type = match kind {
+            LoadedRegionKind::App | LoadedRegionKind::Interp => MemoryRegionType::App,
+            _ => MemoryRegionType::InUse,
+        };
+        self.memory_map.mark_allocated_region(MemoryRegion {
+            range: frame_range(PhysFrame::range(start_frame, end_frame)),
+            region_type,
+        });
+
+        self.loaded_regions.push(LoadedRegion {
+            kind,
+            start,
+            size: end_frame.start_address().as_u64() - start.as_u64(),
+        });
+        Ok(())
+    }
+
+    /// Copy the arguments and environment set with `set_args()` to `start`
+    fn args_load(&mut self, start: PhysAddr) -> Result<ArgsBlob, Error> {
+        let data = match self.args.take() {
+            Some(data) => data,
+            None => return Ok(ArgsBlob::default()),
+        };
+
+        self.blob_load(&data, start, LoadedRegionKind::Args)?;
+        let blob = ArgsBlob {
+            start: start.as_u64(),
+            len: data.len() as u64,
+        };
+        self.args.replace(data);
+        Ok(blob)
+    }
+
+    /// Copy the archive set with `set_initrd()` to `start`
+    fn initrd_load(&mut self, start: PhysAddr) -> Result<InitrdBlob, Error> {
+        let data = match self.initrd.take() {
+            Some(data) => data,
+            None => return Ok(InitrdBlob::default()),
+        };
+
+        self.blob_load(&data, start, LoadedRegionKind::Initrd)?;
+        let blob = InitrdBlob {
+            start: start.as_u64(),
+            len: data.len() as u64,
+        };
+        self.initrd.replace(data);
+        Ok(blob)
+    }
+
+    /// Write the `BootInfo` for the kernel to the syscall page
+    pub fn write_boot_info(
+        &mut self,
+        app: ElfBlob,
+        interp: ElfBlob,
+        args: ArgsBlob,
+        initrd: InitrdBlob,
+    ) -> Result<PhysAddr, Error> {
+        let syscall_vaddr = PhysAddr::new(SYSCALL_PHYS_ADDR);
+
+        let mut boot_info = BootInfo {
+            memory_map: self.memory_map.clone(),
+            app,
+            interp,
+            args,
+            initrd,
+            tmpfs: self.tmpfs,
+            syscalls: self.syscalls,
+            log_level: self.log_level,
+            syscall_trigger_port: SYSCALL_TRIGGER_PORT,
+            ghcb: match self.ghcb_mode {
+                GhcbMode::Disabled => 0,
+                _ => GHCB_PHYS_ADDR,
+            },
+            ghcb_trigger_port: match self.ghcb_mode {
+                GhcbMode::Software => GHCB_TRIGGER_PORT,
+                _ => 0,
+            },
+            secret: if self.launch_secret {
+                LAUNCH_SECRET_PHYS_ADDR
+            } else {
+                0
+            },
+        };
+
+        boot_info.memory_map.sort();
+        // Write boot info to syscall page.
+        unsafe {
+            self.addr_gpa2hva(syscall_vaddr)?
+                .as_mut_ptr::<BootInfo>()
+                .write(boot_info)
+        };
+
+        self.loaded_regions.push(LoadedRegion {
+            kind: LoadedRegionKind::BootInfo,
+            start: syscall_vaddr,
+            size: self.page_size as _,
+        });
+
+        Ok(syscall_vaddr)
+    }
+
+    /// Load the app and the kernel and write the `BootInfo`
+    ///
+    /// This is the complete initial image of a guest after `setup_boot_memory()`.
+    pub fn load_default(&mut self, kernel: &[u8], app: &[u8]) -> Result<GuestEntry, Error> {
+        /* Setup kernel guest code */
+        let kernel_entry = self.elf_load(kernel, MemoryRegionType::Kernel)?;
+
+        /* Setup the app for the kernel */
+        let (app, interp) = self.app_load(app)?;
+        self.app = app;
+        self.interp = interp;
+
+        let blobs_end = match interp.len {
+            0 => app.start + app.len,
+            _ => interp.start + interp.len,
+        };
+        let args = self.args_load(PhysAddr::new(blobs_end).align_up(self.page_size as u64))?;
+
+        let blobs_end = match args.len {
+            0 => blobs_end,
+            _ => args.start + args.len,
+        };
+        let initrd = self.initrd_load(PhysAddr::new(blobs_end).align_up(self.page_size as u64))?;
+
+        let boot_info = self.write_boot_info(app, interp, args, initrd)?;
+
+        Ok(GuestEntry {
+            kernel_entry,
+            boot_info,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(kind: LoadedRegionKind, start: u64, size: u64) -> LoadedRegion {
+        LoadedRegion {
+            kind,
+            start: PhysAddr::new(start),
+            size,
+        }
+    }
+
+    #[test]
+    fn launch_chunks_skip_shared_pages() {
+        let regions = [
+            region(LoadedRegionKind::PageTables, 0x9000, 0x3000),
+            region(LoadedRegionKind::App, 0x40_0000, 0x2000),
+            // second segment sharing the last page of the first one
+            region(LoadedRegionKind::App, 0x40_1000, 0x2000),
+            region(LoadedRegionKind::BootInfo, 0x1000, 0x1000),
+        ];
+        assert_eq!(
+            launch_chunks(&regions),
+            vec![
+                region(LoadedRegionKind::PageTables, 0x9000, 0x3000),
+                region(LoadedRegionKind::App, 0x40_0000, 0x3000),
+                region(LoadedRegionKind::BootInfo, 0x1000, 0x1000),
+            ]
+        );
+    }
+
+    #[test]
+    fn tmpfs_in_guest_memory() {
+        assert!(tmpfs_fits(DEFAULT_TMPFS_SIZE, 64 * 1024 * 1024));
+        assert!(tmpfs_fits(0, 0));
+        assert!(!tmpfs_fits(64 * 1024 * 1024, 64 * 1024 * 1024));
+        assert!(!tmpfs_fits(1024 * 1024 * 1024 * 1024, DEFAULT_GUEST_MEM));
+        assert!(!tmpfs_fits(std::u64::MAX, std::u64::MAX));
+    }
+
+    #[test]
+    fn blob_outside_memory() {
+        let mut mem = GuestMemory::new(0);
+        mem.region_add(PhysAddr::new(0), 0, 16).unwrap();
+        mem.setup_boot_memory().unwrap();
+
+        let data = vec![0xAA; 0x2000];
+        let start = PhysAddr::new(0xF000);
+        assert!(mem
+            .blob_load(&data, start, LoadedRegionKind::Initrd)
+            .is_err());
+        assert!(mem
+            .blob_load(&data[..0x1000], start, LoadedRegionKind::Initrd)
+            .is_ok());
+        assert_eq!(
+            mem.loaded_regions().last(),
+            Some(&region(LoadedRegionKind::Initrd, 0xF000, 0x1000))
+        );
+    }
+
+    #[test]
+    fn launch_chunks_partial_pages() {
+        let regions = [region(LoadedRegionKind::Kernel, 0x10_0000, 0x10)];
+        assert_eq!(
+            launch_chunks(&regions),
+            vec![region(LoadedRegionKind::Kernel, 0x10_0000, 0x1000)]
+        );
+    }
+
+    #[test]
+    fn boot_memory_page_tables() {
+        let mut mem = GuestMemory::new(1 << 47);
+        mem.region_add(PhysAddr::new(0), 0, 512).unwrap();
+        mem.setup_boot_memory().unwrap();
+
+        assert_eq!(
+            mem.loaded_regions(),
+            &[region(
+                LoadedRegionKind::PageTables,
+                PML4_START as _,
+                PAGETABLE_LEN
+            )]
+        );
+
+        let tables = unsafe {
+            &*mem
+                .addr_gpa2hva(PhysAddr::new(PML4_START as _))
+                .unwrap()
+                .as_ptr::<PageTables>()
+        };
+        assert_eq!(tables.pml4t[0], PDPTE_START as u64 | 0x3 | 1 << 47);
+        assert_eq!(tables.pml2t_ident[0], 0x183 | 1 << 47);
+    }
+
+    #[test]
+    fn boot_memory_ghcb_page() {
+        let mut mem = GuestMemory::new(0);
+        mem.set_ghcb_mode(GhcbMode::Software);
+        mem.region_add(PhysAddr::new(0), 0, 512).unwrap();
+        mem.setup_boot_memory().unwrap();
+
+        let ghcb_region = mem
+            .memory_map()
+            .iter()
+            .find(|r| r.range.start_addr() == GHCB_PHYS_ADDR)
+            .unwrap();
+        assert_eq!(ghcb_region.region_type, MemoryRegionType::InUse);
+        assert_eq!(ghcb_region.range.end_addr(), GHCB_PHYS_ADDR + 0x1000);
+    }
+
+    #[test]
+    fn boot_memory_launch_secret_page() {
+        let mut mem = GuestMemory::new(0);
+        assert!(mem.launch_secret_page().is_err());
+        mem.set_launch_secret(true);
+        mem.region_add(PhysAddr::new(0), 0, 512).unwrap();
+        mem.setup_boot_memory().unwrap();
+
+        let secret_region = mem
+            .memory_map()
+            .iter()
+            .find(|r| r.range.start_addr() == LAUNCH_SECRET_PHYS_ADDR)
+            .unwrap();
+        assert_eq!(secret_region.region_type, MemoryRegionType::InUse);
+        assert_eq!(mem.launch_secret_page().unwrap().len(), 0x1000);
+        // injected after the measurement
+        assert!(mem
+            .loaded_regions()
+            .iter()
+            .all(|r| r.start.as_u64() != LAUNCH_SECRET_PHYS_ADDR));
+    }
+
+    #[test]
+    fn page_table_walk() {
+        let mut mem = GuestMemory::new(1 << 47);
+        mem.region_add(PhysAddr::new(0), 0, 512).unwrap();
+        mem.setup_boot_memory().unwrap();
+
+        // the initial 2 MiB identity mapping
+        let t = mem
+            .gva2gpa(PML4_START as u64 | 1 << 47, VirtAddr::new(0x1234))
+            .unwrap();
+        assert_eq!(
+            t,
+            Translation {
+                phys: PhysAddr::new(0x1234),
+                page_size: Size2MiB::SIZE,
+                writable: true,
+                user: false,
+                executable: true,
+            }
+        );
+
+        // a 4 KiB page and a 1 GiB page
+        let write = |addr: u64, entry: u64| unsafe {
+            mem.addr_gpa2hva(PhysAddr::new(addr))
+                .unwrap()
+                .as_mut_ptr::<u64>()
+                .write(entry | 1 << 47)
+        };
+        let (user, nx) = (0x7u64, 1u64 << 63);
+        write(0x20000, 0x21000 | user);
+        write(0x21000, 0x22000 | user);
+        write(0x21000 + 8, 0x4000_0000 | 0x80 | 0x5);
+        write(0x22000, 0x23000 | 0x3);
+        write(0x23000 + 5 * 8, 0x7000 | 0x5 | nx);
+
+        let t = mem.gva2gpa(0x20000, VirtAddr::new(0x5010)).unwrap();
+        assert_eq!(t.phys, PhysAddr::new(0x7010));
+        assert_eq!(t.page_size, Size4KiB::SIZE);
+        assert!(!t.writable && !t.user && !t.executable);
+
+        let t = mem.gva2gpa(0x20000, VirtAddr::new(0x7654_3210)).unwrap();
+        assert_eq!(t.phys, PhysAddr::new(0x7654_3210));
+        assert_eq!(t.page_size, Size1GiB::SIZE);
+        assert!(!t.writable && t.user && t.executable);
+
+        assert!(mem.gva2gpa(0x20000, VirtAddr::new(0x6000)).is_err());
+        assert!(mem.gva2gpa(0x20000, VirtAddr::new(0x80_0000_0000)).is_err());
+    }
+
+    #[test]
+    fn page_table_mappings() {
+        let mut mem = GuestMemory::new(1 << 47);
+        mem.region_add(PhysAddr::new(0), 0, 512).unwrap();
+        mem.setup_boot_memory().unwrap();
+
+        let write = |addr: u64, entry: u64| unsafe {
+            mem.addr_gpa2hva(PhysAddr::new(addr))
+                .unwrap()
+                .as_mut_ptr::<u64>()
+                .write(entry | 1 << 47)
+        };
+        // two contiguous pages and the top 4 KiB page of the address space
+        write(0x20000, 0x21000 | 0x7);
+        write(0x21000, 0x22000 | 0x7);
+        write(0x22000, 0x23000 | 0x7);
+        write(0x23000 + 8, 0x7000 | 0x7);
+        write(0x23000 + 2 * 8, 0x8000 | 0x7);
+        write(0x20000 + 511 * 8, 0x24000 | 0x7);
+        write(0x24000 + 511 * 8, 0x25000 | 0x7);
+        write(0x25000 + 511 * 8, 0x26000 | 0x7);
+        write(0x26000 + 511 * 8, 0x9000 | 0x5 | 1 << 63);
+
+        let page = |virt: u64, phys: u64, size: u64, writable: bool, executable: bool| Mapping {
+            virt: VirtAddr::new(virt),
+            phys: PhysAddr::new(phys),
+            size,
+            writable,
+            user: true,
+            executable,
+        };
+        assert_eq!(
+            mem.mappings(0x20000),
+            vec![
+                page(0x1000, 0x7000, 0x2000, true, true),
+                page(0xFFFF_FFFF_FFFF_F000, 0x9000, 0x1000, false, false),
+            ]
+        );
+
+        // the initial 2 MiB identity mapping
+        assert_eq!(
+            mem.mappings(PML4_START as _),
+            vec![Mapping {
+                user: false,
+                ..page(0, 0, Size2MiB::SIZE, true, true)
+            }]
+        );
+    }
+
+    #[test]
+    fn guest_virtual_memory() {
+        let mut mem = GuestMemory::new(0);
+        mem.region_add(PhysAddr::new(0), 0, 512).unwrap();
+        mem.setup_boot_memory().unwrap();
+        let cr3 = PML4_START as u64;
+
+        // across a page boundary
+        let data = b"across the page boundary";
+        mem.write_virt(cr3, VirtAddr::new(0x1FFF0), data).unwrap();
+        let mut buf = [0u8; 24];
+        mem.read_virt(cr3, VirtAddr::new(0x1FFF0), &mut buf)
+            .unwrap();
+        assert_eq!(&buf, data);
+
+        // beyond the 2 MiB of the identity mapping
+        assert!(mem
+            .read_virt(cr3, VirtAddr::new(0x1F_FFF0), &mut buf)
+            .is_err());
+    }
+
+    #[test]
+    fn boot_info_syscall_policy() {
+        use vmsyscall::bootinfo::UnknownSyscall;
+
+        let mut policy = SyscallPolicy::default();
+        policy.unknown = UnknownSyscall::LogAndEnosys;
+        policy.deny(41);
+
+        let mut mem = GuestMemory::new(0);
+        mem.set_syscall_policy(policy);
+        mem.set_log_level(LogLevel::Debug);
+        mem.region_add(PhysAddr::new(0), 0, 512).unwrap();
+        mem.setup_boot_memory().unwrap();
+        let addr = mem
+            .write_boot_info(
+                ElfBlob::default(),
+                ElfBlob::default(),
+                ArgsBlob::default(),
+                InitrdBlob::default(),
+            )
+            .unwrap();
+
+        let boot_info = unsafe { &*mem.addr_gpa2hva(addr).unwrap().as_ptr::<BootInfo>() };
+        assert_eq!(boot_info.syscalls.unknown, UnknownSyscall::LogAndEnosys);
+        assert!(boot_info.syscalls.is_denied(41));
+        assert!(!boot_info.syscalls.is_denied(0));
+        assert_eq!(boot_info.log_level, LogLevel::Debug);
+    }
+
+    #[test]
+    fn args_blob() {
+        let mut mem = GuestMemory::new(0);
+        mem.region_add(PhysAddr::new(0), 0, 2048).unwrap();
+        assert_eq!(mem.args_load(PhysAddr::new(APP_BLOB_START)).unwrap().len, 0);
+
+        mem.set_args(
+            &["/bin/app".into(), "".into(), "-v".into()],
+            &["A=1".into()],
+        );
+        let blob = mem.args_load(PhysAddr::new(APP_BLOB_START)).unwrap();
+        assert_eq!(blob.start, APP_BLOB_START);
+        assert_eq!(blob.len, 16);
+
+        let region = mem.loaded_regions().last().unwrap();
+        assert_eq!(region.kind, LoadedRegionKind::Args);
+        let data = unsafe { mem.host_slice(region).unwrap() };
+        let (args, env) = vmsyscall::bootinfo::decode_args(&data[..blob.len as usize]);
+        assert_eq!(args.collect::<Vec<_>>(), [&b"/bin/app"[..], &b"-v"[..]]);
+        assert_eq!(env.collect::<Vec<_>>(), [&b"A=1"[..]]);
+    }
+
+    #[test]
+    fn initrd_format() {
+        let mut mem = GuestMemory::new(0);
+        assert!(mem.set_initrd(b"not an archive".to_vec()).is_err());
+        mem.set_initrd(b"070701".to_vec()).unwrap();
+
+        let mut tar = vec![0u8; 1024];
+        tar[257..262].copy_from_slice(b"ustar");
+        mem.set_initrd(tar).unwrap();
+    }
+
+    #[test]
+    fn pie_base_without_seed() {
+        assert_eq!(pie_base(None, 0x10_0000, 0x1000).unwrap(), PIE_BASE_MIN);
+        assert_eq!(pie_base(None, 0x10_0000, 0).unwrap(), PIE_BASE_MIN);
+        assert_eq!(
+            pie_base(None, 0x10_0000, 0x300_0000).unwrap(),
+            0x300_0000,
+            "aligned up"
+        );
+    }
+
+    #[test]
+    fn pie_base_with_seed() {
+        let size = 0x20_0000;
+        let mut bases = HashSet::new();
+
+        for seed in 0..32 {
+            let base = pie_base(Some(seed), size, 0x1000).unwrap();
+            assert_eq!(base, pie_base(Some(seed), size, 0x1000).unwrap());
+            assert_eq!(base % 0x1000, 0);
+            assert!(base >= PIE_BASE_MIN);
+            assert!(base + size <= PIE_BASE_END);
+            bases.insert(base);
+        }
+        assert!(bases.len() > 30);
+    }
+
+    #[test]
+    fn pie_base_too_large() {
+        assert!(pie_base(Some(1), PIE_BASE_END, 0x1000).is_err());
+        assert_eq!(
+            pie_base(Some(1), PIE_BASE_END - PIE_BASE_MIN, 0x1000).unwrap(),
+            PIE_BASE_MIN
+        );
+    }
+
+    #[test]
+    fn interp_base_range() {
+        assert_eq!(
+            interp_base(None, 0x4_0000, 0x1000).unwrap(),
+            INTERP_BASE_MIN
+        );
+
+        for seed in 0..32 {
+            let base = interp_base(Some(seed), 0x4_0000, 0x1000).unwrap();
+            assert!(base >= INTERP_BASE_MIN);
+            assert!(base + 0x4_0000 <= INTERP_BASE_END);
+            assert!(base >= pie_base(Some(seed), 0x20_0000, 0x1000).unwrap() + 0x20_0000);
+        }
+        assert!(interp_base(None, INTERP_BASE_END, 0x1000).is_err());
+    }
+
+    #[test]
+    fn region_add_overlapping() {
+        let mut mem = GuestMemory::new(0);
+        mem.region_add(PhysAddr::new(0), 0, 16).unwrap();
+        assert_eq!(
+            mem.region_add(PhysAddr::new(0x8000), 1, 16)
+                .err()
+                .unwrap()
+                .kind(),
+            &ErrorKind::OverlappingUserspaceMemRegionExists
+        );
+        assert_eq!(
+            mem.region_add(PhysAddr::new(0x10_0000), 0, 16)
+                .err()
+                .unwrap()
+                .kind(),
+            &ErrorKind::MemRegionWithSlotAlreadyExists
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Output of the kernel log messages
+//!
+//! The kernel sends its messages with `VmSyscall::Log`, apart from the writes
+//! of the app. A message goes to `--kernel-log` as `level: message`, else to
+//! the stderr of the hypervisor with a `kernel ` prefix. Messages more verbose
+//! than `--log-level` are dropped.
+
+use linux_errno::ErrNo;
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use vmsyscall::bootinfo::LogLevel;
+
+fn errno(e: io::Error) -> vmsyscall::Error {
+    vmsyscall::Error::Errno(
+        e.raw_os_error()
+            .map_or(Into::<i64>::into(ErrNo::EIO), |e| e as _),
+    )
+}
+
+/// The writer of the kernel log messages
+///
+/// Every line is written at once, `vmrun` exits without dropping the VM.
+pub struct KernelLog {
+    file: Option<LineWriter<File>>,
+    level: LogLevel,
+}
+
+impl KernelLog {
+    /// Create the file of `--kernel-log`
+    pub fn new(path: Option<&Path>, level: LogLevel) -> io::Result<Self> {
+        Ok(KernelLog {
+            file: path
+                .map(|path| File::create(path).map(LineWriter::new))
+                .transpose()?,
+            level,
+        })
+    }
+
+    /// Write a message of `level`, if the level allows it
+    pub fn log(&mut self, level: LogLevel, msg: &str) -> io::Result<()> {
+        if !self.level.allows(level) {
+            return Ok(());
+        }
+        match self.file.as_mut() {
+            Some(file) => writeln!(file, "{}: {}", level.name(), msg),
+            None => {
+                eprintln!("kernel {}: {}", level.name(), msg);
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the `VmSyscall::Log` of the kernel
+    pub fn syscall(&mut self, level: LogLevel, msg: &[u8]) -> Result<i32, vmsyscall::Error> {
+        self.log(level, &String::from_utf8_lossy(msg))
+            .map(|_| 0)
+            .map_err(errno)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_file() {
+        let dir = std::env::temp_dir().join(format!("vmrun-log-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("kernel.log");
+
+        let mut log = KernelLog::new(Some(&path), LogLevel::Info).unwrap();
+        log.log(LogLevel::Error, "app_entry_point.is_null()")
+            .unwrap();
+        log.log(LogLevel::Info, "SEV enabled, C-bit 47").unwrap();
+        log.log(LogLevel::Debug, "NEXT_MMAP = 0x400000").unwrap();
+        log.syscall(LogLevel::Warn, b"No secret\xff").unwrap();
+        log.log(LogLevel::Off, "never").unwrap();
+        drop(log);
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "error: app_entry_point.is_null()\ninfo: SEV enabled, C-bit 47\nwarn: No secret\u{fffd}\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+//! A local mock of the guest owner's key broker
+//!
+//! The key broker of the guest owner verifies the launch measurement of a
+//! guest and only then releases the key for the workload's secret, wrapped
+//! for LAUNCH_SECRET with the keys of the launch session. The secret itself
+//! is sealed with that key and can be handed to the guest by the hypervisor.
+//!
+//! [`MockKeyBroker`] does the same locally, for tests only. Its
+//! [`SecretPacket`]s are not transport encrypted, so they are only accepted by
+//! the [`MockFirmware`]. Real SEV guests get the packet of the guest owner.
+//!
+//! [`MockFirmware`]: crate::sev::MockFirmware
+
+use crate::error::*;
+use crate::sev::{LaunchMeasurement, SecretPacket};
+use crate::{context, map_context};
+use std::cell::Cell;
+use std::fs::File;
+use std::io::Read;
+use vmsyscall::secret::{LaunchSecret, SecretBlob, KEY_LEN, NONCE_LEN};
+
+/// Releases the key of a workload secret to a guest with the expected measurement
+pub struct MockKeyBroker {
+    key: [u8; KEY_LEN],
+    expected: Option<[u8; 32]>,
+    nonce: Cell<u64>,
+}
+
+impl MockKeyBroker {
+    /// Create a key broker with a random key
+    pub fn new() -> Result<Self, Error> {
+        let mut key = [0u8; KEY_LEN];
+        File::open("/dev/urandom")
+            .and_then(|mut f| f.read_exact(&mut key))
+            .map_err(map_context!())?;
+        Ok(Self::with_key(key))
+    }
+
+    /// Create a key broker with a fixed key
+    ///
+    /// The nonces start at zero for every key broker, so a key must not be
+    /// used for more than one broker.
+    pub fn with_key(key: [u8; KEY_LEN]) -> Self {
+        MockKeyBroker {
+            key,
+            expected: None,
+            nonce: Cell::new(0),
+        }
+    }
+
+    /// Only release the key to a guest with the launch digest `measure`
+    pub fn expect_measurement(&mut self, measure: [u8; 32]) {
+        self.expected.replace(measure);
+    }
+
+    /// Seal `secret` with the key of the broker
+    pub fn seal(&self, secret: &[u8]) -> Result<SecretBlob, Error> {
+        let counter = self.nonce.get();
+        self.nonce.set(counter + 1);
+
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..8].copy_from_slice(&counter.to_le_bytes());
+
+        SecretBlob::seal(&self.key, &nonce, secret).map_err(|_| context!(ErrorKind::SecretTooLong))
+    }
+
+    /// The LAUNCH_SECRET packet with the key, if `measurement` is the expected one
+    ///
+    /// `measurement` is `None` for a guest without SEV, which is only accepted
+    /// if no measurement is expected.
+    pub fn release_key(
+        &self,
+        measurement: Option<&LaunchMeasurement>,
+    ) -> Result<SecretPacket, Error> {
+        if let Some(expected) = self.expected {
+            match measurement {
+                Some(m) if m.measure == expected => {}
+                _ => return Err(context!(ErrorKind::KeyBrokerRejected)),
+            }
+        }
+
+        Ok(SecretPacket {
+            header: vec![],
+            data: LaunchSecret::new(self.key).as_bytes().to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sev::{MockFirmware, SevLaunch, SevPolicy};
+
+    fn launch(data: &mut [u8]) -> (SevLaunch<MockFirmware>, LaunchMeasurement) {
+        let mut sev = SevLaunch::new(MockFirmware::new());
+        sev.init().unwrap();
+        sev.launch_start(SevPolicy::NODBG).unwrap();
+        sev.launch_update_data(data).unwrap();
+        let measurement = sev.launch_measure().unwrap();
+        (sev, measurement)
+    }
+
+    #[test]
+    fn secret_roundtrip() {
+        let mut image = [0x11u8; 4096];
+        let (mut sev, measurement) = launch(&mut image[..]);
+
+        let mut broker = MockKeyBroker::with_key([0x42; KEY_LEN]);
+        broker.expect_measurement(measurement.measure);
+        let mut blob = broker.seal(b"database password").unwrap();
+
+        let mut page = [0u8; 4096];
+        let packet = broker.release_key(Some(&measurement)).unwrap();
+        sev.launch_secret(&packet, &mut page[..]).unwrap();
+        sev.launch_finish().unwrap();
+
+        // what the kernel does with the launch secret page
+        let launch_secret = unsafe { (page.as_ptr() as *const LaunchSecret).read_unaligned() };
+        let key = launch_secret.key().unwrap();
+        assert_eq!(blob.open(key).unwrap(), b"database password");
+    }
+
+    #[test]
+    fn wrong_measurement_rejected() {
+        let mut image = [0x22u8; 4096];
+        let (_, measurement) = launch(&mut image[..]);
+
+        let mut broker = MockKeyBroker::with_key([0x42; KEY_LEN]);
+        broker.expect_measurement([0; 32]);
+        assert_eq!(
+            broker.release_key(Some(&measurement)).unwrap_err().kind(),
+            &ErrorKind::KeyBrokerRejected
+        );
+        assert_eq!(
+            broker.release_key(None).unwrap_err().kind(),
+            &ErrorKind::KeyBrokerRejected
+        );
+    }
+
+    #[test]
+    fn unique_nonces() {
+        let broker = MockKeyBroker::new().unwrap();
+        let mut a = broker.seal(b"secret").unwrap();
+        let mut b = broker.seal(b"secret").unwrap();
+        assert_ne!(a.nonce(), b.nonce());
+
+        let packet = broker.release_key(None).unwrap();
+        let launch_secret =
+            unsafe { (packet.data.as_ptr() as *const LaunchSecret).read_unaligned() };
+        let key = launch_secret.key().unwrap();
+        assert_eq!(a.open(key).unwrap(), b"secret");
+        assert_eq!(b.open(key).unwrap(), b"secret");
+
+        assert_eq!(
+            broker.seal(&[0u8; 4096]).unwrap_err().kind(),
+            &ErrorKind::SecretTooLong
+        );
+    }
+}
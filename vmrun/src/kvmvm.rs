@@ -1,117 +1,235 @@
 use crate::arch::x86_64::{
     consts::*,
     gdt::{gdt_entry, kvm_segment_from_gdt},
-    structures::paging::{frame::PhysFrameRange, PhysFrame},
     HostVirtAddr, PhysAddr, VirtAddr,
 };
+use crate::context;
 use crate::error::*;
-use crate::{context, map_context};
+use crate::gdb::{self, Resume};
+pub use crate::guest::{
+    launch_chunks, GhcbMode, GuestMemory, LoadedRegion, LoadedRegionKind, PageTables, Translation,
+    DEFAULT_GUEST_MEM, DEFAULT_GUEST_PAGE_SIZE, DEFAULT_TMPFS_INODES, DEFAULT_TMPFS_SIZE,
+    HIMEM_START, PAGETABLE_LEN, PDE_START, PDPTE_START, PML4_START, SYSCALL_PHYS_ADDR,
+    SYSCALL_TRIGGER_PORT,
+};
+use crate::kernel_log::KernelLog;
+use crate::poll;
+use crate::sev::{
+    self, KvmSevFirmware, LaunchMeasurement, LaunchSession, SecretPacket, SevFirmware, SevLaunch,
+    SevPolicy,
+};
+use crate::snapshot::{Marker, Region, Snapshot, VcpuState};
+use crate::socket::{self, Sockets};
+use crate::sysroot::Sysroot;
+use crate::trace::Tracer;
 use kvm_bindings::{
-    kvm_mp_state, kvm_pit_config, kvm_segment, kvm_userspace_memory_region, KVM_MAX_CPUID_ENTRIES,
-    KVM_PIT_SPEAKER_DUMMY,
+    kvm_irqchip, kvm_mp_state, kvm_msr_entry, kvm_pit_config, kvm_segment,
+    kvm_userspace_memory_region, CpuId, Msrs, KVM_CPUID_FLAG_SIGNIFCANT_INDEX, KVM_IRQCHIP_IOAPIC,
+    KVM_IRQCHIP_PIC_MASTER, KVM_IRQCHIP_PIC_SLAVE, KVM_MAX_CPUID_ENTRIES, KVM_PIT_SPEAKER_DUMMY,
 };
-use kvm_ioctls::{Kvm, VcpuFd, VmFd};
+use kvm_ioctls::{Kvm, VcpuExit, VcpuFd, VmFd};
 use linux_errno::ErrNo;
-use std::io::Write;
-use vmsyscall::bootinfo::BootInfo;
-use vmsyscall::memory_map::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType};
-use vmsyscall::{VmSyscall, VmSyscallRet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::mem::ManuallyDrop;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use vmsyscall::bootinfo::{LogLevel, SyscallPolicy};
+use vmsyscall::ghcb::{
+    ioio_decode, Ghcb, GHCB_PHYS_ADDR, SVM_EXIT_CPUID, SVM_EXIT_IOIO, SVM_EXIT_MSR,
+};
+use vmsyscall::secret::{LaunchSecret, SecretBlob, KEY_LEN, NONCE_LEN, SECRET_BLOB_LEN};
+use vmsyscall::{
+    VmSyscall, VmSyscallRet, LOG_MSG_LEN, MAX_POLL_FDS, SOCKET_BUF_LEN, SOCKOPT_LEN,
+    TRACE_LINE_LEN, WRITE_BUF_LEN,
+};
 
-const DEFAULT_GUEST_MEM: u64 = 2 * 1024 * 1024 * 1024; // 2GiB
-const DEFAULT_GUEST_PAGE_SIZE: usize = 4096;
+pub const BOOT_GDT_OFFSET: usize = 0x500;
+pub const BOOT_IDT_OFFSET: usize = 0x520;
 
-pub const HIMEM_START: usize = 0x0010_0000; //1 MB.
+pub const DEFAULT_SEV_POLICY: SevPolicy = SevPolicy::NODBG;
 
-pub const SYSCALL_PHYS_ADDR: u64 = 0x1000;
-pub const SYSCALL_TRIGGER_PORT: u16 = 0xFF;
+/// The guest policy for LAUNCH_START
+pub fn sev_policy(es: bool) -> SevPolicy {
+    if es {
+        DEFAULT_SEV_POLICY | SevPolicy::ES
+    } else {
+        DEFAULT_SEV_POLICY
+    }
+}
 
-// Initial pagetables.
-pub const PML4_START: usize = 0x9000;
-pub const PDPTE_START: usize = 0xA000;
-pub const PDE_START: usize = 0xB000;
-pub const PAGETABLE_LEN: u64 = core::mem::size_of::<PageTables>() as _;
+/// Configuration of the VM created by `KvmVm::vm_create_default()`
+#[derive(Clone, Debug, Default)]
+pub struct VmConfig {
+    /// Launch the guest with AMD SEV memory encryption
+    pub sev: bool,
+    /// Launch the guest with SEV-ES register state encryption, implies `sev`
+    pub sev_es: bool,
+    /// Use the software GHCB protocol without SEV-ES
+    pub ghcb_software: bool,
+    /// The secret of a guest without SEV, sealed with a key of the host
+    ///
+    /// The host sees the secret and its key, this is for testing only.
+    pub secret: Option<Vec<u8>>,
+    /// The launch session of the guest owner for an SEV guest
+    pub sev_session: Option<LaunchSession>,
+    /// The guest owner's LAUNCH_SECRET packet with the key of `sealed_secret`
+    ///
+    /// The file is read after the launch measurement was printed, it can be a
+    /// named pipe fed by the key broker of the guest owner.
+    pub secret_packet: Option<PathBuf>,
+    /// The `SecretBlob` sealed by the guest owner for an SEV guest
+    pub sealed_secret: Option<PathBuf>,
+    /// Seed for the load base of a static-PIE app, `None` for the lowest base
+    pub aslr_seed: Option<u64>,
+    /// Directory with the ELF interpreter and the shared libraries, `/` if `None`
+    pub sysroot: Option<PathBuf>,
+    /// Size of the guest memory in bytes, `DEFAULT_GUEST_MEM` if `None`
+    pub mem_size: Option<u64>,
+    /// Arguments of the app, the kernel defaults are used if empty
+    pub args: Vec<String>,
+    /// Environment of the app as `KEY=value`
+    pub env: Vec<String>,
+    /// The initial RAM filesystem of the app, a tar or cpio archive
+    pub initrd: Option<Vec<u8>>,
+    /// Size of the kernel's `/tmp` in bytes, `DEFAULT_TMPFS_SIZE` if `None`
+    pub tmpfs_size: Option<u64>,
+    /// The addresses the app may listen on and connect to
+    pub socket_policy: socket::Policy,
+    /// What the kernel does with unknown, denied and traced syscalls of the app
+    pub syscall_policy: SyscallPolicy,
+    /// The file of the traced syscalls, the hypervisor's stderr if `None`
+    pub trace_output: Option<PathBuf>,
+    /// The file of the traced syscalls as JSON lines
+    pub trace_json: Option<PathBuf>,
+    /// The file of the kernel log messages, the hypervisor's stderr if `None`
+    pub kernel_log: Option<PathBuf>,
+    /// The most verbose level of the kernel log messages
+    pub log_level: LogLevel,
+    /// The address gdb connects to, like `127.0.0.1:1234`
+    pub gdb: Option<String>,
+    /// The ELF core file written if the guest fails
+    pub core_dump: Option<PathBuf>,
+    /// The snapshot file written when the app outputs `snapshot_at`
+    pub snapshot: Option<PathBuf>,
+    /// The output of the app to take the snapshot at
+    pub snapshot_at: Option<String>,
+}
 
-pub const BOOT_GDT_OFFSET: usize = 0x500;
-pub const BOOT_IDT_OFFSET: usize = 0x520;
+impl VmConfig {
+    /// The guest gets a key in the launch secret page
+    pub fn launch_secret(&self) -> bool {
+        self.secret.is_some() || self.secret_packet.is_some()
+    }
 
-#[repr(C)]
-pub struct PageTables {
-    pub pml4t: [u64; 512],
-    pub pml3t_ident: [u64; 512],
-    pub pml2t_ident: [u64; 512],
-}
+    /// The directory files of dynamically linked apps are read from
+    pub fn sysroot(&self) -> PathBuf {
+        self.sysroot.clone().unwrap_or_else(|| PathBuf::from("/"))
+    }
 
-impl Default for PageTables {
-    fn default() -> Self {
-        PageTables {
-            pml4t: [0u64; 512],
-            pml3t_ident: [0u64; 512],
-            pml2t_ident: [0u64; 512],
+    /// How the guest talks to the hypervisor
+    pub fn ghcb_mode(&self) -> GhcbMode {
+        if self.sev_es {
+            GhcbMode::Vmgexit
+        } else if self.ghcb_software {
+            GhcbMode::Software
+        } else {
+            GhcbMode::Disabled
         }
     }
 }
 
-struct UserspaceMemRegion {
-    region: kvm_userspace_memory_region,
-    host_mem: HostVirtAddr,
-    mmap_start: HostVirtAddr,
-    mmap_size: usize,
+/// An `out` instruction forwarded by the software GHCB protocol
+pub struct GhcbIoOut {
+    pub port: u16,
+    data: [u8; 4],
+    size: usize,
+}
+
+impl GhcbIoOut {
+    /// The data written, in the same format as `VcpuExit::IoOut`
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.size]
+    }
 }
 
 pub struct KvmVm {
     pub kvm: Kvm,
     pub cpu_fd: Vec<VcpuFd>,
     pub kvm_fd: VmFd,
-    page_size: usize,
-    memory_map: MemoryMap,
-    userspace_mem_regions: Vec<UserspaceMemRegion>,
+    pub mem: GuestMemory,
     has_irqchip: bool,
     pub syscall_hostvaddr: Option<HostVirtAddr>,
-}
-
-fn frame_range(range: PhysFrameRange) -> FrameRange {
-    FrameRange::new(
-        range.start.start_address().as_u64(),
-        range.end.start_address().as_u64(),
-    )
+    sev: Option<SevLaunch<Box<dyn SevFirmware>>>,
+    cpuid: Option<CpuId>,
+    secret: Option<SecretBlob>,
+    files: Sysroot,
+    sockets: Sockets,
+    tracer: Tracer,
+    kernel_log: KernelLog,
+    marker: Option<Marker>,
+    marker_reached: bool,
 }
 
 impl KvmVm {
-    pub fn vm_create(phy_pages: u64) -> Result<Self, Error> {
+    pub fn vm_create(phy_pages: u64, config: &VmConfig) -> Result<Self, Error> {
         let kvm = Kvm::new().unwrap();
 
         let kvm_fd: VmFd = kvm.create_vm().map_err(|e| ErrorKind::from(&e))?;
 
+        let mut sev = None;
+        let mut cbit_mask = 0;
+
+        if config.sev || config.sev_es {
+            let cbit = sev::host_cbit_position()
+                .ok_or_else(|| context!(ErrorKind::Str("SEV is not supported by the host CPU")))?;
+            let firmware: Box<dyn SevFirmware> = Box::new(KvmSevFirmware::new(&kvm_fd)?);
+            let mut launch = SevLaunch::new(firmware);
+            if let Some(session) = &config.sev_session {
+                launch.set_session(session.clone());
+            }
+            // KVM_SEV_INIT has to be issued before any vCPU is created
+            if config.sev_es {
+                launch.es_init()?;
+            } else {
+                launch.init()?;
+            }
+            sev.replace(launch);
+            cbit_mask = 1 << cbit;
+        }
+
         let mut vm = KvmVm {
             kvm,
             cpu_fd: vec![],
             kvm_fd,
-            page_size: DEFAULT_GUEST_PAGE_SIZE,
-            memory_map: MemoryMap::new(),
-            userspace_mem_regions: vec![],
+            mem: GuestMemory::new(cbit_mask),
             has_irqchip: false,
             syscall_hostvaddr: None,
+            sev,
+            cpuid: None,
+            secret: None,
+            files: Sysroot::new(config.sysroot()),
+            sockets: Sockets::new(config.socket_policy.clone()),
+            tracer: Tracer::new(config.trace_output.as_deref(), config.trace_json.as_deref())
+                .map_err(|e| ErrorKind::from(&e))?,
+            kernel_log: KernelLog::new(config.kernel_log.as_deref(), config.log_level)
+                .map_err(|e| ErrorKind::from(&e))?,
+            marker: config
+                .snapshot_at
+                .as_ref()
+                .map(|marker| Marker::new(marker.as_bytes())),
+            marker_reached: false,
         };
 
+        vm.mem.set_ghcb_mode(config.ghcb_mode());
+        vm.mem.set_launch_secret(config.launch_secret());
+        vm.mem.set_aslr_seed(config.aslr_seed);
+        vm.mem.set_sysroot(config.sysroot());
+
         //FIXME: remove phy_pages
         if phy_pages != 0 {
             vm.vm_userspace_mem_region_add(PhysAddr::new(0), 0, phy_pages, 0)?;
-
-            let zero_frame: PhysFrame = PhysFrame::from_start_address(PhysAddr::new(0)).unwrap();
-
-            vm.memory_map.mark_allocated_region(MemoryRegion {
-                range: frame_range(PhysFrame::range(zero_frame, zero_frame + 1)),
-                region_type: MemoryRegionType::FrameZero,
-            });
-
-            let syscall_frame: PhysFrame =
-                PhysFrame::from_start_address(PhysAddr::new(SYSCALL_PHYS_ADDR)).unwrap();
-            vm.memory_map.mark_allocated_region(MemoryRegion {
-                range: frame_range(PhysFrame::range(syscall_frame, syscall_frame + 1)),
-                region_type: MemoryRegionType::InUse,
-            });
-
-            vm.setup_page_tables()?;
+            vm.mem.setup_boot_memory()?;
         }
 
         Ok(vm)
@@ -124,199 +242,64 @@ impl KvmVm {
         npages: u64,
         flags: u32,
     ) -> Result<(), Error> {
-        for r in self.userspace_mem_regions.iter() {
-            if r.region.slot == slot {
-                return Err(context!(ErrorKind::MemRegionWithSlotAlreadyExists));
-            }
-
-            if guest_paddr.as_u64() <= (r.region.guest_phys_addr + r.region.memory_size)
-                && (guest_paddr.as_u64() + npages * self.page_size as u64)
-                    >= r.region.guest_phys_addr
-            {
-                return Err(context!(ErrorKind::OverlappingUserspaceMemRegionExists));
-            }
-        }
-
-        let mut region = UserspaceMemRegion {
-            region: Default::default(),
-            host_mem: HostVirtAddr::new(0),
-            mmap_start: HostVirtAddr::new(0),
-            mmap_size: (npages * self.page_size as u64) as _,
+        let region = self.mem.region_add(guest_paddr, slot, npages)?;
+
+        let kvm_region = kvm_userspace_memory_region {
+            slot,
+            flags,
+            guest_phys_addr: region.guest_phys_addr.as_u64(),
+            memory_size: region.size as u64,
+            userspace_addr: region.host_mem.as_u64(),
         };
-        let mm = mmap::MemoryMap::new(
-            region.mmap_size,
-            &[mmap::MapOption::MapReadable, mmap::MapOption::MapWritable],
-        )
-        .map_err(|_| context!(ErrorKind::MmapFailed))?;
-        let mmap_start = mm.data();
-        // FIXME: No drop for mm
-        std::mem::forget(mm);
-
-        region.mmap_start = HostVirtAddr::new(mmap_start as u64);
 
-        region.host_mem = region.mmap_start;
-
-        region.region.slot = slot;
-        region.region.flags = flags;
-        region.region.guest_phys_addr = guest_paddr.as_u64();
-        region.region.memory_size = npages * self.page_size as u64;
-        region.region.userspace_addr = region.host_mem.as_u64();
+        let mem = unsafe {
+            core::slice::from_raw_parts_mut(region.host_mem.as_mut_ptr::<u8>(), region.size)
+        };
 
         unsafe {
             self.kvm_fd
-                .set_user_memory_region(region.region)
+                .set_user_memory_region(kvm_region)
                 .map_err(|e| ErrorKind::from(&e))?
         };
 
-        self.memory_map.add_region(MemoryRegion {
-            range: FrameRange::new(
-                region.region.guest_phys_addr,
-                region.region.guest_phys_addr + region.region.memory_size,
-            ),
-            region_type: MemoryRegionType::Usable,
-        });
-
-        self.userspace_mem_regions.push(region);
+        if let Some(sev) = self.sev.as_mut() {
+            sev.register_region(mem)?;
+        }
 
         Ok(())
     }
 
     pub fn addr_gpa2hva(&self, guest_phys_addr: PhysAddr) -> Result<HostVirtAddr, Error> {
-        for region in &self.userspace_mem_regions {
-            if (guest_phys_addr.as_u64() >= region.region.guest_phys_addr)
-                && (guest_phys_addr.as_u64()
-                    <= (region.region.guest_phys_addr + region.region.memory_size - 1))
-            {
-                return Ok(HostVirtAddr::new(
-                    region.host_mem.as_u64()
-                        + (guest_phys_addr.as_u64() - region.region.guest_phys_addr),
-                ));
-            }
-        }
-        Err(context!(ErrorKind::NoMappingForVirtualAddress))
+        self.mem.addr_gpa2hva(guest_phys_addr)
     }
 
-    fn setup_page_tables(&mut self) -> Result<(), Error> {
-        let mut page_tables = PageTables::default();
-
-        // Note we are assuming CPU supports 2MB pages. All modern CPUs do.
-        page_tables.pml4t[0] = PDPTE_START as u64 | 0x7;
-        page_tables.pml3t_ident[0] = PDE_START as u64 | 0x7;
-        page_tables.pml2t_ident[0] = 0x183u64;
-
-        let guest_pg_addr: *mut PageTables = self
-            .addr_gpa2hva(PhysAddr::new(PML4_START as _))?
-            .as_mut_ptr();
-
-        unsafe {
-            // FIXME: SEV LOAD
-            guest_pg_addr.write(page_tables);
-        }
-
-        Ok(())
+    /// Translate the guest virtual `vaddr` with the page tables at `cr3`
+    pub fn gva2gpa(&self, cr3: u64, vaddr: VirtAddr) -> Result<Translation, Error> {
+        self.mem.gva2gpa(cr3, vaddr)
     }
 
-    pub fn elf_load(
-        &mut self,
-        program_invocation_name: &str,
-        region_type: MemoryRegionType,
-    ) -> Result<(VirtAddr, VirtAddr, usize), Error> {
-        use std::fs::File;
-        use std::os::unix::io::AsRawFd;
-        use xmas_elf::program::{self, ProgramHeader};
-        use xmas_elf::ElfFile;
-
-        let file = File::open(program_invocation_name).map_err(map_context!())?;
-        let mmap_size = file.metadata().map_err(map_context!())?.len() as usize;
-        let mm = mmap::MemoryMap::new(
-            mmap_size,
-            &[
-                mmap::MapOption::MapFd(file.as_raw_fd()),
-                mmap::MapOption::MapReadable,
-            ],
-        )
-        .map_err(|_| context!(ErrorKind::MmapFailed))?;
-
-        let data = unsafe { core::slice::from_raw_parts(mm.data(), mmap_size) };
-
-        let elf_file = ElfFile::new(data).map_err(map_context!())?;
-
-        xmas_elf::header::sanity_check(&elf_file).map_err(map_context!())?;
-
-        let guest_code: VirtAddr = VirtAddr::new(elf_file.header.pt2.entry_point());
-        let mut load_addr: Option<VirtAddr> = None;
-        let phnum: usize = elf_file.program_iter().count();
-
-        for program_header in elf_file.program_iter() {
-            match program_header {
-                ProgramHeader::Ph64(header) => {
-                    let segment = *header;
-                    match segment.get_type().unwrap() {
-                        program::Type::Load => {}
-                        program::Type::Interp => {
-                            return Err(ErrorKind::NotAStaticBinary.into());
-                        }
-                        _ => continue,
-                    }
-
-                    if load_addr.is_none() {
-                        load_addr.replace(VirtAddr::new(segment.virtual_addr) - segment.offset);
-                    }
-
-                    // dbg!(segment);
-
-                    if segment.mem_size == 0 {
-                        continue;
-                    }
-
-                    let start_phys = PhysAddr::new(segment.physical_addr);
-                    let start_frame: PhysFrame =
-                        PhysFrame::from_start_address(start_phys.align_down(self.page_size as u64))
-                            .unwrap();
-
-                    let end_frame: PhysFrame = PhysFrame::from_start_address(
-                        PhysAddr::new((segment.physical_addr) + segment.mem_size - 1)
-                            .align_up(self.page_size as u64),
-                    )
-                    .unwrap();
-
-                    let region = MemoryRegion {
-                        range: frame_range(PhysFrame::range(start_frame, end_frame)),
-                        region_type,
-                    };
-
-                    //dbg!(region);
-                    //dbg!(&self.memory_map);
-                    self.memory_map.mark_allocated_region(region);
-
-                    // FIXME: SEV LOAD
-                    let host_slice = unsafe {
-                        core::slice::from_raw_parts_mut(
-                            self.addr_gpa2hva(start_phys)?.as_u64() as *mut u8,
-                            segment.mem_size as usize,
-                        )
-                    };
-
-                    host_slice[..segment.file_size as usize].copy_from_slice(
-                        &data[segment.offset as usize
-                            ..(segment.offset + segment.file_size) as usize],
-                    );
+    /// Read the guest virtual memory at `vaddr`, as mapped for vCPU `vcpuid`
+    pub fn read_guest_virt(
+        &self,
+        vcpuid: u8,
+        vaddr: VirtAddr,
+        buf: &mut [u8],
+    ) -> Result<(), Error> {
+        self.mem.read_virt(self.cr3(vcpuid)?, vaddr, buf)
+    }
 
-                    unsafe {
-                        if segment.mem_size > segment.file_size {
-                            core::ptr::write_bytes(
-                                &mut host_slice[segment.file_size as usize] as *mut u8,
-                                0u8,
-                                segment.mem_size as usize - segment.file_size as usize,
-                            );
-                        }
-                    }
-                }
-                ProgramHeader::Ph32(_) => panic!("does not support 32 bit elf files"),
-            }
-        }
+    /// Write the guest virtual memory at `vaddr`, as mapped for vCPU `vcpuid`
+    ///
+    /// Read-only pages are written, too.
+    pub fn write_guest_virt(&self, vcpuid: u8, vaddr: VirtAddr, data: &[u8]) -> Result<(), Error> {
+        self.mem.write_virt(self.cr3(vcpuid)?, vaddr, data)
+    }
 
-        Ok((guest_code, load_addr.unwrap(), phnum))
+    fn cr3(&self, vcpuid: u8) -> Result<u64, Error> {
+        let sregs = self.cpu_fd[vcpuid as usize]
+            .get_sregs()
+            .map_err(|e| ErrorKind::from(&e))?;
+        Ok(sregs.cr3)
     }
 
     fn write_gdt_table(&self, table: &[u64]) -> Result<(), Error> {
@@ -425,30 +408,9 @@ impl KvmVm {
         &mut self,
         vcpuid: u8,
         guest_code: VirtAddr,
-        elf_code: VirtAddr,
-        elf_phdr: VirtAddr,
-        elf_phnum: usize,
+        boot_info: PhysAddr,
     ) -> Result<(), Error> {
-        let syscall_vaddr = PhysAddr::new(SYSCALL_PHYS_ADDR);
-
-        self.syscall_hostvaddr = Some(self.addr_gpa2hva(syscall_vaddr)?);
-
-        let mut boot_info = BootInfo {
-            memory_map: self.memory_map.clone(),
-            entry_point: elf_code.as_ptr(),
-            load_addr: elf_phdr.as_ptr(),
-            elf_phnum: elf_phnum,
-            syscall_trigger_port: SYSCALL_TRIGGER_PORT,
-        };
-
-        boot_info.memory_map.sort();
-        // Write boot info to syscall page.
-        unsafe {
-            self.syscall_hostvaddr
-                .unwrap()
-                .as_mut_ptr::<BootInfo>()
-                .write(boot_info)
-        };
+        self.syscall_hostvaddr = Some(self.addr_gpa2hva(boot_info)?);
 
         /* Create VCPU */
         self.vcpu_add(vcpuid)?;
@@ -459,7 +421,11 @@ impl KvmVm {
             .map_err(|e| ErrorKind::from(&e))?;
         regs.rflags |= 0x2;
         regs.rip = guest_code.as_u64();
-        regs.rdi = syscall_vaddr.as_u64();
+        regs.rdi = boot_info.as_u64();
+        // SEV-ES boot flag for `_setup_pto`, as the kernel can't use cpuid yet
+        if self.mem.ghcb_mode() == GhcbMode::Vmgexit {
+            regs.rsi = 1;
+        }
 
         self.cpu_fd[vcpuid as usize]
             .set_regs(&regs)
@@ -484,41 +450,13 @@ impl KvmVm {
 
             reply.write_volatile(match request.read_volatile() {
                 VmSyscall::Write { fd, count, data } => match fd {
-                    1 => {
-                        let mut count: usize = count;
-                        if count > 4000 {
-                            count = 4000;
-                        }
-                        VmSyscallRet::Write(
-                            std::io::stdout()
-                                .write_all(&data[..count])
-                                .map(|_| count as _)
-                                .map_err(|e| {
-                                    vmsyscall::Error::Errno(
-                                        e.raw_os_error()
-                                            .unwrap_or(Into::<i64>::into(ErrNo::EBADF) as _)
-                                            .into(),
-                                    )
-                                }),
-                        )
-                    }
-                    2 => {
-                        let mut count: usize = count;
-                        if count > 4000 {
-                            count = 4000;
+                    1 | 2 => {
+                        let data = &data[..count.min(WRITE_BUF_LEN)];
+                        let ret = host_write(fd as _, data);
+                        if let (Some(marker), Ok(len)) = (self.marker.as_mut(), &ret) {
+                            self.marker_reached |= marker.find(&data[..*len as usize]);
                         }
-                        VmSyscallRet::Write(
-                            std::io::stderr()
-                                .write_all(&data[..count])
-                                .map(|_| count as _)
-                                .map_err(|e| {
-                                    vmsyscall::Error::Errno(
-                                        e.raw_os_error()
-                                            .unwrap_or(Into::<i64>::into(ErrNo::EBADF) as _)
-                                            .into(),
-                                    )
-                                }),
-                        )
+                        VmSyscallRet::Write(ret)
                     }
                     _ => VmSyscallRet::Write(Err(vmsyscall::Error::Errno(ErrNo::EBADF.into()))),
                 },
@@ -594,11 +532,214 @@ impl KvmVm {
                     length: _,
                     prot: _,
                 } => VmSyscallRet::Mprotect(Err(vmsyscall::Error::Errno(ErrNo::ENOSYS.into()))),
+                VmSyscall::GetSecret => VmSyscallRet::GetSecret(
+                    self.secret
+                        .ok_or_else(|| vmsyscall::Error::Errno(ErrNo::ENOENT.into())),
+                ),
+                VmSyscall::Open { path, len, flags } => {
+                    VmSyscallRet::Open(self.files.open(&path[..len.min(path.len())], flags))
+                }
+                VmSyscall::Pread { fd, count, offset } => {
+                    VmSyscallRet::Pread(self.files.pread(fd, count, offset))
+                }
+                VmSyscall::Fstat { fd } => VmSyscallRet::Fstat(self.files.fstat(fd)),
+                VmSyscall::Close { fd } => VmSyscallRet::Close(self.files.close(fd)),
+                VmSyscall::Socket {
+                    domain,
+                    ty,
+                    protocol,
+                } => VmSyscallRet::Socket(self.sockets.socket_create(domain, ty, protocol)),
+                VmSyscall::Socketpair {
+                    domain,
+                    ty,
+                    protocol,
+                } => VmSyscallRet::Socketpair(self.sockets.socketpair(domain, ty, protocol)),
+                VmSyscall::Bind { fd, addr } => VmSyscallRet::Bind(self.sockets.bind(fd, &addr)),
+                VmSyscall::Listen { fd, backlog } => {
+                    VmSyscallRet::Listen(self.sockets.listen(fd, backlog))
+                }
+                VmSyscall::Accept { fd, nonblock } => {
+                    VmSyscallRet::Accept(self.sockets.accept(fd, nonblock))
+                }
+                VmSyscall::Connect { fd, addr, nonblock } => {
+                    VmSyscallRet::Connect(self.sockets.connect(fd, &addr, nonblock))
+                }
+                VmSyscall::Sendto {
+                    fd,
+                    count,
+                    data,
+                    flags,
+                    addr,
+                    nonblock,
+                } => VmSyscallRet::Sendto(self.sockets.sendto(
+                    fd,
+                    &data[..count.min(SOCKET_BUF_LEN)],
+                    flags,
+                    addr.as_ref(),
+                    nonblock,
+                )),
+                VmSyscall::Recvfrom {
+                    fd,
+                    count,
+                    flags,
+                    nonblock,
+                } => VmSyscallRet::Recvfrom(self.sockets.recvfrom(fd, count, flags, nonblock)),
+                VmSyscall::Getsockname { fd } => {
+                    VmSyscallRet::Getsockname(self.sockets.getsockname(fd))
+                }
+                VmSyscall::Getpeername { fd } => {
+                    VmSyscallRet::Getpeername(self.sockets.getpeername(fd))
+                }
+                VmSyscall::Setsockopt {
+                    fd,
+                    level,
+                    name,
+                    value,
+                    len,
+                } => VmSyscallRet::Setsockopt(self.sockets.setsockopt(
+                    fd,
+                    level,
+                    name,
+                    &value[..(len as usize).min(SOCKOPT_LEN)],
+                )),
+                VmSyscall::Getsockopt {
+                    fd,
+                    level,
+                    name,
+                    len,
+                } => VmSyscallRet::Getsockopt(self.sockets.getsockopt(fd, level, name, len)),
+                VmSyscall::Shutdown { fd, how } => {
+                    VmSyscallRet::Shutdown(self.sockets.shutdown(fd, how))
+                }
+                VmSyscall::CloseSocket { fd } => VmSyscallRet::CloseSocket(self.sockets.close(fd)),
+                VmSyscall::Poll { fds, nfds, timeout } => VmSyscallRet::Poll(poll::poll(
+                    &self.sockets,
+                    &fds[..nfds.min(MAX_POLL_FDS)],
+                    timeout,
+                )),
+                VmSyscall::Trace {
+                    nr,
+                    args,
+                    ret,
+                    len,
+                    line,
+                } => VmSyscallRet::Trace(self.tracer.syscall(
+                    nr,
+                    args,
+                    ret,
+                    &line[..len.min(TRACE_LINE_LEN)],
+                )),
+                VmSyscall::Log { level, len, msg } => {
+                    VmSyscallRet::Log(self.kernel_log.syscall(level, &msg[..len.min(LOG_MSG_LEN)]))
+                }
             });
         }
         Ok(())
     }
 
+    /// Handle a request of the software GHCB protocol
+    ///
+    /// `cpuid` and MSR requests are answered here. An `out` is returned, to be
+    /// handled like a `VcpuExit::IoOut`.
+    pub fn handle_ghcb(&mut self, vcpuid: u8) -> Result<Option<GhcbIoOut>, Error> {
+        let ghcb: &mut Ghcb = unsafe {
+            &mut *self
+                .addr_gpa2hva(PhysAddr::new(GHCB_PHYS_ADDR))?
+                .as_mut_ptr::<Ghcb>()
+        };
+        let exit_code = ghcb.sw_exit_code();
+        let invalid = || context!(ErrorKind::GhcbInvalidRequest(exit_code));
+        let mut io_out = None;
+
+        match exit_code {
+            SVM_EXIT_IOIO => {
+                let (port, size, is_in) = ioio_decode(ghcb.sw_exit_info_1());
+                if ![1, 2, 4].contains(&size) {
+                    return Err(invalid());
+                }
+                if is_in {
+                    // No input ports are emulated, read like an unconnected ISA bus
+                    ghcb.set_rax(!0);
+                } else {
+                    let value = ghcb.rax().ok_or_else(invalid)?;
+                    io_out = Some(GhcbIoOut {
+                        port,
+                        data: (value as u32).to_le_bytes(),
+                        size: size as usize,
+                    });
+                }
+            }
+            SVM_EXIT_CPUID => {
+                let leaf = ghcb.rax().ok_or_else(invalid)? as u32;
+                let index = ghcb.rcx().unwrap_or(0) as u32;
+                let cpuid = self.cpuid.as_ref().ok_or_else(invalid)?;
+
+                let (eax, mut ebx, mut ecx, edx) = cpuid
+                    .as_slice()
+                    .iter()
+                    .find(|e| {
+                        e.function == leaf
+                            && (e.flags & KVM_CPUID_FLAG_SIGNIFCANT_INDEX == 0 || e.index == index)
+                    })
+                    .map_or((0, 0, 0, 0), |e| (e.eax, e.ebx, e.ecx, e.edx));
+
+                // The dynamic bits KVM would compute for the guest
+                if leaf == 1 {
+                    let sregs = self.cpu_fd[vcpuid as usize]
+                        .get_sregs()
+                        .map_err(|e| ErrorKind::from(&e))?;
+                    if sregs.cr4 & X86_CR4_OSXSAVE as u64 != 0 {
+                        ecx |= 1 << 27;
+                    } else {
+                        ecx &= !(1 << 27);
+                    }
+                }
+                if leaf == 0xD && index == 0 {
+                    if let Some(xcr0) = ghcb.xcr0() {
+                        ebx = xsave_size(cpuid, xcr0);
+                    }
+                }
+
+                ghcb.set_rax(eax as u64);
+                ghcb.set_rbx(ebx as u64);
+                ghcb.set_rcx(ecx as u64);
+                ghcb.set_rdx(edx as u64);
+            }
+            SVM_EXIT_MSR => {
+                let index = ghcb.rcx().ok_or_else(invalid)? as u32;
+                let vcpu = &self.cpu_fd[vcpuid as usize];
+
+                if ghcb.sw_exit_info_1() == 1 {
+                    let lo = ghcb.rax().ok_or_else(invalid)?;
+                    let hi = ghcb.rdx().ok_or_else(invalid)?;
+                    let msrs = Msrs::from_entries(&[kvm_msr_entry {
+                        index,
+                        data: (lo & 0xFFFF_FFFF) | (hi << 32),
+                        ..Default::default()
+                    }]);
+                    if vcpu.set_msrs(&msrs).map_err(|e| ErrorKind::from(&e))? != 1 {
+                        return Err(invalid());
+                    }
+                } else {
+                    let mut msrs = Msrs::from_entries(&[kvm_msr_entry {
+                        index,
+                        ..Default::default()
+                    }]);
+                    if vcpu.get_msrs(&mut msrs).map_err(|e| ErrorKind::from(&e))? != 1 {
+                        return Err(invalid());
+                    }
+                    let data = msrs.as_slice()[0].data;
+                    ghcb.set_rax(data & 0xFFFF_FFFF);
+                    ghcb.set_rdx(data >> 32);
+                }
+            }
+            _ => return Err(invalid()),
+        }
+
+        ghcb.set_result(0, 0);
+        Ok(io_out)
+    }
+
     fn create_irqchip(&mut self) -> Result<(), Error> {
         self.kvm_fd
             .create_irq_chip()
@@ -616,32 +757,393 @@ impl KvmVm {
         Ok(())
     }
 
-    pub fn vm_create_default(kernel_name: &str, elf_name: &str, vcpuid: u8) -> Result<Self, Error> {
+    /// The SEV launch measurement, if the VM was launched with SEV
+    pub fn launch_measurement(&self) -> Option<LaunchMeasurement> {
+        self.sev.as_ref().and_then(|sev| sev.measurement())
+    }
+
+    /// Encrypt and measure all loaded regions and finish the SEV launch.
+    ///
+    /// The launch measurement is printed. Then the key of the sealed secret is
+    /// written to the launch secret page: a `SecretKey::Host` directly for a
+    /// guest without SEV, the `SecretKey::Packet` of the guest owner with
+    /// LAUNCH_SECRET.
+    fn sev_launch(&mut self, key: Option<&SecretKey>) -> Result<(), Error> {
+        let mut sev = match self.sev.take() {
+            Some(sev) => sev,
+            None => {
+                // Without SEV there is no measurement and nothing to encrypt
+                match key {
+                    Some(SecretKey::Host(secret)) => {
+                        let data = secret.as_bytes();
+                        self.mem.launch_secret_page()?[..data.len()].copy_from_slice(data);
+                    }
+                    Some(SecretKey::Packet(_)) => {
+                        return Err(context!(ErrorKind::Str(
+                            "a LAUNCH_SECRET packet needs an SEV guest"
+                        )))
+                    }
+                    None => {}
+                }
+                return Ok(());
+            }
+        };
+
+        sev.launch_start(sev_policy(sev.is_es()))?;
+
+        for chunk in launch_chunks(self.mem.loaded_regions()) {
+            let data = unsafe { self.mem.host_slice(&chunk)? };
+            sev.launch_update_data(data)?;
+        }
+
+        if sev.is_es() {
+            sev.launch_update_vmsa()?;
+        }
+
+        let measurement = sev.launch_measure()?;
+        eprintln!("Hypervisor: SEV launch measurement {}", measurement);
+
+        match key {
+            Some(SecretKey::Packet(path)) => {
+                let bytes = std::fs::read(path).map_err(|e| ErrorKind::from(&e))?;
+                let packet = SecretPacket::from_bytes(&bytes)?;
+                sev.launch_secret(&packet, self.mem.launch_secret_page()?)?;
+            }
+            Some(SecretKey::Host(_)) => {
+                return Err(context!(ErrorKind::Str(
+                    "the host must not know the secret key of an SEV guest"
+                )))
+            }
+            None => {}
+        }
+
+        sev.launch_finish()?;
+
+        self.sev.replace(sev);
+        Ok(())
+    }
+
+    pub fn vm_create_default(
+        kernel: &[u8],
+        app: &[u8],
+        vcpuid: u8,
+        config: &VmConfig,
+    ) -> Result<Self, Error> {
         /* Create VM */
-        let mut vm = KvmVm::vm_create((DEFAULT_GUEST_MEM / DEFAULT_GUEST_PAGE_SIZE as u64) as _)?;
+        let mem_size = config.mem_size.unwrap_or(DEFAULT_GUEST_MEM);
+        let mut vm = KvmVm::vm_create((mem_size / DEFAULT_GUEST_PAGE_SIZE as u64) as _, config)?;
+
+        if !config.args.is_empty() || !config.env.is_empty() {
+            vm.mem.set_args(&config.args, &config.env);
+        }
+
+        if let Some(initrd) = &config.initrd {
+            vm.mem.set_initrd(initrd.clone())?;
+        }
+
+        if let Some(size) = config.tmpfs_size {
+            vm.mem.set_tmpfs(size, DEFAULT_TMPFS_INODES);
+        }
+
+        vm.mem.set_syscall_policy(config.syscall_policy);
+        vm.mem.set_log_level(config.log_level);
 
         /* Setup IRQ Chip */
         vm.create_irqchip()?;
 
-        /* Setup app guest code */
-        let (elf_code, elf_phdr, elf_phnum) = vm.elf_load(elf_name, MemoryRegionType::App)?;
-
-        /* Setup kernel guest code */
-        let (guest_code, _, _) = vm.elf_load(kernel_name, MemoryRegionType::Kernel)?;
+        /* Setup app and kernel guest code and the boot info */
+        let entry = vm.mem.load_default(kernel, app)?;
 
         /* Add the first vCPU. */
-        vm.vcpu_add_default(vcpuid, guest_code, elf_code, elf_phdr, elf_phnum)?;
+        vm.vcpu_add_default(vcpuid, entry.kernel_entry, entry.boot_info)?;
+        vm.vcpu_set_cpuid(vcpuid)?;
+
+        /* The sealed secret for the guest and the key to open it */
+        let key = match (&config.secret, &config.secret_packet) {
+            (Some(_), Some(_)) => {
+                return Err(context!(ErrorKind::Str(
+                    "a secret of the host and of the guest owner"
+                )))
+            }
+            (Some(_), None) if vm.sev.is_some() => {
+                return Err(context!(ErrorKind::Str(
+                    "a secret for an SEV guest needs the guest owner's packet"
+                )))
+            }
+            (Some(secret), None) => {
+                // A fresh key, so the zero nonce is used only once
+                let key = host_secret_key()?;
+                let blob = SecretBlob::seal(&key, &[0; NONCE_LEN], secret)
+                    .map_err(|_| context!(ErrorKind::SecretTooLong))?;
+                vm.secret.replace(blob);
+                Some(SecretKey::Host(LaunchSecret::new(key)))
+            }
+            (None, Some(packet)) => {
+                let path = config.sealed_secret.as_ref().ok_or_else(|| {
+                    context!(ErrorKind::Str(
+                        "a LAUNCH_SECRET packet needs its sealed secret"
+                    ))
+                })?;
+                vm.secret.replace(read_sealed_secret(path)?);
+                Some(SecretKey::Packet(packet.clone()))
+            }
+            (None, None) => None,
+        };
 
-        /* Set CPUID */
-        let cpuid = vm
+        /* Encrypt and measure the initial guest memory */
+        vm.sev_launch(key.as_ref())?;
+
+        Ok(vm)
+    }
+
+    fn vcpu_set_cpuid(&mut self, vcpuid: u8) -> Result<(), Error> {
+        let mut cpuid = self
             .kvm
             .get_supported_cpuid(KVM_MAX_CPUID_ENTRIES)
             .map_err(|e| ErrorKind::from(&e))?;
 
-        vm.cpu_fd[vcpuid as usize]
+        // The kernel reads the SEV status MSR, if the SEV CPUID leaf is present
+        if self.sev.is_none() {
+            for entry in cpuid.as_mut_slice() {
+                if entry.function == 0x8000_001F {
+                    entry.eax = 0;
+                    entry.ebx = 0;
+                }
+            }
+        }
+
+        self.cpu_fd[vcpuid as usize]
             .set_cpuid2(&cpuid)
             .map_err(|e| ErrorKind::from(&e))?;
+        self.cpuid.replace(cpuid);
+        Ok(())
+    }
 
-        Ok(vm)
+    /// The app wrote the `snapshot_at` marker of the `VmConfig`
+    pub fn marker_reached(&self) -> bool {
+        self.marker_reached
+    }
+
+    /// Write a snapshot of the stopped VM to `path`, with the `kernel` ELF it
+    /// was started with
+    ///
+    /// The `out` of the last exit is completed first, so the guest runs on
+    /// after it when restored.
+    pub fn snapshot(&mut self, path: &Path, kernel: &[u8]) -> Result<(), Error> {
+        if self.sev.is_some() {
+            return Err(context!(ErrorKind::Str(
+                "the state of an SEV guest is encrypted"
+            )));
+        }
+        if !self.sockets.is_empty() {
+            return Err(context!(ErrorKind::Str(
+                "the sockets of the guest can not be saved"
+            )));
+        }
+
+        // KVM skips the `out` instruction on the next run, stop right after it
+        for vcpu in &self.cpu_fd {
+            gdb::set_guest_debug(vcpu, Resume::Step)?;
+            let exit = vcpu.run().map_err(|e| ErrorKind::from(&e))?;
+            let stopped = matches!(exit, VcpuExit::Debug { .. });
+            gdb::set_guest_debug(vcpu, Resume::Detach)?;
+            if !stopped {
+                return Err(context!(ErrorKind::Str(
+                    "the vCPU did not stop for the snapshot"
+                )));
+            }
+        }
+
+        let mut irqchips = Vec::new();
+        for &chip_id in &[
+            KVM_IRQCHIP_PIC_MASTER,
+            KVM_IRQCHIP_PIC_SLAVE,
+            KVM_IRQCHIP_IOAPIC,
+        ] {
+            let mut irqchip = kvm_irqchip {
+                chip_id,
+                ..Default::default()
+            };
+            self.kvm_fd
+                .get_irqchip(&mut irqchip)
+                .map_err(|e| ErrorKind::from(&e))?;
+            irqchips.push(irqchip);
+        }
+
+        let (app, interp) = self.mem.elf_blobs();
+        let snapshot = Snapshot {
+            regions: self
+                .mem
+                .regions()
+                .iter()
+                .map(|region| Region {
+                    slot: region.slot,
+                    guest_phys_addr: region.guest_phys_addr.as_u64(),
+                    size: region.size as _,
+                })
+                .collect(),
+            irqchips,
+            pit: self.kvm_fd.get_pit2().map_err(|e| ErrorKind::from(&e))?,
+            vcpus: self
+                .cpu_fd
+                .iter()
+                .map(VcpuState::get)
+                .collect::<Result<_, _>>()?,
+            kernel: kernel.to_vec(),
+            app,
+            interp,
+            secret: self.secret,
+            files: self.files.open_paths(),
+        };
+
+        let file = File::create(path).map_err(|e| ErrorKind::from(&e))?;
+        let mut out = BufWriter::new(file);
+        snapshot.write(&mut out).map_err(|e| ErrorKind::from(&e))?;
+        for region in self.mem.regions() {
+            let mem =
+                unsafe { std::slice::from_raw_parts(region.host_mem.as_ptr::<u8>(), region.size) };
+            out.write_all(mem).map_err(|e| ErrorKind::from(&e))?;
+        }
+        out.flush().map_err(|e| ErrorKind::from(&e))?;
+        Ok(())
+    }
+
+    /// Create a VM from the snapshot at `path`, returns it with the kernel ELF
+    /// of the snapshot
+    ///
+    /// The snapshot replaces the guest related settings of `config`, its
+    /// trace, kernel log, socket and snapshot settings are used.
+    pub fn restore(path: &Path, config: &VmConfig) -> Result<(Self, Vec<u8>), Error> {
+        if config.sev || config.sev_es {
+            return Err(context!(ErrorKind::Str("an SEV guest can not be restored")));
+        }
+
+        let file = File::open(path).map_err(|e| ErrorKind::from(&e))?;
+        let mut input = BufReader::new(file);
+        let snapshot = Snapshot::read(&mut input).map_err(|e| ErrorKind::from(&e))?;
+
+        let mut vm = KvmVm::vm_create(0, config)?;
+
+        let page_size = vm.mem.page_size() as u64;
+        for region in &snapshot.regions {
+            if region.size % page_size != 0 {
+                return Err(context!(ErrorKind::Str(
+                    "a memory region of the snapshot is not page aligned"
+                )));
+            }
+            vm.vm_userspace_mem_region_add(
+                PhysAddr::new(region.guest_phys_addr),
+                region.slot,
+                region.size / page_size,
+                0,
+            )?;
+        }
+
+        vm.create_irqchip()?;
+
+        // Adding a vCPU writes the boot GDT, the memory of the snapshot is
+        // read after
+        for vcpuid in 0..snapshot.vcpus.len() {
+            vm.vcpu_add(vcpuid as _)?;
+            vm.vcpu_set_cpuid(vcpuid as _)?;
+        }
+
+        for region in vm.mem.regions() {
+            let mem = unsafe {
+                std::slice::from_raw_parts_mut(region.host_mem.as_mut_ptr::<u8>(), region.size)
+            };
+            input.read_exact(mem).map_err(|e| ErrorKind::from(&e))?;
+        }
+
+        for (vcpu, state) in vm.cpu_fd.iter().zip(&snapshot.vcpus) {
+            state.set(vcpu)?;
+        }
+        for irqchip in &snapshot.irqchips {
+            vm.kvm_fd
+                .set_irqchip(irqchip)
+                .map_err(|e| ErrorKind::from(&e))?;
+        }
+        vm.kvm_fd
+            .set_pit2(&snapshot.pit)
+            .map_err(|e| ErrorKind::from(&e))?;
+
+        vm.syscall_hostvaddr = Some(vm.addr_gpa2hva(PhysAddr::new(SYSCALL_PHYS_ADDR))?);
+        vm.secret = snapshot.secret;
+        vm.files
+            .reopen(&snapshot.files)
+            .map_err(|e| ErrorKind::from(&e))?;
+        vm.mem.set_elf_blobs(snapshot.app, snapshot.interp);
+
+        Ok((vm, snapshot.kernel))
+    }
+}
+
+/// Where the key in the launch secret page comes from
+enum SecretKey {
+    /// A key of the host for a guest without SEV
+    Host(LaunchSecret),
+    /// The file of the guest owner's LAUNCH_SECRET packet
+    Packet(PathBuf),
+}
+
+/// A random key for a secret sealed by the host
+fn host_secret_key() -> Result<[u8; KEY_LEN], Error> {
+    let mut key = [0u8; KEY_LEN];
+    File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut key))
+        .map_err(|e| ErrorKind::from(&e))?;
+    Ok(key)
+}
+
+/// Read a `SecretBlob` file of the guest owner
+fn read_sealed_secret(path: &Path) -> Result<SecretBlob, Error> {
+    let bytes = std::fs::read(path).map_err(|e| ErrorKind::from(&e))?;
+    let mut blob = [0u8; SECRET_BLOB_LEN];
+    if bytes.len() != blob.len() {
+        return Err(context!(ErrorKind::Str("invalid sealed secret")));
+    }
+    blob.copy_from_slice(&bytes);
+    SecretBlob::from_bytes(&blob).map_err(|_| context!(ErrorKind::SecretTooLong))
+}
+
+/// Write the raw bytes of the guest to the host `fd`, unbuffered
+///
+/// Returns the number of bytes written, which may be less than `data` for a
+/// pipe or a non-blocking `fd`, and `EAGAIN` if nothing could be written.
+fn host_write(fd: RawFd, data: &[u8]) -> Result<i32, vmsyscall::Error> {
+    // Borrow the fd, it must not be closed on drop
+    let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    loop {
+        match file.write(data) {
+            Ok(len) => return Ok(len as _),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                return Err(vmsyscall::Error::Errno(
+                    e.raw_os_error()
+                        .unwrap_or(Into::<i64>::into(ErrNo::EIO) as _)
+                        .into(),
+                ))
+            }
+        }
+    }
+}
+
+/// The size of the XSAVE area for the features enabled in `xcr0`
+fn xsave_size(cpuid: &CpuId, xcr0: u64) -> u32 {
+    // legacy area and XSAVE header
+    let mut size = 512 + 64;
+    for feature in 2..63 {
+        if xcr0 & (1 << feature) == 0 {
+            continue;
+        }
+        if let Some(e) = cpuid
+            .as_slice()
+            .iter()
+            .find(|e| e.function == 0xD && e.index == feature)
+        {
+            // eax: size, ebx: offset of the feature state
+            size = size.max(e.ebx + e.eax);
+        }
     }
+    size
 }
@@ -1,5 +1,20 @@
+pub mod backtrace;
+pub mod bundle;
+pub mod core_dump;
 pub mod error;
+pub mod gdb;
+pub mod guest;
+pub mod kernel_log;
+#[cfg(test)]
+mod keybroker;
 pub mod kvmvm;
+pub mod measure;
+pub mod poll;
 pub use error::*;
 pub mod arch;
+pub mod sev;
+pub mod snapshot;
+pub mod socket;
+pub mod sysroot;
+pub mod trace;
 //pub mod device_manager;
@@ -2,33 +2,635 @@ use kvm_ioctls::{Kvm, VcpuExit};
 use std::path::Path;
 use std::process::{exit, Command};
 use std::time::Instant;
-use vmrun::kvmvm::{self, SYSCALL_TRIGGER_PORT};
+use vmrun::backtrace;
+use vmrun::bundle::{self, Bundle, Manifest, PUBLIC_KEY_LEN};
+use vmrun::core_dump;
+use vmrun::gdb::{self, GdbStub, Resume};
+use vmrun::guest::{self, DEFAULT_GUEST_MEM, DEFAULT_TMPFS_SIZE};
+use vmrun::kvmvm::{self, KvmVm, VmConfig, SYSCALL_TRIGGER_PORT};
+use vmrun::measure;
+use vmrun::sev::LaunchSession;
+use vmrun::socket;
+use vmsyscall::bootinfo::{LogLevel, SyscallPolicy, UnknownSyscall, MAX_SYSCALL_NR};
+use vmsyscall::ghcb::GHCB_TRIGGER_PORT;
+use vmsyscall::syscalls;
 
 const PORT_QEMU_EXIT: u16 = 0xF4;
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
     let kvm = Kvm::new();
+    let mut config = VmConfig::default();
+
+    if args.len() > 1 && args[1].eq("measure") {
+        main_measure(args.split_off(1));
+    }
+
+    if args.len() > 1 && args[1].eq("pack") {
+        main_pack(&args[2..]);
+    }
+
+    let mut bundle_key = None;
+    let mut any_signer = false;
+    let mut godh_cert = None;
+    let mut session = None;
+    let mut restore = None;
+    let mut syscall_lists = SyscallLists::default();
+
+    while args.len() > 1 {
+        match args[1].as_str() {
+            "--sev" => config.sev = true,
+            "--ghcb-soft" => config.ghcb_software = true,
+            "--secret" if args.len() > 2 => {
+                match std::fs::read(&args[2]) {
+                    Ok(secret) => config.secret.replace(secret),
+                    Err(e) => {
+                        eprintln!("Can't read the secret `{}`: {}", args[2], e);
+                        exit(1);
+                    }
+                };
+                args.remove(1);
+            }
+            "--sev-godh" if args.len() > 2 => {
+                godh_cert.replace(read_file(&args[2], "guest owner's certificate"));
+                args.remove(1);
+            }
+            "--sev-session" if args.len() > 2 => {
+                session.replace(read_file(&args[2], "launch session"));
+                args.remove(1);
+            }
+            "--secret-packet" if args.len() > 2 => {
+                config.secret_packet.replace(args[2].clone().into());
+                args.remove(1);
+            }
+            "--sealed-secret" if args.len() > 2 => {
+                config.sealed_secret.replace(args[2].clone().into());
+                args.remove(1);
+            }
+            "--allow-listen" if args.len() > 2 => {
+                config
+                    .socket_policy
+                    .listen
+                    .push(parse_socket_rule(&args[2]));
+                args.remove(1);
+            }
+            "--allow-connect" if args.len() > 2 => {
+                config
+                    .socket_policy
+                    .connect
+                    .push(parse_socket_rule(&args[2]));
+                args.remove(1);
+            }
+            "--trace-output" if args.len() > 2 => {
+                config.trace_output.replace(args[2].clone().into());
+                args.remove(1);
+            }
+            "--trace-json" if args.len() > 2 => {
+                config.trace_json.replace(args[2].clone().into());
+                args.remove(1);
+            }
+            "--kernel-log" if args.len() > 2 => {
+                config.kernel_log.replace(args[2].clone().into());
+                args.remove(1);
+            }
+            "--gdb" if args.len() > 2 => {
+                config.gdb.replace(args[2].clone());
+                args.remove(1);
+            }
+            "--core-dump" if args.len() > 2 => {
+                config.core_dump.replace(args[2].clone().into());
+                args.remove(1);
+            }
+            "--snapshot" if args.len() > 2 => {
+                config.snapshot.replace(args[2].clone().into());
+                args.remove(1);
+            }
+            "--snapshot-at" if args.len() > 2 => {
+                config.snapshot_at.replace(args[2].clone());
+                args.remove(1);
+            }
+            "--restore" if args.len() > 2 => {
+                restore.replace(args[2].clone());
+                args.remove(1);
+            }
+            "--bundle-key" if args.len() > 2 => {
+                bundle_key.replace(parse_public_key(&args[2]));
+                args.remove(1);
+            }
+            "--insecure-any-signer" => any_signer = true,
+            "--aslr" => {
+                let seed = random_aslr_seed();
+                eprintln!("Hypervisor: ASLR seed {}", seed);
+                config.aslr_seed.replace(seed);
+            }
+            _ => {
+                if parse_guest_option(&mut args, &mut config, &mut syscall_lists) {
+                    continue;
+                }
+                break;
+            }
+        }
+        args.remove(1);
+    }
+
+    set_syscall_lists(
+        &mut config.syscall_policy,
+        syscall_lists.allow.as_deref(),
+        &syscall_lists.deny,
+    );
+
+    match (godh_cert, session) {
+        (Some(godh_cert), Some(session)) => {
+            config
+                .sev_session
+                .replace(LaunchSession { godh_cert, session });
+        }
+        (None, None) => {}
+        _ => {
+            eprintln!("--sev-godh and --sev-session have to be given together");
+            exit(1);
+        }
+    }
+
+    if config.secret_packet.is_some()
+        && (config.sev_session.is_none() || !(config.sev || config.sev_es))
+    {
+        eprintln!(
+            "--secret-packet needs --sev or --sev-es, and the launch session of the guest owner"
+        );
+        exit(1);
+    }
+
+    if config.secret_packet.is_some() != config.sealed_secret.is_some() {
+        eprintln!("--secret-packet and --sealed-secret have to be given together");
+        exit(1);
+    }
+
+    if config.snapshot.is_some() != config.snapshot_at.is_some() {
+        eprintln!("--snapshot and --snapshot-at have to be given together");
+        exit(1);
+    }
 
     match args.len() {
         4..=std::usize::MAX if args[1].eq("--force-qemu") => {
             main_qemu(&args[2], &args[3], &args[4..])
         }
         4..=std::usize::MAX if args[1].eq("--fallback-qemu") => match kvm {
-            Ok(_) => main_kvm(&args[2], &args[3]),
+            Ok(_) => main_kvm(&args[2], &args[3], &config),
             Err(_) => main_qemu(&args[2], &args[3], &args[4..]),
         },
-        3 => main_kvm(&args[1], &args[2]),
+        3 if args[1].eq("run") && restore.is_none() => {
+            main_bundle(&args[2], bundle_key.as_ref(), any_signer, config)
+        }
+        3 if restore.is_none() => main_kvm(&args[1], &args[2], &config),
+        1 if restore.is_some() => main_restore(restore.as_deref().unwrap(), &config),
         _ => {
             eprintln!(
-                "Usage: {} [--fallback-qemu] <elf binary> <kernelblob>",
+                "Usage: {} [--sev | --sev-es | --ghcb-soft] [--secret <file>] [--sev-godh <file> --sev-session <file>] [--secret-packet <file> --sealed-secret <file>] [--aslr | --aslr-seed <seed>] [--sysroot <dir>] [--initrd <file>] [--tmpfs-size <MiB>] [--allow-listen <addr>]... [--allow-connect <addr>]... [--unknown-syscalls enosys|panic|log-and-enosys] [--allow-syscalls <list>]... [--deny-syscalls <list>]... [--trace-syscalls[=<filter>]] [--trace-output <file>] [--trace-json <file>] [--kernel-log <file>] [--log-level <level>] [--gdb <addr>] [--core-dump <file>] [--snapshot <file> --snapshot-at <marker>] [--fallback-qemu] <elf binary> <kernelblob>",
+                args[0],
+            );
+            eprintln!("       {} [<options>] --restore <snapshot>", args[0]);
+            eprintln!(
+                "       {} [<options>] (--bundle-key <public key> | --insecure-any-signer) run <bundle>",
                 args[0],
             );
+            eprintln!(
+                "       {} pack --key <secret key> [--initrd <file>] [--manifest <file>] <elf binary> <kernelblob> <bundle>",
+                args[0],
+            );
+            exit(1);
+        }
+    }
+}
+
+fn main_measure(mut args: Vec<String>) -> ! {
+    let mut config = VmConfig::default();
+    let mut syscall_lists = SyscallLists::default();
+    let mut cbit = None;
+
+    while args.len() > 1 {
+        match args[1].as_str() {
+            // Only reserves the page, the secret itself is not measured
+            "--secret" => {
+                config.secret.replace(Vec::new());
+            }
+            "--sev-cbit" if args.len() > 2 => {
+                cbit.replace(parse_cbit(&args[2]));
+                args.remove(1);
+            }
+            _ => {
+                if parse_guest_option(&mut args, &mut config, &mut syscall_lists) {
+                    continue;
+                }
+                break;
+            }
+        }
+        args.remove(1);
+    }
+
+    set_syscall_lists(
+        &mut config.syscall_policy,
+        syscall_lists.allow.as_deref(),
+        &syscall_lists.deny,
+    );
+
+    let measured = match args.len() {
+        // The digest covers the kernel and the app, whoever signed the bundle
+        2 => {
+            let data = read_file(&args[1], "bundle");
+            let (bundle, public_key) = match Bundle::open(&data, None) {
+                Ok(opened) => opened,
+                Err(e) => {
+                    eprintln!("Can't open the bundle `{}`: {}", args[1], e);
+                    exit(1);
+                }
+            };
+            eprintln!("Bundle signed by {}", to_hex(&public_key));
+            apply_bundle(&bundle, &mut config);
+            check_tmpfs_size(&config);
+            measure::measure_image(&bundle.kernel, &bundle.app, cbit, &config)
+        }
+        3 => {
+            check_tmpfs_size(&config);
+            measure::measure(&args[2], &args[1], cbit, &config)
+        }
+        _ => {
+            eprintln!(
+                "Usage: vmrun measure [--sev-es] [--secret] [--aslr-seed <seed>] [--sysroot <dir>] [--initrd <file>] [--tmpfs-size <MiB>] [--unknown-syscalls <action>] [--allow-syscalls <list>]... [--deny-syscalls <list>]... [--trace-syscalls[=<filter>]] [--log-level <level>] [--sev-cbit <pos>] <elf binary> <kernelblob>"
+            );
+            eprintln!("       vmrun measure [<options>] <bundle>");
+            exit(1);
+        }
+    };
+
+    match measured {
+        Ok(measurement) => {
+            print!("{}", measurement.to_json());
+            exit(0);
+        }
+        Err(e) => {
+            eprintln!("Measuring failed: {:?}", e);
+            exit(1);
+        }
+    }
+}
+
+fn main_pack(args: &[String]) -> ! {
+    let mut key = None;
+    let mut initrd = None;
+    let mut manifest = None;
+    let mut args = args;
+
+    loop {
+        match args {
+            [flag, file, rest @ ..] if flag.eq("--key") => {
+                key.replace(read_file(file, "secret key"));
+                args = rest;
+            }
+            [flag, file, rest @ ..] if flag.eq("--initrd") => {
+                initrd.replace(read_file(file, "initrd"));
+                args = rest;
+            }
+            [flag, file, rest @ ..] if flag.eq("--manifest") => {
+                let text = read_file(file, "manifest");
+                let parsed = std::str::from_utf8(&text)
+                    .map_err(|_| "manifest is not UTF-8".to_string())
+                    .and_then(|text| Manifest::parse(text).map_err(|e| e.to_string()));
+                match parsed {
+                    Ok(parsed) => manifest.replace(parsed),
+                    Err(e) => {
+                        eprintln!("Can't parse the manifest `{}`: {}", file, e);
+                        exit(1);
+                    }
+                };
+                args = rest;
+            }
+            _ => break,
+        }
+    }
+
+    let key = match (key, args.len()) {
+        (Some(key), 3) => key,
+        _ => {
+            eprintln!(
+                "Usage: vmrun pack --key <secret key> [--initrd <file>] [--manifest <file>] <elf binary> <kernelblob> <bundle>"
+            );
+            exit(1);
+        }
+    };
+
+    let bundle = Bundle {
+        kernel: read_file(&args[1], "kernel image"),
+        app: read_file(&args[0], "application elf binary"),
+        initrd,
+        manifest: manifest.unwrap_or_else(|| Manifest::parse("").unwrap()),
+    };
+
+    let packed = match bundle.pack(&key) {
+        Ok(packed) => packed,
+        Err(e) => {
+            eprintln!("Packing failed: {}", e);
+            exit(1);
+        }
+    };
+
+    if let Err(e) = std::fs::write(&args[2], packed) {
+        eprintln!("Can't write the bundle `{}`: {}", args[2], e);
+        exit(1);
+    }
+
+    let public_key = bundle::public_key(&key).unwrap();
+    println!("{}", to_hex(&public_key));
+    exit(0);
+}
+
+fn main_bundle(
+    bundle_file: &str,
+    trusted: Option<&[u8; PUBLIC_KEY_LEN]>,
+    any_signer: bool,
+    mut config: VmConfig,
+) {
+    // Anyone can sign a modified bundle with their own key
+    if trusted.is_none() && !any_signer {
+        eprintln!("`run` needs the --bundle-key of the signer, or --insecure-any-signer");
+        exit(1);
+    }
+
+    let data = read_file(bundle_file, "bundle");
+
+    let (bundle, public_key) = match Bundle::open(&data, trusted) {
+        Ok(opened) => opened,
+        Err(e) => {
+            eprintln!("Can't open the bundle `{}`: {}", bundle_file, e);
+            exit(1);
+        }
+    };
+
+    if trusted.is_none() {
+        eprintln!(
+            "Hypervisor: bundle signed by {}, accepted with --insecure-any-signer",
+            to_hex(&public_key)
+        );
+    }
+
+    apply_bundle(&bundle, &mut config);
+
+    eprintln!("Starting bundle {}", bundle_file);
+
+    run_kvm(&bundle.kernel, &bundle.app, &config);
+}
+
+/// Take the initrd and the manifest settings of `bundle`
+fn apply_bundle(bundle: &Bundle, config: &mut VmConfig) {
+    if bundle.initrd.is_some() {
+        config.initrd = bundle.initrd.clone();
+    }
+
+    config.mem_size = bundle.manifest.mem_size();
+    if let Some(size) = bundle.manifest.tmpfs_size() {
+        config.tmpfs_size.replace(size);
+    }
+    config.args = bundle.manifest.args.clone();
+    config.env = bundle.manifest.env.clone();
+}
+
+/// Refuse a tmpfs the guest memory can't hold, the kernel backs it at boot
+fn check_tmpfs_size(config: &VmConfig) {
+    let size = config.tmpfs_size.unwrap_or(DEFAULT_TMPFS_SIZE);
+    let mem_size = config.mem_size.unwrap_or(DEFAULT_GUEST_MEM);
+    if !guest::tmpfs_fits(size, mem_size) {
+        eprintln!(
+            "A tmpfs of {} MiB does not fit in {} MiB of guest memory",
+            size / (1024 * 1024),
+            mem_size / (1024 * 1024)
+        );
+        exit(1);
+    }
+}
+
+/// The syscall numbers of all `--allow-syscalls` and `--deny-syscalls`
+#[derive(Default)]
+struct SyscallLists {
+    allow: Option<Vec<u64>>,
+    deny: Vec<u64>,
+}
+
+/// Parse an option at `args[1]` that changes the initial guest memory, for
+/// running and measuring a guest alike.
+///
+/// The option and its value are removed from `args`, returns `false` if
+/// `args[1]` is not such an option.
+fn parse_guest_option(
+    args: &mut Vec<String>,
+    config: &mut VmConfig,
+    syscall_lists: &mut SyscallLists,
+) -> bool {
+    match args[1].as_str() {
+        "--sev-es" => config.sev_es = true,
+        "--aslr-seed" if args.len() > 2 => {
+            config.aslr_seed.replace(parse_aslr_seed(&args[2]));
+            args.remove(1);
+        }
+        "--initrd" if args.len() > 2 => {
+            config.initrd.replace(read_file(&args[2], "initrd"));
+            args.remove(1);
+        }
+        "--sysroot" if args.len() > 2 => {
+            config.sysroot.replace(args[2].clone().into());
+            args.remove(1);
+        }
+        "--tmpfs-size" if args.len() > 2 => {
+            config.tmpfs_size.replace(parse_tmpfs_size(&args[2]));
+            args.remove(1);
+        }
+        "--unknown-syscalls" if args.len() > 2 => {
+            config.syscall_policy.unknown = parse_unknown_syscalls(&args[2]);
+            args.remove(1);
+        }
+        "--allow-syscalls" if args.len() > 2 => {
+            syscall_lists
+                .allow
+                .get_or_insert_with(Vec::new)
+                .extend(parse_syscalls(&args[2]));
+            args.remove(1);
+        }
+        "--deny-syscalls" if args.len() > 2 => {
+            syscall_lists.deny.extend(parse_syscalls(&args[2]));
+            args.remove(1);
+        }
+        "--trace-syscalls" => config.syscall_policy.trace_all(),
+        "--log-level" if args.len() > 2 => {
+            config.log_level = parse_log_level(&args[2]);
+            args.remove(1);
+        }
+        flag => match flag.strip_prefix("--trace-syscalls=") {
+            Some(filter) => set_trace_filter(&mut config.syscall_policy, filter),
+            None => return false,
+        },
+    }
+    args.remove(1);
+    true
+}
+
+fn read_file(name: &str, what: &str) -> Vec<u8> {
+    match std::fs::read(name) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Can't read the {} `{}`: {}", what, name, e);
+            exit(1);
+        }
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_public_key(hex: &str) -> [u8; PUBLIC_KEY_LEN] {
+    let mut key = [0u8; PUBLIC_KEY_LEN];
+
+    if hex.len() != PUBLIC_KEY_LEN * 2 || !hex.is_ascii() {
+        eprintln!("Invalid bundle key `{}`", hex);
+        exit(1);
+    }
+
+    for (i, byte) in key.iter_mut().enumerate() {
+        match u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16) {
+            Ok(b) => *byte = b,
+            Err(_) => {
+                eprintln!("Invalid bundle key `{}`", hex);
+                exit(1);
+            }
+        }
+    }
+    key
+}
+
+fn parse_aslr_seed(seed: &str) -> u64 {
+    match seed.parse::<u64>() {
+        Ok(seed) => seed,
+        Err(_) => {
+            eprintln!("Invalid ASLR seed `{}`", seed);
+            exit(1);
+        }
+    }
+}
+
+/// The C-bit position of `--sev-cbit`, below 64
+fn parse_cbit(cbit: &str) -> u32 {
+    match cbit.parse::<u32>() {
+        Ok(cbit) if cbit < 64 => cbit,
+        _ => {
+            eprintln!("Invalid C-bit position `{}`", cbit);
+            exit(1);
+        }
+    }
+}
+
+/// The size of the tmpfs in bytes from MiB, `0` disables it
+fn parse_tmpfs_size(mib: &str) -> u64 {
+    match mib.parse::<u64>() {
+        Ok(mib) if mib <= bundle::MAX_TMPFS_MIB => mib * 1024 * 1024,
+        _ => {
+            eprintln!("Invalid tmpfs size `{}`", mib);
+            exit(1);
+        }
+    }
+}
+
+/// An address of `--allow-listen` or `--allow-connect`
+fn parse_socket_rule(addr: &str) -> socket::Rule {
+    match addr.parse() {
+        Ok(rule) => rule,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+/// The action of `--unknown-syscalls`
+fn parse_unknown_syscalls(action: &str) -> UnknownSyscall {
+    match action {
+        "enosys" => UnknownSyscall::Enosys,
+        "panic" => UnknownSyscall::Panic,
+        "log-and-enosys" => UnknownSyscall::LogAndEnosys,
+        _ => {
+            eprintln!("Invalid action for unknown syscalls `{}`", action);
+            exit(1);
+        }
+    }
+}
+
+/// The level of `--log-level`
+fn parse_log_level(level: &str) -> LogLevel {
+    match LogLevel::from_name(level) {
+        Some(level) => level,
+        None => {
+            eprintln!(
+                "Invalid log level `{}`, one of off, error, warn, info, debug, trace",
+                level
+            );
             exit(1);
         }
     }
 }
 
+/// The comma separated syscall names or numbers of `--allow-syscalls` and
+/// `--deny-syscalls`
+fn parse_syscalls(list: &str) -> Vec<u64> {
+    list.split(',')
+        .filter(|name| !name.is_empty())
+        .map(
+            |name| match syscalls::number(name).or_else(|| name.parse().ok()) {
+                Some(nr) if nr < MAX_SYSCALL_NR as u64 => nr,
+                _ => {
+                    eprintln!("Invalid syscall `{}`", name);
+                    exit(1);
+                }
+            },
+        )
+        .collect()
+}
+
+/// With an allow list all other syscalls are denied, the deny list wins
+///
+/// `SyscallPolicy::is_denied()` never denies `exit` and `exit_group`.
+fn set_syscall_lists(policy: &mut SyscallPolicy, allow: Option<&[u64]>, deny: &[u64]) {
+    if let Some(allow) = allow {
+        policy.deny_all();
+        allow.iter().for_each(|nr| policy.allow(*nr));
+    }
+    deny.iter().for_each(|nr| policy.deny(*nr));
+}
+
+/// The filter of `--trace-syscalls=`, a list of syscalls, after a `!` all
+/// syscalls but the list
+fn set_trace_filter(policy: &mut SyscallPolicy, filter: &str) {
+    match filter.strip_prefix('!') {
+        Some(list) => {
+            policy.trace_all();
+            parse_syscalls(list)
+                .iter()
+                .for_each(|nr| policy.trace(*nr, false));
+        }
+        None => parse_syscalls(filter)
+            .iter()
+            .for_each(|nr| policy.trace(*nr, true)),
+    }
+}
+
+fn random_aslr_seed() -> u64 {
+    use std::io::Read;
+
+    let mut seed = [0u8; 8];
+    if let Err(e) = std::fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut seed)) {
+        eprintln!("Can't read /dev/urandom: {}", e);
+        exit(1);
+    }
+    u64::from_le_bytes(seed)
+}
+
 fn main_qemu(_elf_binary: &str, kernel_blob: &str, extra_args: &[String]) -> ! {
     if !Path::new(kernel_blob).exists() {
         eprintln!("Kernel image `{}` not found!", kernel_blob);
@@ -92,9 +694,7 @@ fn main_qemu(_elf_binary: &str, kernel_blob: &str, extra_args: &[String]) -> ! {
     }
 }
 
-fn main_kvm(elf_blob: &str, kernel_blob: &str) {
-    let start = Instant::now();
-
+fn main_kvm(elf_blob: &str, kernel_blob: &str, config: &VmConfig) {
     if !Path::new(kernel_blob).exists() {
         eprintln!("Kernel image `{}` not found!", kernel_blob);
         exit(1);
@@ -107,7 +707,54 @@ fn main_kvm(elf_blob: &str, kernel_blob: &str) {
 
     eprintln!("Starting {} with {}", kernel_blob, elf_blob);
 
-    let mut kvm = kvmvm::KvmVm::vm_create_default(&kernel_blob, &elf_blob, 0).unwrap();
+    let kernel = read_file(kernel_blob, "kernel image");
+    let app = read_file(elf_blob, "application elf binary");
+    run_kvm(&kernel, &app, config);
+}
+
+fn main_restore(snapshot: &str, config: &VmConfig) {
+    eprintln!("Restoring {}", snapshot);
+
+    let start = Instant::now();
+
+    let (kvm, kernel) = KvmVm::restore(Path::new(snapshot), config).unwrap_or_else(|e| {
+        eprintln!("Can't restore the snapshot `{}`: {:?}", snapshot, e);
+        exit(1);
+    });
+
+    // The app is in the guest memory of the snapshot
+    run_vm(kvm, start, &kernel, &[], config);
+}
+
+fn run_kvm(kernel: &[u8], app: &[u8], config: &VmConfig) {
+    check_tmpfs_size(config);
+
+    let start = Instant::now();
+
+    // Prints the launch measurement before reading the --secret-packet
+    let kvm = kvmvm::KvmVm::vm_create_default(kernel, app, 0, config).unwrap();
+
+    run_vm(kvm, start, kernel, app, config);
+}
+
+/// Run the guest until it exits, `kernel` and `app` are only used for
+/// backtraces
+fn run_vm(mut kvm: KvmVm, start: Instant, kernel: &[u8], app: &[u8], config: &VmConfig) {
+    if config.core_dump.is_some() && (config.sev || config.sev_es) {
+        eprintln!("--core-dump can not read the memory of an encrypted SEV guest");
+        exit(1);
+    }
+    let mut gdb = config.gdb.as_deref().map(|addr| {
+        if config.sev || config.sev_es {
+            eprintln!("--gdb can not debug an encrypted SEV guest");
+            exit(1);
+        }
+        GdbStub::listen(addr).unwrap_or_else(|e| {
+            eprintln!("Can't listen for gdb on `{}`: {}", addr, e);
+            exit(1);
+        })
+    });
+    gdb_stop(&mut kvm, &mut gdb, gdb::SIGTRAP);
 
     loop {
         let ret = kvm
@@ -117,30 +764,20 @@ fn main_kvm(elf_blob: &str, kernel_blob: &str) {
             .run()
             .expect("Hypervisor: VM run failed");
 
-        match ret {
-            VcpuExit::IoOut(port, data) => match port {
-                // Qemu exit simulation
-                PORT_QEMU_EXIT if data.eq(&[0x10, 0, 0, 0]) => {
-                    let elapsed = start.elapsed();
-                    eprintln!("Hypervisor: Creating and running took {:?}", elapsed);
-                    std::process::exit(0);
-                }
-                PORT_QEMU_EXIT if data.eq(&[0x11, 0, 0, 0]) => {
-                    std::process::exit(1);
-                }
-                SYSCALL_TRIGGER_PORT => {
-                    if let Err(e) = kvm.handle_syscall() {
-                        panic!("Handle syscall: {:#?}", e);
-                    }
-                }
-                _ => {
-                    let regs = kvm.cpu_fd.get(0).unwrap().get_regs().unwrap();
-                    panic!(
-                        "Hypervisor: Unexpected IO port {:#X} {:#?}!\n{:#?}",
-                        port, data, regs
-                    )
-                }
+        let code = match ret {
+            VcpuExit::IoOut(GHCB_TRIGGER_PORT, _) => match kvm.handle_ghcb(0) {
+                Ok(Some(io_out)) => handle_io_out(&mut kvm, &start, io_out.port, io_out.data()),
+                Ok(None) => None,
+                Err(e) => panic!("Handle GHCB: {:#?}", e),
             },
+            VcpuExit::IoOut(port, data) => {
+                let data = data.to_vec();
+                handle_io_out(&mut kvm, &start, port, &data)
+            }
+            VcpuExit::Debug { .. } if gdb.is_some() => {
+                gdb_stop(&mut kvm, &mut gdb, gdb::SIGTRAP);
+                None
+            }
             VcpuExit::Hlt => {
                 let elapsed = start.elapsed();
                 eprintln!("Hypervisor: VcpuExit::Hlt");
@@ -153,9 +790,114 @@ fn main_kvm(elf_blob: &str, kernel_blob: &str) {
                     "Hypervisor: unexpected exit reason: {:?}\n{:#?}",
                     exit_reason, regs
                 );
-                std::process::exit(1);
+                // the guest can not run on, but gdb can look at it
+                gdb_stop(&mut kvm, &mut gdb, gdb::SIGSEGV);
+                Some(1)
+            }
+        };
+        let code = match code {
+            None if kvm.marker_reached() => Some(take_snapshot(&mut kvm, kernel, config)),
+            code => code,
+        };
+        if let Some(code) = code {
+            if code != 0 {
+                print_backtrace(&kvm, kernel, app, config);
+                write_core_dump(&kvm, config);
+            }
+            if let Some(gdb) = gdb.as_mut() {
+                let _ = gdb.exited(code);
             }
+            exit(code);
         }
     }
+    if let Some(gdb) = gdb.as_mut() {
+        let _ = gdb.exited(0);
+    }
     eprintln!("Hypervisor: Done");
 }
+
+/// Serve gdb while the guest is stopped with `signal`
+fn gdb_stop(kvm: &mut KvmVm, gdb: &mut Option<GdbStub>, signal: u8) {
+    let stub = match gdb.as_mut() {
+        Some(stub) => stub,
+        None => return,
+    };
+    let resume = stub.stop(kvm, signal).unwrap_or_else(|e| {
+        eprintln!("Hypervisor: gdb disconnected: {}", e);
+        Resume::Detach
+    });
+    match resume {
+        Resume::Kill => exit(1),
+        Resume::Detach => {
+            gdb.take();
+        }
+        Resume::Continue | Resume::Step => {}
+    }
+    if let Err(e) = gdb::set_guest_debug(&kvm.cpu_fd[0], resume) {
+        panic!("Hypervisor: KVM_SET_GUEST_DEBUG: {:#?}", e);
+    }
+}
+
+/// Print the backtrace of the failed guest, the memory of SEV guests is encrypted
+fn print_backtrace(kvm: &KvmVm, kernel: &[u8], app: &[u8], config: &VmConfig) {
+    if config.sev || config.sev_es {
+        return;
+    }
+    match backtrace::backtrace(kvm, 0) {
+        Ok(frames) => {
+            let symbols = backtrace::guest_symbols(kvm, kernel, app);
+            eprint!(
+                "Hypervisor: backtrace of the guest\n{}",
+                backtrace::format(&frames, &symbols)
+            );
+        }
+        Err(e) => eprintln!("Hypervisor: Can't walk the guest stack: {:?}", e),
+    }
+}
+
+/// Write the `--core-dump` of the failed guest
+fn write_core_dump(kvm: &KvmVm, config: &VmConfig) {
+    if let Some(path) = config.core_dump.as_deref() {
+        match core_dump::write(kvm, path, gdb::SIGSEGV) {
+            Ok(()) => eprintln!("Hypervisor: core dumped to `{}`", path.display()),
+            Err(e) => eprintln!("Hypervisor: Can't write the core dump: {:?}", e),
+        }
+    }
+}
+
+/// Write the `--snapshot` of the guest, which output the `--snapshot-at` marker
+fn take_snapshot(kvm: &mut KvmVm, kernel: &[u8], config: &VmConfig) -> i32 {
+    let path = config.snapshot.as_deref().unwrap();
+    if let Err(e) = kvm.snapshot(path, kernel) {
+        eprintln!("Hypervisor: Can't write the snapshot: {:?}", e);
+        exit(1);
+    }
+    eprintln!("Hypervisor: snapshot written to `{}`", path.display());
+    0
+}
+
+/// Handle an `out` of the guest, the exit code if the guest is done
+fn handle_io_out(kvm: &mut KvmVm, start: &Instant, port: u16, data: &[u8]) -> Option<i32> {
+    match port {
+        // Qemu exit simulation
+        PORT_QEMU_EXIT if data.eq(&[0x10, 0, 0, 0]) => {
+            let elapsed = start.elapsed();
+            eprintln!("Hypervisor: Creating and running took {:?}", elapsed);
+            Some(0)
+        }
+        PORT_QEMU_EXIT if data.eq(&[0x11, 0, 0, 0]) => Some(1),
+        SYSCALL_TRIGGER_PORT => {
+            if let Err(e) = kvm.handle_syscall() {
+                panic!("Handle syscall: {:#?}", e);
+            }
+            None
+        }
+        _ => {
+            let regs = kvm.cpu_fd.get(0).unwrap().get_regs().unwrap();
+            panic!(
+                "Hypervisor: Unexpected IO port {:#X} {:#?}!\n{:#?}",
+                port, data, regs
+            )
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Offline computation of the SEV launch digest
+//!
+//! The initial guest memory is built exactly like `KvmVm::vm_create_default()`
+//! does, but without KVM. The launch digest is the SHA256 over all pages passed
+//! to LAUNCH_UPDATE_DATA, in load order.
+//!
+//! For SEV-ES guests the digest of the real firmware also covers the VMSA,
+//! which is not part of the offline measurement.
+
+use crate::arch::x86_64::PhysAddr;
+use crate::error::*;
+use crate::guest::{
+    launch_chunks, GhcbMode, GuestMemory, LoadedRegionKind, DEFAULT_GUEST_MEM, DEFAULT_TMPFS_INODES,
+};
+use crate::kvmvm::{sev_policy, VmConfig};
+use crate::map_context;
+use crate::sev::{MockFirmware, SevLaunch, SevPolicy};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+/// The hash of one chunk of guest memory passed to LAUNCH_UPDATE_DATA
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegionMeasurement {
+    pub kind: LoadedRegionKind,
+    pub start: PhysAddr,
+    pub size: u64,
+    pub sha256: [u8; 32],
+}
+
+/// The launch digest of a kernel and app pair
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Measurement {
+    /// C-bit position used in the initial page tables
+    pub cbit: Option<u32>,
+    /// The guest policy passed to LAUNCH_START
+    pub policy: SevPolicy,
+    /// SHA256 over all regions in order
+    pub digest: [u8; 32],
+    pub regions: Vec<RegionMeasurement>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(s, "{:02x}", b).unwrap();
+    }
+    s
+}
+
+impl Measurement {
+    /// The attestation report as JSON
+    pub fn to_json(&self) -> String {
+        let mut s = String::new();
+        s.push_str("{\n");
+        match self.cbit {
+            Some(cbit) => writeln!(s, "  \"cbit\": {},", cbit).unwrap(),
+            None => s.push_str("  \"cbit\": null,\n"),
+        }
+        writeln!(s, "  \"policy\": {},", self.policy.bits()).unwrap();
+        writeln!(s, "  \"digest\": \"{}\",", hex(&self.digest)).unwrap();
+        s.push_str("  \"regions\": [");
+        for (i, region) in self.regions.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            write!(
+                s,
+                "\n    {{ \"kind\": \"{}\", \"start\": \"{:#x}\", \"size\": {}, \"sha256\": \"{}\" }}",
+                region.kind.as_str(),
+                region.start.as_u64(),
+                region.size,
+                hex(&region.sha256)
+            )
+            .unwrap();
+        }
+        if !self.regions.is_empty() {
+            s.push_str("\n  ");
+        }
+        s.push_str("]\n}\n");
+        s
+    }
+}
+
+/// Measure an initial guest memory image
+pub fn measure_memory(mem: &GuestMemory, cbit: Option<u32>) -> Result<Measurement, Error> {
+    let es = mem.ghcb_mode() == GhcbMode::Vmgexit;
+    let policy = sev_policy(es);
+
+    let mut launch = SevLaunch::new(MockFirmware::new());
+    if es {
+        launch.es_init()?;
+    } else {
+        launch.init()?;
+    }
+    launch.launch_start(policy)?;
+
+    let mut regions = vec![];
+
+    for chunk in launch_chunks(mem.loaded_regions()) {
+        let data = unsafe { mem.host_slice(&chunk)? };
+
+        let mut sha256 = [0u8; 32];
+        sha256.copy_from_slice(&Sha256::digest(data)[..]);
+        regions.push(RegionMeasurement {
+            kind: chunk.kind,
+            start: chunk.start,
+            size: chunk.size,
+            sha256,
+        });
+
+        launch.launch_update_data(data)?;
+    }
+
+    if es {
+        launch.launch_update_vmsa()?;
+    }
+
+    Ok(Measurement {
+        cbit,
+        policy,
+        digest: launch.launch_measure()?.measure,
+        regions,
+    })
+}
+
+/// Compute the launch digest of `kernel_name` running `elf_name`
+///
+/// `cbit` is the position of the C-bit of the target host, or `None` for a
+/// guest without memory encryption. `config` has to match the `VmConfig` the
+/// guest is run with, only whether there is a launch secret matters.
+pub fn measure(
+    kernel_name: &str,
+    elf_name: &str,
+    cbit: Option<u32>,
+    config: &VmConfig,
+) -> Result<Measurement, Error> {
+    let kernel = std::fs::read(kernel_name).map_err(map_context!())?;
+    let app = std::fs::read(elf_name).map_err(map_context!())?;
+    measure_image(&kernel, &app, cbit, config)
+}
+
+/// Compute the launch digest of `kernel` running `app`, like `measure()`
+pub fn measure_image(
+    kernel: &[u8],
+    app: &[u8],
+    cbit: Option<u32>,
+    config: &VmConfig,
+) -> Result<Measurement, Error> {
+    let mut mem = GuestMemory::new(cbit.map_or(0, |c| 1 << c));
+    mem.set_ghcb_mode(config.ghcb_mode());
+    mem.set_launch_secret(config.launch_secret());
+    mem.set_aslr_seed(config.aslr_seed);
+    mem.set_sysroot(config.sysroot());
+    if !config.args.is_empty() || !config.env.is_empty() {
+        mem.set_args(&config.args, &config.env);
+    }
+    if let Some(initrd) = &config.initrd {
+        mem.set_initrd(initrd.clone())?;
+    }
+    if let Some(size) = config.tmpfs_size {
+        mem.set_tmpfs(size, DEFAULT_TMPFS_INODES);
+    }
+    mem.set_syscall_policy(config.syscall_policy);
+    mem.set_log_level(config.log_level);
+    let mem_size = config.mem_size.unwrap_or(DEFAULT_GUEST_MEM);
+    mem.region_add(PhysAddr::new(0), 0, mem_size / mem.page_size() as u64)?;
+    mem.setup_boot_memory()?;
+    mem.load_default(kernel, app)?;
+
+    measure_memory(&mem, cbit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boot_memory(cbit: Option<u32>) -> GuestMemory {
+        let mut mem = GuestMemory::new(cbit.map_or(0, |c| 1 << c));
+        mem.region_add(PhysAddr::new(0), 0, 512).unwrap();
+        mem.setup_boot_memory().unwrap();
+        mem
+    }
+
+    #[test]
+    fn digest_over_regions() {
+        let mem = boot_memory(None);
+        let m = measure_memory(&mem, None).unwrap();
+
+        assert_eq!(m.regions.len(), 1);
+        assert_eq!(m.regions[0].kind, LoadedRegionKind::PageTables);
+
+        let data = unsafe { mem.host_slice(&mem.loaded_regions()[0]).unwrap() };
+        assert_eq!(&m.regions[0].sha256[..], &Sha256::digest(data)[..]);
+        // a single region, so the digest is the region hash
+        assert_eq!(m.digest, m.regions[0].sha256);
+    }
+
+    #[test]
+    fn digest_depends_on_cbit() {
+        let a = measure_memory(&boot_memory(None), None).unwrap();
+        let b = measure_memory(&boot_memory(Some(47)), Some(47)).unwrap();
+        assert_ne!(a.digest, b.digest);
+    }
+
+    #[test]
+    fn sev_es_policy() {
+        let mut mem = GuestMemory::new(1 << 47);
+        mem.set_ghcb_mode(GhcbMode::Vmgexit);
+        mem.region_add(PhysAddr::new(0), 0, 512).unwrap();
+        mem.setup_boot_memory().unwrap();
+
+        let m = measure_memory(&mem, Some(47)).unwrap();
+        assert_eq!(m.policy, SevPolicy::NODBG | SevPolicy::ES);
+        assert_eq!(
+            measure_memory(&boot_memory(Some(47)), Some(47))
+                .unwrap()
+                .policy,
+            SevPolicy::NODBG
+        );
+    }
+
+    #[test]
+    fn json_report() {
+        let m = Measurement {
+            cbit: Some(47),
+            policy: SevPolicy::NODBG | SevPolicy::ES,
+            digest: [0xAB; 32],
+            regions: vec![RegionMeasurement {
+                kind: LoadedRegionKind::BootInfo,
+                start: PhysAddr::new(0x1000),
+                size: 0x1000,
+                sha256: [0x01; 32],
+            }],
+        };
+        let json = m.to_json();
+        assert!(json.contains("\"cbit\": 47,"));
+        assert!(json.contains("\"policy\": 5,"));
+        assert!(json.contains(&format!("\"digest\": \"{}\"", "ab".repeat(32))));
+        assert!(json.contains(&format!(
+            "{{ \"kind\": \"boot_info\", \"start\": \"0x1000\", \"size\": 4096, \"sha256\": \"{}\" }}",
+            "01".repeat(32)
+        )));
+    }
+}
@@ -0,0 +1,141 @@
+//! Readiness of host files for the guest
+//!
+//! The kernel knows the readiness of its own files, pipes and eventfds. For
+//! sockets and the stdio of the hypervisor it sends a `VmSyscall::Poll`,
+//! which waits with the host `poll(2)`. The guest has a single vCPU, nothing
+//! in the kernel can become ready while it waits.
+
+use crate::socket::Sockets;
+use linux_errno::ErrNo;
+use std::io;
+use std::time::{Duration, Instant};
+use vmsyscall::{PollFd, PollKind, MAX_POLL_FDS};
+
+fn err(errno: ErrNo) -> vmsyscall::Error {
+    vmsyscall::Error::Errno(errno.into())
+}
+
+/// Wait up to `timeout` milliseconds for `fds`, `-1` waits forever
+///
+/// Unknown files are `POLLNVAL` and count as ready, like with `poll(2)`.
+pub fn poll(
+    sockets: &Sockets,
+    fds: &[PollFd],
+    timeout: i32,
+) -> Result<(i32, [i16; MAX_POLL_FDS]), vmsyscall::Error> {
+    if fds.len() > MAX_POLL_FDS {
+        return Err(err(ErrNo::EINVAL));
+    }
+
+    let mut invalid = false;
+    let mut pollfds: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|fd| {
+            let raw = match fd.kind {
+                PollKind::Stdio if fd.fd <= 2 => Some(fd.fd as _),
+                PollKind::Stdio => None,
+                PollKind::Socket => sockets.raw_fd(fd.fd),
+            };
+            invalid |= raw.is_none();
+            libc::pollfd {
+                // a negative fd is ignored by poll(2)
+                fd: raw.unwrap_or(-1),
+                events: fd.events,
+                revents: 0,
+            }
+        })
+        .collect();
+
+    let deadline = match timeout {
+        _ if invalid => Some(Instant::now()),
+        t if t < 0 => None,
+        t => Some(Instant::now() + Duration::from_millis(t as _)),
+    };
+
+    loop {
+        let timeout = deadline.map_or(-1, |deadline| {
+            let left = deadline.saturating_duration_since(Instant::now());
+            // round up, to not wake up before the deadline
+            ((left.as_micros() + 999) / 1000).min(i32::MAX as _) as i32
+        });
+        let ret = unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as _, timeout) };
+        if ret >= 0 {
+            break;
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(vmsyscall::Error::Errno(
+                e.raw_os_error().unwrap_or(libc::EIO) as _,
+            ));
+        }
+    }
+
+    let mut revents = [0i16; MAX_POLL_FDS];
+    for (revents, pollfd) in revents.iter_mut().zip(&pollfds) {
+        *revents = if pollfd.fd < 0 {
+            libc::POLLNVAL
+        } else {
+            pollfd.revents
+        };
+    }
+    let ready = revents.iter().filter(|r| **r != 0).count();
+    Ok((ready as _, revents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::Policy;
+
+    fn socket(fd: i32, events: i16) -> PollFd {
+        PollFd {
+            kind: PollKind::Socket,
+            fd: fd as _,
+            events,
+        }
+    }
+
+    #[test]
+    fn socketpair_readiness() {
+        let mut sockets = Sockets::new(Policy::default());
+        let (a, b) = sockets
+            .socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0)
+            .unwrap();
+
+        let fds = [socket(a, libc::POLLIN), socket(b, libc::POLLOUT)];
+        let (ready, revents) = poll(&sockets, &fds, 0).unwrap();
+        assert_eq!(ready, 1);
+        assert_eq!(&revents[..2], &[0, libc::POLLOUT]);
+
+        sockets.sendto(b as _, b"x", 0, None, false).unwrap();
+        let (ready, revents) = poll(&sockets, &fds, -1).unwrap();
+        assert_eq!(ready, 2);
+        assert_eq!(revents[0], libc::POLLIN);
+
+        sockets.close(b as _).unwrap();
+        let (_, revents) = poll(&sockets, &fds, 0).unwrap();
+        assert_eq!(revents[0] & libc::POLLHUP, libc::POLLHUP);
+        assert_eq!(revents[1], libc::POLLNVAL);
+    }
+
+    #[test]
+    fn timeout_and_invalid() {
+        let sockets = Sockets::new(Policy::default());
+
+        let start = Instant::now();
+        assert_eq!(poll(&sockets, &[], 20).unwrap().0, 0);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        let stdio = PollFd {
+            kind: PollKind::Stdio,
+            fd: 3,
+            events: libc::POLLOUT,
+        };
+        let (ready, revents) = poll(&sockets, &[stdio], -1).unwrap();
+        assert_eq!(ready, 1);
+        assert_eq!(revents[0], libc::POLLNVAL);
+
+        let too_many = [PollFd::default(); MAX_POLL_FDS + 1];
+        assert_eq!(poll(&sockets, &too_many, 0).err(), Some(err(ErrNo::EINVAL)));
+    }
+}
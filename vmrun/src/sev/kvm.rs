@@ -0,0 +1,224 @@
+//! SEV firmware access via `/dev/sev` and the KVM memory encryption ioctls
+
+use super::{LaunchMeasurement, LaunchSession, SecretPacket, SevFirmware, SevPolicy, SEV_DEV};
+use crate::error::*;
+use crate::{context, map_context};
+use kvm_ioctls::VmFd;
+use std::fs::{File, OpenOptions};
+use std::os::raw::c_ulong;
+use std::os::unix::io::{AsRawFd, RawFd};
+use vmm_sys_util::errno;
+use vmm_sys_util::ioctl::ioctl_with_mut_ref;
+use vmm_sys_util::{ioctl_ioc_nr, ioctl_ior_nr, ioctl_iowr_nr};
+
+const KVMIO: u32 = 0xAE;
+
+ioctl_iowr_nr!(KVM_MEMORY_ENCRYPT_OP, KVMIO, 0xba, c_ulong);
+ioctl_ior_nr!(KVM_MEMORY_ENCRYPT_REG_REGION, KVMIO, 0xbb, KvmEncRegion);
+
+// see linux/kvm.h `enum sev_cmd_id`
+const KVM_SEV_INIT: u32 = 0;
+const KVM_SEV_ES_INIT: u32 = 1;
+const KVM_SEV_LAUNCH_START: u32 = 2;
+const KVM_SEV_LAUNCH_UPDATE_DATA: u32 = 3;
+const KVM_SEV_LAUNCH_UPDATE_VMSA: u32 = 4;
+const KVM_SEV_LAUNCH_SECRET: u32 = 5;
+const KVM_SEV_LAUNCH_MEASURE: u32 = 6;
+const KVM_SEV_LAUNCH_FINISH: u32 = 7;
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmSevCmd {
+    id: u32,
+    data: u64,
+    error: u32,
+    sev_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmEncRegion {
+    addr: u64,
+    size: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmSevLaunchStart {
+    handle: u32,
+    policy: u32,
+    dh_uaddr: u64,
+    dh_len: u32,
+    session_uaddr: u64,
+    session_len: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmSevLaunchUpdateData {
+    uaddr: u64,
+    len: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmSevLaunchMeasure {
+    uaddr: u64,
+    len: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmSevLaunchSecret {
+    hdr_uaddr: u64,
+    hdr_len: u32,
+    guest_uaddr: u64,
+    guest_len: u32,
+    trans_uaddr: u64,
+    trans_len: u32,
+}
+
+/// The SEV firmware of the host, used for a KVM VM
+pub struct KvmSevFirmware {
+    vm_fd: RawFd,
+    sev: File,
+    handle: u32,
+}
+
+impl KvmSevFirmware {
+    /// Open `/dev/sev` for the VM `vm_fd`
+    ///
+    /// `vm_fd` has to outlive the returned object.
+    pub fn new(vm_fd: &VmFd) -> Result<Self, Error> {
+        let sev = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(SEV_DEV)
+            .map_err(map_context!())?;
+
+        Ok(KvmSevFirmware {
+            vm_fd: vm_fd.as_raw_fd(),
+            sev,
+            handle: 0,
+        })
+    }
+
+    /// The firmware handle of the guest, valid after LAUNCH_START
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    fn sev_cmd<T>(&self, id: u32, data: Option<&mut T>) -> Result<(), Error> {
+        let mut cmd = KvmSevCmd {
+            id,
+            data: data.map_or(0, |d| d as *mut T as u64),
+            error: 0,
+            sev_fd: self.sev.as_raw_fd() as _,
+        };
+
+        let ret = unsafe { ioctl_with_mut_ref(self, KVM_MEMORY_ENCRYPT_OP(), &mut cmd) };
+        if ret < 0 {
+            if cmd.error != 0 {
+                return Err(context!(ErrorKind::SevFirmware(cmd.error)));
+            }
+            return Err(context!(ErrorKind::from(&errno::Error::last())));
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for KvmSevFirmware {
+    fn as_raw_fd(&self) -> RawFd {
+        self.vm_fd
+    }
+}
+
+impl SevFirmware for KvmSevFirmware {
+    fn init(&mut self) -> Result<(), Error> {
+        self.sev_cmd::<()>(KVM_SEV_INIT, None)
+    }
+
+    fn es_init(&mut self) -> Result<(), Error> {
+        self.sev_cmd::<()>(KVM_SEV_ES_INIT, None)
+    }
+
+    fn register_region(&mut self, mem: &mut [u8]) -> Result<(), Error> {
+        let mut region = KvmEncRegion {
+            addr: mem.as_mut_ptr() as u64,
+            size: mem.len() as u64,
+        };
+        let ret = unsafe { ioctl_with_mut_ref(self, KVM_MEMORY_ENCRYPT_REG_REGION(), &mut region) };
+        if ret < 0 {
+            return Err(context!(ErrorKind::from(&errno::Error::last())));
+        }
+        Ok(())
+    }
+
+    fn launch_start(
+        &mut self,
+        policy: SevPolicy,
+        session: Option<&LaunchSession>,
+    ) -> Result<(), Error> {
+        let mut start = KvmSevLaunchStart {
+            policy: policy.bits(),
+            ..Default::default()
+        };
+        if let Some(session) = session {
+            start.dh_uaddr = session.godh_cert.as_ptr() as u64;
+            start.dh_len = session.godh_cert.len() as u32;
+            start.session_uaddr = session.session.as_ptr() as u64;
+            start.session_len = session.session.len() as u32;
+        }
+        self.sev_cmd(KVM_SEV_LAUNCH_START, Some(&mut start))?;
+        self.handle = start.handle;
+        Ok(())
+    }
+
+    fn launch_update_data(&mut self, data: &mut [u8]) -> Result<(), Error> {
+        let mut update = KvmSevLaunchUpdateData {
+            uaddr: data.as_mut_ptr() as u64,
+            len: data.len() as u32,
+        };
+        self.sev_cmd(KVM_SEV_LAUNCH_UPDATE_DATA, Some(&mut update))
+    }
+
+    fn launch_update_vmsa(&mut self) -> Result<(), Error> {
+        self.sev_cmd::<()>(KVM_SEV_LAUNCH_UPDATE_VMSA, None)
+    }
+
+    fn launch_measure(&mut self) -> Result<LaunchMeasurement, Error> {
+        let mut blob = [0u8; 48];
+        let mut measure = KvmSevLaunchMeasure {
+            uaddr: blob.as_mut_ptr() as u64,
+            len: blob.len() as u32,
+        };
+        self.sev_cmd(KVM_SEV_LAUNCH_MEASURE, Some(&mut measure))?;
+
+        let mut measurement = LaunchMeasurement {
+            measure: [0u8; 32],
+            mnonce: [0u8; 16],
+        };
+        measurement.measure.copy_from_slice(&blob[..32]);
+        measurement.mnonce.copy_from_slice(&blob[32..]);
+        Ok(measurement)
+    }
+
+    fn launch_secret(&mut self, packet: &SecretPacket, guest: &mut [u8]) -> Result<(), Error> {
+        if packet.data.len() > guest.len() {
+            return Err(context!(ErrorKind::SecretTooLong));
+        }
+        let mut secret = KvmSevLaunchSecret {
+            hdr_uaddr: packet.header.as_ptr() as u64,
+            hdr_len: packet.header.len() as u32,
+            guest_uaddr: guest.as_mut_ptr() as u64,
+            guest_len: packet.data.len() as u32,
+            trans_uaddr: packet.data.as_ptr() as u64,
+            trans_len: packet.data.len() as u32,
+        };
+        self.sev_cmd(KVM_SEV_LAUNCH_SECRET, Some(&mut secret))
+    }
+
+    fn launch_finish(&mut self) -> Result<(), Error> {
+        self.sev_cmd::<()>(KVM_SEV_LAUNCH_FINISH, None)
+    }
+}
@@ -0,0 +1,119 @@
+//! Software SEV firmware for machines without SEV hardware
+
+use super::{LaunchMeasurement, LaunchSession, SecretPacket, SevFirmware, SevPolicy};
+use crate::context;
+use crate::error::*;
+use sha2::{Digest, Sha256};
+
+/// A software implementation of the SEV launch commands.
+///
+/// Guest memory is left unencrypted. The measurement is the plain SHA256
+/// launch digest over all data passed to `launch_update_data()`, which is
+/// what the real firmware HMACs with the transport integrity key.
+///
+/// The VMSA of SEV-ES guests is not part of the mock digest.
+///
+/// LAUNCH_SECRET packets are expected in plain text and copied to the guest.
+pub struct MockFirmware {
+    digest: Sha256,
+    policy: SevPolicy,
+    regions: usize,
+    updates: usize,
+    vmsa_updates: usize,
+}
+
+impl MockFirmware {
+    /// Create a new mock firmware
+    pub fn new() -> Self {
+        MockFirmware {
+            digest: Sha256::new(),
+            policy: SevPolicy::empty(),
+            regions: 0,
+            updates: 0,
+            vmsa_updates: 0,
+        }
+    }
+
+    /// The policy passed to LAUNCH_START
+    pub fn policy(&self) -> SevPolicy {
+        self.policy
+    }
+
+    /// Number of registered memory regions
+    pub fn regions(&self) -> usize {
+        self.regions
+    }
+
+    /// Number of LAUNCH_UPDATE_DATA commands
+    pub fn updates(&self) -> usize {
+        self.updates
+    }
+
+    /// Number of LAUNCH_UPDATE_VMSA commands
+    pub fn vmsa_updates(&self) -> usize {
+        self.vmsa_updates
+    }
+}
+
+impl Default for MockFirmware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SevFirmware for MockFirmware {
+    fn init(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn es_init(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn register_region(&mut self, _mem: &mut [u8]) -> Result<(), Error> {
+        self.regions += 1;
+        Ok(())
+    }
+
+    fn launch_start(
+        &mut self,
+        policy: SevPolicy,
+        _session: Option<&LaunchSession>,
+    ) -> Result<(), Error> {
+        self.policy = policy;
+        self.digest = Sha256::new();
+        Ok(())
+    }
+
+    fn launch_update_data(&mut self, data: &mut [u8]) -> Result<(), Error> {
+        self.digest.input(&data[..]);
+        self.updates += 1;
+        Ok(())
+    }
+
+    fn launch_update_vmsa(&mut self) -> Result<(), Error> {
+        self.vmsa_updates += 1;
+        Ok(())
+    }
+
+    fn launch_measure(&mut self) -> Result<LaunchMeasurement, Error> {
+        let mut measure = [0u8; 32];
+        measure.copy_from_slice(&self.digest.clone().result()[..]);
+        Ok(LaunchMeasurement {
+            measure,
+            mnonce: [0u8; 16],
+        })
+    }
+
+    fn launch_secret(&mut self, packet: &SecretPacket, guest: &mut [u8]) -> Result<(), Error> {
+        if packet.data.len() > guest.len() {
+            return Err(context!(ErrorKind::SecretTooLong));
+        }
+        guest[..packet.data.len()].copy_from_slice(&packet.data);
+        Ok(())
+    }
+
+    fn launch_finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
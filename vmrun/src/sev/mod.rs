@@ -0,0 +1,455 @@
+//! AMD SEV launch flow
+//!
+//! The SEV firmware commands are issued through the [`SevFirmware`] trait.
+//! [`SevLaunch`] drives the firmware through the launch state machine:
+//!
+//! ```text
+//! INIT -> LAUNCH_START -> LAUNCH_UPDATE_DATA* -> LAUNCH_MEASURE -> LAUNCH_SECRET*
+//!      -> LAUNCH_FINISH
+//! ```
+//!
+//! With SEV-ES, INIT is replaced by ES_INIT and the encrypted vCPU register
+//! state is added with LAUNCH_UPDATE_VMSA after the last LAUNCH_UPDATE_DATA.
+//!
+//! [`KvmSevFirmware`] talks to the real firmware via `/dev/sev` and the
+//! `KVM_MEMORY_ENCRYPT_OP` ioctl, [`MockFirmware`] is a software implementation
+//! for machines without SEV hardware.
+
+mod kvm;
+mod mock;
+
+pub use kvm::KvmSevFirmware;
+pub use mock::MockFirmware;
+
+use crate::context;
+use crate::error::*;
+use bitflags::bitflags;
+
+/// The SEV firmware device
+pub const SEV_DEV: &str = "/dev/sev";
+
+bitflags! {
+    /// The guest policy passed to LAUNCH_START
+    pub struct SevPolicy: u32 {
+        /// Debugging of the guest is disallowed
+        const NODBG = 1 << 0;
+        /// Sharing keys with other guests is disallowed
+        const NOKS = 1 << 1;
+        /// SEV-ES is required
+        const ES = 1 << 2;
+        /// Sending the guest to another platform is disallowed
+        const NOSEND = 1 << 3;
+        /// The guest must not be transmitted to another platform not in the domain
+        const DOMAIN = 1 << 4;
+        /// The guest must not be transmitted to another platform that is not SEV capable
+        const SEV = 1 << 5;
+    }
+}
+
+/// The result of LAUNCH_MEASURE
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LaunchMeasurement {
+    /// HMAC-SHA256 over the launch digest (plain SHA256 digest for the mock)
+    pub measure: [u8; 32],
+    /// The nonce used for the measurement
+    pub mnonce: [u8; 16],
+}
+
+impl std::fmt::Display for LaunchMeasurement {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for b in self.measure.iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        f.write_str(" mnonce=")?;
+        for b in self.mnonce.iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+/// Length of the LAUNCH_SECRET packet header: flags, IV and MAC
+pub const SECRET_HEADER_LEN: usize = 4 + 16 + 32;
+
+/// A secret for LAUNCH_SECRET, as prepared by the guest owner
+///
+/// The guest owner wraps `data` with the transport encryption key of the
+/// launch session and authenticates it with the `header`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SecretPacket {
+    /// The packet header: flags, IV and MAC
+    pub header: Vec<u8>,
+    /// The transport encrypted secret
+    pub data: Vec<u8>,
+}
+
+impl SecretPacket {
+    /// Split a packet file of the guest owner: the header, then the data
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() <= SECRET_HEADER_LEN {
+            return Err(context!(ErrorKind::Str(
+                "the LAUNCH_SECRET packet is shorter than its header"
+            )));
+        }
+        Ok(SecretPacket {
+            header: bytes[..SECRET_HEADER_LEN].to_vec(),
+            data: bytes[SECRET_HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+/// The launch session of the guest owner, passed to LAUNCH_START
+///
+/// The firmware derives the transport keys of the guest owner's
+/// LAUNCH_SECRET packets from it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LaunchSession {
+    /// The Diffie-Hellman certificate of the guest owner
+    pub godh_cert: Vec<u8>,
+    /// The session parameters, with the wrapped transport keys
+    pub session: Vec<u8>,
+}
+
+/// The SEV firmware commands needed to launch a guest
+pub trait SevFirmware {
+    /// KVM_SEV_INIT
+    fn init(&mut self) -> Result<(), Error>;
+    /// KVM_SEV_ES_INIT
+    fn es_init(&mut self) -> Result<(), Error>;
+    /// Pin the guest memory backing `mem` (KVM_MEMORY_ENCRYPT_REG_REGION)
+    fn register_region(&mut self, mem: &mut [u8]) -> Result<(), Error>;
+    /// KVM_SEV_LAUNCH_START, with the guest owner's `session` if there is one
+    fn launch_start(
+        &mut self,
+        policy: SevPolicy,
+        session: Option<&LaunchSession>,
+    ) -> Result<(), Error>;
+    /// KVM_SEV_LAUNCH_UPDATE_DATA
+    ///
+    /// `data` is guest memory, which is encrypted in place.
+    fn launch_update_data(&mut self, data: &mut [u8]) -> Result<(), Error>;
+    /// KVM_SEV_LAUNCH_UPDATE_VMSA
+    ///
+    /// Encrypts the register state of all vCPUs.
+    fn launch_update_vmsa(&mut self) -> Result<(), Error>;
+    /// KVM_SEV_LAUNCH_MEASURE
+    fn launch_measure(&mut self) -> Result<LaunchMeasurement, Error>;
+    /// KVM_SEV_LAUNCH_SECRET
+    ///
+    /// Decrypts `packet` and writes it encrypted with the guest key to the
+    /// guest memory `guest`.
+    fn launch_secret(&mut self, packet: &SecretPacket, guest: &mut [u8]) -> Result<(), Error>;
+    /// KVM_SEV_LAUNCH_FINISH
+    fn launch_finish(&mut self) -> Result<(), Error>;
+}
+
+impl<T: SevFirmware + ?Sized> SevFirmware for Box<T> {
+    fn init(&mut self) -> Result<(), Error> {
+        (**self).init()
+    }
+
+    fn es_init(&mut self) -> Result<(), Error> {
+        (**self).es_init()
+    }
+
+    fn register_region(&mut self, mem: &mut [u8]) -> Result<(), Error> {
+        (**self).register_region(mem)
+    }
+
+    fn launch_start(
+        &mut self,
+        policy: SevPolicy,
+        session: Option<&LaunchSession>,
+    ) -> Result<(), Error> {
+        (**self).launch_start(policy, session)
+    }
+
+    fn launch_update_data(&mut self, data: &mut [u8]) -> Result<(), Error> {
+        (**self).launch_update_data(data)
+    }
+
+    fn launch_update_vmsa(&mut self) -> Result<(), Error> {
+        (**self).launch_update_vmsa()
+    }
+
+    fn launch_measure(&mut self) -> Result<LaunchMeasurement, Error> {
+        (**self).launch_measure()
+    }
+
+    fn launch_secret(&mut self, packet: &SecretPacket, guest: &mut [u8]) -> Result<(), Error> {
+        (**self).launch_secret(packet, guest)
+    }
+
+    fn launch_finish(&mut self) -> Result<(), Error> {
+        (**self).launch_finish()
+    }
+}
+
+/// The guest states of the launch state machine
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SevState {
+    /// Firmware not yet initialized
+    Uninit,
+    /// KVM_SEV_INIT done
+    Init,
+    /// LAUNCH_START done, guest memory can be added
+    LaunchUpdate,
+    /// LAUNCH_MEASURE done, secrets could be injected
+    LaunchSecret,
+    /// LAUNCH_FINISH done, the guest can run
+    Running,
+}
+
+/// Drives a [`SevFirmware`] through the launch sequence
+pub struct SevLaunch<F: SevFirmware> {
+    firmware: F,
+    state: SevState,
+    es: bool,
+    session: Option<LaunchSession>,
+    measurement: Option<LaunchMeasurement>,
+}
+
+impl<F: SevFirmware> SevLaunch<F> {
+    /// Create a new launch sequence with `firmware`
+    pub fn new(firmware: F) -> Self {
+        SevLaunch {
+            firmware,
+            state: SevState::Uninit,
+            es: false,
+            session: None,
+            measurement: None,
+        }
+    }
+
+    /// The current state
+    pub fn state(&self) -> SevState {
+        self.state
+    }
+
+    /// `true`, if the platform was initialized for SEV-ES
+    pub fn is_es(&self) -> bool {
+        self.es
+    }
+
+    /// The measurement returned by LAUNCH_MEASURE
+    pub fn measurement(&self) -> Option<LaunchMeasurement> {
+        self.measurement
+    }
+
+    fn expect_state(&self, state: SevState) -> Result<(), Error> {
+        if self.state != state {
+            return Err(context!(ErrorKind::SevInvalidState));
+        }
+        Ok(())
+    }
+
+    /// Initialize the SEV platform for this VM
+    pub fn init(&mut self) -> Result<(), Error> {
+        self.expect_state(SevState::Uninit)?;
+        self.firmware.init()?;
+        self.state = SevState::Init;
+        Ok(())
+    }
+
+    /// Initialize the SEV platform for this VM with SEV-ES
+    pub fn es_init(&mut self) -> Result<(), Error> {
+        self.expect_state(SevState::Uninit)?;
+        self.firmware.es_init()?;
+        self.state = SevState::Init;
+        self.es = true;
+        Ok(())
+    }
+
+    /// Register guest memory, which will be encrypted
+    pub fn register_region(&mut self, mem: &mut [u8]) -> Result<(), Error> {
+        match self.state {
+            SevState::Init | SevState::LaunchUpdate => self.firmware.register_region(mem),
+            _ => Err(context!(ErrorKind::SevInvalidState)),
+        }
+    }
+
+    /// Start the launch in the guest owner's `session`, before `launch_start()`
+    pub fn set_session(&mut self, session: LaunchSession) {
+        self.session.replace(session);
+    }
+
+    /// Create the guest context with `policy`
+    pub fn launch_start(&mut self, policy: SevPolicy) -> Result<(), Error> {
+        self.expect_state(SevState::Init)?;
+        self.firmware.launch_start(policy, self.session.as_ref())?;
+        self.state = SevState::LaunchUpdate;
+        Ok(())
+    }
+
+    /// Encrypt `data` in place and add it to the launch digest
+    pub fn launch_update_data(&mut self, data: &mut [u8]) -> Result<(), Error> {
+        self.expect_state(SevState::LaunchUpdate)?;
+        self.firmware.launch_update_data(data)
+    }
+
+    /// Encrypt the vCPU register state and add it to the launch digest
+    pub fn launch_update_vmsa(&mut self) -> Result<(), Error> {
+        self.expect_state(SevState::LaunchUpdate)?;
+        if !self.es {
+            return Err(context!(ErrorKind::SevInvalidState));
+        }
+        self.firmware.launch_update_vmsa()
+    }
+
+    /// Finish the launch digest and return the measurement
+    pub fn launch_measure(&mut self) -> Result<LaunchMeasurement, Error> {
+        self.expect_state(SevState::LaunchUpdate)?;
+        let measurement = self.firmware.launch_measure()?;
+        self.measurement.replace(measurement);
+        self.state = SevState::LaunchSecret;
+        Ok(measurement)
+    }
+
+    /// Inject a secret into the guest memory `guest`, after the guest owner
+    /// verified the measurement
+    pub fn launch_secret(&mut self, packet: &SecretPacket, guest: &mut [u8]) -> Result<(), Error> {
+        self.expect_state(SevState::LaunchSecret)?;
+        self.firmware.launch_secret(packet, guest)
+    }
+
+    /// Finish the launch, after this the guest can run
+    pub fn launch_finish(&mut self) -> Result<(), Error> {
+        self.expect_state(SevState::LaunchSecret)?;
+        self.firmware.launch_finish()?;
+        self.state = SevState::Running;
+        Ok(())
+    }
+}
+
+/// Returns the position of the C-bit, if the host CPU supports SEV.
+pub fn host_cbit_position() -> Option<u32> {
+    use core::arch::x86_64::__cpuid;
+
+    let max_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+    if max_leaf < 0x8000_001F {
+        return None;
+    }
+    let leaf = unsafe { __cpuid(0x8000_001F) };
+    // eax bit 1: SEV supported
+    if leaf.eax & 0b10 == 0 {
+        return None;
+    }
+    Some(leaf.ebx & 0x3F)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn launch() -> SevLaunch<MockFirmware> {
+        let mut sev = SevLaunch::new(MockFirmware::new());
+        sev.init().unwrap();
+        sev.launch_start(SevPolicy::NODBG).unwrap();
+        sev
+    }
+
+    #[test]
+    fn launch_sequence() {
+        let mut sev = launch();
+        let mut page = [0xAAu8; 4096];
+        sev.launch_update_data(&mut page[..]).unwrap();
+        let measurement = sev.launch_measure().unwrap();
+        sev.launch_finish().unwrap();
+        assert_eq!(sev.state(), SevState::Running);
+        assert_eq!(sev.measurement(), Some(measurement));
+        assert_eq!(&measurement.measure[..], &Sha256::digest(&page[..])[..]);
+    }
+
+    #[test]
+    fn launch_out_of_order() {
+        let mut sev = SevLaunch::new(MockFirmware::new());
+        let mut page = [0u8; 4096];
+        assert_eq!(
+            sev.launch_start(SevPolicy::empty()).unwrap_err().kind(),
+            &ErrorKind::SevInvalidState
+        );
+        sev.init().unwrap();
+        assert_eq!(
+            sev.launch_update_data(&mut page[..]).unwrap_err().kind(),
+            &ErrorKind::SevInvalidState
+        );
+        assert_eq!(
+            sev.launch_finish().unwrap_err().kind(),
+            &ErrorKind::SevInvalidState
+        );
+        sev.launch_start(SevPolicy::empty()).unwrap();
+        sev.launch_measure().unwrap();
+        assert_eq!(
+            sev.launch_update_data(&mut page[..]).unwrap_err().kind(),
+            &ErrorKind::SevInvalidState
+        );
+        assert_eq!(sev.init().unwrap_err().kind(), &ErrorKind::SevInvalidState);
+    }
+
+    #[test]
+    fn launch_update_vmsa_needs_es() {
+        let mut sev = launch();
+        assert_eq!(
+            sev.launch_update_vmsa().unwrap_err().kind(),
+            &ErrorKind::SevInvalidState
+        );
+
+        let mut sev = SevLaunch::new(MockFirmware::new());
+        sev.es_init().unwrap();
+        assert!(sev.is_es());
+        sev.launch_start(SevPolicy::NODBG | SevPolicy::ES).unwrap();
+        sev.launch_update_vmsa().unwrap();
+        sev.launch_measure().unwrap();
+    }
+
+    #[test]
+    fn launch_secret_after_measure() {
+        let mut sev = launch();
+        let packet = SecretPacket {
+            header: vec![],
+            data: vec![0x55; 40],
+        };
+        let mut guest = [0u8; 4096];
+        assert_eq!(
+            sev.launch_secret(&packet, &mut guest[..])
+                .unwrap_err()
+                .kind(),
+            &ErrorKind::SevInvalidState
+        );
+        sev.launch_measure().unwrap();
+        sev.launch_secret(&packet, &mut guest[..]).unwrap();
+        assert_eq!(&guest[..40], &packet.data[..]);
+        assert!(guest[40..].iter().all(|b| *b == 0));
+        sev.launch_finish().unwrap();
+    }
+
+    #[test]
+    fn secret_packet_file() {
+        let mut bytes = vec![1u8; SECRET_HEADER_LEN];
+        bytes.extend_from_slice(&[2u8; 40]);
+        let packet = SecretPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(packet.header, vec![1u8; SECRET_HEADER_LEN]);
+        assert_eq!(packet.data, vec![2u8; 40]);
+
+        assert!(SecretPacket::from_bytes(&bytes[..SECRET_HEADER_LEN]).is_err());
+    }
+
+    #[test]
+    fn measurement_depends_on_order() {
+        let a = [1u8; 4096];
+        let b = [2u8; 4096];
+
+        let mut sev = launch();
+        sev.launch_update_data(&mut a.clone()[..]).unwrap();
+        sev.launch_update_data(&mut b.clone()[..]).unwrap();
+        let ab = sev.launch_measure().unwrap();
+
+        let mut sev = launch();
+        sev.launch_update_data(&mut b.clone()[..]).unwrap();
+        sev.launch_update_data(&mut a.clone()[..]).unwrap();
+        let ba = sev.launch_measure().unwrap();
+
+        assert_ne!(ab, ba);
+    }
+}
@@ -0,0 +1,459 @@
+//! Snapshots of a running VM
+//!
+//! `vmrun --snapshot <file> --snapshot-at <marker>` runs the guest until the
+//! app writes `marker` to its stdout or stderr, then writes the snapshot and
+//! exits. `vmrun --restore <file>` runs the guest on from the snapshot, without
+//! booting the kernel and starting the app again.
+//!
+//! A snapshot has the guest memory, the state of the vCPUs, of the in-kernel
+//! irqchip and of the PIT, and the host side state of the syscalls: the files
+//! opened in the sysroot and the sealed secret. The kernel ELF is kept for the
+//! backtraces of the restored guest. The files are opened again by
+//! their path on restore, the trace and the kernel log go to the files of the
+//! restoring `vmrun`.
+//!
+//! SEV guests and guests with open sockets can not be snapshotted, neither
+//! the encrypted state nor the connections of the host can be saved.
+
+use crate::error::*;
+use kvm_bindings::{
+    kvm_fpu, kvm_irqchip, kvm_lapic_state, kvm_mp_state, kvm_msr_entry, kvm_pit_state2, kvm_regs,
+    kvm_sregs, kvm_vcpu_events, kvm_xcrs, kvm_xsave, Msrs,
+};
+use kvm_ioctls::VcpuFd;
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::mem::{size_of, MaybeUninit};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use vmsyscall::bootinfo::ElfBlob;
+use vmsyscall::secret::SecretBlob;
+
+const MAGIC: &[u8; 8] = b"VMRUNSNP";
+const VERSION: u32 = 2;
+/// The largest kernel ELF in a snapshot
+const MAX_KERNEL_LEN: usize = 256 * 1024 * 1024;
+
+/// The MSRs saved in a snapshot
+///
+/// EFER and the FS and GS bases are part of `kvm_sregs`. KVM stops reading at
+/// the first MSR it does not know, so the optional ones are last.
+pub const MSRS: &[u32] = &[
+    0x0000_0010, // TSC
+    0x0000_0174, // SYSENTER_CS
+    0x0000_0175, // SYSENTER_ESP
+    0x0000_0176, // SYSENTER_EIP
+    0x0000_0277, // PAT
+    0xC000_0081, // STAR
+    0xC000_0082, // LSTAR
+    0xC000_0083, // CSTAR
+    0xC000_0084, // SFMASK
+    0xC000_0102, // KERNEL_GS_BASE
+    0xC000_0103, // TSC_AUX
+    0x0000_06E0, // TSC_DEADLINE
+];
+
+/// Finds the snapshot marker in the output of the app
+///
+/// A marker split across writes is found as well.
+pub struct Marker {
+    marker: Vec<u8>,
+    tail: Vec<u8>,
+}
+
+impl Marker {
+    pub fn new(marker: &[u8]) -> Self {
+        Marker {
+            marker: marker.to_vec(),
+            tail: Vec::new(),
+        }
+    }
+
+    /// Is the marker in the output `data`
+    pub fn find(&mut self, data: &[u8]) -> bool {
+        if self.marker.is_empty() {
+            return true;
+        }
+        let mut window = std::mem::take(&mut self.tail);
+        window.extend_from_slice(data);
+        let found = window
+            .windows(self.marker.len())
+            .any(|w| w == &self.marker[..]);
+        let keep = (self.marker.len() - 1).min(window.len());
+        self.tail = window.split_off(window.len() - keep);
+        found
+    }
+}
+
+/// A KVM memory slot, its contents follow the state in the snapshot file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub slot: u32,
+    pub guest_phys_addr: u64,
+    pub size: u64,
+}
+
+/// The state of a vCPU
+pub struct VcpuState {
+    pub regs: kvm_regs,
+    pub sregs: kvm_sregs,
+    pub fpu: kvm_fpu,
+    pub xsave: kvm_xsave,
+    pub xcrs: kvm_xcrs,
+    pub lapic: kvm_lapic_state,
+    pub mp_state: kvm_mp_state,
+    pub events: kvm_vcpu_events,
+    pub msrs: Vec<kvm_msr_entry>,
+}
+
+impl VcpuState {
+    /// Read the state of a stopped vCPU
+    pub fn get(vcpu: &VcpuFd) -> Result<Self, Error> {
+        let entries: Vec<_> = MSRS
+            .iter()
+            .map(|&index| kvm_msr_entry {
+                index,
+                ..Default::default()
+            })
+            .collect();
+        let mut msrs = Msrs::from_entries(&entries);
+        let nmsrs = vcpu.get_msrs(&mut msrs).map_err(|e| ErrorKind::from(&e))?;
+
+        Ok(VcpuState {
+            regs: vcpu.get_regs().map_err(|e| ErrorKind::from(&e))?,
+            sregs: vcpu.get_sregs().map_err(|e| ErrorKind::from(&e))?,
+            fpu: vcpu.get_fpu().map_err(|e| ErrorKind::from(&e))?,
+            xsave: vcpu.get_xsave().map_err(|e| ErrorKind::from(&e))?,
+            xcrs: vcpu.get_xcrs().map_err(|e| ErrorKind::from(&e))?,
+            lapic: vcpu.get_lapic().map_err(|e| ErrorKind::from(&e))?,
+            mp_state: vcpu.get_mp_state().map_err(|e| ErrorKind::from(&e))?,
+            events: vcpu.get_vcpu_events().map_err(|e| ErrorKind::from(&e))?,
+            msrs: msrs.as_slice()[..nmsrs].to_vec(),
+        })
+    }
+
+    /// Write the state to a vCPU, after its CPUID is set
+    pub fn set(&self, vcpu: &VcpuFd) -> Result<(), Error> {
+        vcpu.set_mp_state(self.mp_state)
+            .map_err(|e| ErrorKind::from(&e))?;
+        vcpu.set_sregs(&self.sregs)
+            .map_err(|e| ErrorKind::from(&e))?;
+        vcpu.set_regs(&self.regs).map_err(|e| ErrorKind::from(&e))?;
+        vcpu.set_fpu(&self.fpu).map_err(|e| ErrorKind::from(&e))?;
+        vcpu.set_xsave(&self.xsave)
+            .map_err(|e| ErrorKind::from(&e))?;
+        vcpu.set_xcrs(&self.xcrs).map_err(|e| ErrorKind::from(&e))?;
+        vcpu.set_lapic(&self.lapic)
+            .map_err(|e| ErrorKind::from(&e))?;
+
+        let msrs = Msrs::from_entries(&self.msrs);
+        if vcpu.set_msrs(&msrs).map_err(|e| ErrorKind::from(&e))? != self.msrs.len() {
+            return Err(context!(ErrorKind::Str(
+                "the MSRs of the snapshot can not be set"
+            )));
+        }
+
+        vcpu.set_vcpu_events(&self.events)
+            .map_err(|e| ErrorKind::from(&e))?;
+        Ok(())
+    }
+}
+
+/// The state of a VM, without the contents of its memory
+pub struct Snapshot {
+    pub regions: Vec<Region>,
+    pub irqchips: Vec<kvm_irqchip>,
+    pub pit: kvm_pit_state2,
+    pub vcpus: Vec<VcpuState>,
+    /// The kernel ELF, the app and its ELF interpreter, for backtraces
+    pub kernel: Vec<u8>,
+    pub app: ElfBlob,
+    pub interp: ElfBlob,
+    pub secret: Option<SecretBlob>,
+    /// The host paths of the open sysroot files, by file descriptor
+    pub files: Vec<Option<PathBuf>>,
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid vmrun snapshot")
+}
+
+fn write_u32(out: &mut impl Write, value: u32) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_u64(out: &mut impl Write, value: u64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+/// Write the raw bytes of a plain C struct
+fn write_raw<T: Copy>(out: &mut impl Write, value: &T) -> io::Result<()> {
+    let bytes =
+        unsafe { std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+    out.write_all(bytes)
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Read a plain C struct, every bit pattern is a valid value
+fn read_raw<T: Copy>(input: &mut impl Read) -> io::Result<T> {
+    let mut value = MaybeUninit::<T>::zeroed();
+    let bytes =
+        unsafe { std::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    input.read_exact(bytes)?;
+    Ok(unsafe { value.assume_init() })
+}
+
+/// A count of items in the snapshot, at most `max`
+fn read_len(input: &mut impl Read, max: usize) -> io::Result<usize> {
+    match read_u32(input)? as usize {
+        len if len <= max => Ok(len),
+        _ => Err(invalid()),
+    }
+}
+
+impl Snapshot {
+    /// Write the state, the memory of the regions has to follow
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        write_u32(out, VERSION)?;
+
+        write_u32(out, self.regions.len() as _)?;
+        for region in &self.regions {
+            write_u32(out, region.slot)?;
+            write_u64(out, region.guest_phys_addr)?;
+            write_u64(out, region.size)?;
+        }
+
+        write_u32(out, self.irqchips.len() as _)?;
+        for irqchip in &self.irqchips {
+            write_raw(out, irqchip)?;
+        }
+        write_raw(out, &self.pit)?;
+
+        write_u32(out, self.vcpus.len() as _)?;
+        for vcpu in &self.vcpus {
+            write_raw(out, &vcpu.regs)?;
+            write_raw(out, &vcpu.sregs)?;
+            write_raw(out, &vcpu.fpu)?;
+            write_raw(out, &vcpu.xsave)?;
+            write_raw(out, &vcpu.xcrs)?;
+            write_raw(out, &vcpu.lapic)?;
+            write_raw(out, &vcpu.mp_state)?;
+            write_raw(out, &vcpu.events)?;
+            write_u32(out, vcpu.msrs.len() as _)?;
+            for msr in &vcpu.msrs {
+                write_u32(out, msr.index)?;
+                write_u64(out, msr.data)?;
+            }
+        }
+
+        write_u32(out, self.kernel.len() as _)?;
+        out.write_all(&self.kernel)?;
+        write_raw(out, &self.app)?;
+        write_raw(out, &self.interp)?;
+
+        match &self.secret {
+            Some(secret) => {
+                write_u32(out, 1)?;
+                write_raw(out, secret)?;
+            }
+            None => write_u32(out, 0)?,
+        }
+
+        write_u32(out, self.files.len() as _)?;
+        for file in &self.files {
+            match file {
+                Some(path) => {
+                    let path = path.as_os_str().as_bytes();
+                    write_u32(out, path.len() as _)?;
+                    out.write_all(path)?;
+                }
+                None => write_u32(out, u32::MAX)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the state written by `write()`
+    pub fn read(input: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u32(input)? != VERSION {
+            return Err(invalid());
+        }
+
+        let mut regions = Vec::new();
+        for _ in 0..read_len(input, 64)? {
+            regions.push(Region {
+                slot: read_u32(input)?,
+                guest_phys_addr: read_u64(input)?,
+                size: read_u64(input)?,
+            });
+        }
+
+        let mut irqchips = Vec::new();
+        for _ in 0..read_len(input, 3)? {
+            irqchips.push(read_raw(input)?);
+        }
+        let pit = read_raw(input)?;
+
+        let mut vcpus = Vec::new();
+        for _ in 0..read_len(input, 256)? {
+            let mut vcpu = VcpuState {
+                regs: read_raw(input)?,
+                sregs: read_raw(input)?,
+                fpu: read_raw(input)?,
+                xsave: read_raw(input)?,
+                xcrs: read_raw(input)?,
+                lapic: read_raw(input)?,
+                mp_state: read_raw(input)?,
+                events: read_raw(input)?,
+                msrs: Vec::new(),
+            };
+            for _ in 0..read_len(input, MSRS.len())? {
+                vcpu.msrs.push(kvm_msr_entry {
+                    index: read_u32(input)?,
+                    data: read_u64(input)?,
+                    ..Default::default()
+                });
+            }
+            vcpus.push(vcpu);
+        }
+
+        let mut kernel = vec![0u8; read_len(input, MAX_KERNEL_LEN)?];
+        input.read_exact(&mut kernel)?;
+        let app = read_raw(input)?;
+        let interp = read_raw(input)?;
+
+        let secret = match read_u32(input)? {
+            0 => None,
+            1 => Some(read_raw(input)?),
+            _ => return Err(invalid()),
+        };
+
+        let mut files = Vec::new();
+        for _ in 0..read_len(input, crate::sysroot::MAX_OPEN_FILES)? {
+            let len = match read_u32(input)? {
+                u32::MAX => {
+                    files.push(None);
+                    continue;
+                }
+                len if len <= 4096 => len,
+                _ => return Err(invalid()),
+            };
+            let mut path = vec![0u8; len as usize];
+            input.read_exact(&mut path)?;
+            files.push(Some(PathBuf::from(OsStr::from_bytes(&path))));
+        }
+
+        Ok(Snapshot {
+            regions,
+            irqchips,
+            pit,
+            vcpus,
+            kernel,
+            app,
+            interp,
+            secret,
+            files,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marker() {
+        let mut marker = Marker::new(b"ready\n");
+        assert!(!marker.find(b"starting\n"));
+        assert!(!marker.find(b"rea"));
+        assert!(marker.find(b"dy\n"));
+        assert!(marker.find(b"still ready\n"));
+        assert!(!marker.find(b"r"));
+        assert!(!marker.find(b"eady"));
+
+        assert!(Marker::new(b"").find(b""));
+    }
+
+    #[test]
+    fn state_roundtrip() {
+        let mut vcpu = VcpuState {
+            regs: Default::default(),
+            sregs: Default::default(),
+            fpu: Default::default(),
+            xsave: unsafe { std::mem::zeroed() },
+            xcrs: Default::default(),
+            lapic: unsafe { std::mem::zeroed() },
+            mp_state: Default::default(),
+            events: Default::default(),
+            msrs: vec![kvm_msr_entry {
+                index: MSRS[0],
+                data: 42,
+                ..Default::default()
+            }],
+        };
+        vcpu.regs.rip = 0xFFFF_8000_0001_2345;
+        vcpu.sregs.cr3 = 0x9000;
+        vcpu.lapic.regs[0x20] = 1;
+
+        let mut irqchip: kvm_irqchip = unsafe { std::mem::zeroed() };
+        irqchip.chip_id = 2;
+
+        let snapshot = Snapshot {
+            regions: vec![Region {
+                slot: 0,
+                guest_phys_addr: 0,
+                size: 0x8000_0000,
+            }],
+            irqchips: vec![irqchip],
+            pit: unsafe { std::mem::zeroed() },
+            vcpus: vec![vcpu],
+            kernel: b"\x7fELF kernel".to_vec(),
+            app: ElfBlob {
+                start: 0x40_0000,
+                len: 0x1234,
+                base: 0,
+            },
+            interp: ElfBlob::default(),
+            secret: None,
+            files: vec![None, Some(PathBuf::from("/sysroot/lib/libc.so"))],
+        };
+
+        let mut buf = Vec::new();
+        snapshot.write(&mut buf).unwrap();
+        buf.extend_from_slice(b"memory");
+
+        let mut input = &buf[..];
+        let read = Snapshot::read(&mut input).unwrap();
+        assert_eq!(input, b"memory");
+        assert_eq!(read.regions, snapshot.regions);
+        assert_eq!(read.irqchips.len(), 1);
+        assert_eq!(read.irqchips[0].chip_id, 2);
+        assert_eq!(read.vcpus.len(), 1);
+        assert_eq!(read.vcpus[0].regs.rip, 0xFFFF_8000_0001_2345);
+        assert_eq!(read.vcpus[0].sregs.cr3, 0x9000);
+        assert_eq!(read.vcpus[0].lapic.regs[0x20], 1);
+        assert_eq!(read.vcpus[0].msrs.len(), 1);
+        assert_eq!(read.vcpus[0].msrs[0].data, 42);
+        assert_eq!(read.kernel, snapshot.kernel);
+        assert_eq!(read.app.len, 0x1234);
+        assert!(read.secret.is_none());
+        assert_eq!(read.files, snapshot.files);
+
+        assert!(Snapshot::read(&mut &buf[1..]).is_err());
+        assert!(Snapshot::read(&mut &buf[..100]).is_err());
+    }
+}
@@ -0,0 +1,726 @@
+//! Sockets of the host for the guest
+//!
+//! The kernel forwards the socket syscalls of the app. The hypervisor creates
+//! real host sockets, the guest only gets their index in [`Sockets`]. Which
+//! addresses the guest may bind, listen on and connect to is limited by a
+//! [`Policy`], everything else fails with `EACCES`:
+//!
+//! * `bind` to a TCP or UDP port `0` of any address is always allowed, it
+//!   picks an ephemeral port for a client socket,
+//! * `bind` to any other address and `listen` need an `--allow-listen` rule,
+//! * `connect` and `sendto` with an address need an `--allow-connect` rule.
+//!
+//! A rule is `<ip>:<port>`, `[<ipv6>]:<port>` or `unix:<path>`, where the port
+//! may be `*` for any port and a path starting with `@` is an abstract Unix
+//! socket. `socketpair` needs no rule.
+//!
+//! The host sockets are non-blocking. A blocking call of the guest waits with
+//! `poll(2)`, which stops the vCPU like a blocking syscall would.
+
+use linux_errno::ErrNo;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::io::RawFd;
+use std::str::FromStr;
+use vmsyscall::{SockAddr, SOCKADDR_LEN, SOCKET_BUF_LEN, SOCKOPT_LEN};
+
+/// Maximum number of sockets opened at the same time
+pub const MAX_SOCKETS: usize = 256;
+
+fn errno(e: io::Error) -> vmsyscall::Error {
+    vmsyscall::Error::Errno(
+        e.raw_os_error()
+            .map_or(Into::<i64>::into(ErrNo::EIO), |e| e as _),
+    )
+}
+
+fn err(errno: ErrNo) -> vmsyscall::Error {
+    vmsyscall::Error::Errno(errno.into())
+}
+
+/// The result of a libc call, `errno` for `-1`
+fn cvt<T: Default + PartialOrd>(ret: T) -> Result<T, vmsyscall::Error> {
+    if ret < T::default() {
+        Err(errno(io::Error::last_os_error()))
+    } else {
+        Ok(ret)
+    }
+}
+
+fn is_errno(e: &vmsyscall::Error, errno: i32) -> bool {
+    *e == vmsyscall::Error::Errno(errno as _)
+}
+
+/// The address of a socket
+#[derive(Clone, Debug, PartialEq)]
+pub enum Addr {
+    Inet(SocketAddr),
+    /// The path of a Unix socket, an abstract name starts with `@`
+    Unix(Vec<u8>),
+}
+
+impl Addr {
+    /// The address in the `struct sockaddr` bytes of the guest
+    pub fn parse(bytes: &[u8]) -> Result<Self, vmsyscall::Error> {
+        if bytes.len() < 2 {
+            return Err(err(ErrNo::EINVAL));
+        }
+
+        let port = |b: &[u8]| u16::from_be_bytes([b[2], b[3]]);
+        match u16::from_ne_bytes([bytes[0], bytes[1]]) as i32 {
+            libc::AF_INET if bytes.len() >= 8 => {
+                let ip = Ipv4Addr::new(bytes[4], bytes[5], bytes[6], bytes[7]);
+                Ok(Addr::Inet(SocketAddr::new(ip.into(), port(bytes))))
+            }
+            libc::AF_INET6 if bytes.len() >= 24 => {
+                let mut ip = [0u8; 16];
+                ip.copy_from_slice(&bytes[8..24]);
+                let ip = Ipv6Addr::from(ip);
+                Ok(Addr::Inet(SocketAddr::new(ip.into(), port(bytes))))
+            }
+            libc::AF_UNIX => {
+                let path = &bytes[2..];
+                let path = match path.split_first() {
+                    Some((0, name)) => [b"@", name].concat(),
+                    _ => path.split(|b| *b == 0).next().unwrap_or(&[]).to_vec(),
+                };
+                Ok(Addr::Unix(path))
+            }
+            libc::AF_INET | libc::AF_INET6 => Err(err(ErrNo::EINVAL)),
+            _ => Err(err(ErrNo::EAFNOSUPPORT)),
+        }
+    }
+}
+
+/// An address of an `--allow-listen` or `--allow-connect` rule
+#[derive(Clone, Debug, PartialEq)]
+pub enum Rule {
+    /// An IP address and a port, `None` for any port
+    Inet(IpAddr, Option<u16>),
+    /// The path of a Unix socket, an abstract name starts with `@`
+    Unix(Vec<u8>),
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(format!("Empty Unix socket path in `{}`", s));
+            }
+            return Ok(Rule::Unix(path.as_bytes().to_vec()));
+        }
+
+        let invalid = || format!("Invalid address `{}`, expected <ip>:<port>", s);
+        let colon = s.rfind(':').ok_or_else(invalid)?;
+        let (host, port) = (&s[..colon], &s[colon + 1..]);
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+        let ip = host.parse::<IpAddr>().map_err(|_| invalid())?;
+        let port = match port {
+            "*" => None,
+            port => Some(port.parse::<u16>().map_err(|_| invalid())?),
+        };
+        Ok(Rule::Inet(ip, port))
+    }
+}
+
+impl Rule {
+    fn matches(&self, addr: &Addr) -> bool {
+        match (self, addr) {
+            (Rule::Inet(ip, port), Addr::Inet(addr)) => {
+                *ip == addr.ip() && port.map_or(true, |port| port == addr.port())
+            }
+            (Rule::Unix(rule), Addr::Unix(path)) => rule == path,
+            _ => false,
+        }
+    }
+}
+
+/// The addresses the guest may listen on and connect to
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    pub listen: Vec<Rule>,
+    pub connect: Vec<Rule>,
+}
+
+impl Policy {
+    pub fn allows_listen(&self, addr: &Addr) -> bool {
+        self.listen.iter().any(|rule| rule.matches(addr))
+    }
+
+    pub fn allows_connect(&self, addr: &Addr) -> bool {
+        self.connect.iter().any(|rule| rule.matches(addr))
+    }
+
+    /// May the guest bind a socket to `addr`
+    fn allows_bind(&self, addr: &Addr) -> bool {
+        matches!(addr, Addr::Inet(addr) if addr.port() == 0) || self.allows_listen(addr)
+    }
+}
+
+/// A host socket, closed on drop
+struct Socket {
+    fd: RawFd,
+    /// The address of `bind`
+    bound: Option<Addr>,
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Wait until `fd` is ready for `events`
+fn wait(fd: RawFd, events: i16) -> Result<(), vmsyscall::Error> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    };
+    loop {
+        match cvt(unsafe { libc::poll(&mut pollfd, 1, -1) }) {
+            Err(e) if is_errno(&e, libc::EINTR) => {}
+            ret => return ret.map(|_| ()),
+        }
+    }
+}
+
+/// Call `op` until it doesn't fail with `EAGAIN`, waiting for `events` unless `nonblock`
+fn retry<T>(
+    fd: RawFd,
+    events: i16,
+    nonblock: bool,
+    mut op: impl FnMut() -> Result<T, vmsyscall::Error>,
+) -> Result<T, vmsyscall::Error> {
+    loop {
+        match op() {
+            Err(e) if is_errno(&e, libc::EAGAIN) && !nonblock => wait(fd, events)?,
+            Err(e) if is_errno(&e, libc::EINTR) => {}
+            ret => return ret,
+        }
+    }
+}
+
+/// The address of `getsockname` or `getpeername`
+fn sockname(
+    fd: RawFd,
+    f: unsafe extern "C" fn(RawFd, *mut libc::sockaddr, *mut libc::socklen_t) -> i32,
+) -> Result<SockAddr, vmsyscall::Error> {
+    let mut addr = SockAddr::new(&[0u8; SOCKADDR_LEN]).unwrap();
+    let mut len = SOCKADDR_LEN as libc::socklen_t;
+    cvt(unsafe { f(fd, addr.data.as_mut_ptr() as _, &mut len) })?;
+    addr.len = len.min(SOCKADDR_LEN as _);
+    Ok(addr)
+}
+
+/// The socket option levels the guest may use
+fn check_level(level: i32) -> Result<(), vmsyscall::Error> {
+    match level {
+        libc::SOL_SOCKET
+        | libc::IPPROTO_IP
+        | libc::IPPROTO_IPV6
+        | libc::IPPROTO_TCP
+        | libc::IPPROTO_UDP => Ok(()),
+        _ => Err(err(ErrNo::ENOPROTOOPT)),
+    }
+}
+
+/// The sockets opened by the guest
+pub struct Sockets {
+    policy: Policy,
+    sockets: Vec<Option<Socket>>,
+}
+
+impl Sockets {
+    pub fn new(policy: Policy) -> Self {
+        Sockets {
+            policy,
+            sockets: vec![],
+        }
+    }
+
+    fn socket(&mut self, fd: u32) -> Result<&mut Socket, vmsyscall::Error> {
+        self.sockets
+            .get_mut(fd as usize)
+            .and_then(Option::as_mut)
+            .ok_or_else(|| err(ErrNo::EBADF))
+    }
+
+    /// The host file descriptor of the socket `fd`
+    pub fn raw_fd(&self, fd: u32) -> Option<RawFd> {
+        self.sockets
+            .get(fd as usize)
+            .and_then(Option::as_ref)
+            .map(|socket| socket.fd)
+    }
+
+    /// No socket is open
+    pub fn is_empty(&self) -> bool {
+        self.sockets.iter().all(Option::is_none)
+    }
+
+    /// Add `fd` to the table, closes it if the table is full
+    fn insert(&mut self, fd: RawFd) -> Result<i32, vmsyscall::Error> {
+        let socket = Socket { fd, bound: None };
+        let index = match self.sockets.iter().position(Option::is_none) {
+            Some(index) => index,
+            None if self.sockets.len() < MAX_SOCKETS => {
+                self.sockets.push(None);
+                self.sockets.len() - 1
+            }
+            None => return Err(err(ErrNo::EMFILE)),
+        };
+        self.sockets[index].replace(socket);
+        Ok(index as _)
+    }
+
+    pub fn socket_create(
+        &mut self,
+        domain: i32,
+        ty: i32,
+        protocol: i32,
+    ) -> Result<i32, vmsyscall::Error> {
+        match domain {
+            libc::AF_UNIX | libc::AF_INET | libc::AF_INET6 => {}
+            _ => return Err(err(ErrNo::EAFNOSUPPORT)),
+        }
+        match ty {
+            libc::SOCK_STREAM | libc::SOCK_DGRAM => {}
+            libc::SOCK_SEQPACKET if domain == libc::AF_UNIX => {}
+            _ => return Err(err(ErrNo::ESOCKTNOSUPPORT)),
+        }
+
+        let flags = libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+        let fd = cvt(unsafe { libc::socket(domain, ty | flags, protocol) })?;
+        self.insert(fd)
+    }
+
+    pub fn socketpair(
+        &mut self,
+        domain: i32,
+        ty: i32,
+        protocol: i32,
+    ) -> Result<(i32, i32), vmsyscall::Error> {
+        if domain != libc::AF_UNIX {
+            return Err(err(ErrNo::EOPNOTSUPP));
+        }
+        match ty {
+            libc::SOCK_STREAM | libc::SOCK_DGRAM | libc::SOCK_SEQPACKET => {}
+            _ => return Err(err(ErrNo::ESOCKTNOSUPPORT)),
+        }
+
+        let flags = libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+        let mut fds = [0; 2];
+        cvt(unsafe { libc::socketpair(domain, ty | flags, protocol, fds.as_mut_ptr()) })?;
+        let first = match self.insert(fds[0]) {
+            Ok(first) => first,
+            Err(e) => {
+                unsafe { libc::close(fds[1]) };
+                return Err(e);
+            }
+        };
+        match self.insert(fds[1]) {
+            Ok(second) => Ok((first, second)),
+            Err(e) => {
+                self.sockets[first as usize].take();
+                Err(e)
+            }
+        }
+    }
+
+    pub fn bind(&mut self, fd: u32, addr: &SockAddr) -> Result<i32, vmsyscall::Error> {
+        let parsed = Addr::parse(addr.as_bytes())?;
+        if !self.policy.allows_bind(&parsed) {
+            return Err(err(ErrNo::EACCES));
+        }
+
+        let socket = self.socket(fd)?;
+        cvt(unsafe { libc::bind(socket.fd, addr.data.as_ptr() as _, addr.len) })?;
+        socket.bound.replace(parsed);
+        Ok(0)
+    }
+
+    /// Only a socket bound to an `--allow-listen` address may listen
+    pub fn listen(&mut self, fd: u32, backlog: i32) -> Result<i32, vmsyscall::Error> {
+        let policy = &self.policy;
+        let socket = self
+            .sockets
+            .get(fd as usize)
+            .and_then(Option::as_ref)
+            .ok_or_else(|| err(ErrNo::EBADF))?;
+        if !socket
+            .bound
+            .as_ref()
+            .is_some_and(|a| policy.allows_listen(a))
+        {
+            return Err(err(ErrNo::EACCES));
+        }
+        cvt(unsafe { libc::listen(socket.fd, backlog) })
+    }
+
+    pub fn accept(&mut self, fd: u32, nonblock: bool) -> Result<(i32, SockAddr), vmsyscall::Error> {
+        let listener = self.socket(fd)?.fd;
+        let mut addr = SockAddr::new(&[0u8; SOCKADDR_LEN]).unwrap();
+        let mut len = SOCKADDR_LEN as libc::socklen_t;
+        let conn = retry(listener, libc::POLLIN, nonblock, || {
+            len = SOCKADDR_LEN as _;
+            let flags = libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+            cvt(unsafe { libc::accept4(listener, addr.data.as_mut_ptr() as _, &mut len, flags) })
+        })?;
+        addr.len = len.min(SOCKADDR_LEN as _);
+        Ok((self.insert(conn)?, addr))
+    }
+
+    /// An `AF_UNSPEC` address dissolves the association of a datagram socket
+    pub fn connect(
+        &mut self,
+        fd: u32,
+        addr: &SockAddr,
+        nonblock: bool,
+    ) -> Result<i32, vmsyscall::Error> {
+        let bytes = addr.as_bytes();
+        let unspec = bytes.len() >= 2 && u16::from_ne_bytes([bytes[0], bytes[1]]) == 0;
+        if !unspec && !self.policy.allows_connect(&Addr::parse(bytes)?) {
+            return Err(err(ErrNo::EACCES));
+        }
+
+        let socket = self.socket(fd)?.fd;
+        match cvt(unsafe { libc::connect(socket, addr.data.as_ptr() as _, addr.len) }) {
+            Err(e) if is_errno(&e, libc::EINPROGRESS) && !nonblock => {
+                wait(socket, libc::POLLOUT)?;
+                let mut error = 0i32;
+                let mut len = std::mem::size_of::<i32>() as libc::socklen_t;
+                cvt(unsafe {
+                    libc::getsockopt(
+                        socket,
+                        libc::SOL_SOCKET,
+                        libc::SO_ERROR,
+                        &mut error as *mut i32 as _,
+                        &mut len,
+                    )
+                })?;
+                match error {
+                    0 => Ok(0),
+                    error => Err(vmsyscall::Error::Errno(error as _)),
+                }
+            }
+            ret => ret,
+        }
+    }
+
+    /// Send `data`, to `addr` if given, a broken connection is `EPIPE` without `SIGPIPE`
+    pub fn sendto(
+        &mut self,
+        fd: u32,
+        data: &[u8],
+        flags: i32,
+        addr: Option<&SockAddr>,
+        nonblock: bool,
+    ) -> Result<i32, vmsyscall::Error> {
+        if let Some(addr) = addr {
+            if !self.policy.allows_connect(&Addr::parse(addr.as_bytes())?) {
+                return Err(err(ErrNo::EACCES));
+            }
+        }
+
+        let socket = self.socket(fd)?.fd;
+        let (addr, addr_len) = addr.map_or((std::ptr::null(), 0), |a| (a.data.as_ptr(), a.len));
+        let flags = flags | libc::MSG_DONTWAIT | libc::MSG_NOSIGNAL;
+        retry(socket, libc::POLLOUT, nonblock, || {
+            cvt(unsafe {
+                libc::sendto(
+                    socket,
+                    data.as_ptr() as _,
+                    data.len(),
+                    flags,
+                    addr as _,
+                    addr_len,
+                )
+            })
+            .map(|len| len as _)
+        })
+    }
+
+    /// Receive up to `count` bytes and the address of the sender
+    pub fn recvfrom(
+        &mut self,
+        fd: u32,
+        count: usize,
+        flags: i32,
+        nonblock: bool,
+    ) -> Result<(i32, [u8; SOCKET_BUF_LEN], SockAddr), vmsyscall::Error> {
+        let socket = self.socket(fd)?.fd;
+        let mut buf = [0u8; SOCKET_BUF_LEN];
+        let mut addr = SockAddr::new(&[0u8; SOCKADDR_LEN]).unwrap();
+        let mut len = SOCKADDR_LEN as libc::socklen_t;
+        let count = count.min(SOCKET_BUF_LEN);
+        let flags = flags | libc::MSG_DONTWAIT;
+        let received = retry(socket, libc::POLLIN, nonblock, || {
+            len = SOCKADDR_LEN as _;
+            cvt(unsafe {
+                libc::recvfrom(
+                    socket,
+                    buf.as_mut_ptr() as _,
+                    count,
+                    flags,
+                    addr.data.as_mut_ptr() as _,
+                    &mut len,
+                )
+            })
+        })?;
+        addr.len = len.min(SOCKADDR_LEN as _);
+        Ok((received as _, buf, addr))
+    }
+
+    pub fn getsockname(&mut self, fd: u32) -> Result<SockAddr, vmsyscall::Error> {
+        sockname(self.socket(fd)?.fd, libc::getsockname)
+    }
+
+    pub fn getpeername(&mut self, fd: u32) -> Result<SockAddr, vmsyscall::Error> {
+        sockname(self.socket(fd)?.fd, libc::getpeername)
+    }
+
+    pub fn setsockopt(
+        &mut self,
+        fd: u32,
+        level: i32,
+        name: i32,
+        value: &[u8],
+    ) -> Result<i32, vmsyscall::Error> {
+        check_level(level)?;
+        if level == libc::SOL_SOCKET && name == libc::SO_BINDTODEVICE {
+            return Err(err(ErrNo::EPERM));
+        }
+        let socket = self.socket(fd)?.fd;
+        cvt(unsafe { libc::setsockopt(socket, level, name, value.as_ptr() as _, value.len() as _) })
+    }
+
+    pub fn getsockopt(
+        &mut self,
+        fd: u32,
+        level: i32,
+        name: i32,
+        len: u32,
+    ) -> Result<(u32, [u8; SOCKOPT_LEN]), vmsyscall::Error> {
+        check_level(level)?;
+        let socket = self.socket(fd)?.fd;
+        let mut value = [0u8; SOCKOPT_LEN];
+        let mut len = len.min(SOCKOPT_LEN as _);
+        cvt(unsafe { libc::getsockopt(socket, level, name, value.as_mut_ptr() as _, &mut len) })?;
+        Ok((len, value))
+    }
+
+    pub fn shutdown(&mut self, fd: u32, how: i32) -> Result<i32, vmsyscall::Error> {
+        cvt(unsafe { libc::shutdown(self.socket(fd)?.fd, how) })
+    }
+
+    pub fn close(&mut self, fd: u32) -> Result<i32, vmsyscall::Error> {
+        self.sockets
+            .get_mut(fd as usize)
+            .and_then(Option::take)
+            .map(|_| 0)
+            .ok_or_else(|| err(ErrNo::EBADF))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `sockaddr_in` or a `sockaddr_in6` with a zero flow info and scope
+    fn inet(addr: &str) -> SockAddr {
+        let addr: SocketAddr = addr.parse().unwrap();
+        let family = match addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        let mut bytes = (family as u16).to_ne_bytes().to_vec();
+        bytes.extend_from_slice(&addr.port().to_be_bytes());
+        match addr.ip() {
+            IpAddr::V4(ip) => {
+                bytes.extend_from_slice(&ip.octets());
+                bytes.resize(16, 0);
+            }
+            IpAddr::V6(ip) => {
+                bytes.extend_from_slice(&[0u8; 4]);
+                bytes.extend_from_slice(&ip.octets());
+                bytes.resize(28, 0);
+            }
+        }
+        SockAddr::new(&bytes).unwrap()
+    }
+
+    fn policy(listen: &[&str], connect: &[&str]) -> Policy {
+        Policy {
+            listen: listen.iter().map(|r| r.parse().unwrap()).collect(),
+            connect: connect.iter().map(|r| r.parse().unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn parse_rules() {
+        let any_port = Rule::Inet(Ipv4Addr::LOCALHOST.into(), None);
+        assert_eq!("127.0.0.1:*".parse(), Ok(any_port));
+        assert_eq!(
+            "[::1]:8080".parse(),
+            Ok(Rule::Inet(Ipv6Addr::LOCALHOST.into(), Some(8080)))
+        );
+        assert_eq!("unix:@app".parse(), Ok(Rule::Unix(b"@app".to_vec())));
+        assert!("localhost:8080".parse::<Rule>().is_err());
+        assert!("127.0.0.1".parse::<Rule>().is_err());
+        assert!("127.0.0.1:65536".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn parse_addresses() {
+        assert_eq!(
+            Addr::parse(inet("127.0.0.1:8080").as_bytes()),
+            Ok(Addr::Inet("127.0.0.1:8080".parse().unwrap()))
+        );
+        assert_eq!(
+            Addr::parse(inet("[::1]:8080").as_bytes()),
+            Ok(Addr::Inet("[::1]:8080".parse().unwrap()))
+        );
+
+        let mut unix = (libc::AF_UNIX as u16).to_ne_bytes().to_vec();
+        unix.extend_from_slice(b"/run/app.sock\0");
+        assert_eq!(
+            Addr::parse(&unix),
+            Ok(Addr::Unix(b"/run/app.sock".to_vec()))
+        );
+
+        let mut abstract_name = (libc::AF_UNIX as u16).to_ne_bytes().to_vec();
+        abstract_name.extend_from_slice(b"\0app");
+        assert_eq!(
+            Addr::parse(&abstract_name),
+            Ok(Addr::Unix(b"@app".to_vec()))
+        );
+
+        assert_eq!(
+            Addr::parse(&[0xff, 0xff, 0, 0]),
+            Err(err(ErrNo::EAFNOSUPPORT))
+        );
+    }
+
+    #[test]
+    fn policy_denies() {
+        let mut sockets = Sockets::new(policy(&["127.0.0.1:8080"], &[]));
+        let fd = sockets
+            .socket_create(libc::AF_INET, libc::SOCK_STREAM, 0)
+            .unwrap() as u32;
+
+        assert_eq!(
+            sockets.bind(fd, &inet("127.0.0.1:8081")),
+            Err(err(ErrNo::EACCES))
+        );
+        assert_eq!(sockets.listen(fd, 1), Err(err(ErrNo::EACCES)));
+        assert_eq!(
+            sockets.connect(fd, &inet("127.0.0.1:8080"), false),
+            Err(err(ErrNo::EACCES))
+        );
+
+        // An ephemeral port may be bound, but not listened on
+        sockets.bind(fd, &inet("127.0.0.1:0")).unwrap();
+        assert_eq!(sockets.listen(fd, 1), Err(err(ErrNo::EACCES)));
+
+        assert_eq!(
+            sockets.socket_create(libc::AF_PACKET, libc::SOCK_RAW, 0),
+            Err(err(ErrNo::EAFNOSUPPORT))
+        );
+        assert_eq!(
+            sockets.socket_create(libc::AF_INET, libc::SOCK_RAW, 0),
+            Err(err(ErrNo::ESOCKTNOSUPPORT))
+        );
+        assert_eq!(sockets.close(fd), Ok(0));
+        assert_eq!(sockets.close(fd), Err(err(ErrNo::EBADF)));
+    }
+
+    #[test]
+    fn tcp_loopback() {
+        let mut sockets = Sockets::new(policy(&["127.0.0.1:*"], &["127.0.0.1:*"]));
+        let listener = sockets
+            .socket_create(libc::AF_INET, libc::SOCK_STREAM, 0)
+            .unwrap() as u32;
+        sockets.bind(listener, &inet("127.0.0.1:0")).unwrap();
+        sockets.listen(listener, 1).unwrap();
+        let addr = sockets.getsockname(listener).unwrap();
+        assert_eq!(
+            sockets.accept(listener, true).err(),
+            Some(err(ErrNo::EAGAIN))
+        );
+
+        let client = sockets
+            .socket_create(libc::AF_INET, libc::SOCK_STREAM, 0)
+            .unwrap() as u32;
+        sockets.connect(client, &addr, false).unwrap();
+        let (conn, peer) = sockets.accept(listener, false).unwrap();
+        let conn = conn as u32;
+        assert_eq!(
+            Addr::parse(peer.as_bytes()),
+            Addr::parse(sockets.getsockname(client).unwrap().as_bytes())
+        );
+
+        assert_eq!(sockets.sendto(client, b"ping", 0, None, false), Ok(4));
+        let (len, buf, _) = sockets.recvfrom(conn, 16, 0, false).unwrap();
+        assert_eq!(&buf[..len as usize], b"ping");
+
+        sockets.shutdown(client, libc::SHUT_WR).unwrap();
+        assert_eq!(sockets.recvfrom(conn, 16, 0, false).unwrap().0, 0);
+
+        sockets.close(client).unwrap();
+        assert_eq!(
+            sockets.sendto(client, b"pong", 0, None, false),
+            Err(err(ErrNo::EBADF))
+        );
+    }
+
+    #[test]
+    fn udp_and_socketpair() {
+        let mut sockets = Sockets::new(policy(&[], &["127.0.0.1:*"]));
+        let receiver = sockets
+            .socket_create(libc::AF_INET, libc::SOCK_DGRAM, 0)
+            .unwrap() as u32;
+        sockets.bind(receiver, &inet("127.0.0.1:0")).unwrap();
+        let addr = sockets.getsockname(receiver).unwrap();
+
+        let sender = sockets
+            .socket_create(libc::AF_INET, libc::SOCK_DGRAM, 0)
+            .unwrap() as u32;
+        assert_eq!(
+            sockets.sendto(sender, b"datagram", 0, Some(&addr), false),
+            Ok(8)
+        );
+        let (len, buf, from) = sockets.recvfrom(receiver, 16, 0, false).unwrap();
+        assert_eq!(&buf[..len as usize], b"datagram");
+        let port = |addr: &SockAddr| match Addr::parse(addr.as_bytes()) {
+            Ok(Addr::Inet(addr)) => addr.port(),
+            _ => 0,
+        };
+        assert_eq!(port(&from), port(&sockets.getsockname(sender).unwrap()));
+
+        let (a, b) = sockets
+            .socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0)
+            .unwrap();
+        assert_eq!(sockets.sendto(a as _, b"x", 0, None, false), Ok(1));
+        assert_eq!(sockets.recvfrom(b as _, 1, 0, true).unwrap().0, 1);
+        assert_eq!(
+            sockets.recvfrom(b as _, 1, 0, true).err(),
+            Some(err(ErrNo::EAGAIN))
+        );
+
+        let mut value = [0u8; 4];
+        value.copy_from_slice(&1i32.to_ne_bytes());
+        sockets
+            .setsockopt(a as _, libc::SOL_SOCKET, libc::SO_KEEPALIVE, &value)
+            .unwrap();
+        let (len, value) = sockets
+            .getsockopt(a as _, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 4)
+            .unwrap();
+        assert_eq!(&value[..len as usize], &1i32.to_ne_bytes());
+    }
+}
@@ -0,0 +1,338 @@
+//! Read-only files of the host for the guest
+//!
+//! The ELF interpreter of a dynamically linked app and the shared libraries it
+//! loads are read from a sysroot directory on the host, `/` by default. Paths
+//! of the guest are resolved relative to the sysroot, symlinks included.
+
+use linux_errno::ErrNo;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Component, Path, PathBuf};
+use vmsyscall::{FileStat, READ_BUF_LEN};
+
+/// Maximum number of files opened at the same time
+pub const MAX_OPEN_FILES: usize = 64;
+
+/// Maximum number of symlinks followed for one path, like Linux
+const MAX_SYMLINKS: usize = 40;
+
+/// Push the components of `path` to `todo`, the first one last
+fn push_components(todo: &mut Vec<OsString>, path: &Path) {
+    for component in path.components().rev() {
+        match component {
+            Component::Normal(c) => todo.push(c.to_os_string()),
+            Component::ParentDir => todo.push(OsString::from("..")),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+}
+
+/// Resolve the absolute guest `path` in `root`
+///
+/// Symlinks are followed in `root`, like with `RESOLVE_IN_ROOT` of
+/// `openat2()`: an absolute target starts at `root` again and `..` in a
+/// target stops at `root`. `None` for relative paths, paths with `..`
+/// components and too many symlinks.
+pub fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    if !path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+        return None;
+    }
+
+    let mut todo = Vec::new();
+    push_components(&mut todo, path);
+
+    let mut resolved = root.to_path_buf();
+    let mut depth = 0;
+    let mut symlinks = 0;
+    while let Some(name) = todo.pop() {
+        // only symlink targets have `..`
+        if name == ".." {
+            if depth > 0 {
+                resolved.pop();
+                depth -= 1;
+            }
+            continue;
+        }
+
+        resolved.push(&name);
+        let is_symlink = fs::symlink_metadata(&resolved)
+            .map(|metadata| metadata.file_type().is_symlink())
+            .unwrap_or(false);
+        if !is_symlink {
+            depth += 1;
+            continue;
+        }
+
+        symlinks += 1;
+        if symlinks > MAX_SYMLINKS {
+            return None;
+        }
+        let target = fs::read_link(&resolved).ok()?;
+        resolved.pop();
+        if target.is_absolute() {
+            resolved = root.to_path_buf();
+            depth = 0;
+        }
+        push_components(&mut todo, &target);
+    }
+    Some(resolved)
+}
+
+fn errno(e: io::Error) -> vmsyscall::Error {
+    vmsyscall::Error::Errno(
+        e.raw_os_error()
+            .map_or(Into::<i64>::into(ErrNo::EIO), |e| e as _),
+    )
+}
+
+fn err(errno: ErrNo) -> vmsyscall::Error {
+    vmsyscall::Error::Errno(errno.into())
+}
+
+/// A file opened by the guest and its host path
+struct OpenFile {
+    path: PathBuf,
+    file: File,
+}
+
+/// The files opened by the guest in a sysroot
+pub struct Sysroot {
+    root: PathBuf,
+    files: Vec<Option<OpenFile>>,
+}
+
+impl Sysroot {
+    /// Serve the files in `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Sysroot {
+            root: root.into(),
+            files: vec![],
+        }
+    }
+
+    /// The directory guest paths are resolved in
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn file(&self, fd: u32) -> Result<&File, vmsyscall::Error> {
+        self.files
+            .get(fd as usize)
+            .and_then(Option::as_ref)
+            .map(|open| &open.file)
+            .ok_or_else(|| err(ErrNo::EBADF))
+    }
+
+    /// Open the file `path` read-only, returns the file descriptor
+    pub fn open(&mut self, path: &[u8], flags: i32) -> Result<i32, vmsyscall::Error> {
+        const O_ACCMODE: i32 = 0o3;
+
+        if flags & O_ACCMODE != 0 {
+            return Err(err(ErrNo::EACCES));
+        }
+
+        let path = std::str::from_utf8(path).map_err(|_| err(ErrNo::ENOENT))?;
+        let path = resolve(&self.root, path).ok_or_else(|| err(ErrNo::ENOENT))?;
+        let file = File::open(&path).map_err(errno)?;
+
+        let fd = match self.files.iter().position(Option::is_none) {
+            Some(fd) => fd,
+            None if self.files.len() < MAX_OPEN_FILES => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => return Err(err(ErrNo::EMFILE)),
+        };
+        self.files[fd].replace(OpenFile { path, file });
+        Ok(fd as _)
+    }
+
+    /// Read up to `count` bytes at `offset`
+    pub fn pread(
+        &self,
+        fd: u32,
+        count: usize,
+        offset: i64,
+    ) -> Result<(i32, [u8; READ_BUF_LEN]), vmsyscall::Error> {
+        let file = self.file(fd)?;
+        if offset < 0 {
+            return Err(err(ErrNo::EINVAL));
+        }
+
+        let mut buf = [0u8; READ_BUF_LEN];
+        let count = count.min(READ_BUF_LEN);
+        let len = file
+            .read_at(&mut buf[..count], offset as _)
+            .map_err(errno)?;
+        Ok((len as _, buf))
+    }
+
+    /// The mode and size of an open file
+    pub fn fstat(&self, fd: u32) -> Result<FileStat, vmsyscall::Error> {
+        let metadata = self.file(fd)?.metadata().map_err(errno)?;
+        Ok(FileStat {
+            st_mode: metadata.mode(),
+            st_size: metadata.len(),
+        })
+    }
+
+    /// Close an open file
+    pub fn close(&mut self, fd: u32) -> Result<i32, vmsyscall::Error> {
+        self.files
+            .get_mut(fd as usize)
+            .and_then(Option::take)
+            .map(|_| 0)
+            .ok_or_else(|| err(ErrNo::EBADF))
+    }
+
+    /// The host paths of the open files, by file descriptor
+    pub fn open_paths(&self) -> Vec<Option<PathBuf>> {
+        self.files
+            .iter()
+            .map(|open| open.as_ref().map(|open| open.path.clone()))
+            .collect()
+    }
+
+    /// Open the files of `open_paths()` again, with the same file descriptors
+    pub fn reopen(&mut self, paths: &[Option<PathBuf>]) -> io::Result<()> {
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            files.push(match path {
+                Some(path) => Some(OpenFile {
+                    path: path.clone(),
+                    file: File::open(path)?,
+                }),
+                None => None,
+            });
+        }
+        self.files = files;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sysroot(name: &str) -> Sysroot {
+        let root =
+            std::env::temp_dir().join(format!("vmrun-sysroot-{}-{}", name, std::process::id()));
+        fs::create_dir_all(root.join("lib")).unwrap();
+        fs::write(root.join("lib/libfoo.so"), b"0123456789").unwrap();
+        Sysroot::new(root)
+    }
+
+    #[test]
+    fn resolve_paths() {
+        let root = Path::new("/sysroot");
+        assert_eq!(
+            resolve(root, "/lib/./libc.so").unwrap(),
+            Path::new("/sysroot/lib/libc.so")
+        );
+        assert_eq!(resolve(root, "/").unwrap(), root);
+        assert_eq!(resolve(root, "lib/libc.so"), None);
+        assert_eq!(resolve(root, "/lib/../../etc/shadow"), None);
+    }
+
+    #[test]
+    fn resolve_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let sysroot = sysroot("symlinks");
+        let root = sysroot.root();
+        symlink("/lib/libfoo.so", root.join("lib/abs.so")).unwrap();
+        symlink("/usr/etc", root.join("etc")).unwrap();
+        symlink("../../../lib/libfoo.so", root.join("lib/rel.so")).unwrap();
+        symlink("loop", root.join("lib/loop")).unwrap();
+
+        // an absolute symlink stays in the sysroot
+        assert_eq!(
+            resolve(root, "/lib/abs.so").unwrap(),
+            root.join("lib/libfoo.so")
+        );
+        assert_eq!(
+            resolve(root, "/etc/shadow").unwrap(),
+            root.join("usr/etc/shadow")
+        );
+        assert_eq!(
+            resolve(root, "/lib/rel.so").unwrap(),
+            root.join("lib/libfoo.so")
+        );
+        assert_eq!(resolve(root, "/lib/loop"), None);
+
+        let mut sysroot = sysroot;
+        let fd = sysroot.open(b"/lib/abs.so", 0).unwrap() as u32;
+        assert_eq!(sysroot.fstat(fd).unwrap().st_size, 10);
+        assert_eq!(sysroot.open(b"/etc/passwd", 0), Err(err(ErrNo::ENOENT)));
+
+        fs::remove_dir_all(sysroot.root()).unwrap();
+    }
+
+    #[test]
+    fn open_read_close() {
+        let mut sysroot = sysroot("read");
+
+        let fd = sysroot.open(b"/lib/libfoo.so", 0).unwrap() as u32;
+        assert_eq!(sysroot.fstat(fd).unwrap().st_size, 10);
+
+        let (len, buf) = sysroot.pread(fd, 4, 6).unwrap();
+        assert_eq!(&buf[..len as usize], b"6789");
+        let (len, _) = sysroot.pread(fd, 4, 10).unwrap();
+        assert_eq!(len, 0);
+
+        assert_eq!(sysroot.close(fd), Ok(0));
+        assert_eq!(sysroot.close(fd), Err(err(ErrNo::EBADF)));
+        assert_eq!(sysroot.pread(fd, 4, 0).err(), Some(err(ErrNo::EBADF)));
+
+        fs::remove_dir_all(sysroot.root()).unwrap();
+    }
+
+    #[test]
+    fn open_errors() {
+        let mut sysroot = sysroot("errors");
+        const O_RDWR: i32 = 2;
+
+        assert_eq!(
+            sysroot.open(b"/lib/libfoo.so", O_RDWR),
+            Err(err(ErrNo::EACCES))
+        );
+        assert_eq!(sysroot.open(b"/lib/libbar.so", 0), Err(err(ErrNo::ENOENT)));
+        assert_eq!(
+            sysroot.open(b"/lib/../lib/libfoo.so", 0),
+            Err(err(ErrNo::ENOENT))
+        );
+
+        // a closed file descriptor is reused
+        let fd = sysroot.open(b"/lib/libfoo.so", 0).unwrap();
+        sysroot.close(fd as _).unwrap();
+        assert_eq!(sysroot.open(b"/lib/libfoo.so", 0), Ok(fd));
+
+        fs::remove_dir_all(sysroot.root()).unwrap();
+    }
+
+    #[test]
+    fn reopen_files() {
+        let mut sysroot = sysroot("reopen");
+
+        let fd = sysroot.open(b"/lib/libfoo.so", 0).unwrap();
+        let closed = sysroot.open(b"/lib/libfoo.so", 0).unwrap();
+        sysroot.close(closed as _).unwrap();
+        let paths = sysroot.open_paths();
+        assert_eq!(
+            paths,
+            vec![Some(sysroot.root().join("lib/libfoo.so")), None]
+        );
+
+        let mut restored = Sysroot::new(sysroot.root());
+        restored.reopen(&paths).unwrap();
+        let (len, buf) = restored.pread(fd as _, 4, 0).unwrap();
+        assert_eq!(&buf[..len as usize], b"0123");
+        assert_eq!(restored.fstat(closed as _).err(), Some(err(ErrNo::EBADF)));
+
+        fs::remove_dir_all(sysroot.root()).unwrap();
+        assert!(restored.reopen(&paths).is_err());
+    }
+}
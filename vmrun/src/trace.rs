@@ -0,0 +1,186 @@
+//! Output of the syscalls traced by the kernel
+//!
+//! The kernel decodes a traced syscall of the app and sends it with
+//! `VmSyscall::Trace`, apart from the writes of the app. The decoded lines go
+//! to `--trace-output`, else to the stderr of the hypervisor with a `trace: `
+//! prefix. `--trace-json` writes a JSON object per syscall with the raw
+//! values as well.
+
+use linux_errno::ErrNo;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use vmsyscall::syscalls;
+
+fn errno(e: io::Error) -> vmsyscall::Error {
+    vmsyscall::Error::Errno(
+        e.raw_os_error()
+            .map_or(Into::<i64>::into(ErrNo::EIO), |e| e as _),
+    )
+}
+
+/// A traced syscall
+pub struct Event<'a> {
+    pub nr: u64,
+    pub args: [u64; 6],
+    /// `None` for a syscall that does not return
+    pub ret: Option<i64>,
+    /// The syscall decoded by the kernel
+    pub line: &'a str,
+}
+
+/// The writers of the traced syscalls
+///
+/// Every line is written at once, `vmrun` exits without dropping the VM.
+pub struct Tracer {
+    text: Option<LineWriter<File>>,
+    json: Option<LineWriter<File>>,
+}
+
+impl Tracer {
+    /// Create the files of `--trace-output` and `--trace-json`
+    pub fn new(text: Option<&Path>, json: Option<&Path>) -> io::Result<Self> {
+        let create = |path: Option<&Path>| -> io::Result<_> {
+            path.map(|path| File::create(path).map(LineWriter::new))
+                .transpose()
+        };
+        Ok(Tracer {
+            text: create(text)?,
+            json: create(json)?,
+        })
+    }
+
+    /// Write a traced syscall
+    pub fn trace(&mut self, event: &Event) -> io::Result<()> {
+        match self.text.as_mut() {
+            Some(text) => writeln!(text, "{}", event.line)?,
+            None => eprintln!("trace: {}", event.line),
+        }
+        if let Some(json) = self.json.as_mut() {
+            json.write_all(json_line(event).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Write the `VmSyscall::Trace` of the kernel
+    pub fn syscall(
+        &mut self,
+        nr: u64,
+        args: [u64; 6],
+        ret: Option<i64>,
+        line: &[u8],
+    ) -> Result<i32, vmsyscall::Error> {
+        let line = String::from_utf8_lossy(line);
+        let event = Event {
+            nr,
+            args,
+            ret,
+            line: &line,
+        };
+        self.trace(&event).map(|_| 0).map_err(errno)
+    }
+}
+
+/// `s` as a JSON string
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A traced syscall as one line of JSON
+fn json_line(event: &Event) -> String {
+    let mut s = String::new();
+    write!(s, "{{\"nr\": {}, \"name\": ", event.nr).unwrap();
+    match syscalls::name(event.nr) {
+        Some(name) => s.push_str(&json_str(name)),
+        None => s.push_str("null"),
+    }
+    s.push_str(", \"args\": [");
+    for (i, arg) in event.args.iter().enumerate() {
+        if i > 0 {
+            s.push_str(", ");
+        }
+        write!(s, "{}", arg).unwrap();
+    }
+    s.push_str("], \"ret\": ");
+    match event.ret {
+        Some(ret) => write!(s, "{}", ret).unwrap(),
+        None => s.push_str("null"),
+    }
+    match event.ret.filter(|ret| (-4095..0).contains(ret)) {
+        Some(errno) => write!(
+            s,
+            ", \"errno\": {}",
+            syscalls::errno_name(-errno).map_or_else(|| (-errno).to_string(), json_str)
+        )
+        .unwrap(),
+        None => s.push_str(", \"errno\": null"),
+    }
+    writeln!(s, ", \"line\": {}}}", json_str(event.line)).unwrap();
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_lines() {
+        let event = Event {
+            nr: 257,
+            args: [(-100i64) as u64, 0x1000, 0o2000000, 0, 0, 0],
+            ret: Some(-2),
+            line: "openat(AT_FDCWD, \"/nope\", O_RDONLY|O_CLOEXEC, 0000) = -1 ENOENT",
+        };
+        assert_eq!(
+            json_line(&event),
+            "{\"nr\": 257, \"name\": \"openat\", \"args\": [18446744073709551516, 4096, 524288, 0, 0, 0], \"ret\": -2, \"errno\": \"ENOENT\", \"line\": \"openat(AT_FDCWD, \\\"/nope\\\", O_RDONLY|O_CLOEXEC, 0000) = -1 ENOENT\"}\n"
+        );
+
+        let event = Event {
+            nr: 1000,
+            args: [0; 6],
+            ret: None,
+            line: "syscall_1000(\t)",
+        };
+        assert_eq!(
+            json_line(&event),
+            "{\"nr\": 1000, \"name\": null, \"args\": [0, 0, 0, 0, 0, 0], \"ret\": null, \"errno\": null, \"line\": \"syscall_1000(\\t)\"}\n"
+        );
+    }
+
+    #[test]
+    fn trace_files() {
+        let dir = std::env::temp_dir().join(format!("vmrun-trace-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (text, json) = (dir.join("trace.txt"), dir.join("trace.json"));
+
+        let mut tracer = Tracer::new(Some(&text), Some(&json)).unwrap();
+        let event = Event {
+            nr: 3,
+            args: [3, 0, 0, 0, 0, 0],
+            ret: Some(0),
+            line: "close(3) = 0",
+        };
+        tracer.trace(&event).unwrap();
+        drop(tracer);
+
+        assert_eq!(std::fs::read_to_string(&text).unwrap(), "close(3) = 0\n");
+        assert_eq!(std::fs::read_to_string(&json).unwrap(), json_line(&event));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -35,20 +35,265 @@ pub struct BootInfo {
     /// the memory map before passing it to the kernel. Regions marked as usable can be freely
     /// used by the kernel.
     pub memory_map: MemoryMap,
-    /// Elf entry point of the ring3 executable
-    pub entry_point: *const u8,
-    /// Elf program headers of the ring3 executable
-    pub load_addr: *const u8,
-    /// Elf number of program headers of the ring3 executable
-    pub elf_phnum: usize,
+    /// The raw ELF file of the ring3 executable, loaded by the kernel
+    pub app: ElfBlob,
+    /// The raw ELF file of the ELF interpreter of the ring3 executable,
+    /// `len` is `0` for a static executable
+    pub interp: ElfBlob,
+    /// The arguments and environment of the ring3 executable
+    pub args: ArgsBlob,
+    /// The initial RAM filesystem, a tar or cpio archive
+    pub initrd: InitrdBlob,
+    /// Limits of the writable in-memory filesystem at `/tmp`
+    pub tmpfs: TmpfsLimits,
+    /// What the kernel does with unknown, denied and traced syscalls of the app
+    pub syscalls: SyscallPolicy,
+    /// The most verbose level of the kernel log messages sent to the hypervisor
+    pub log_level: LogLevel,
     /// Syscall trigger port
     pub syscall_trigger_port: u16,
+    /// Guest physical address of the GHCB page, `0` if there is none
+    pub ghcb: u64,
+    /// Port to signal a filled GHCB with the software GHCB protocol,
+    /// `0` if `VMGEXIT` is used
+    pub ghcb_trigger_port: u16,
+    /// Guest physical address of the launch secret page, `0` if there is none
+    pub secret: u64,
+}
+
+/// A raw ELF file in guest physical memory
+///
+/// The kernel parses the program headers and maps the `PT_LOAD` segments
+/// to user virtual memory.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ElfBlob {
+    /// Guest physical address of the first byte of the file
+    pub start: u64,
+    /// Length of the file, `0` if there is none
+    pub len: u64,
+    /// Load base of a position independent (`ET_DYN`) file,
+    /// `0` for an `ET_EXEC` file
+    pub base: u64,
+}
+
+/// The arguments and environment of the app in guest physical memory
+///
+/// NUL terminated strings: the arguments, an empty string, then the
+/// environment variables. Without a blob (`len` is `0`) the kernel uses
+/// its defaults.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ArgsBlob {
+    /// Guest physical address of the first string
+    pub start: u64,
+    /// Length of all strings with their NUL bytes
+    pub len: u64,
+}
+
+/// A tar (ustar) or cpio (newc) archive in guest physical memory
+///
+/// The kernel serves the files of the archive read-only to the app.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct InitrdBlob {
+    /// Guest physical address of the archive
+    pub start: u64,
+    /// Length of the archive, `0` if there is none
+    pub len: u64,
+}
+
+/// Limits of the tmpfs the kernel keeps on its heap
+///
+/// The kernel enlarges its heap by about `size`. Without the `allocator`
+/// feature of the kernel there is no tmpfs.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct TmpfsLimits {
+    /// Maximum size of all file contents in bytes, `0` for no tmpfs
+    pub size: u64,
+    /// Maximum number of files and directories
+    pub max_inodes: u64,
+}
+
+/// What the kernel does with a syscall it does not implement
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(u8)]
+pub enum UnknownSyscall {
+    /// Return `ENOSYS`, like Linux does, so libc can probe optional syscalls
+    #[default]
+    Enosys = 0,
+    /// Stop the VM with a kernel panic
+    Panic = 1,
+    /// Log the syscall and its arguments, then return `ENOSYS`
+    LogAndEnosys = 2,
+}
+
+/// Number of syscall numbers with a bit in [`SyscallPolicy::deny`] and
+/// [`SyscallPolicy::trace`]
+///
+/// Larger numbers are unknown syscalls.
+pub const MAX_SYSCALL_NR: usize = 512;
+
+/// A bit per syscall number
+pub type SyscallSet = [u64; MAX_SYSCALL_NR / 64];
+
+fn set_bit(set: &mut SyscallSet, nr: u64, on: bool) {
+    if let Some(word) = set.get_mut(nr as usize / 64) {
+        if on {
+            *word |= 1 << (nr % 64);
+        } else {
+            *word &= !(1 << (nr % 64));
+        }
+    }
+}
+
+/// `exit` and `exit_group`, without them the app could not end
+const ALWAYS_ALLOWED: [u64; 2] = [60, 231];
+
+fn bit(set: &SyscallSet, nr: u64) -> bool {
+    set.get(nr as usize / 64)
+        .is_some_and(|word| word & (1 << (nr % 64)) != 0)
+}
+
+/// The syscall policy of the app, a seccomp-like deny list and the traced
+/// syscalls
+///
+/// A denied syscall fails with `EPERM` before the kernel looks at it,
+/// `exit` and `exit_group` are never denied. A traced syscall is decoded by
+/// the kernel and sent to the hypervisor with `VmSyscall::Trace`, tracing is
+/// off without any bit set.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct SyscallPolicy {
+    /// The action for syscalls the kernel does not implement
+    pub unknown: UnknownSyscall,
+    /// A bit per syscall number, set for the denied ones
+    pub deny: SyscallSet,
+    /// A bit per syscall number, set for the traced ones
+    pub trace: SyscallSet,
+}
+
+impl SyscallPolicy {
+    /// Deny the syscall `nr`
+    pub fn deny(&mut self, nr: u64) {
+        set_bit(&mut self.deny, nr, true);
+    }
+
+    /// Allow the syscall `nr`
+    pub fn allow(&mut self, nr: u64) {
+        set_bit(&mut self.deny, nr, false);
+    }
+
+    /// Deny all syscalls, for an allow list
+    pub fn deny_all(&mut self) {
+        self.deny = [!0; MAX_SYSCALL_NR / 64];
+    }
+
+    /// Is the syscall `nr` denied
+    pub fn is_denied(&self, nr: u64) -> bool {
+        !ALWAYS_ALLOWED.contains(&nr) && bit(&self.deny, nr)
+    }
+
+    /// Trace the syscall `nr`, or stop tracing it
+    pub fn trace(&mut self, nr: u64, on: bool) {
+        set_bit(&mut self.trace, nr, on);
+    }
+
+    /// Trace all syscalls, also the unknown ones
+    pub fn trace_all(&mut self) {
+        self.trace = [!0; MAX_SYSCALL_NR / 64];
+    }
+
+    /// Is the syscall `nr` traced
+    pub fn is_traced(&self, nr: u64) -> bool {
+        bit(&self.trace, nr)
+    }
+}
+
+/// The level of a kernel log message of `VmSyscall::Log`
+///
+/// As the level of the `BootInfo` the most verbose one sent to the
+/// hypervisor.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub enum LogLevel {
+    /// No messages, only as the level of the `BootInfo`
+    Off = 0,
+    /// The kernel or the app can not continue
+    Error = 1,
+    /// Something the user should know about
+    #[default]
+    Warn = 2,
+    /// Steps of the boot
+    Info = 3,
+    /// Details of the boot
+    Debug = 4,
+    /// Every timer tick and the like
+    Trace = 5,
+}
+
+impl LogLevel {
+    /// All levels, from the quietest
+    pub const ALL: [LogLevel; 6] = [
+        LogLevel::Off,
+        LogLevel::Error,
+        LogLevel::Warn,
+        LogLevel::Info,
+        LogLevel::Debug,
+        LogLevel::Trace,
+    ];
+
+    /// The lower case name, like `"warn"`
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+
+    /// The level of the lower case `name`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|level| level.name() == name)
+    }
+
+    /// Is a message of `level` logged with `self` as the level of the `BootInfo`
+    pub fn allows(self, level: LogLevel) -> bool {
+        level != LogLevel::Off && level <= self
+    }
+}
+
+/// Split an [`ArgsBlob`] into the arguments and the environment variables
+pub fn decode_args(blob: &[u8]) -> (impl Iterator<Item = &[u8]>, impl Iterator<Item = &[u8]>) {
+    let args_len = blob
+        .split(|&b| b == 0)
+        .take_while(|s| !s.is_empty())
+        .map(|s| s.len() + 1)
+        .sum::<usize>();
+    let (args, env) = blob.split_at(args_len);
+    // skip the empty string separating the arguments from the environment
+    let env = env.get(1..).unwrap_or(&[]);
+    (
+        args.split(|&b| b == 0).filter(|s| !s.is_empty()),
+        env.split(|&b| b == 0).filter(|s| !s.is_empty()),
+    )
 }
 
 impl fmt::Debug for BootInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("BootInfo")
             .field("memory_map", &self.memory_map)
+            .field("app", &self.app)
+            .field("interp", &self.interp)
+            .field("args", &self.args)
+            .field("initrd", &self.initrd)
+            .field("tmpfs", &self.tmpfs)
+            .field("syscalls", &self.syscalls.unknown)
+            .field("log_level", &self.log_level)
             .finish()
     }
 }
@@ -57,6 +302,60 @@ extern "C" {
     fn _improper_ctypes_check(_boot_info: BootInfo);
 }
 
+#[test]
+fn decode_args_blob() {
+    let (mut args, mut env) = decode_args(b"/init\0-v\0\0LANG=C\0");
+    assert_eq!(args.next(), Some(&b"/init"[..]));
+    assert_eq!(args.next(), Some(&b"-v"[..]));
+    assert_eq!(args.next(), None);
+    assert_eq!(env.next(), Some(&b"LANG=C"[..]));
+    assert_eq!(env.next(), None);
+
+    let (mut args, mut env) = decode_args(b"\0");
+    assert_eq!(args.next(), None);
+    assert_eq!(env.next(), None);
+}
+
+#[test]
+fn syscall_policy() {
+    let mut policy = SyscallPolicy::default();
+    assert_eq!(policy.unknown, UnknownSyscall::Enosys);
+    assert!(!policy.is_denied(41));
+    policy.deny(41);
+    assert!(policy.is_denied(41) && !policy.is_denied(42));
+
+    policy.deny_all();
+    policy.allow(1);
+    assert!(policy.is_denied(0) && !policy.is_denied(1));
+    // exit and exit_group
+    assert!(!policy.is_denied(60) && !policy.is_denied(231));
+    policy.deny(60);
+    assert!(!policy.is_denied(60));
+    // beyond the bitmap, an unknown syscall
+    assert!(!policy.is_denied(MAX_SYSCALL_NR as _));
+    policy.deny(u64::MAX);
+
+    assert!(!policy.is_traced(0));
+    policy.trace(1, true);
+    assert!(policy.is_traced(1) && !policy.is_traced(0));
+    policy.trace_all();
+    policy.trace(1, false);
+    assert!(policy.is_traced(0) && !policy.is_traced(1));
+}
+
+#[test]
+fn log_levels() {
+    assert_eq!(LogLevel::default(), LogLevel::Warn);
+    assert!(LogLevel::Warn.allows(LogLevel::Error));
+    assert!(!LogLevel::Warn.allows(LogLevel::Info));
+    assert!(!LogLevel::Off.allows(LogLevel::Error));
+    assert!(!LogLevel::Trace.allows(LogLevel::Off));
+    for level in LogLevel::ALL.iter() {
+        assert_eq!(LogLevel::from_name(level.name()), Some(*level));
+    }
+    assert_eq!(LogLevel::from_name("verbose"), None);
+}
+
 #[test]
 fn check_bootinfo_size() {
     use crate::memory_map::PAGE_SIZE;
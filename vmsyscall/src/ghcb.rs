@@ -0,0 +1,221 @@
+//! The Guest-Hypervisor Communication Block (GHCB)
+//!
+//! With SEV-ES the guest register state is encrypted, so for intercepted
+//! instructions the guest has to pass the needed register values to the
+//! hypervisor explicitly via this shared page and `VMGEXIT`.
+//!
+//! See the AMD "SEV-ES Guest-Hypervisor Communication Block Standardization"
+//! specification for the layout.
+
+/// Hard coded guest physical address of the GHCB page
+pub const GHCB_PHYS_ADDR: u64 = 0x2000;
+
+/// Trigger port for the software GHCB protocol
+///
+/// Without SEV-ES, `VMGEXIT` is a plain `vmmcall` handled inside KVM, so the
+/// guest signals a filled GHCB to the hypervisor with a write to this port.
+pub const GHCB_TRIGGER_PORT: u16 = 0xFE;
+
+/// The GHCB MSR, holding the guest physical address of the GHCB
+pub const MSR_GHCB: u32 = 0xC001_0130;
+
+/// GHCB protocol version implemented
+pub const GHCB_PROTOCOL_VERSION: u16 = 1;
+
+/// SVM exit code of `cpuid`
+pub const SVM_EXIT_CPUID: u64 = 0x72;
+/// SVM exit code of `in` and `out`
+pub const SVM_EXIT_IOIO: u64 = 0x7B;
+/// SVM exit code of `rdmsr` and `wrmsr`
+pub const SVM_EXIT_MSR: u64 = 0x7C;
+
+/// `sw_exit_info_1` of an IOIO exit: `in` instead of `out`
+pub const IOIO_TYPE_IN: u64 = 1;
+/// `sw_exit_info_1` of an IOIO exit: shift of the port number
+pub const IOIO_PORT_SHIFT: u64 = 16;
+/// `sw_exit_info_1` of an IOIO exit: shift of the one-hot operand size (1, 2 or 4 bytes)
+pub const IOIO_SIZE_SHIFT: u64 = 4;
+/// `sw_exit_info_1` of an IOIO exit: 16 bit address size
+pub const IOIO_ADDR_16: u64 = 1 << 7;
+
+const RAX_OFFSET: usize = 0x1F8;
+const RCX_OFFSET: usize = 0x308;
+const RDX_OFFSET: usize = 0x310;
+const RBX_OFFSET: usize = 0x318;
+const SW_EXIT_CODE_OFFSET: usize = 0x390;
+const SW_EXIT_INFO_1_OFFSET: usize = 0x398;
+const SW_EXIT_INFO_2_OFFSET: usize = 0x3A0;
+const XCR0_OFFSET: usize = 0x3E8;
+
+/// Encode `sw_exit_info_1` of an IOIO exit
+pub fn ioio_exit_info(port: u16, size: u8, is_in: bool) -> u64 {
+    ((port as u64) << IOIO_PORT_SHIFT)
+        | (((size & 0b111) as u64) << IOIO_SIZE_SHIFT)
+        | IOIO_ADDR_16
+        | if is_in { IOIO_TYPE_IN } else { 0 }
+}
+
+/// Decode `sw_exit_info_1` of an IOIO exit into `(port, size, is_in)`
+pub fn ioio_decode(exit_info_1: u64) -> (u16, u8, bool) {
+    (
+        (exit_info_1 >> IOIO_PORT_SHIFT) as u16,
+        ((exit_info_1 >> IOIO_SIZE_SHIFT) & 0b111) as u8,
+        exit_info_1 & IOIO_TYPE_IN != 0,
+    )
+}
+
+/// The GHCB page
+///
+/// Only the fields used by the kernel are public, the rest of the save area
+/// is reserved.
+#[repr(C, align(4096))]
+pub struct Ghcb {
+    _reserved_0: [u8; RAX_OFFSET],
+    rax: u64,
+    _reserved_1: [u8; RCX_OFFSET - RAX_OFFSET - 8],
+    rcx: u64,
+    rdx: u64,
+    rbx: u64,
+    _reserved_2: [u8; SW_EXIT_CODE_OFFSET - RBX_OFFSET - 8],
+    sw_exit_code: u64,
+    sw_exit_info_1: u64,
+    sw_exit_info_2: u64,
+    sw_scratch: u64,
+    _reserved_3: [u8; XCR0_OFFSET - 0x3B0],
+    xcr0: u64,
+    valid_bitmap: [u8; 16],
+    _reserved_4: [u8; 0xFFA - 0x400],
+    /// protocol version of the GHCB format
+    pub protocol_version: u16,
+    /// `0` for the standard GHCB format
+    pub ghcb_usage: u32,
+}
+
+macro_rules! ghcb_reg {
+    ($get:ident, $set:ident, $field:ident, $offset:expr) => {
+        /// Get the register, if marked valid
+        pub fn $get(&self) -> Option<u64> {
+            if self.is_valid($offset) {
+                Some(self.$field)
+            } else {
+                None
+            }
+        }
+
+        /// Set the register and mark it valid
+        pub fn $set(&mut self, value: u64) {
+            self.$field = value;
+            self.mark_valid($offset);
+        }
+    };
+}
+
+impl Ghcb {
+    ghcb_reg!(rax, set_rax, rax, RAX_OFFSET);
+    ghcb_reg!(rcx, set_rcx, rcx, RCX_OFFSET);
+    ghcb_reg!(rdx, set_rdx, rdx, RDX_OFFSET);
+    ghcb_reg!(rbx, set_rbx, rbx, RBX_OFFSET);
+    ghcb_reg!(xcr0, set_xcr0, xcr0, XCR0_OFFSET);
+
+    fn mark_valid(&mut self, offset: usize) {
+        let bit = offset / 8;
+        self.valid_bitmap[bit / 8] |= 1 << (bit % 8);
+    }
+
+    fn is_valid(&self, offset: usize) -> bool {
+        let bit = offset / 8;
+        self.valid_bitmap[bit / 8] & (1 << (bit % 8)) != 0
+    }
+
+    /// Invalidate all fields before a new request
+    pub fn clear(&mut self) {
+        self.valid_bitmap = [0; 16];
+        self.sw_exit_code = 0;
+        self.sw_exit_info_1 = 0;
+        self.sw_exit_info_2 = 0;
+        self.protocol_version = GHCB_PROTOCOL_VERSION;
+        self.ghcb_usage = 0;
+    }
+
+    /// Set the exit code and exit info of a request
+    pub fn set_sw_exit(&mut self, code: u64, info_1: u64, info_2: u64) {
+        self.sw_exit_code = code;
+        self.sw_exit_info_1 = info_1;
+        self.sw_exit_info_2 = info_2;
+        self.mark_valid(SW_EXIT_CODE_OFFSET);
+        self.mark_valid(SW_EXIT_INFO_1_OFFSET);
+        self.mark_valid(SW_EXIT_INFO_2_OFFSET);
+    }
+
+    /// The exit code of the request
+    pub fn sw_exit_code(&self) -> u64 {
+        self.sw_exit_code
+    }
+
+    /// The first exit info of the request, or the result after the request
+    ///
+    /// The hypervisor sets it to `0` on success.
+    pub fn sw_exit_info_1(&self) -> u64 {
+        self.sw_exit_info_1
+    }
+
+    /// The second exit info of the request, or the error details after the request
+    pub fn sw_exit_info_2(&self) -> u64 {
+        self.sw_exit_info_2
+    }
+
+    /// Set the result of a request
+    ///
+    /// To be called by the hypervisor. The register values returned have to be
+    /// set and marked valid before.
+    pub fn set_result(&mut self, info_1: u64, info_2: u64) {
+        self.sw_exit_info_1 = info_1;
+        self.sw_exit_info_2 = info_2;
+    }
+}
+
+#[test]
+fn check_ghcb_layout() {
+    use core::mem::{size_of, MaybeUninit};
+
+    assert_eq!(size_of::<Ghcb>(), 4096);
+
+    let ghcb = MaybeUninit::<Ghcb>::uninit();
+    let base = ghcb.as_ptr() as usize;
+    let offset = |p: *const u64| p as usize - base;
+    unsafe {
+        let p = ghcb.as_ptr();
+        assert_eq!(offset(&(*p).rax), RAX_OFFSET);
+        assert_eq!(offset(&(*p).rcx), RCX_OFFSET);
+        assert_eq!(offset(&(*p).rdx), RDX_OFFSET);
+        assert_eq!(offset(&(*p).rbx), RBX_OFFSET);
+        assert_eq!(offset(&(*p).sw_exit_code), SW_EXIT_CODE_OFFSET);
+        assert_eq!(offset(&(*p).sw_exit_info_1), SW_EXIT_INFO_1_OFFSET);
+        assert_eq!(offset(&(*p).sw_exit_info_2), SW_EXIT_INFO_2_OFFSET);
+        assert_eq!(offset(&(*p).sw_scratch), 0x3A8);
+        assert_eq!(offset(&(*p).xcr0), XCR0_OFFSET);
+        assert_eq!(&(*p).valid_bitmap as *const _ as usize - base, 0x3F0);
+        assert_eq!(&(*p).protocol_version as *const _ as usize - base, 0xFFA);
+    }
+}
+
+#[test]
+fn check_valid_bitmap() {
+    let mut ghcb: Ghcb = unsafe { core::mem::zeroed() };
+    assert_eq!(ghcb.rax(), None);
+    ghcb.set_rax(0x10);
+    ghcb.set_xcr0(7);
+    assert_eq!(ghcb.rax(), Some(0x10));
+    assert_eq!(ghcb.xcr0(), Some(7));
+    assert_eq!(ghcb.rcx(), None);
+    ghcb.clear();
+    assert_eq!(ghcb.rax(), None);
+    assert_eq!(ghcb.protocol_version, GHCB_PROTOCOL_VERSION);
+}
+
+#[test]
+fn check_ioio_exit_info() {
+    let info = ioio_exit_info(0xF4, 4, false);
+    assert_eq!(ioio_decode(info), (0xF4, 4, false));
+    assert_eq!(ioio_decode(ioio_exit_info(0x3F8, 1, true)), (0x3F8, 1, true));
+}
@@ -11,9 +11,14 @@
 #![no_std]
 
 pub mod bootinfo;
+pub mod ghcb;
 pub mod memory_map;
+pub mod secret;
+pub mod syscalls;
 
+use bootinfo::LogLevel;
 use core::fmt::{Debug, Formatter};
+use secret::SecretBlob;
 
 impl Debug for VmSyscall {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
@@ -25,6 +30,28 @@ impl Debug for VmSyscall {
             VmSyscall::Mremap { .. } => f.write_str("mremap(2)"),
             VmSyscall::Munmap { .. } => f.write_str("munmap(2)"),
             VmSyscall::Mprotect { .. } => f.write_str("mprotect(2)"),
+            VmSyscall::GetSecret => f.write_str("get_secret"),
+            VmSyscall::Open { .. } => f.write_str("open(2)"),
+            VmSyscall::Pread { .. } => f.write_str("pread(2)"),
+            VmSyscall::Fstat { .. } => f.write_str("fstat(2)"),
+            VmSyscall::Close { .. } => f.write_str("close(2)"),
+            VmSyscall::Socket { .. } => f.write_str("socket(2)"),
+            VmSyscall::Socketpair { .. } => f.write_str("socketpair(2)"),
+            VmSyscall::Bind { .. } => f.write_str("bind(2)"),
+            VmSyscall::Listen { .. } => f.write_str("listen(2)"),
+            VmSyscall::Accept { .. } => f.write_str("accept(2)"),
+            VmSyscall::Connect { .. } => f.write_str("connect(2)"),
+            VmSyscall::Sendto { .. } => f.write_str("sendto(2)"),
+            VmSyscall::Recvfrom { .. } => f.write_str("recvfrom(2)"),
+            VmSyscall::Getsockname { .. } => f.write_str("getsockname(2)"),
+            VmSyscall::Getpeername { .. } => f.write_str("getpeername(2)"),
+            VmSyscall::Setsockopt { .. } => f.write_str("setsockopt(2)"),
+            VmSyscall::Getsockopt { .. } => f.write_str("getsockopt(2)"),
+            VmSyscall::Shutdown { .. } => f.write_str("shutdown(2)"),
+            VmSyscall::CloseSocket { .. } => f.write_str("close_socket"),
+            VmSyscall::Poll { .. } => f.write_str("poll(2)"),
+            VmSyscall::Trace { .. } => f.write_str("trace"),
+            VmSyscall::Log { .. } => f.write_str("log"),
         }
     }
 }
@@ -32,6 +59,100 @@ impl Debug for VmSyscall {
 /// maximum length of write(2) buffer
 pub const WRITE_BUF_LEN: usize = 4000;
 
+/// maximum length of read(2) and pread(2) buffers
+pub const READ_BUF_LEN: usize = 4000;
+
+/// maximum length of an open(2) path, without the terminating NUL
+pub const PATH_BUF_LEN: usize = 1024;
+
+/// maximum length of a socket address, the size of `struct sockaddr_storage`
+pub const SOCKADDR_LEN: usize = 128;
+
+/// maximum length of sendto(2) and recvfrom(2) buffers
+///
+/// Smaller than `WRITE_BUF_LEN`, the page also holds the socket address.
+pub const SOCKET_BUF_LEN: usize = 3800;
+
+/// maximum length of a setsockopt(2) or getsockopt(2) value
+pub const SOCKOPT_LEN: usize = 64;
+
+/// A socket address in the layout of `struct sockaddr`
+#[derive(Clone, Copy)]
+pub struct SockAddr {
+    /// length of the address in `data`
+    pub len: u32,
+    /// the `struct sockaddr` bytes
+    pub data: [u8; SOCKADDR_LEN],
+}
+
+impl SockAddr {
+    /// The address in `bytes`, `None` if it is too long
+    pub fn new(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() > SOCKADDR_LEN {
+            return None;
+        }
+        let mut data = [0u8; SOCKADDR_LEN];
+        data[..bytes.len()].copy_from_slice(bytes);
+        Some(SockAddr {
+            len: bytes.len() as _,
+            data,
+        })
+    }
+
+    /// The bytes of the address
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..(self.len as usize).min(SOCKADDR_LEN)]
+    }
+}
+
+/// maximum number of host files of a poll(2)
+pub const MAX_POLL_FDS: usize = 128;
+
+/// maximum length of a decoded syscall of `VmSyscall::Trace`
+pub const TRACE_LINE_LEN: usize = 1024;
+
+/// maximum length of a kernel log message of `VmSyscall::Log`
+pub const LOG_MSG_LEN: usize = 4000;
+
+/// The kind of a host file of `VmSyscall::Poll`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PollKind {
+    /// stdin, stdout or stderr of the hypervisor
+    Stdio,
+    /// a socket of `VmSyscall::Socket`, `VmSyscall::Socketpair` or `VmSyscall::Accept`
+    Socket,
+}
+
+/// A host file of `VmSyscall::Poll`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PollFd {
+    /// what `fd` refers to
+    pub kind: PollKind,
+    /// the file descriptor or socket handle
+    pub fd: u32,
+    /// see poll(2)
+    pub events: i16,
+}
+
+impl Default for PollFd {
+    fn default() -> Self {
+        PollFd {
+            kind: PollKind::Stdio,
+            fd: 0,
+            events: 0,
+        }
+    }
+}
+
+/// The status of a file opened with `VmSyscall::Open`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileStat {
+    /// see stat(2)
+    pub st_mode: u32,
+    /// see stat(2)
+    pub st_size: u64,
+}
+
 /// The syscalls for the Hypervisor <-> VM syscall proxy
 pub enum VmSyscall {
     /// ssize_t read(int fd, void *buf, size_t count);
@@ -97,6 +218,197 @@ pub enum VmSyscall {
         /// see mprotect(2)
         prot: i32,
     },
+    /// Get the sealed secret of the workload
+    GetSecret,
+    /// int open(const char *pathname, int flags);
+    ///
+    /// Opens a read-only file in the sysroot of the hypervisor.
+    Open {
+        /// see open(2), not NUL terminated
+        path: [u8; PATH_BUF_LEN],
+        /// length of `path`
+        len: usize,
+        /// see open(2)
+        flags: i32,
+    },
+    /// ssize_t pread(int fd, void *buf, size_t count, off_t offset);
+    Pread {
+        /// see pread(2)
+        fd: u32,
+        /// see pread(2)
+        count: usize,
+        /// see pread(2)
+        offset: i64,
+    },
+    /// int fstat(int fd, struct stat *statbuf);
+    Fstat {
+        /// see fstat(2)
+        fd: u32,
+    },
+    /// int close(int fd);
+    Close {
+        /// see close(2)
+        fd: u32,
+    },
+    /// int socket(int domain, int type, int protocol);
+    ///
+    /// Creates a socket of the hypervisor, without `SOCK_NONBLOCK` and
+    /// `SOCK_CLOEXEC`.
+    Socket {
+        /// see socket(2)
+        domain: i32,
+        /// see socket(2)
+        ty: i32,
+        /// see socket(2)
+        protocol: i32,
+    },
+    /// int socketpair(int domain, int type, int protocol, int sv[2]);
+    Socketpair {
+        /// see socketpair(2)
+        domain: i32,
+        /// see socketpair(2)
+        ty: i32,
+        /// see socketpair(2)
+        protocol: i32,
+    },
+    /// int bind(int sockfd, const struct sockaddr *addr, socklen_t addrlen);
+    Bind {
+        /// see bind(2)
+        fd: u32,
+        /// see bind(2)
+        addr: SockAddr,
+    },
+    /// int listen(int sockfd, int backlog);
+    Listen {
+        /// see listen(2)
+        fd: u32,
+        /// see listen(2)
+        backlog: i32,
+    },
+    /// int accept(int sockfd, struct sockaddr *addr, socklen_t *addrlen);
+    Accept {
+        /// see accept(2)
+        fd: u32,
+        /// fail with `EAGAIN` instead of waiting
+        nonblock: bool,
+    },
+    /// int connect(int sockfd, const struct sockaddr *addr, socklen_t addrlen);
+    Connect {
+        /// see connect(2)
+        fd: u32,
+        /// see connect(2)
+        addr: SockAddr,
+        /// fail with `EINPROGRESS` instead of waiting
+        nonblock: bool,
+    },
+    /// ssize_t sendto(int sockfd, const void *buf, size_t len, int flags, …);
+    Sendto {
+        /// see sendto(2)
+        fd: u32,
+        /// see sendto(2)
+        count: usize,
+        /// see sendto(2)
+        data: [u8; SOCKET_BUF_LEN],
+        /// see sendto(2), without `MSG_DONTWAIT`
+        flags: i32,
+        /// see sendto(2)
+        addr: Option<SockAddr>,
+        /// fail with `EAGAIN` instead of waiting
+        nonblock: bool,
+    },
+    /// ssize_t recvfrom(int sockfd, void *buf, size_t len, int flags, …);
+    Recvfrom {
+        /// see recvfrom(2)
+        fd: u32,
+        /// see recvfrom(2)
+        count: usize,
+        /// see recvfrom(2), without `MSG_DONTWAIT`
+        flags: i32,
+        /// fail with `EAGAIN` instead of waiting
+        nonblock: bool,
+    },
+    /// int getsockname(int sockfd, struct sockaddr *addr, socklen_t *addrlen);
+    Getsockname {
+        /// see getsockname(2)
+        fd: u32,
+    },
+    /// int getpeername(int sockfd, struct sockaddr *addr, socklen_t *addrlen);
+    Getpeername {
+        /// see getpeername(2)
+        fd: u32,
+    },
+    /// int setsockopt(int sockfd, int level, int optname, const void *optval, socklen_t optlen);
+    Setsockopt {
+        /// see setsockopt(2)
+        fd: u32,
+        /// see setsockopt(2)
+        level: i32,
+        /// see setsockopt(2)
+        name: i32,
+        /// see setsockopt(2)
+        value: [u8; SOCKOPT_LEN],
+        /// length of `value`
+        len: u32,
+    },
+    /// int getsockopt(int sockfd, int level, int optname, void *optval, socklen_t *optlen);
+    Getsockopt {
+        /// see getsockopt(2)
+        fd: u32,
+        /// see getsockopt(2)
+        level: i32,
+        /// see getsockopt(2)
+        name: i32,
+        /// see getsockopt(2)
+        len: u32,
+    },
+    /// int shutdown(int sockfd, int how);
+    Shutdown {
+        /// see shutdown(2)
+        fd: u32,
+        /// see shutdown(2)
+        how: i32,
+    },
+    /// Close a socket of `Socket`, `Socketpair` or `Accept`
+    CloseSocket {
+        /// see close(2)
+        fd: u32,
+    },
+    /// int poll(struct pollfd *fds, nfds_t nfds, int timeout);
+    ///
+    /// Waits for host files, without any it only sleeps for `timeout`.
+    Poll {
+        /// see poll(2)
+        fds: [PollFd; MAX_POLL_FDS],
+        /// see poll(2)
+        nfds: usize,
+        /// see poll(2), in milliseconds
+        timeout: i32,
+    },
+    /// A traced syscall of the app, after it returned
+    ///
+    /// `line` is decoded by the kernel in the style of strace, without the
+    /// newline.
+    Trace {
+        /// the syscall number
+        nr: u64,
+        /// the raw arguments
+        args: [u64; 6],
+        /// the raw return value, `None` if the syscall does not return
+        ret: Option<i64>,
+        /// the length of `line`
+        len: usize,
+        /// the decoded syscall
+        line: [u8; TRACE_LINE_LEN],
+    },
+    /// A log message of the kernel, apart from the output of the app
+    Log {
+        /// the level of the message
+        level: LogLevel,
+        /// the length of `msg`
+        len: usize,
+        /// the message, UTF-8 without the newline
+        msg: [u8; LOG_MSG_LEN],
+    },
     // Todo: extend with needed hypervisor proxy syscalls
 }
 
@@ -104,7 +416,7 @@ pub enum VmSyscall {
 /// for the Hypervisor <-> VM syscall proxy
 pub enum VmSyscallRet {
     /// ssize_t read(int fd, void *buf, size_t count);
-    Read(Result<(i32, [u8; 4000]), Error>),
+    Read(Result<(i32, [u8; READ_BUF_LEN]), Error>),
     /// ssize_t write(int fd, const void *buf, size_t count);
     Write(Result<i32, Error>),
     /// int madvise(void *addr, size_t length, int advice);
@@ -117,6 +429,52 @@ pub enum VmSyscallRet {
     Munmap(Result<i32, Error>),
     /// int mprotect(void *addr, size_t len, int prot);
     Mprotect(Result<i32, Error>),
+    /// The sealed secret of the workload
+    GetSecret(Result<SecretBlob, Error>),
+    /// int open(const char *pathname, int flags);
+    Open(Result<i32, Error>),
+    /// ssize_t pread(int fd, void *buf, size_t count, off_t offset);
+    Pread(Result<(i32, [u8; READ_BUF_LEN]), Error>),
+    /// int fstat(int fd, struct stat *statbuf);
+    Fstat(Result<FileStat, Error>),
+    /// int close(int fd);
+    Close(Result<i32, Error>),
+    /// int socket(int domain, int type, int protocol);
+    Socket(Result<i32, Error>),
+    /// int socketpair(int domain, int type, int protocol, int sv[2]);
+    Socketpair(Result<(i32, i32), Error>),
+    /// int bind(int sockfd, const struct sockaddr *addr, socklen_t addrlen);
+    Bind(Result<i32, Error>),
+    /// int listen(int sockfd, int backlog);
+    Listen(Result<i32, Error>),
+    /// int accept(int sockfd, struct sockaddr *addr, socklen_t *addrlen);
+    Accept(Result<(i32, SockAddr), Error>),
+    /// int connect(int sockfd, const struct sockaddr *addr, socklen_t addrlen);
+    Connect(Result<i32, Error>),
+    /// ssize_t sendto(int sockfd, const void *buf, size_t len, int flags, …);
+    Sendto(Result<i32, Error>),
+    /// ssize_t recvfrom(int sockfd, void *buf, size_t len, int flags, …);
+    Recvfrom(Result<(i32, [u8; SOCKET_BUF_LEN], SockAddr), Error>),
+    /// int getsockname(int sockfd, struct sockaddr *addr, socklen_t *addrlen);
+    Getsockname(Result<SockAddr, Error>),
+    /// int getpeername(int sockfd, struct sockaddr *addr, socklen_t *addrlen);
+    Getpeername(Result<SockAddr, Error>),
+    /// int setsockopt(int sockfd, int level, int optname, const void *optval, socklen_t optlen);
+    Setsockopt(Result<i32, Error>),
+    /// int getsockopt(int sockfd, int level, int optname, void *optval, socklen_t *optlen);
+    Getsockopt(Result<(u32, [u8; SOCKOPT_LEN]), Error>),
+    /// int shutdown(int sockfd, int how);
+    Shutdown(Result<i32, Error>),
+    /// Close a socket
+    CloseSocket(Result<i32, Error>),
+    /// int poll(struct pollfd *fds, nfds_t nfds, int timeout);
+    ///
+    /// The number of ready files and the `revents` of each.
+    Poll(Result<(i32, [i16; MAX_POLL_FDS]), Error>),
+    /// A traced syscall was recorded
+    Trace(Result<i32, Error>),
+    /// A log message was recorded
+    Log(Result<i32, Error>),
 }
 
 /// The error codes of the syscalls
@@ -130,3 +488,17 @@ pub enum Error {
     /// deserialize error
     DeSerializeError,
 }
+
+#[test]
+fn check_syscall_page_size() {
+    use crate::memory_map::PAGE_SIZE;
+    assert!(core::mem::size_of::<VmSyscall>() <= (PAGE_SIZE as _));
+    assert!(core::mem::size_of::<VmSyscallRet>() <= (PAGE_SIZE as _));
+}
+
+#[test]
+fn sockaddr_bytes() {
+    let addr = SockAddr::new(&[2, 0, 0x1f, 0x90, 127, 0, 0, 1]).unwrap();
+    assert_eq!(addr.as_bytes(), &[2, 0, 0x1f, 0x90, 127, 0, 0, 1]);
+    assert!(SockAddr::new(&[0u8; SOCKADDR_LEN + 1]).is_none());
+}
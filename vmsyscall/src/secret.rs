@@ -0,0 +1,300 @@
+//! Secret injection for confidential workloads
+//!
+//! The guest owner's key broker releases a 256 bit key to the guest, after it
+//! verified the launch measurement. The key is injected into the private
+//! [`LaunchSecret`] page before the guest runs (LAUNCH_SECRET with SEV), so the
+//! hypervisor never sees it.
+//!
+//! The secret itself is sealed with that key by the key broker and can be
+//! handed to the kernel by the untrusted hypervisor as a [`SecretBlob`] via
+//! `VmSyscall::GetSecret`.
+//!
+//! The blob is sealed with ChaCha20-Poly1305 (RFC 8439).
+
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+
+/// Hard coded guest physical address of the launch secret page
+pub const LAUNCH_SECRET_PHYS_ADDR: u64 = 0x3000;
+
+/// Marks a launch secret page with a valid key
+pub const LAUNCH_SECRET_MAGIC: u64 = 0x4345_5358_5241_4E45; // "ENARXSEC"
+
+/// Length of the key in bytes
+pub const KEY_LEN: usize = 32;
+/// Length of the nonce in bytes
+pub const NONCE_LEN: usize = 12;
+/// Length of the authentication tag in bytes
+pub const TAG_LEN: usize = 16;
+
+/// Maximum length of a secret
+pub const SECRET_MAX_LEN: usize = 3072;
+
+/// Length of a `SecretBlob` file: the length as `u64`, nonce, tag and data
+pub const SECRET_BLOB_LEN: usize = 8 + NONCE_LEN + TAG_LEN + SECRET_MAX_LEN;
+
+/// Errors sealing or opening a secret
+#[derive(Debug, PartialEq)]
+pub enum SecretError {
+    /// The secret does not fit into a `SecretBlob`
+    TooLong,
+    /// The authentication tag does not match: wrong key or modified data
+    BadTag,
+}
+
+/// The content of the launch secret page
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct LaunchSecret {
+    /// `LAUNCH_SECRET_MAGIC`, if the page holds a key
+    pub magic: u64,
+    /// The key to open the `SecretBlob`
+    pub key: [u8; KEY_LEN],
+}
+
+impl LaunchSecret {
+    /// A launch secret page holding `key`
+    pub fn new(key: [u8; KEY_LEN]) -> Self {
+        LaunchSecret {
+            magic: LAUNCH_SECRET_MAGIC,
+            key,
+        }
+    }
+
+    /// The key, if the page was injected
+    pub fn key(&self) -> Option<&[u8; KEY_LEN]> {
+        if self.magic == LAUNCH_SECRET_MAGIC {
+            Some(&self.key)
+        } else {
+            None
+        }
+    }
+
+    /// The raw bytes to be injected into guest memory
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self as *const Self as *const u8,
+                core::mem::size_of::<Self>(),
+            )
+        }
+    }
+}
+
+/// A secret sealed with the launch secret key
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct SecretBlob {
+    len: usize,
+    nonce: [u8; NONCE_LEN],
+    tag: [u8; TAG_LEN],
+    data: [u8; SECRET_MAX_LEN],
+}
+
+impl SecretBlob {
+    /// Seal `secret` with `key`
+    ///
+    /// `nonce` must never be reused with the same key.
+    pub fn seal(
+        key: &[u8; KEY_LEN],
+        nonce: &[u8; NONCE_LEN],
+        secret: &[u8],
+    ) -> Result<Self, SecretError> {
+        if secret.len() > SECRET_MAX_LEN {
+            return Err(SecretError::TooLong);
+        }
+
+        let mut blob = SecretBlob {
+            len: secret.len(),
+            nonce: *nonce,
+            tag: [0; TAG_LEN],
+            data: [0; SECRET_MAX_LEN],
+        };
+        blob.data[..secret.len()].copy_from_slice(secret);
+        blob.tag = encrypt_in_place(key, nonce, &[], &mut blob.data[..secret.len()]);
+        Ok(blob)
+    }
+
+    /// Verify and decrypt the secret in place with `key`
+    pub fn open(&mut self, key: &[u8; KEY_LEN]) -> Result<&[u8], SecretError> {
+        if self.len > SECRET_MAX_LEN {
+            return Err(SecretError::TooLong);
+        }
+        decrypt_in_place(key, &self.nonce, &[], &mut self.data[..self.len], &self.tag)?;
+        Ok(&self.data[..self.len])
+    }
+
+    /// The nonce the secret was sealed with
+    pub fn nonce(&self) -> &[u8; NONCE_LEN] {
+        &self.nonce
+    }
+
+    /// Length of the secret
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// `true`, if the secret is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The blob as the guest owner hands it to the hypervisor
+    pub fn to_bytes(&self) -> [u8; SECRET_BLOB_LEN] {
+        let mut bytes = [0u8; SECRET_BLOB_LEN];
+        let (len, rest) = bytes.split_at_mut(8);
+        let (nonce, rest) = rest.split_at_mut(NONCE_LEN);
+        let (tag, data) = rest.split_at_mut(TAG_LEN);
+        len.copy_from_slice(&(self.len as u64).to_le_bytes());
+        nonce.copy_from_slice(&self.nonce);
+        tag.copy_from_slice(&self.tag);
+        data.copy_from_slice(&self.data);
+        bytes
+    }
+
+    /// Parse the bytes of `to_bytes()`, the secret is opened by the kernel
+    pub fn from_bytes(bytes: &[u8; SECRET_BLOB_LEN]) -> Result<Self, SecretError> {
+        let mut len = [0u8; 8];
+        len.copy_from_slice(&bytes[..8]);
+        let len = u64::from_le_bytes(len);
+        if len > SECRET_MAX_LEN as u64 {
+            return Err(SecretError::TooLong);
+        }
+
+        let mut blob = SecretBlob {
+            len: len as usize,
+            nonce: [0; NONCE_LEN],
+            tag: [0; TAG_LEN],
+            data: [0; SECRET_MAX_LEN],
+        };
+        blob.nonce.copy_from_slice(&bytes[8..8 + NONCE_LEN]);
+        blob.tag
+            .copy_from_slice(&bytes[8 + NONCE_LEN..8 + NONCE_LEN + TAG_LEN]);
+        blob.data.copy_from_slice(&bytes[8 + NONCE_LEN + TAG_LEN..]);
+        Ok(blob)
+    }
+}
+
+/// ChaCha20-Poly1305 encrypt `buf` in place, returns the tag
+pub fn encrypt_in_place(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    buf: &mut [u8],
+) -> [u8; TAG_LEN] {
+    let tag = ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt_in_place_detached(Nonce::from_slice(nonce), aad, buf)
+        // only fails for more than 256 GiB
+        .expect("ChaCha20-Poly1305 buffer too long");
+
+    let mut out = [0u8; TAG_LEN];
+    out.copy_from_slice(&tag);
+    out
+}
+
+/// ChaCha20-Poly1305 verify `tag` and decrypt `buf` in place
+///
+/// `buf` is left unmodified, if the tag does not match.
+pub fn decrypt_in_place(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    buf: &mut [u8],
+    tag: &[u8; TAG_LEN],
+) -> Result<(), SecretError> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt_in_place_detached(Nonce::from_slice(nonce), aad, buf, Tag::from_slice(tag))
+        .map_err(|_| SecretError::BadTag)
+}
+
+#[cfg(test)]
+fn unhex(s: &str) -> [u8; 128] {
+    let mut out = [0u8; 128];
+    for (i, b) in s.as_bytes().chunks(2).enumerate() {
+        let nibble = |c: u8| (c as char).to_digit(16).unwrap() as u8;
+        out[i] = nibble(b[0]) << 4 | nibble(b[1]);
+    }
+    out
+}
+
+#[test]
+fn check_aead_rfc8439() {
+    // RFC 8439 2.8.2
+    let plaintext: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+        only one tip for the future, sunscreen would be it.";
+    let mut key = [0u8; KEY_LEN];
+    for (i, k) in key.iter_mut().enumerate() {
+        *k = 0x80 + i as u8;
+    }
+    let nonce = [
+        0x07, 0, 0, 0, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
+    ];
+    let aad = [
+        0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+    ];
+
+    let mut buf = [0u8; 114];
+    buf.copy_from_slice(plaintext);
+    let tag = encrypt_in_place(&key, &nonce, &aad, &mut buf);
+
+    let ciphertext = unhex(
+        "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+         3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+         92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+         3ff4def08e4b7a9de576d26586cec64b6116",
+    );
+    assert_eq!(&buf[..], &ciphertext[..114]);
+    assert_eq!(&tag[..], &unhex("1ae10b594f09e26a7e902ecbd0600691")[..16]);
+
+    decrypt_in_place(&key, &nonce, &aad, &mut buf, &tag).unwrap();
+    assert_eq!(&buf[..], plaintext);
+}
+
+#[test]
+fn check_secret_blob() {
+    let key = [0x42u8; KEY_LEN];
+    let nonce = [0x01u8; NONCE_LEN];
+
+    let mut blob = SecretBlob::seal(&key, &nonce, b"top secret").unwrap();
+    assert_eq!(blob.len(), 10);
+    assert_ne!(&blob.data[..10], b"top secret");
+
+    let mut tampered = blob;
+    tampered.data[0] ^= 1;
+    assert_eq!(tampered.open(&key).unwrap_err(), SecretError::BadTag);
+
+    let mut wrong_key = blob;
+    assert_eq!(
+        wrong_key.open(&[0x43u8; KEY_LEN]).unwrap_err(),
+        SecretError::BadTag
+    );
+
+    let mut parsed = SecretBlob::from_bytes(&blob.to_bytes()).unwrap();
+    assert_eq!(parsed.open(&key).unwrap(), b"top secret");
+
+    let mut too_long = blob.to_bytes();
+    too_long[..8].copy_from_slice(&(SECRET_MAX_LEN as u64 + 1).to_le_bytes());
+    assert_eq!(
+        SecretBlob::from_bytes(&too_long).err().unwrap(),
+        SecretError::TooLong
+    );
+
+    assert_eq!(blob.open(&key).unwrap(), b"top secret");
+
+    assert_eq!(
+        SecretBlob::seal(&key, &nonce, &[0u8; SECRET_MAX_LEN + 1])
+            .err()
+            .unwrap(),
+        SecretError::TooLong
+    );
+}
+
+#[test]
+fn check_launch_secret() {
+    let secret = LaunchSecret::new([7u8; KEY_LEN]);
+    assert_eq!(secret.key(), Some(&[7u8; KEY_LEN]));
+    assert_eq!(secret.as_bytes().len(), 40);
+
+    let empty: LaunchSecret = unsafe { core::mem::zeroed() };
+    assert_eq!(empty.key(), None);
+}